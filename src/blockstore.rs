@@ -1,176 +1,2958 @@
+// deku derives generate these
+#![allow(clippy::manual_div_ceil)]
+
+//! Stores of entries: titles and texts of pages, in zstd-compressed blocks of many entries.
+
 use std::{
+	borrow::Cow,
+	collections::{BTreeMap, BTreeSet, HashMap},
 	convert::{TryFrom, TryInto},
 	fmt,
-	fs::{create_dir_all, File},
-	io::{BufReader, Read, Write},
+	fs::{create_dir_all, remove_file, rename, File},
+	io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
 	iter::once,
 	mem,
+	ops::{Deref, Range},
 	path::{Path, PathBuf},
 	str::FromStr,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		mpsc::{sync_channel, SyncSender},
+		Arc, Condvar, Mutex,
+	},
+	thread::{self, JoinHandle},
 };
 
-use color_eyre::{
-	eyre::{eyre, Result},
-	Report,
+use crate::{
+	error::{Result, WiktError},
+	pack::Pack,
+	redirects::Redirects,
+	titles::{TitleBloom, TitleRefs},
+	wordfilter::WordFilter,
+	xmldump::{self, SiteInfo},
 };
-use deku::prelude::*;
-use log::{debug, error, trace};
+use color_eyre::eyre::eyre;
+use deku::{
+	bitvec::{BitSlice, BitVec, Msb0},
+	prelude::*,
+};
+use log::{debug, info, trace, warn};
+use lru::LruCache;
+use memmap::{Mmap, MmapOptions};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use zstd::{
 	dict::{from_continuous, DecoderDictionary, EncoderDictionary},
-	Decoder, Encoder,
+	Decoder,
 };
+use zstd_safe::{CCtx, CParameter, DictAttachPref};
 
+/// A store of entries, in zstd-compressed blocks sharing a dictionary.
+///
+/// It's either a directory of block files and metadata, or a single file packed from one (which is
+/// read-only). Create one with `new`, then `open` it to read or add blocks.
 pub struct Store {
+	/// the store directory (or packed file)
 	pub dir: PathBuf,
-	pub dict_en: Option<EncoderDictionary<'static>>,
-	pub dict_de: Option<DecoderDictionary<'static>>,
+	pub(crate) dict_en: Option<Arc<EncoderDict>>,
+	/// the dictionaries blocks were compressed with, by id
+	pub(crate) dict_de: HashMap<u32, DecoderDictionary<'static>>,
+	/// whether to train a new dictionary on the next block committed (see `with_new_dictionary`)
+	retrain: bool,
+	cache: Option<BlockCache>,
+	writers: Option<Writers>,
+	pack: Option<Pack>,
+	lenient: bool,
+	mmap: bool,
+	level: i32,
+	/// the window log of long-distance matching, if it's on (see `with_long`)
+	long: Option<u32>,
+	/// whether blocks are written with a filter of their words (see `with_word_filters`)
+	word_filters: bool,
+	/// whether block files are spread over subdirectories (see `with_subdirs`)
+	subdirs: bool,
+	/// entries deleted since their blocks were written (see `delete`)
+	deleted: BTreeSet<Ref>,
+	/// the blocks of the store, once opened (not for packs, which have their own index)
+	manifest: Option<Arc<ManifestFile>>,
+}
+
+/// The blocks of a store, as recorded in its `manifest.json`.
+///
+/// It's kept up to date as blocks are written, so the blocks of a store and their entry counts
+/// are known without listing its directory or reading any block.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+	/// block id => block
+	pub blocks: BTreeMap<u32, BlockInfo>,
+}
+
+/// A block, as recorded in the manifest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockInfo {
+	/// the number of entries, deleted ones included: the block covers refs `id/0` to
+	/// `id/(entries-1)`
+	pub entries: u32,
+	/// the size of the block file, in bytes
+	pub size: u64,
+}
+
+impl Manifest {
+	/// the number of entries in all blocks, deleted ones included
+	pub fn entries(&self) -> u64 {
+		self.blocks
+			.values()
+			.map(|block| u64::from(block.entries))
+			.sum()
+	}
+}
+
+/// The manifest of an open store, shared with the threads writing blocks.
+///
+/// It's written out whole on every change, under a temporary name then renamed over the old one.
+#[derive(Debug)]
+struct ManifestFile {
+	path: PathBuf,
+	manifest: Mutex<Manifest>,
+}
+
+impl ManifestFile {
+	/// records a block that was written (or rewritten)
+	fn record(&self, id: u32, entries: u32, size: u64) -> Result<()> {
+		let mut manifest = self.manifest.lock().unwrap();
+		manifest.blocks.insert(id, BlockInfo { entries, size });
+		Self::write(&self.path, &manifest)
+	}
+
+	/// forgets blocks that were removed
+	fn forget(&self, ids: impl IntoIterator<Item = u32>) -> Result<()> {
+		let mut manifest = self.manifest.lock().unwrap();
+		for id in ids {
+			manifest.blocks.remove(&id);
+		}
+		Self::write(&self.path, &manifest)
+	}
+
+	fn write(path: &Path, manifest: &Manifest) -> Result<()> {
+		let tmp = path.with_extension("json.tmp");
+		let mut file = BufWriter::new(File::create(&tmp)?);
+		serde_json::to_writer(&mut file, manifest)?;
+		file.flush()?;
+		drop(file);
+		rename(tmp, path)?;
+		Ok(())
+	}
+}
+
+/// The file recording a compaction whose blocks are being swapped in (see `Store::compact`).
+const COMPACTION_FILE: &str = "compact.pending";
+
+/// A run of blocks being replaced by compacted ones, recorded before they're swapped.
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingCompaction {
+	/// the ids of the blocks replaced
+	old: Vec<u32>,
+	/// the id, number of entries, and size of each block replacing them, written under its
+	/// temporary name
+	new: Vec<(u32, u32, u64)>,
+}
+
+impl PendingCompaction {
+	fn read(path: &Path) -> Result<Self> {
+		let file = BufReader::new(File::open(path)?);
+		Ok(serde_json::from_reader(file)
+			.map_err(|err| eyre!("bad record of a compaction {:?}: {}", path, err))?)
+	}
+
+	/// writes the record under a temporary name then renames it into place, so it's whole
+	fn write(&self, path: &Path) -> Result<()> {
+		let tmp = path.with_extension("pending.tmp");
+		let mut file = BufWriter::new(File::create(&tmp)?);
+		serde_json::to_writer(&mut file, self)?;
+		file.flush()?;
+		drop(file);
+		rename(tmp, path)?;
+		Ok(())
+	}
+}
+
+/// The dictionary new blocks are compressed with, and its id, which they record.
+pub(crate) struct EncoderDict {
+	id: u32,
+	dict: EncoderDictionary<'static>,
+	/// the window log of long-distance matching, if it's on
+	long: Option<u32>,
+	/// whether to write a filter of the words of each block next to it
+	word_filters: bool,
+}
+
+/// Recently read blocks, shared across threads.
+struct BlockCache {
+	blocks: Mutex<LruCache<u32, Arc<Block>>>,
+	hits: AtomicUsize,
+	misses: AtomicUsize,
+}
+
+/// Threads compressing and writing committed blocks, while the next ones are being made.
+///
+/// Blocks are handed over through a channel as deep as there are threads, so at most about twice
+/// that many blocks are in memory. Each is written to a temporary file, then renamed into place
+/// in the order blocks were committed, so if it's interrupted, the store has no gaps.
+struct Writers {
+	threads: usize,
+	queue: Option<SyncSender<Queued>>,
+	handles: Vec<JoinHandle<Result<()>>>,
+	turn: Arc<(Mutex<Turn>, Condvar)>,
+	sent: u64,
+}
+
+/// A block to write: its place in the commit order, the dictionary to compress it with, the block,
+/// and its path.
+type Queued = (u64, Arc<EncoderDict>, Block, PathBuf);
+
+/// The manifest blocks are recorded in once written, if the store has one.
+type SharedManifest = Option<Arc<ManifestFile>>;
+
+/// Which block is next to be renamed into place, and whether one failed (which stops the rest).
+#[derive(Debug, Default)]
+struct Turn {
+	next: u64,
+	failed: bool,
+}
+
+impl Writers {
+	fn send(
+		&mut self,
+		dict: &Arc<EncoderDict>,
+		manifest: &SharedManifest,
+		block: Block,
+		path: PathBuf,
+	) -> Result<()> {
+		if self.turn.0.lock().unwrap().failed {
+			return Err(self
+				.finish()
+				.err()
+				.unwrap_or_else(|| eyre!("writing a block failed").into()));
+		}
+
+		if self.queue.is_none() {
+			self.queue = Some(self.start(manifest));
+		}
+
+		self.queue
+			.as_ref()
+			.unwrap()
+			.send((self.sent, dict.clone(), block, path))
+			.map_err(|_| eyre!("block writers stopped"))?;
+		self.sent += 1;
+		Ok(())
+	}
+
+	/// spawns the threads, returning the end of the queue to send them blocks through
+	fn start(&mut self, manifest: &SharedManifest) -> SyncSender<Queued> {
+		let (blocks, queue) = sync_channel::<Queued>(self.threads);
+		let queue = Arc::new(Mutex::new(queue));
+		self.handles = (0..self.threads)
+			.map(|_| {
+				let queue = queue.clone();
+				let turn = self.turn.clone();
+				let manifest = manifest.clone();
+				thread::spawn(move || loop {
+					let next = queue.lock().unwrap().recv();
+					match next {
+						Ok((seq, dict, block, path)) => {
+							Self::write(&dict, &manifest, &turn, seq, block, &path)?
+						}
+						Err(_) => return Ok(()),
+					}
+				})
+			})
+			.collect();
+		blocks
+	}
+
+	/// writes a block to a temporary file, then renames it into place (and records it in the
+	/// manifest) once it's its turn
+	fn write(
+		dict: &EncoderDict,
+		manifest: &SharedManifest,
+		turn: &(Mutex<Turn>, Condvar),
+		seq: u64,
+		block: Block,
+		path: &Path,
+	) -> Result<()> {
+		let tmp = path.with_extension("zst.tmp");
+		let entries = block.n;
+		let written = Store::write_block(dict, block, &tmp);
+
+		let (lock, turned) = turn;
+		let mut turn = turned
+			.wait_while(lock.lock().unwrap(), |turn| {
+				turn.next != seq && !turn.failed
+			})
+			.unwrap();
+		let written = written.and_then(|size| {
+			if turn.failed {
+				Err(eyre!("not writing {}: an earlier block failed", path.display()).into())
+			} else {
+				place_block(&tmp, path)?;
+				match manifest {
+					Some(manifest) => manifest.record(block_id(path)?, entries, size),
+					None => Ok(()),
+				}
+			}
+		});
+		match written {
+			Ok(()) => turn.next += 1,
+			Err(_) => turn.failed = true,
+		}
+		turned.notify_all();
+		written
+	}
+
+	/// waits for every block sent to be written, returning the first error if any failed
+	fn finish(&mut self) -> Result<()> {
+		// closing the queue lets the threads finish what's in it, then stop
+		self.queue = None;
+		let mut result = Ok(());
+		for handle in self.handles.drain(..) {
+			let written = handle
+				.join()
+				.unwrap_or_else(|_| Err(eyre!("a block writer panicked").into()));
+			if result.is_ok() {
+				result = written;
+			}
+		}
+		result
+	}
+}
+
+impl Drop for Writers {
+	fn drop(&mut self) {
+		if let Err(err) = self.finish() {
+			warn!("writing blocks failed: {}", err);
+		}
+	}
 }
 
 impl Store {
+	/// compresses and writes a block of entries, the first time (or after `with_new_dictionary`)
+	/// training the dictionary on it
+	///
+	/// With `with_writers`, that's done in the background, after the first block: call `flush`
+	/// to wait for it.
+	///
+	/// `n` is the number of entries written so far, including this block's, which names the
+	/// block. The block is emptied, ready for the next entries.
 	pub fn commit(&mut self, block: &mut Block, n: usize) -> Result<()> {
+		self.writable()?;
 		let block = mem::take(block);
 
-		let dict = if let Some(ref d) = self.dict_en {
-			d
-		} else {
+		// the blocks already there need the dictionaries they were compressed with, which a
+		// dictionary trained now would take the place of: carry on with those instead
+		if self.dict_de.is_empty() && !self.blocks()?.is_empty() {
+			self.open()?;
+		}
+
+		if self.dict_en.is_none() || self.retrain {
 			// create dictionary from first block
-			let sample_sizes: Vec<usize> = once(&0_u64)
-				.chain(block.starts.iter())
-				.zip(block.starts.iter())
-				.fuse()
+			let data_len = u64::try_from(block.data.len())?;
+			let sample_sizes: Vec<usize> = block
+				.starts
+				.iter()
+				.zip(block.starts.iter().skip(1).chain(once(&data_len)))
 				.map(|(start, end)| end - start)
 				.map(|n| usize::try_from(n).unwrap())
 				.collect();
 
-			let dict_data = from_continuous(&block.data, &sample_sizes, 150_000)?;
-			let mut file = File::create(self.dir.join("zst.dictionary"))?;
-			file.write_all(&dict_data)?;
-			self.dict_en = Some(EncoderDictionary::copy(&dict_data, 3));
-			self.dict_de = Some(DecoderDictionary::copy(&dict_data));
-			self.dict_en.as_ref().unwrap()
+			let dict_data = train_dictionary(&block.data, &sample_sizes);
+			let id = self.add_dictionary(&dict_data)?;
+			debug!("trained dictionary id={} on block id={}", id, n);
+			self.retrain = false;
+		}
+		// the field, not `encoder`, as the writers are borrowed mutably alongside
+		let dict = self
+			.dict_en
+			.as_ref()
+			.ok_or(WiktError::DictionaryNotLoaded)?;
+
+		let path = self.block_path(u32::try_from(n)?);
+		match self.writers {
+			Some(ref mut writers) => writers.send(dict, &self.manifest, block, path),
+			None => {
+				// under a temporary name until it's whole, so a block cut short never looks done
+				let entries = block.n;
+				let tmp = path.with_extension("zst.tmp");
+				let size = Self::write_block(dict, block, &tmp)?;
+				place_block(&tmp, &path)?;
+				self.record_block(u32::try_from(n)?, entries, size)
+			}
+		}
+	}
+
+	/// waits for blocks committed in the background to be written (see `with_writers`)
+	///
+	/// Returns the first error writing them, if any; once one failed, no later block was written.
+	pub fn flush(&mut self) -> Result<()> {
+		match self.writers {
+			Some(ref mut writers) => writers.finish(),
+			None => Ok(()),
+		}
+	}
+
+	/// writes the dictionary blocks are compressed with, and the level they're compressed at
+	///
+	/// That's the first dictionary (id 0), for a new store: see `add_dictionary` for more.
+	pub fn set_dictionary(&mut self, dict: &[u8]) -> Result<()> {
+		self.writable()?;
+		File::create(self.dir.join(dictionary_file(0)))?.write_all(dict)?;
+		// decompression doesn't need these, but they say how the blocks were made
+		writeln!(File::create(self.dir.join("zst.level"))?, "{}", self.level)?;
+		if let Some(log) = self.long {
+			writeln!(File::create(self.dir.join("zst.long"))?, "{}", log)?;
+		}
+		if self.word_filters {
+			File::create(self.dir.join("words.filters"))?;
+		}
+		self.dict_en = Some(self.encoder_dict(0, dict));
+		self.dict_de.clear();
+		self.dict_de.insert(0, DecoderDictionary::copy(dict));
+		Ok(())
+	}
+
+	/// adds a dictionary to compress new blocks with from now on, returning its id
+	///
+	/// Blocks already written keep theirs: each block records the id of the dictionary it was
+	/// compressed with, and a store keeps all of its dictionaries to read them. In a store without
+	/// any yet, this is `set_dictionary`.
+	pub fn add_dictionary(&mut self, dict: &[u8]) -> Result<u32> {
+		self.writable()?;
+		let id = match self.dict_de.keys().max() {
+			Some(last) => last + 1,
+			None => {
+				self.set_dictionary(dict)?;
+				return Ok(0);
+			}
+		};
+
+		File::create(self.dir.join(dictionary_file(id)))?.write_all(dict)?;
+		self.dict_en = Some(self.encoder_dict(id, dict));
+		self.dict_de.insert(id, DecoderDictionary::copy(dict));
+		Ok(id)
+	}
+
+	/// the dictionary new blocks are compressed with, once the store is open (or created)
+	fn encoder(&self) -> Result<&Arc<EncoderDict>> {
+		self.dict_en.as_ref().ok_or(WiktError::DictionaryNotLoaded)
+	}
+
+	/// a dictionary to compress new blocks with, at the store's level and with its long mode
+	fn encoder_dict(&self, id: u32, dict: &[u8]) -> Arc<EncoderDict> {
+		Arc::new(EncoderDict {
+			id,
+			dict: EncoderDictionary::copy(dict, self.level),
+			long: self.long,
+			word_filters: self.word_filters,
+		})
+	}
+
+	/// how many dictionaries blocks of the store are compressed with (once opened)
+	pub fn dictionaries(&self) -> usize {
+		self.dict_de.len()
+	}
+
+	/// uses a dictionary trained ahead of time (with `store train-dict`) for a new store
+	///
+	/// Returns whether there was one: in a store that has blocks already, the dictionary is that
+	/// of the blocks, not one to use for new ones, so it's ignored.
+	pub fn use_trained_dictionary(&mut self) -> Result<bool> {
+		let path = self.dir.join("zst.dictionary");
+		if !path.exists() || !self.blocks()?.is_empty() {
+			return Ok(false);
+		}
+
+		let dict = self.read_file("zst.dictionary")?;
+		self.set_dictionary(&dict)?;
+		Ok(true)
+	}
+
+	/// compresses and writes a block, returning the size of its file
+	///
+	/// With word filters, the filter is written next to it, under a name `place_block` moves along
+	/// with it.
+	fn write_block(dict: &EncoderDict, block: Block, path: &Path) -> Result<u64> {
+		let bodies = if dict.word_filters {
+			Some(
+				(0..block.n)
+					.map(|n| Ok(block.entry(n)?.open().1))
+					.collect::<Result<Vec<_>>>()?,
+			)
+		} else {
+			None
+		};
+
+		let n = usize::try_from(block.n)?;
+		let block_bytes = block.finish(dict)?;
+		if let Some(dir) = path.parent() {
+			create_dir_all(dir)?;
+		}
+		File::create(path)?.write_all(&block_bytes)?;
+
+		if let Some(bodies) = bodies {
+			let sum_at = frames_start(BLOCK_VERSION, n).unwrap() - BLOCK_CHECKSUM_LEN;
+			let checksum = u32::from_le_bytes(block_bytes[sum_at..sum_at + 4].try_into()?);
+			let filter = WordFilter::build(checksum, bodies.iter().map(String::as_str));
+			File::create(words_path(path))?.write_all(&filter.to_bytes()?)?;
+		}
+		Ok(u64::try_from(block_bytes.len())?)
+	}
+
+	/// the word filter of a block, if it has one made from it as it is now
+	///
+	/// Filters of blocks that were rewritten since (by an older wikt, say) are left out, as their
+	/// checksum doesn't match. Packs don't have any.
+	pub fn word_filter(&self, path: impl AsRef<Path>) -> Option<WordFilter> {
+		if self.pack.is_some() {
+			return None;
+		}
+
+		let path = path.as_ref();
+		let read = || -> Result<Option<WordFilter>> {
+			let filter = WordFilter::from_bytes(&std::fs::read(words_path(path))?)?;
+			let mut file = File::open(path)?;
+			let mut prefix = [0; BLOCK_PREFIX_LEN];
+			file.read_exact(&mut prefix)?;
+			let (version, n, _) = Block::check_prefix(block_id(path)?, &prefix)?;
+			let sum_at = frames_start(version, usize::try_from(n)?)
+				.ok_or_else(|| eyre!("too many entries"))?
+				- BLOCK_CHECKSUM_LEN;
+			file.seek(SeekFrom::Start(u64::try_from(sum_at)?))?;
+			let mut checksum = [0; BLOCK_CHECKSUM_LEN];
+			file.read_exact(&mut checksum)?;
+			Ok(Some(filter).filter(|filter| filter.checksum == u32::from_le_bytes(checksum)))
+		};
+		read().unwrap_or_else(|err| {
+			trace!("no word filter for {}: {}", path.display(), err);
+			None
+		})
+	}
+
+	/// records a block in the manifest, if the store has one
+	fn record_block(&self, id: u32, entries: u32, size: u64) -> Result<()> {
+		match self.manifest {
+			Some(ref manifest) => manifest.record(id, entries, size),
+			None => Ok(()),
+		}
+	}
+
+	/// forgets blocks in the manifest, if the store has one
+	fn forget_blocks(&self, ids: impl IntoIterator<Item = u32>) -> Result<()> {
+		match self.manifest {
+			Some(ref manifest) => manifest.forget(ids),
+			None => Ok(()),
+		}
+	}
+
+	/// the path of the block with this id (within the store, or the pack)
+	pub fn block_path(&self, id: u32) -> PathBuf {
+		let name = format!("{}.zst", id);
+		if self.subdirs {
+			self.dir.join(block_subdir(id)).join(name)
+		} else {
+			self.dir.join(name)
+		}
+	}
+
+	/// the directories block files are in: the store directory, or its subdirectories of blocks
+	fn block_dirs(&self) -> Result<Vec<PathBuf>> {
+		if !self.subdirs {
+			return Ok(vec![self.dir.clone()]);
+		}
+
+		let mut dirs = Vec::new();
+		for d in self.dir.read_dir()? {
+			let d = d?;
+			let name = d.file_name().to_string_lossy().into_owned();
+			if d.file_type()?.is_dir()
+				&& name.len() == 2
+				&& name.chars().all(|c| c.is_ascii_hexdigit())
+			{
+				dirs.push(d.path());
+			}
+		}
+		Ok(dirs)
+	}
+
+	/// merges runs of adjacent small blocks into full ones, and drops deleted entries
+	///
+	/// Blocks with fewer than `block_size` entries are re-packed, in order, into as few blocks as
+	/// possible, and full blocks with deleted entries are rewritten, leaving the deleted entries
+	/// out. New block ids keep the convention of being the running count of entries (deleted ones
+	/// included), so they fall within the range of the blocks they replace. Returns the `(old, new)` ref of every entry
+	/// that moved, with no new ref for those dropped: old refs of those may now be other entries',
+	/// so any index built on the store needs rebuilding afterwards. The redirects and title map
+	/// are moved along.
+	///
+	/// Each run's new blocks are written under temporary names and the swap is recorded before
+	/// the old blocks are touched, so if it's interrupted, opening the store finishes it (see
+	/// `finish_compaction`) and no entry is lost.
+	///
+	/// errors with `WiktError::DictionaryNotLoaded` unless the store is open (see `open`)
+	pub fn compact(&mut self, block_size: usize) -> Result<Vec<(Ref, Option<Ref>)>> {
+		self.writable()?;
+		let mut ids = self
+			.blocks()?
+			.iter()
+			.map(block_id)
+			.collect::<Result<Vec<u32>>>()?;
+		ids.sort_unstable();
+
+		let mut remap = Vec::new();
+		let mut previous = 0;
+		let mut run: Vec<Arc<Block>> = Vec::new();
+		for id in ids {
+			let block = self.read_block(self.block_path(id))?;
+			if usize::try_from(block.n)? < block_size {
+				run.push(block);
+				continue;
+			}
+
+			self.compact_run(mem::take(&mut run), previous, block_size, &mut remap)?;
+			// full blocks are only rewritten on their own, if they have entries to drop
+			self.compact_run(vec![block], previous, block_size, &mut remap)?;
+			previous = id;
+		}
+		self.compact_run(run, previous, block_size, &mut remap)?;
+
+		if let Some(ref cache) = self.cache {
+			cache.blocks.lock().unwrap().clear();
+		}
+
+		remap.retain(|(old, new)| Some(*old) != *new);
+		if !remap.is_empty() {
+			if let Ok(mut redirects) = self.read_redirects() {
+				redirects.remap(&remap);
+				self.write_redirects(&redirects)?;
+			}
+			if let Ok(titles) = self.read_title_refs() {
+				self.write_title_refs(&titles.remap(&remap)?)?;
+			}
+		}
+
+		// the record of the last run's swap says a compaction is under way until it's all done
+		match remove_file(self.dir.join(COMPACTION_FILE)) {
+			Err(err) if err.kind() == ErrorKind::NotFound => {}
+			removed => removed?,
+		}
+		Ok(remap)
+	}
+
+	fn compact_run(
+		&mut self,
+		run: Vec<Arc<Block>>,
+		previous: u32,
+		block_size: usize,
+		remap: &mut Vec<(Ref, Option<Ref>)>,
+	) -> Result<()> {
+		match self.write_compaction(run, previous, block_size, remap)? {
+			Some(pending) => self.finish_compaction(&pending),
+			None => Ok(()),
+		}
+	}
+
+	/// writes the compacted blocks of a run under their temporary names, and the record of the
+	/// swap to come, unless there's nothing to compact
+	fn write_compaction(
+		&self,
+		run: Vec<Arc<Block>>,
+		previous: u32,
+		block_size: usize,
+		remap: &mut Vec<(Ref, Option<Ref>)>,
+	) -> Result<Option<PendingCompaction>> {
+		let deletes = run.iter().any(|block| self.has_deleted(block.id));
+		if run.len() < 2 && !deletes {
+			return Ok(None);
+		}
+
+		let base = run[0].id - run[0].n;
+		if base < previous {
+			return Err(eyre!(
+				"block ids in this store aren't running entry counts (block {} has {} entries but follows block {}), can't compact",
+				run[0].id,
+				run[0].n,
+				previous
+			).into());
+		}
+
+		let dict = self.encoder()?;
+		let mut written = Vec::new();
+		let mut packed = Block::default();
+		let mut count = base;
+		for old in &run {
+			for n in 0..old.n {
+				let entry = old.entry(n)?;
+				let old_ref = entry.store_ref;
+				count += 1;
+				if self.is_deleted(old_ref) {
+					remap.push((old_ref, None));
+					continue;
+				}
+
+				packed.add(entry)?;
+				remap.push((old_ref, Some(Ref::new(0, packed.n - 1))));
+
+				if usize::try_from(packed.n)? == block_size {
+					written.push(self.write_compacted(
+						dict,
+						mem::take(&mut packed),
+						count,
+						remap,
+					)?);
+				}
+			}
+		}
+		if packed.n > 0 {
+			written.push(self.write_compacted(dict, packed, count, remap)?);
+		}
+
+		debug!(
+			"compacting {} blocks from {} to {} into {}",
+			run.len(),
+			run[0].id,
+			count,
+			count - base
+		);
+
+		// the swap is recorded before any block is touched, so if it's cut short the next `open`
+		// finishes it rather than finding some of the old blocks gone and the new ones not there
+		let pending = PendingCompaction {
+			old: run.iter().map(|block| block.id).collect(),
+			new: written,
+		};
+		pending.write(&self.dir.join(COMPACTION_FILE))?;
+		Ok(Some(pending))
+	}
+
+	/// swaps the blocks of a compaction in, whether it was just written or was interrupted
+	///
+	/// Each step can be done again, so this can itself be interrupted: new blocks are renamed into
+	/// place (over the old blocks of the same id) if they're still under their temporary name, then
+	/// the other old blocks are removed if they're still there, and the manifest and deleted
+	/// entries are brought up to date.
+	fn finish_compaction(&mut self, pending: &PendingCompaction) -> Result<()> {
+		for &(id, _, _) in &pending.new {
+			let path = self.block_path(id);
+			let tmp = path.with_extension("zst.tmp");
+			if tmp.exists() {
+				place_block(&tmp, &path)?;
+			} else if !path.exists() {
+				return Err(eyre!(
+					"block {} of an interrupted compaction is missing, can't finish it",
+					id
+				)
+				.into());
+			}
+		}
+		for &id in &pending.old {
+			let path = self.block_path(id);
+			if path.exists() && pending.new.iter().all(|&(new, _, _)| new != id) {
+				remove_block(&path)?;
+			}
+		}
+
+		self.forget_blocks(pending.old.iter().copied())?;
+		for &(id, entries, size) in &pending.new {
+			self.record_block(id, entries, size)?;
+		}
+		let deleted = self.deleted.len();
+		self.deleted
+			.retain(|refid| !pending.old.contains(&refid.block_id));
+		if self.deleted.len() != deleted {
+			self.write_deleted()?;
+		}
+		Ok(())
+	}
+
+	/// writes a re-packed block under a temporary name and fills in its id in the remap
+	///
+	/// Returns its id, number of entries, and size.
+	fn write_compacted(
+		&self,
+		dict: &EncoderDict,
+		block: Block,
+		id: u32,
+		remap: &mut [(Ref, Option<Ref>)],
+	) -> Result<(u32, u32, u64)> {
+		let entries = block.n;
+		let n = usize::try_from(entries)?;
+		for new in remap
+			.iter_mut()
+			.rev()
+			.filter_map(|(_, new)| new.as_mut())
+			.take(n)
+		{
+			new.block_id = id;
+		}
+
+		let size = Self::write_block(dict, block, &self.block_path(id).with_extension("zst.tmp"))?;
+		Ok((id, entries, size))
+	}
+
+	/// appends the blocks of another store to this one, returning the `(old, new)` ref of every
+	/// entry moved over
+	///
+	/// Blocks keep their entries, in order, but are recompressed with this store's dictionary and
+	/// level (each store has a single dictionary), and renumbered after this store's last block:
+	/// ids stay running counts of entries, so refs of this store's entries don't change, and an
+	/// entry `id/n` of the other store becomes `(last + id)/n`. Entries deleted there are deleted
+	/// here too. The new blocks are all written under temporary names before being renamed into
+	/// place. Redirects and titles aren't merged here (see `Redirects::append`).
+	///
+	/// errors with `WiktError::DictionaryNotLoaded` unless both stores are open (see `open`)
+	pub fn merge(&mut self, other: &Store) -> Result<Vec<(Ref, Option<Ref>)>> {
+		self.writable()?;
+		let offset = self
+			.blocks()?
+			.iter()
+			.map(block_id)
+			.collect::<Result<Vec<u32>>>()?
+			.into_iter()
+			.max()
+			.unwrap_or(0);
+		let moved = |refid: Ref| -> Result<Ref> {
+			Ok(Ref::new(
+				refid
+					.block_id
+					.checked_add(offset)
+					.ok_or_else(|| eyre!("too many entries to merge: refs would overflow"))?,
+				refid.entry_id,
+			))
+		};
+
+		let dict = self.encoder()?;
+		let written = other
+			.blocks_by_id()?
+			.par_iter()
+			.map(|(id, path)| {
+				let old = other.read_block(path)?;
+				let mut block = Block {
+					id: moved(Ref::new(*id, 0))?.block_id,
+					..Block::default()
+				};
+				for n in 0..old.n {
+					block.add(old.entry(n)?)?;
+				}
+
+				let id = block.id;
+				let entries = block.n;
+				let size =
+					Self::write_block(dict, block, &self.block_path(id).with_extension("zst.tmp"))?;
+				Ok((id, entries, size))
+			})
+			.collect::<Result<Vec<(u32, u32, u64)>>>()?;
+
+		let mut remap = Vec::new();
+		for &(id, entries, size) in &written {
+			let path = self.block_path(id);
+			place_block(&path.with_extension("zst.tmp"), &path)?;
+			self.record_block(id, entries, size)?;
+			for n in 0..entries {
+				let new = Ref::new(id, n);
+				remap.push((Ref::new(id - offset, n), Some(new)));
+			}
+		}
+
+		let deleted = other.deleted().map(moved).collect::<Result<Vec<Ref>>>()?;
+		if !deleted.is_empty() {
+			self.deleted.extend(deleted);
+			self.write_deleted()?;
+		}
+
+		debug!(
+			"merged {} blocks from {:?} after block {}",
+			written.len(),
+			other.dir,
+			offset
+		);
+		Ok(remap)
+	}
+
+	/// a store at `dir`, which has to be `open`ed or `create`d before use
+	pub fn new(dir: impl AsRef<Path>) -> Self {
+		Self {
+			dir: dir.as_ref().into(),
+			dict_en: None,
+			dict_de: HashMap::new(),
+			retrain: false,
+			cache: None,
+			writers: None,
+			pack: None,
+			lenient: false,
+			mmap: false,
+			level: zstd::DEFAULT_COMPRESSION_LEVEL,
+			long: None,
+			word_filters: false,
+			subdirs: dir.as_ref().join(SUBDIRS_FILE).is_file(),
+			deleted: BTreeSet::new(),
+			manifest: None,
+		}
+	}
+
+	/// compresses new blocks at this zstd level instead of the default
+	pub fn with_level(mut self, level: i32) -> Result<Self> {
+		check_level(level)?;
+		self.level = level;
+		Ok(self)
+	}
+
+	/// compresses new blocks with zstd's long-distance matching, over a window of 2^`window_log`
+	/// bytes
+	///
+	/// Decompression handles it without being told, up to a window log of 30 (1GiB).
+	pub fn with_long(mut self, window_log: Option<u32>) -> Result<Self> {
+		if let Some(log) = window_log {
+			if !LONG_WINDOW_LOGS.contains(&log) {
+				return Err(eyre!(
+					"window log {} is out of range ({} to {})",
+					log,
+					LONG_WINDOW_LOGS.start(),
+					LONG_WINDOW_LOGS.end()
+				)
+				.into());
+			}
+		}
+
+		self.long = window_log;
+		Ok(self)
+	}
+
+	/// writes a bloom filter of the words of each block next to it, for whole-word searches to skip
+	/// blocks without them (see `word_filter`)
+	///
+	/// A store made with them keeps making them for blocks written later (compacted, etc).
+	pub fn with_word_filters(mut self, word_filters: bool) -> Self {
+		self.word_filters = word_filters;
+		self
+	}
+
+	/// spreads the block files of a new store over 256 subdirectories (`00` to `ff`), so no
+	/// directory has more than a fraction of them
+	///
+	/// Blocks go in the subdirectory of a hash of their id, as ids are running counts of entries,
+	/// which would bunch up otherwise. A store made with them keeps them, and one made without
+	/// keeps its blocks in the store directory itself, whatever's asked after.
+	pub fn with_subdirs(mut self, subdirs: bool) -> Self {
+		self.subdirs = self.subdirs || subdirs;
+		self
+	}
+
+	/// the window log of long-distance matching new blocks are compressed with, if any
+	///
+	/// Like the level, once opened that's what the store was made with.
+	pub fn long(&self) -> Option<u32> {
+		self.long
+	}
+
+	/// the zstd level blocks are compressed at
+	///
+	/// Once opened, that's the level the store was made with, which blocks rewritten by `compact`
+	/// or `migrate` are also compressed at.
+	pub fn level(&self) -> i32 {
+		self.level
+	}
+
+	/// keeps up to `capacity` decompressed blocks in memory for `read_block`/`read_entry`
+	pub fn with_cache(mut self, capacity: usize) -> Self {
+		self.cache = Some(BlockCache {
+			blocks: Mutex::new(LruCache::new(capacity)),
+			hits: AtomicUsize::new(0),
+			misses: AtomicUsize::new(0),
+		});
+		self
+	}
+
+	/// compresses and writes committed blocks on this many threads, while the next are made
+	///
+	/// Block ids are still those given to `commit`, so refs are the same as without. Call `flush`
+	/// once done committing.
+	pub fn with_writers(mut self, threads: usize) -> Self {
+		self.writers = Some(Writers {
+			threads: threads.max(1),
+			queue: None,
+			handles: Vec::new(),
+			turn: Arc::default(),
+			sent: 0,
+		});
+		self
+	}
+
+	/// trains a new dictionary on the next block committed, and compresses blocks with it from then
+	/// on (see `add_dictionary`)
+	///
+	/// That's for resuming with entries unlike those the store's dictionary was trained on.
+	pub fn with_new_dictionary(mut self, retrain: bool) -> Self {
+		self.retrain = retrain;
+		self
+	}
+
+	/// reads whatever complete entries a truncated or corrupt block has instead of failing
+	pub fn lenient(mut self, lenient: bool) -> Self {
+		self.lenient = lenient;
+		self
+	}
+
+	/// reads blocks by mapping their files into memory instead of reading them
+	///
+	/// That saves copying each block into a buffer before decompressing it, and leaves caching
+	/// the compressed blocks to the OS, which helps most when the same blocks are read again and
+	/// again (e.g. serving queries). Blocks must not be changed in place while mapped, which wikt
+	/// never does: compact and migrate write new files and rename them over the old ones.
+	pub fn with_mmap(mut self, mmap: bool) -> Self {
+		self.mmap = mmap;
+		self
+	}
+
+	/// hits and misses of the block cache, if there is one
+	pub fn cache_stats(&self) -> Option<(usize, usize)> {
+		self.cache.as_ref().map(|cache| {
+			(
+				cache.hits.load(Ordering::Relaxed),
+				cache.misses.load(Ordering::Relaxed),
+			)
+		})
+	}
+
+	/// reads the first `n` blocks (in id order), returning how many there were
+	///
+	/// With a cache, they're kept decompressed in it (as many as it holds), so the first reads of
+	/// their entries are as quick as the next. Without, that only brings their files into the
+	/// page cache. The dictionaries are already loaded by `open`, which must be called first.
+	pub fn preload(&self, n: usize) -> Result<usize> {
+		let blocks = self.blocks_by_id()?;
+		let blocks = &blocks[..n.min(blocks.len())];
+		for (_, path) in blocks {
+			self.read_block(path)?;
+		}
+		Ok(blocks.len())
+	}
+
+	/// makes the store directory, if it doesn't exist, and loads (or makes) its manifest
+	pub fn create(&mut self) -> Result<()> {
+		self.writable()?;
+		if !self.dir.exists() {
+			create_dir_all(&self.dir)?;
+		}
+
+		let marker = self.dir.join(SUBDIRS_FILE);
+		if self.subdirs && !marker.exists() {
+			if !block_files(std::slice::from_ref(&self.dir))?.is_empty() {
+				return Err(eyre!(
+					"{:?} already has blocks in the store directory itself: only a new store can \
+					 have them in subdirectories",
+					self.dir
+				)
+				.into());
+			}
+			File::create(marker)?;
+		}
+
+		self.load_manifest()
+	}
+
+	/// packed (single-file) stores can be read but not changed
+	fn writable(&self) -> Result<()> {
+		if self.dir.is_file() {
+			Err(eyre!("{:?} is a packed store, which is read-only", self.dir).into())
+		} else {
+			Ok(())
+		}
+	}
+
+	/// loads the dictionary and compression level of an existing store
+	///
+	/// Blocks committed after are compressed with its last dictionary, as those before were.
+	pub fn open(&mut self) -> Result<()> {
+		if self.dir.is_file() {
+			self.pack = Some(Pack::open(&self.dir)?);
+		}
+
+		// stores from before the level was recorded were all made at the default
+		if let Ok(level) = self.read_file("zst.level") {
+			self.level = String::from_utf8_lossy(&level).trim().parse()?;
+		}
+		if let Ok(log) = self.read_file("zst.long") {
+			self.long = Some(String::from_utf8_lossy(&log).trim().parse()?);
+		}
+		if self.pack.is_none() && self.dir.join("words.filters").exists() {
+			self.word_filters = true;
+		}
+
+		// dictionaries are numbered from 0 with no gaps, and new blocks use the last one
+		let mut dict_bytes = self.read_file(&dictionary_file(0))?;
+		self.dict_de.clear();
+		self.dict_de.insert(0, DecoderDictionary::copy(&dict_bytes));
+		let mut id = 0;
+		while let Ok(bytes) = self.read_file(&dictionary_file(id + 1)) {
+			id += 1;
+			self.dict_de.insert(id, DecoderDictionary::copy(&bytes));
+			dict_bytes = bytes;
+		}
+		self.dict_en = Some(self.encoder_dict(id, &dict_bytes));
+		debug!(
+			"loaded {} dictionaries, compressing with id={} size={} level={} long={:?}",
+			self.dict_de.len(),
+			id,
+			dict_bytes.len(),
+			self.level,
+			self.long
+		);
+
+		if let Ok(deleted) = self.read_file("deleted.refs") {
+			self.deleted = String::from_utf8_lossy(&deleted)
+				.lines()
+				.filter(|line| !line.is_empty())
+				.map(str::parse)
+				.collect::<Result<_>>()?;
+			debug!("loaded {} deleted entries", self.deleted.len());
+		}
+
+		if self.pack.is_none() {
+			self.load_manifest()?;
+
+			let path = self.dir.join(COMPACTION_FILE);
+			if path.exists() {
+				self.finish_interrupted_compaction(&path)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// finishes swapping in the blocks of the run a compaction was at when it was interrupted
+	///
+	/// Which refs changed in the runs before isn't known, so the title map and redirects, whose
+	/// refs may be out of date, are removed for `store migrate` to make again.
+	fn finish_interrupted_compaction(&mut self, path: &Path) -> Result<()> {
+		self.writable()?;
+		let pending = PendingCompaction::read(path)?;
+		self.finish_compaction(&pending)?;
+		for file in &["titles.tsv", "redirects.table"] {
+			match remove_file(self.dir.join(file)) {
+				Err(err) if err.kind() == ErrorKind::NotFound => {}
+				removed => removed?,
+			}
+		}
+		remove_file(path)?;
+
+		warn!(
+			"finished a compaction that was interrupted, and removed the title map and redirects"
+		);
+		warn!("refs have changed: run `store migrate`, and rebuild the index with `index make --force`");
+		Ok(())
+	}
+
+	/// reads the manifest, or makes it from the block files if there's none (e.g. in stores made
+	/// before there were manifests)
+	fn load_manifest(&mut self) -> Result<()> {
+		let path = self.dir.join("manifest.json");
+		let manifest = match File::open(&path) {
+			Ok(file) => serde_json::from_reader(BufReader::new(file))
+				.map_err(|err| eyre!("bad manifest {:?}: {}", path, err))?,
+			Err(_) => {
+				let manifest = self.scan_blocks()?;
+				if let Err(err) = ManifestFile::write(&path, &manifest) {
+					warn!("can't write the manifest: {}", err);
+				} else if !manifest.blocks.is_empty() {
+					info!("made a manifest of {} blocks", manifest.blocks.len());
+				}
+				manifest
+			}
+		};
+
+		debug!("loaded manifest of {} blocks", manifest.blocks.len());
+		self.manifest = Some(Arc::new(ManifestFile {
+			path,
+			manifest: Mutex::new(manifest),
+		}));
+		Ok(())
+	}
+
+	/// makes the manifest again from the block files, returning how many blocks it has
+	pub fn rebuild_manifest(&mut self) -> Result<usize> {
+		self.writable()?;
+		let manifest = self.scan_blocks()?;
+		let blocks = manifest.blocks.len();
+		let path = self.dir.join("manifest.json");
+		ManifestFile::write(&path, &manifest)?;
+		self.manifest = Some(Arc::new(ManifestFile {
+			path,
+			manifest: Mutex::new(manifest),
+		}));
+		Ok(blocks)
+	}
+
+	/// the manifest of the store, if it has one (packs don't)
+	pub fn manifest(&self) -> Option<Manifest> {
+		self.manifest
+			.as_ref()
+			.map(|manifest| manifest.manifest.lock().unwrap().clone())
+	}
+
+	/// what's wrong between the manifest and the block files, if anything
+	///
+	/// That's blocks in the manifest without a file, files not in the manifest, and blocks whose
+	/// entry count or size isn't what the manifest says. Every block's header is read.
+	pub fn check_manifest(&self) -> Result<Vec<String>> {
+		let manifest = match self.manifest() {
+			Some(manifest) => manifest,
+			None => return Ok(Vec::new()),
+		};
+		let found = self.scan_blocks()?;
+
+		let mut problems = Vec::new();
+		for (id, info) in &manifest.blocks {
+			match found.blocks.get(id) {
+				None => problems.push(format!("block {} is in the manifest but has no file", id)),
+				Some(file) if file != info => problems.push(format!(
+					"block {} has {} entries in {} bytes, but the manifest says {} in {}",
+					id, file.entries, file.size, info.entries, info.size
+				)),
+				Some(_) => {}
+			}
+		}
+		for id in found.blocks.keys() {
+			if !manifest.blocks.contains_key(id) {
+				problems.push(format!("block {} has a file but isn't in the manifest", id));
+			}
+		}
+		Ok(problems)
+	}
+
+	/// a manifest of the block files in the directory, reading their headers
+	///
+	/// Blocks too broken to count are recorded with no entries, with a warning, so they can still
+	/// be found (and e.g. redone by `resume`).
+	fn scan_blocks(&self) -> Result<Manifest> {
+		let mut manifest = Manifest::default();
+		for path in self.dir_blocks()? {
+			let id = block_id(&path)?;
+			let size = path.metadata()?.len();
+			let entries = match self.entry_count(&path) {
+				Ok(n) => n,
+				// blocks from before the header only say how many entries they have once read
+				Err(_) if !self.dict_de.is_empty() => match self.read_block(&path) {
+					Ok(block) => block.n,
+					Err(err) => {
+						warn!("block id={} can't be read: {}", id, err);
+						0
+					}
+				},
+				Err(err) => {
+					warn!("block id={} can't be counted: {}", id, err);
+					0
+				}
+			};
+			manifest.blocks.insert(id, BlockInfo { entries, size });
+		}
+		Ok(manifest)
+	}
+
+	/// marks an entry as deleted, so it's no longer read
+	///
+	/// It's left out of `read_entry` (which errors instead) and of the iterators over entries, but
+	/// stays in its block until `compact` rewrites that block without it. Returns false if it was
+	/// already deleted.
+	pub fn delete(&mut self, refid: Ref) -> Result<bool> {
+		self.writable()?;
+		let n = self.entry_count(self.block_path(refid.block_id))?;
+		if refid.entry_id >= n {
+			return Err(WiktError::RefOutOfRange(refid));
+		}
+
+		if !self.deleted.insert(refid) {
+			return Ok(false);
+		}
+		self.write_deleted()?;
+		Ok(true)
+	}
+
+	/// replaces the text of an entry
+	///
+	/// The entry keeps its ref, title, and revision. Its block is rewritten whole, as the entries
+	/// after it move within it, with the store's dictionary and level, under a temporary name then
+	/// renamed over the old one.
+	///
+	/// The redirects table follows the new text: if it's a `#REDIRECT`, the entry redirects to the
+	/// entry of its target's title (or to none, with a warning, if there's no such title), and
+	/// otherwise it's no longer a redirect.
+	///
+	/// errors with `WiktError::DictionaryNotLoaded` unless the store is open (see `open`)
+	pub fn update(&mut self, refid: Ref, body: &str) -> Result<()> {
+		self.writable()?;
+		if self.is_deleted(refid) {
+			return Err(WiktError::DeletedEntry(refid));
+		}
+
+		let path = self.block_path(refid.block_id);
+		let old = self.read_block(&path)?;
+		if refid.entry_id >= old.n {
+			return Err(WiktError::RefOutOfRange(refid));
+		}
+
+		let mut block = Block {
+			id: old.id,
+			..Block::default()
+		};
+		for n in 0..old.n {
+			let entry = old.entry(n)?;
+			block.add(if n == refid.entry_id {
+				Entry {
+					body_len: u32::try_from(body.len())?,
+					body: body.as_bytes().into(),
+					..entry
+				}
+			} else {
+				entry
+			})?;
+		}
+
+		let entries = block.n;
+		let tmp = path.with_extension("zst.tmp");
+		let size = Self::write_block(self.encoder()?, block, &tmp)?;
+		place_block(&tmp, &path)?;
+		self.record_block(refid.block_id, entries, size)?;
+		debug!(
+			"rewrote block id={} to update entry {}",
+			refid.block_id, refid
+		);
+
+		if let Some(ref cache) = self.cache {
+			cache.blocks.lock().unwrap().clear();
+		}
+
+		self.update_redirect(refid, body)
+	}
+
+	/// makes the redirects table agree with the new text of an entry (see `update`)
+	fn update_redirect(&self, refid: Ref, body: &str) -> Result<()> {
+		// stores made before redirects were recorded have none to keep up to date
+		if !self.dir.join("redirects.table").exists() {
+			return Ok(());
+		}
+
+		let to = match xmldump::redirect_target(body) {
+			None => None,
+			Some(target) => {
+				let to = self
+					.read_title_refs()?
+					.get(&target)?
+					.into_iter()
+					.find(|to| !self.is_deleted(*to));
+				if to.is_none() {
+					warn!(
+						"[{}] redirects to {:?}, which isn't in the store",
+						refid, target
+					);
+				}
+				to
+			}
+		};
+
+		let mut redirects = self.read_redirects()?;
+		if redirects.set(refid, to) {
+			debug!("[{}] now redirects to {:?}", refid, to);
+			self.write_redirects(&redirects)?;
+		}
+		Ok(())
+	}
+
+	/// whether an entry was deleted (and not yet compacted away)
+	pub fn is_deleted(&self, refid: Ref) -> bool {
+		self.deleted.contains(&refid)
+	}
+
+	/// the entries deleted but not yet compacted away, in ref order
+	pub fn deleted(&self) -> impl Iterator<Item = Ref> + '_ {
+		self.deleted.iter().copied()
+	}
+
+	/// whether any entry of a block was deleted
+	fn has_deleted(&self, block_id: u32) -> bool {
+		self.deleted
+			.range(Ref::new(block_id, 0)..=Ref::new(block_id, u32::MAX))
+			.next()
+			.is_some()
+	}
+
+	/// records the deleted entries, or removes the record if there are none
+	fn write_deleted(&self) -> Result<()> {
+		let path = self.dir.join("deleted.refs");
+		if self.deleted.is_empty() {
+			if path.exists() {
+				remove_file(path)?;
+			}
+			return Ok(());
+		}
+
+		let tmp = path.with_extension("refs.tmp");
+		let mut file = BufWriter::new(File::create(&tmp)?);
+		for refid in &self.deleted {
+			writeln!(file, "{}", refid)?;
+		}
+		file.flush()?;
+		drop(file);
+		rename(tmp, path)?;
+		Ok(())
+	}
+
+	/// prepares to carry on with an interrupted `store make`, returning how many entries the store
+	/// already has
+	///
+	/// Blocks left half-written under their temporary name are removed, as is the last block if it
+	/// was cut short, so it's made again. New blocks are then compressed with the store's
+	/// dictionary and level, rather than training a new dictionary.
+	pub fn resume(&mut self) -> Result<usize> {
+		self.writable()?;
+		// those of an interrupted compaction are kept for `open` to finish it with
+		let compacted = match self.dir.join(COMPACTION_FILE) {
+			path if path.exists() => PendingCompaction::read(&path)?.new,
+			_ => Vec::new(),
+		};
+		let compacted: Vec<PathBuf> = compacted
+			.into_iter()
+			.map(|(id, _, _)| self.block_path(id).with_extension("zst.tmp"))
+			.collect();
+		for dir in self.block_dirs()? {
+			for d in dir.read_dir()? {
+				let path = d?.path();
+				if path.to_string_lossy().ends_with(".zst.tmp") && !compacted.contains(&path) {
+					warn!("removing {:?}, a block that was still being written", path);
+					remove_block(&path)?;
+				}
+			}
+		}
+
+		let last = self
+			.blocks()?
+			.iter()
+			.map(block_id)
+			.collect::<Result<Vec<u32>>>()?
+			.into_iter()
+			.max();
+
+		// without blocks, starting over uses a dictionary from train-dict like a new store does
+		let last = match last {
+			Some(last) => last,
+			None => {
+				if self.use_trained_dictionary()? {
+					info!("using the dictionary trained with train-dict");
+				}
+				return Ok(0);
+			}
+		};
+		self.open()?;
+
+		let path = self.block_path(last);
+		match self.read_block(&path) {
+			Ok(_) => Ok(usize::try_from(last)?),
+			Err(err) => {
+				warn!("last block id={} is incomplete ({}), redoing it", last, err);
+				remove_block(&path)?;
+				self.forget_blocks(once(last))?;
+				Ok(self
+					.blocks()?
+					.iter()
+					.map(block_id)
+					.collect::<Result<Vec<u32>>>()?
+					.into_iter()
+					.max()
+					.map_or(Ok(0), usize::try_from)?)
+			}
+		}
+	}
+
+	/// reads a file of the store other than a block (e.g. the dictionary)
+	fn read_file(&self, name: &str) -> Result<Vec<u8>> {
+		if let Some(ref pack) = self.pack {
+			return pack.read_file(name);
+		}
+
+		let mut file = File::open(self.dir.join(name))?;
+		let mut data = Vec::with_capacity(file.metadata()?.len().try_into()?);
+		file.read_to_end(&mut data)?;
+		Ok(data)
+	}
+
+	/// maps a file of the store other than a block into memory, or none if it's empty
+	fn map_file(&self, name: &str) -> Result<Option<Mmap>> {
+		if let Some(ref pack) = self.pack {
+			return pack.map_file(name);
+		}
+
+		let file = File::open(self.dir.join(name))?;
+		if file.metadata()?.len() == 0 {
+			return Ok(None);
+		}
+
+		// SAFETY: these files are only ever replaced whole (by make, compact, or migrate), not
+		// changed in place, and not while the store is being read
+		Ok(Some(unsafe { Mmap::map(&file)? }))
+	}
+
+	/// records the siteinfo of the dump the store is made from
+	pub fn write_siteinfo(&self, info: &SiteInfo) -> Result<()> {
+		self.writable()?;
+		let file = File::create(self.dir.join("siteinfo.json"))?;
+		serde_json::to_writer_pretty(file, info)?;
+		Ok(())
+	}
+
+	/// the siteinfo of the dump the store was made from
+	pub fn read_siteinfo(&self) -> Result<SiteInfo> {
+		let data = self.read_file("siteinfo.json").map_err(|err| {
+			eyre!(
+				"no siteinfo in store (made before siteinfo was recorded?): {}",
+				err
+			)
+		})?;
+		Ok(serde_json::from_slice(&data)?)
+	}
+
+	/// records the bloom filter of the store's titles
+	pub fn write_title_bloom(&self, bloom: &TitleBloom) -> Result<()> {
+		self.writable()?;
+		Ok(bloom.write(self.dir.join("titles.bloom"))?)
+	}
+
+	/// the bloom filter of the store's titles
+	pub fn read_title_bloom(&self) -> Result<TitleBloom> {
+		let bytes = self.read_file("titles.bloom").map_err(|err| {
+			eyre!(
+				"no title bloom filter in store (make it with store make --bloom): {}",
+				err
+			)
+		})?;
+		Ok(TitleBloom::from_bytes(&bytes)?)
+	}
+
+	/// records which entries have which titles
+	pub fn write_title_refs(&self, titles: &TitleRefs) -> Result<()> {
+		self.writable()?;
+		Ok(titles.write(self.dir.join("titles.tsv"))?)
+	}
+
+	/// which entries have which titles
+	///
+	/// The file is mapped rather than read, so this is quick however many titles there are.
+	pub fn read_title_refs(&self) -> Result<TitleRefs> {
+		let map = self.map_file("titles.tsv").map_err(|err| {
+			eyre!(
+				"no title map in store (made before titles were mapped? run store migrate): {}",
+				err
+			)
+		})?;
+		Ok(match map {
+			Some(map) => TitleRefs::from_bytes(map),
+			None => TitleRefs::from_bytes(Vec::new()),
+		})
+	}
+
+	/// records which entries are redirects, and to where
+	pub fn write_redirects(&self, redirects: &Redirects) -> Result<()> {
+		self.writable()?;
+		Ok(redirects.write(self.dir.join("redirects.table"))?)
+	}
+
+	/// which entries are redirects, and to where
+	pub fn read_redirects(&self) -> Result<Redirects> {
+		let bytes = self.read_file("redirects.table").map_err(|err| {
+			eyre!(
+				"no redirects in store (made before redirects were recorded?): {}",
+				err
+			)
+		})?;
+		Ok(Redirects::from_bytes(&bytes)?)
+	}
+
+	/// the paths of all blocks, in no particular order
+	///
+	/// Once the store is open, that's the blocks in its manifest (or its pack); before, the block
+	/// files in its directory.
+	pub fn blocks(&self) -> Result<Vec<PathBuf>> {
+		if let Some(ref pack) = self.pack {
+			// blocks in a pack are addressed as if the pack was a directory
+			return Ok(pack
+				.block_ids()
+				.into_iter()
+				.map(|id| self.block_path(id))
+				.collect());
+		}
+
+		if let Some(ref manifest) = self.manifest {
+			return Ok(manifest
+				.manifest
+				.lock()
+				.unwrap()
+				.blocks
+				.keys()
+				.map(|id| self.block_path(*id))
+				.collect());
+		}
+
+		self.dir_blocks()
+	}
+
+	/// the paths of the block files in the store directory (or its subdirectories)
+	fn dir_blocks(&self) -> Result<Vec<PathBuf>> {
+		block_files(&self.block_dirs()?)
+	}
+
+	/// the ids and paths of all blocks, in id order (so their entries are in ref order)
+	pub fn blocks_by_id(&self) -> Result<Vec<(u32, PathBuf)>> {
+		let mut blocks = self
+			.blocks()?
+			.into_iter()
+			.map(|path| Ok((block_id(&path)?, path)))
+			.collect::<Result<Vec<_>>>()?;
+		blocks.sort_unstable_by_key(|(id, _)| *id);
+		Ok(blocks)
+	}
+
+	/// the title, body, and ref of every entry of the store, in ref order
+	///
+	/// Blocks are read as the iterator gets to them, so only one is in memory (decompressed) at a
+	/// time, however big the store. A block that can't be read comes out as a single error, and
+	/// an entry that can't be parsed as an error in its place; either way the next ones follow.
+	pub fn entries(&self) -> Result<impl Iterator<Item = Result<(String, String, Ref)>> + '_> {
+		Ok(self
+			.blocks_by_id()?
+			.into_iter()
+			.flat_map(move |(_, path)| self.block_entries(&path)))
+	}
+
+	/// every entry of the store, reading blocks in parallel, in no particular order
+	///
+	/// Each thread goes through a whole block before reading another, so there are at most as
+	/// many blocks in memory as threads (plus those in the cache, if any). Errors are as with
+	/// `entries`.
+	pub fn par_entries(
+		&self,
+	) -> Result<impl ParallelIterator<Item = Result<(String, String, Ref)>> + '_> {
+		Ok(self.par_entries_of(self.blocks()?))
+	}
+
+	/// the entries of some of the blocks, like `par_entries`
+	pub fn par_entries_of(
+		&self,
+		blocks: Vec<PathBuf>,
+	) -> impl ParallelIterator<Item = Result<(String, String, Ref)>> + '_ {
+		blocks
+			.into_par_iter()
+			.flat_map_iter(move |path| self.block_entries(&path))
+	}
+
+	/// the entries of one block, which is read right away
+	pub fn block_entries(
+		&self,
+		path: &Path,
+	) -> impl Iterator<Item = Result<(String, String, Ref)>> {
+		self.block_entries_where(path, |_| true)
+	}
+
+	/// the entries of one block for which `keep` is true, like `block_entries`
+	///
+	/// `keep` sees each entry before it's opened, so it can go by its revision.
+	pub fn block_entries_where(
+		&self,
+		path: &Path,
+		keep: impl Fn(&Entry) -> bool,
+	) -> impl Iterator<Item = Result<(String, String, Ref)>> {
+		let (block, err) = match self.read_block(path) {
+			Ok(block) => (Some(block), None),
+			Err(err) => (None, Some(err)),
+		};
+
+		let id = block.as_ref().map_or(0, |block| block.id);
+		let n = block.as_ref().map_or(0, |block| block.n);
+		let deleted: Vec<u32> = self
+			.deleted
+			.range(Ref::new(id, 0)..=Ref::new(id, u32::MAX))
+			.map(|refid| refid.entry_id)
+			.collect();
+		let live = (0..n).filter(move |n| deleted.binary_search(n).is_err());
+		err.into_iter().map(Err).chain(live.filter_map(move |n| {
+			let block = block.as_ref().unwrap();
+			match block.entry(n) {
+				Ok(entry) if !keep(&entry) => None,
+				entry => Some(entry.map(Entry::open)),
+			}
+		}))
+	}
+
+	/// writes the whole store as a single `.wikt` file
+	pub fn pack(&self, output: impl AsRef<Path>) -> Result<()> {
+		self.writable()?;
+
+		let names: Vec<String> = (0..)
+			.map(dictionary_file)
+			.take_while(|name| self.dir.join(name).exists())
+			.chain(
+				[
+					"zst.level",
+					"zst.long",
+					"siteinfo.json",
+					"titles.bloom",
+					"titles.tsv",
+					"redirects.table",
+					"deleted.refs",
+				]
+				.iter()
+				.map(|name| name.to_string()),
+			)
+			.collect();
+		let files: Vec<(&str, PathBuf)> = names
+			.iter()
+			.map(|name| (name.as_str(), self.dir.join(name)))
+			.filter(|(_, path)| path.exists())
+			.collect();
+
+		let mut blocks = self
+			.blocks()?
+			.into_iter()
+			.map(|path| Ok((block_id(&path)?, path)))
+			.collect::<Result<Vec<_>>>()?;
+		blocks.sort_unstable_by_key(|(id, _)| *id);
+
+		Pack::write(output, &files, &blocks)
+	}
+
+	/// reads a block, from the cache if there is one
+	///
+	/// errors with `WiktError::DictionaryNotLoaded` unless the store is open (see `open`)
+	pub fn read_block(&self, path: impl AsRef<Path>) -> Result<Arc<Block>> {
+		let path = path.as_ref();
+		let id = block_id(path)?;
+
+		let cache = if let Some(ref cache) = self.cache {
+			if let Some(block) = cache.blocks.lock().unwrap().get(&id) {
+				let hits = cache.hits.fetch_add(1, Ordering::Relaxed) + 1;
+				let misses = cache.misses.load(Ordering::Relaxed);
+				debug!(
+					"cache hit block id={} (hit rate {:.1}%)",
+					id,
+					100.0 * hits as f64 / (hits + misses) as f64
+				);
+				return Ok(block.clone());
+			}
+
+			cache.misses.fetch_add(1, Ordering::Relaxed);
+			Some(cache)
+		} else {
+			None
+		};
+
+		let block = Arc::new(self.decode_block(path, id)?);
+		if let Some(cache) = cache {
+			cache.blocks.lock().unwrap().put(id, block.clone());
+		}
+		Ok(block)
+	}
+
+	fn decode_block(&self, path: &Path, id: u32) -> Result<Block> {
+		let bytes = self.read_block_file(path, id)?;
+		let dicts = &self.dict_de;
+
+		match Block::parse(id, &bytes, dicts) {
+			Ok(block) => Ok(block),
+			Err(err) if self.lenient => {
+				// a block of another version can't be salvaged, only a damaged one
+				Block::check_header(id, &bytes)?;
+				let block = Block::salvage(id, &bytes, dicts);
+				warn!(
+					"block id={} is truncated or corrupt ({}), salvaged {} entries",
+					id, err, block.n
+				);
+				Ok(block)
+			}
+			Err(err) => Err(err),
+		}
+	}
+
+	/// the file a block is in, with the offset and length of the block within it
+	fn block_span(&self, path: &Path, id: u32) -> Result<(File, u64, u64)> {
+		if let Some(ref pack) = self.pack {
+			let (offset, len) = pack.block_span(id)?;
+			Ok((File::open(&self.dir)?, offset, len))
+		} else {
+			let file = File::open(path).map_err(|err| match err.kind() {
+				ErrorKind::NotFound => WiktError::MissingBlock(id),
+				_ => err.into(),
+			})?;
+			let len = file.metadata()?.len();
+			Ok((file, 0, len))
+		}
+	}
+
+	/// the size of a block on disk (or in the pack)
+	pub fn block_len(&self, path: impl AsRef<Path>) -> Result<u64> {
+		let path = path.as_ref();
+		Ok(self.block_span(path, block_id(path)?)?.2)
+	}
+
+	/// where to read a block's bytes from: its file, or a map of it (see `with_mmap`)
+	fn block_source(&self, path: &Path, id: u32) -> Result<BlockSource> {
+		let (file, offset, len) = self.block_span(path, id)?;
+		if !self.mmap || len == 0 {
+			return Ok(BlockSource::File { file, offset, len });
+		}
+
+		// SAFETY: block files are never changed in place (see `with_mmap`)
+		let map = unsafe {
+			MmapOptions::new()
+				.offset(offset)
+				.len(usize::try_from(len)?)
+				.map(&file)?
+		};
+		Ok(BlockSource::Mapped(map))
+	}
+
+	/// the raw bytes of a block, as they are on disk
+	fn read_block_file(&self, path: &Path, id: u32) -> Result<BlockBytes> {
+		let bytes = match self.block_source(path, id)? {
+			BlockSource::File {
+				mut file,
+				offset,
+				len,
+			} => {
+				file.seek(SeekFrom::Start(offset))?;
+				let mut bytes = Vec::with_capacity(len.try_into()?);
+				file.take(len).read_to_end(&mut bytes)?;
+				BlockBytes::Read(bytes)
+			}
+			BlockSource::Mapped(map) => BlockBytes::Mapped(map),
+		};
+
+		debug!("loaded block id={} size={}", id, bytes.len());
+		Ok(bytes)
+	}
+
+	/// the number of entries in a block, reading only its header
+	pub fn entry_count(&self, path: impl AsRef<Path>) -> Result<u32> {
+		let path = path.as_ref();
+		let id = block_id(path)?;
+		let mut source = self.block_source(path, id)?;
+
+		let mut prefix = [0; BLOCK_PREFIX_LEN];
+		source.read_at(0, &mut prefix).map_err(|err| {
+			WiktError::corrupt_block(id, format!("is truncated within its header: {}", err))
+		})?;
+		Ok(Block::check_prefix(id, &prefix)?.1)
+	}
+
+	/// rewrites blocks made by older versions of wikt in the current layout
+	///
+	/// Blocks already in the current layout are left alone, so this can be re-run after an
+	/// interruption. Returns how many blocks were rewritten.
+	///
+	/// errors with `WiktError::DictionaryNotLoaded` unless the store is open (see `open`)
+	pub fn migrate(&mut self) -> Result<usize> {
+		self.writable()?;
+		let dict = self.encoder()?;
+
+		let mut migrated = 0;
+		for path in self.blocks()? {
+			let id = block_id(&path)?;
+			let raw = self.read_block_file(&path, id)?;
+			if raw.starts_with(&BLOCK_MAGIC) {
+				// errors out on unknown versions rather than mangling them
+				let (version, _) = Block::check_header(id, &raw)?;
+				if version == BLOCK_VERSION {
+					trace!("block id={} is already version {}", id, BLOCK_VERSION);
+					continue;
+				}
+			}
+
+			let block = self.parse_any_version(id, &raw)?;
+			let entries = block.n;
+			let tmp = path.with_extension("zst.tmp");
+			let size = Self::write_block(dict, block, &tmp)?;
+			place_block(&tmp, &path)?;
+			self.record_block(id, entries, size)?;
+			debug!("migrated block id={}", id);
+			migrated += 1;
+		}
+
+		if let Some(ref cache) = self.cache {
+			cache.blocks.lock().unwrap().clear();
+		}
+
+		Ok(migrated)
+	}
+
+	/// rewrites every block at `level`, with `dict` as a new dictionary if given (see
+	/// `add_dictionary`), or else with the one new blocks are compressed with
+	///
+	/// Blocks keep their ids and all their entries, deleted ones too, so refs stay the same; blocks
+	/// made by older versions come out in the current layout, as with `migrate`. Each block is
+	/// replaced as soon as it's rewritten, so an interrupted run leaves a store that reads fine,
+	/// and can just be run again. Returns the total size of the blocks before and after.
+	///
+	/// errors with `WiktError::DictionaryNotLoaded` unless the store is open (see `open`)
+	pub fn recompress(&mut self, level: i32, dict: Option<&[u8]>) -> Result<(u64, u64)> {
+		self.writable()?;
+		check_level(level)?;
+		self.level = level;
+		writeln!(File::create(self.dir.join("zst.level"))?, "{}", self.level)?;
+		match dict {
+			Some(dict) => {
+				let id = self.add_dictionary(dict)?;
+				debug!("recompressing with new dictionary {}", id);
+			}
+			None => {
+				let id = self.encoder()?.id;
+				let dict = self.read_file(&dictionary_file(id))?;
+				self.dict_en = Some(self.encoder_dict(id, &dict));
+			}
+		}
+
+		let dict = self.encoder()?;
+		let sizes = self
+			.blocks()?
+			.into_par_iter()
+			.map(|path| {
+				let id = block_id(&path)?;
+				let raw = self.read_block_file(&path, id)?;
+				let block = self.parse_any_version(id, &raw)?;
+				let entries = block.n;
+				let tmp = path.with_extension("zst.tmp");
+				let size = Self::write_block(dict, block, &tmp)?;
+				place_block(&tmp, &path)?;
+				self.record_block(id, entries, size)?;
+				debug!("recompressed block id={}: {} -> {}", id, raw.len(), size);
+				Ok((u64::try_from(raw.len())?, size))
+			})
+			.collect::<Result<Vec<_>>>()?;
+
+		if let Some(ref cache) = self.cache {
+			cache.blocks.lock().unwrap().clear();
+		}
+
+		Ok(sizes
+			.into_iter()
+			.fold((0, 0), |(before, after), (old, new)| {
+				(before + old, after + new)
+			}))
+	}
+
+	/// parses a block file of any version wikt has written, in the current entry layout
+	fn parse_any_version(&self, id: u32, raw: &[u8]) -> Result<Block> {
+		if raw.starts_with(&BLOCK_MAGIC) {
+			return Block::parse(id, raw, &self.dict_de);
+		}
+
+		// up to version 2, the whole block was a single zstd stream
+		let mut bytes = Vec::with_capacity(raw.len() * 2);
+		Decoder::with_prepared_dictionary(raw, decoder(&self.dict_de, id, 0)?)?
+			.read_to_end(&mut bytes)?;
+		Block::parse_stream(id, &bytes)?.upgrade(2)
+	}
+
+	/// reads an entry directly from its ref
+	///
+	/// With a cache, the whole block is read (or found in the cache) so that later reads from the
+	/// same block are served from memory. Without, only that entry is read and decompressed.
+	///
+	/// errors with `WiktError::DictionaryNotLoaded` unless the store is open (see `open`)
+	pub fn read_entry(&self, refid: Ref) -> Result<Entry> {
+		if self.is_deleted(refid) {
+			return Err(WiktError::DeletedEntry(refid));
+		}
+
+		if self.cache.is_some() {
+			let block = self.read_block(self.block_path(refid.block_id))?;
+			return block.entry(refid.entry_id);
+		}
+
+		self.frame_reader(refid.block_id)?
+			.read(&self.dict_de, refid)
+	}
+
+	/// opens a block to read single entries from, reading its header once
+	fn frame_reader(&self, id: u32) -> Result<FrameReader> {
+		let mut source = self.block_source(&self.block_path(id), id)?;
+		let mut prefix = [0; BLOCK_PREFIX_LEN];
+		source.read_at(0, &mut prefix).map_err(|err| {
+			WiktError::corrupt_block(id, format!("is truncated within its header: {}", err))
+		})?;
+		let (version, n, dictionary) = Block::check_prefix(id, &prefix)?;
+		let frames = u64::try_from(frames_start(version, usize::try_from(n)?).unwrap())?;
+		Ok(FrameReader {
+			source,
+			version,
+			n,
+			dictionary,
+			frames,
+		})
+	}
+
+	/// reads many entries at once, opening each block they're in only once
+	///
+	/// Blocks are read in parallel, and the entries come back in the order of `refs`. A ref that
+	/// can't be read (or whose block can't) only fails its own result. Without a cache, only the
+	/// entries asked for are decompressed, as with `read_entry`; with one, their blocks are read
+	/// whole (or found in the cache).
+	pub fn read_entries(&self, refs: &[Ref]) -> Vec<Result<Entry>> {
+		let mut by_block: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+		for (i, refid) in refs.iter().enumerate() {
+			by_block.entry(refid.block_id).or_default().push(i);
+		}
+
+		enum Opened {
+			Whole(Arc<Block>),
+			Frames(FrameReader),
+		}
+
+		let read: Vec<(usize, Result<Entry>)> = by_block
+			.into_par_iter()
+			.flat_map_iter(|(block_id, wanted)| {
+				let mut block = if self.cache.is_some() {
+					self.read_block(self.block_path(block_id))
+						.map(Opened::Whole)
+				} else {
+					self.frame_reader(block_id).map(Opened::Frames)
+				};
+
+				wanted
+					.into_iter()
+					.map(|i| {
+						let refid = refs[i];
+						let entry = match block {
+							_ if self.is_deleted(refid) => Err(WiktError::DeletedEntry(refid)),
+							Ok(Opened::Whole(ref block)) => block.entry(refid.entry_id),
+							Ok(Opened::Frames(ref mut frames)) => frames.read(&self.dict_de, refid),
+							Err(ref err) => Err(err.again()),
+						};
+						(i, entry)
+					})
+					.collect::<Vec<_>>()
+			})
+			.collect();
+
+		let mut entries: Vec<Option<Result<Entry>>> = refs.iter().map(|_| None).collect();
+		for (i, entry) in read {
+			entries[i] = Some(entry);
+		}
+		entries.into_iter().map(Option::unwrap).collect()
+	}
+
+	/// the `n` entries before and after an entry, in ref order, going into the blocks around its
+	/// own if need be
+	///
+	/// Deleted entries are skipped, and there are fewer than `n` on a side near the start or end of
+	/// the store. Only those entries are read: how many each block has comes from the manifest.
+	pub fn neighbors(&self, refid: Ref, n: usize) -> Result<(Vec<Entry>, Vec<Entry>)> {
+		let ids: Vec<u32> = self.blocks_by_id()?.into_iter().map(|(id, _)| id).collect();
+		let at = ids
+			.binary_search(&refid.block_id)
+			.map_err(|_| WiktError::MissingBlock(refid.block_id))?;
+		if refid.entry_id >= self.entries_in(refid.block_id)? {
+			return Err(WiktError::RefOutOfRange(refid));
+		}
+
+		let mut before = Vec::with_capacity(n);
+		for &id in ids[..=at].iter().rev() {
+			if before.len() == n {
+				break;
+			}
+			let end = if id == refid.block_id {
+				refid.entry_id
+			} else {
+				self.entries_in(id)?
+			};
+			before.extend(
+				(0..end)
+					.rev()
+					.map(|entry_id| Ref::new(id, entry_id))
+					.filter(|r| !self.is_deleted(*r))
+					.take(n - before.len()),
+			);
+		}
+		before.reverse();
+
+		let mut after = Vec::with_capacity(n);
+		for &id in &ids[at..] {
+			if after.len() == n {
+				break;
+			}
+			let start = if id == refid.block_id {
+				refid.entry_id + 1
+			} else {
+				0
+			};
+			after.extend(
+				(start..self.entries_in(id)?)
+					.map(|entry_id| Ref::new(id, entry_id))
+					.filter(|r| !self.is_deleted(*r))
+					.take(n - after.len()),
+			);
+		}
+
+		let read = |refs: &[Ref]| {
+			self.read_entries(refs)
+				.into_iter()
+				.collect::<Result<Vec<_>>>()
+		};
+		Ok((read(&before)?, read(&after)?))
+	}
+
+	/// what's in a block file, as it's laid out there, to look into the format or a corruption
+	///
+	/// Unlike `read_block`, an entry that can't be decompressed or parsed doesn't fail the rest:
+	/// it has its own error. Entries are parsed as laid out in blocks of the block's version,
+	/// rather than upgraded to the current one.
+	pub fn inspect_block(&self, id: u32) -> Result<BlockLayout> {
+		let raw = self.read_block_file(&self.block_path(id), id)?;
+		let (version, dictionary, frames, sum_at) = Block::frame_table(id, &raw)?;
+		let dict = decoder(&self.dict_de, id, dictionary)?;
+		let checksum_bytes = raw
+			.get(sum_at..sum_at + BLOCK_CHECKSUM_LEN)
+			.ok_or_else(|| WiktError::corrupt_block(id, "is truncated before its checksum"))?;
+
+		let mut data = Vec::new();
+		let mut entries = Vec::with_capacity(frames.len());
+		for (n, (start, end)) in (0..).zip(frames) {
+			let refid = Ref::new(id, n);
+			let data_start = data.len();
+			let entry = raw
+				.get(start..end)
+				.ok_or_else(|| {
+					let problem =
+						format!("frame is past the end of the block ({} bytes)", raw.len());
+					WiktError::corrupt_entry(refid, problem)
+				})
+				.and_then(|frame| decompress_frame(dict, frame))
+				.and_then(|bytes| {
+					data.extend_from_slice(&bytes);
+					Entry::parse(refid, &bytes, version)
+				});
+			entries.push(EntryLayout {
+				frame: start..end,
+				data: data_start..data.len(),
+				entry,
+			});
+		}
+
+		let intact = entries.iter().all(|entry| entry.entry.is_ok());
+		Ok(BlockLayout {
+			id,
+			version,
+			dictionary,
+			len: raw.len(),
+			frames_start: sum_at + BLOCK_CHECKSUM_LEN,
+			checksum: u32::from_le_bytes(checksum_bytes.try_into()?),
+			actual_checksum: if intact {
+				Some(checksum(&[&raw[..sum_at], &data]))
+			} else {
+				None
+			},
+			entries,
+			data,
+		})
+	}
+
+	/// the number of entries in a block, deleted ones included, from the manifest if there's one
+	fn entries_in(&self, id: u32) -> Result<u32> {
+		if let Some(ref manifest) = self.manifest {
+			if let Some(block) = manifest.manifest.lock().unwrap().blocks.get(&id) {
+				return Ok(block.entries);
+			}
+		}
+
+		let path = self.block_path(id);
+		match self.entry_count(&path) {
+			Ok(n) => Ok(n),
+			// blocks from before version 3 have no header, so they have to be read whole
+			Err(_) => Ok(self.read_block(&path)?.n),
+		}
+	}
+}
+
+#[cfg(feature = "async")]
+impl Store {
+	/// reads a block as `read_block` does, on rayon's thread pool, so that an async runtime isn't
+	/// held up by the file reads and decompression
+	///
+	/// Works with any runtime (or executor): the future only waits for the read to be done.
+	pub fn read_block_async(
+		self: &Arc<Self>,
+		path: impl AsRef<Path>,
+	) -> impl std::future::Future<Output = Result<Arc<Block>>> {
+		let store = Arc::clone(self);
+		let path = path.as_ref().to_path_buf();
+		off_thread(move || store.read_block(path))
+	}
+
+	/// reads an entry as `read_entry` does, on rayon's thread pool (see `read_block_async`)
+	pub fn read_entry_async(
+		self: &Arc<Self>,
+		refid: Ref,
+	) -> impl std::future::Future<Output = Result<Entry>> {
+		let store = Arc::clone(self);
+		off_thread(move || store.read_entry(refid))
+	}
+}
+
+/// runs a read on rayon's thread pool, as a future of its result
+#[cfg(feature = "async")]
+fn off_thread<T: Send + 'static>(
+	read: impl FnOnce() -> Result<T> + Send + 'static,
+) -> impl std::future::Future<Output = Result<T>> {
+	let (send, receive) = futures_channel::oneshot::channel();
+	rayon::spawn(move || {
+		// nothing to do if the future was dropped
+		let _ = send.send(read());
+	});
+	async move {
+		receive
+			.await
+			.map_err(|_| eyre!("the read was dropped before it was done"))?
+	}
+}
+
+/// What's in a block file, as `Store::inspect_block` finds it.
+#[derive(Debug)]
+pub struct BlockLayout {
+	pub id: u32,
+	pub version: u16,
+	/// the id of the dictionary the entries are compressed with
+	pub dictionary: u32,
+	/// the size of the file, in bytes
+	pub len: usize,
+	/// where the entry frames start in the file, after the header and the frame table
+	pub frames_start: usize,
+	/// the checksum in the header
+	pub checksum: u32,
+	/// the checksum of what's in the block, if all its entries could be decompressed
+	pub actual_checksum: Option<u32>,
+	pub entries: Vec<EntryLayout>,
+	/// the entries, decompressed and one after the other
+	pub data: Vec<u8>,
+}
+
+/// Where an entry is in a block, as `Store::inspect_block` finds it.
+#[derive(Debug)]
+pub struct EntryLayout {
+	/// the byte range of its zstd frame in the block file
+	pub frame: Range<usize>,
+	/// the byte range of it decompressed, in the `data` of the layout (empty if it couldn't be)
+	pub data: Range<usize>,
+	/// the entry, or why it couldn't be read
+	pub entry: Result<Entry>,
+}
+
+/// A block opened to read single entries from, without decompressing the others.
+struct FrameReader {
+	source: BlockSource,
+	version: u16,
+	n: u32,
+	dictionary: u32,
+	/// where the frames start in the block
+	frames: u64,
+}
+
+impl FrameReader {
+	/// reads and decompresses one entry of the block
+	fn read(
+		&mut self,
+		dicts: &HashMap<u32, DecoderDictionary<'static>>,
+		refid: Ref,
+	) -> Result<Entry> {
+		if refid.entry_id >= self.n {
+			return Err(WiktError::RefOutOfRange(refid));
+		}
+
+		// the offsets of this entry's frame and of the next one, which is where this one ends
+		let mut offsets = [0; 16];
+		self.source.read_at(
+			u64::try_from(prefix_len(self.version))? + 8 * u64::from(refid.entry_id),
+			&mut offsets,
+		)?;
+		let start = u64::from_le_bytes(offsets[..8].try_into()?);
+		let end = u64::from_le_bytes(offsets[8..].try_into()?);
+
+		if start > end || self.frames.saturating_add(end) > self.source.len() {
+			return Err(WiktError::corrupt_entry(
+				refid,
+				format!(
+					"entry frame ({}..{}) is past the end of the block",
+					start, end
+				),
+			));
+		}
+
+		let mut frame = vec![0; usize::try_from(end - start)?];
+		self.source.read_at(self.frames + start, &mut frame)?;
+		debug!("[{}] read entry frame size={}", refid, frame.len());
+
+		let dict = decoder(dicts, refid.block_id, self.dictionary)?;
+		let data = decompress_frame(dict, &frame).map_err(|err| {
+			WiktError::corrupt_entry(refid, format!("entry can't be decompressed: {}", err))
+		})?;
+		Entry::parse(refid, &data, self.version)
+	}
+}
+
+/// parses the block id out of a block's filename
+pub fn block_id(path: impl AsRef<Path>) -> Result<u32> {
+	let path = path.as_ref();
+	Ok(path
+		.file_stem()
+		.ok_or_else(|| eyre!("no file stem for block filename {:?}", path))?
+		.to_string_lossy()
+		.parse()?)
+}
+
+/// the paths of the block files in these directories
+fn block_files(dirs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+	let mut blocks = Vec::new();
+	for dir in dirs {
+		for d in dir.read_dir()? {
+			let d = d?;
+			if !d.file_type()?.is_file() {
+				continue;
+			}
+			if !d.path().display().to_string().ends_with(".zst") {
+				continue;
+			}
+
+			blocks.push(d.path());
+		}
+	}
+
+	Ok(blocks)
+}
+
+/// The file whose presence says a store has its blocks in subdirectories (see `with_subdirs`).
+const SUBDIRS_FILE: &str = "blocks.subdirs";
+
+/// the subdirectory of a block, when they're spread over them: the top byte of a Fibonacci hash of
+/// its id, in hex
+fn block_subdir(id: u32) -> String {
+	format!("{:02x}", id.wrapping_mul(0x9E37_79B9) >> 24)
+}
+
+/// the path of the word filter of a block: `10000.words` for `10000.zst`, and `10000.words.tmp`
+/// for `10000.zst.tmp`
+fn words_path(path: &Path) -> PathBuf {
+	let name = path.file_name().unwrap_or_default().to_string_lossy();
+	let (id, tmp) = match name.split_once('.') {
+		Some((id, ext)) => (id, ext.ends_with(".tmp")),
+		None => (name.as_ref(), false),
+	};
+	path.with_file_name(format!("{}.words{}", id, if tmp { ".tmp" } else { "" }))
+}
+
+/// renames a block written under a temporary name into place, along with its word filter (or
+/// removing the filter of the block it replaces, if it has none)
+fn place_block(tmp: &Path, path: &Path) -> Result<()> {
+	rename(tmp, path)?;
+	match rename(words_path(tmp), words_path(path)) {
+		Err(err) if err.kind() == ErrorKind::NotFound => remove_words(path),
+		renamed => Ok(renamed?),
+	}
+}
+
+/// removes a block and its word filter
+fn remove_block(path: &Path) -> Result<()> {
+	remove_file(path)?;
+	remove_words(path)
+}
+
+/// removes the word filter of a block, if it has one
+fn remove_words(path: &Path) -> Result<()> {
+	match remove_file(words_path(path)) {
+		Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+		removed => Ok(removed?),
+	}
+}
+
+/// errors if zstd has no such compression level
+fn check_level(level: i32) -> Result<()> {
+	let range = zstd::compression_level_range();
+	if !range.contains(&level) {
+		return Err(eyre!(
+			"compression level {} is out of zstd's range ({} to {})",
+			level,
+			range.start(),
+			range.end()
+		)
+		.into());
+	}
+
+	Ok(())
+}
+
+/// the name of the file of a store's dictionary: the first is `zst.dictionary`, then
+/// `zst.dictionary.1`, etc
+fn dictionary_file(id: u32) -> String {
+	match id {
+		0 => String::from("zst.dictionary"),
+		id => format!("zst.dictionary.{}", id),
+	}
+}
+
+/// the dictionary a block was compressed with, by the id it records
+fn decoder<'d>(
+	dicts: &'d HashMap<u32, DecoderDictionary<'static>>,
+	block_id: u32,
+	dictionary: u32,
+) -> Result<&'d DecoderDictionary<'static>> {
+	dicts.get(&dictionary).ok_or({
+		if dicts.is_empty() {
+			WiktError::DictionaryNotLoaded
+		} else {
+			WiktError::MissingDictionary {
+				block: block_id,
+				dictionary,
+			}
+		}
+	})
+}
+
+/// Window logs long-distance matching can use: zstd's minimum, up to the largest window it can
+/// decode on 32-bit targets too.
+pub const LONG_WINDOW_LOGS: std::ops::RangeInclusive<u32> = 10..=30;
+
+/// Size of trained dictionaries.
+const DICTIONARY_SIZE: usize = 150_000;
+
+/// trains a dictionary on samples (e.g. serialised entries), concatenated in `data`
+pub fn train_dictionary(data: &[u8], sample_sizes: &[usize]) -> Vec<u8> {
+	match from_continuous(data, sample_sizes, DICTIONARY_SIZE) {
+		Ok(dict) => dict,
+		Err(err) => {
+			// too few samples (e.g. a tiny first block), so use the
+			// samples themselves as a raw content dictionary instead
+			warn!("dictionary training failed ({}), using raw content", err);
+			data.iter().take(DICTIONARY_SIZE).copied().collect()
+		}
+	}
+}
+
+/// how many bytes samples take once compressed each on its own with a dictionary, as entries are
+pub fn compressed_len(dict: &[u8], level: i32, samples: &[Vec<u8>]) -> Result<usize> {
+	let dict = EncoderDictionary::copy(dict, level);
+	samples
+		.iter()
+		.map(|sample| Ok(compress_frame(&dict, None, sample)?.len()))
+		.sum()
+}
+
+/// the CRC32 of a block's bytes, in parts
+fn checksum(parts: &[&[u8]]) -> u32 {
+	let mut hasher = crc32fast::Hasher::new();
+	for part in parts {
+		hasher.update(part);
+	}
+	hasher.finalize()
+}
+
+/// compresses an entry as a zstd frame of its own, which checksums its content, with long-distance
+/// matching over a window of that log if given
+///
+/// It's compressed in one go, so the frame also records the entry's size, for `size_hint`.
+fn compress_frame(
+	dict: &EncoderDictionary<'static>,
+	long: Option<u32>,
+	bytes: &[u8],
+) -> Result<Vec<u8>> {
+	let mut parameters = vec![
+		CParameter::ChecksumFlag(true),
+		// knowing the size, zstd would otherwise use the dictionary's tables as they are rather
+		// than copying them, which for entries compresses about 1.5% worse
+		CParameter::ForceAttachDict(DictAttachPref::ForceCopy),
+	];
+	if let Some(log) = long {
+		parameters.push(CParameter::EnableLongDistanceMatching(true));
+		parameters.push(CParameter::WindowLog(log));
+	}
+
+	let zstd_error = |code| eyre!("zstd: {}", zstd_safe::get_error_name(code));
+	let mut context = CCtx::create();
+	context.ref_cdict(dict.as_cdict()).map_err(zstd_error)?;
+	for parameter in parameters {
+		context.set_parameter(parameter).map_err(zstd_error)?;
+	}
+
+	let mut frame = Vec::with_capacity(zstd_safe::compress_bound(bytes.len()));
+	context.compress2(&mut frame, bytes).map_err(zstd_error)?;
+	Ok(frame)
+}
+
+/// the most bytes allocated up front from the sizes frames record, so corrupt ones can't make for a
+/// huge allocation (more still decompresses, growing the buffer as it goes)
+const MAX_SIZE_HINT: usize = 64 << 20;
+
+/// the size of what a frame decompresses to, if it records it (frames written before wikt did
+/// don't), or else a guess
+fn size_hint(frame: &[u8]) -> usize {
+	match zstd_safe::get_frame_content_size(frame) {
+		zstd_safe::CONTENTSIZE_UNKNOWN | zstd_safe::CONTENTSIZE_ERROR => frame.len() * 4,
+		size => usize::try_from(size).map_or(MAX_SIZE_HINT, |size| size.min(MAX_SIZE_HINT)),
+	}
+}
+
+fn decompress_frame(dict: &DecoderDictionary<'static>, frame: &[u8]) -> Result<Vec<u8>> {
+	let mut bytes = Vec::with_capacity(size_hint(frame));
+	let mut decoder = Decoder::with_prepared_dictionary(frame, dict)?.single_frame();
+	// frames compressed with a long window say so, but zstd won't go past 2^27 unless allowed to
+	decoder.window_log_max(*LONG_WINDOW_LOGS.end())?;
+	decoder.read_to_end(&mut bytes)?;
+	Ok(bytes)
+}
+
+/// Where a block's bytes are read from.
+enum BlockSource {
+	/// a span of a file: the whole block file, or part of a pack
+	File { file: File, offset: u64, len: u64 },
+	/// a map of that span
+	Mapped(Mmap),
+}
+
+impl BlockSource {
+	fn len(&self) -> u64 {
+		match self {
+			Self::File { len, .. } => *len,
+			Self::Mapped(map) => map.len() as u64,
+		}
+	}
+
+	/// fills `buf` from `at` bytes into the block
+	fn read_at(&mut self, at: u64, buf: &mut [u8]) -> Result<()> {
+		match self {
+			Self::File { file, offset, .. } => {
+				file.seek(SeekFrom::Start(*offset + at))?;
+				file.read_exact(buf)?;
+			}
+			Self::Mapped(map) => {
+				let at = usize::try_from(at)?;
+				let bytes = map
+					.get(at..at + buf.len())
+					.ok_or_else(|| eyre!("read past the end of the block"))?;
+				buf.copy_from_slice(bytes);
+			}
+		}
+		Ok(())
+	}
+}
+
+/// A block's bytes, read or mapped.
+enum BlockBytes {
+	Read(Vec<u8>),
+	Mapped(Mmap),
+}
+
+impl Deref for BlockBytes {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		match self {
+			Self::Read(bytes) => bytes,
+			Self::Mapped(map) => map,
+		}
+	}
+}
+
+/// first bytes of every block file, followed by the `BLOCK_VERSION` as a u16
+const BLOCK_MAGIC: [u8; 4] = *b"wikB";
+
+/// layout of blocks, bumped whenever it changes
+///
+/// Blocks without a header are from before versioning, where `n` was padded to 8 bytes. Version 1
+/// blocks have no checksum. Up to version 2, the whole block was a single zstd stream, header
+/// included; since version 3 the header is left uncompressed and each entry is compressed on its
+/// own, so that one entry can be read without the rest. Since version 4 entries also have the page
+/// id and revision timestamp they came from. Since version 5 the prefix also has the id of the
+/// dictionary the entries were compressed with; blocks before that all use the first one. Since
+/// version 6 the lengths and revision at the start of entries are varints rather than fixed-size.
+pub const BLOCK_VERSION: u16 = 6;
+
+/// the oldest block version that's read as it is, without migrating
+const MIN_BLOCK_VERSION: u16 = 3;
+
+const BLOCK_HEADER_LEN: usize = BLOCK_MAGIC.len() + 2;
+
+/// the version header, the number of entries, and the dictionary id, which is all that's needed to
+/// find and decompress an entry
+const BLOCK_PREFIX_LEN: usize = BLOCK_HEADER_LEN + 8;
+
+/// the length of the prefix of blocks of that version, which only has the dictionary id since
+/// version 5
+fn prefix_len(version: u16) -> usize {
+	if version >= 5 {
+		BLOCK_PREFIX_LEN
+	} else {
+		BLOCK_PREFIX_LEN - 4
+	}
+}
+
+/// the CRC32 of the header and of the uncompressed entries
+const BLOCK_CHECKSUM_LEN: usize = 4;
+
+/// where the frames of a block of `n` entries start: after the prefix, the `n + 1` frame offsets
+/// (the last one being the end of the last frame), and the checksum
+fn frames_start(version: u16, n: usize) -> Option<usize> {
+	n.checked_add(1)?
+		.checked_mul(8)?
+		.checked_add(prefix_len(version) + BLOCK_CHECKSUM_LEN)
+}
+
+/// What `Block::frame_table` finds in a block file: its version, dictionary id, frames, and where
+/// its checksum is.
+type FrameTable = (u16, u32, Vec<(usize, usize)>, usize);
+
+/// A block, uncompressed.
+///
+/// The deku layout is that of the contents of blocks up to version 2, and is only used to read
+/// those. Since version 3, `finish` and `parse` convert to and from the file layout.
+#[derive(Debug, Default, DekuRead, DekuWrite)]
+#[deku(endian = "little")]
+pub struct Block {
+	/// the block's id, which is the number of entries in the store up to and including it
+	#[deku(skip)]
+	pub id: u32,
+
+	/// the number of entries
+	#[deku(update = "self.starts.len()")]
+	pub n: u32,
+	#[deku(count = "n")]
+	pub(crate) starts: Vec<u64>,
+	/// the entries, one after the other
+	#[deku(bits_read = "deku::rest.len()")]
+	pub data: Vec<u8>,
+}
+
+impl Block {
+	/// appends an entry
+	pub fn add(&mut self, entry: Entry) -> Result<()> {
+		let data = entry.to_bytes()?;
+		self.n += 1;
+		self.starts.push(u64::try_from(self.data.len())?);
+		self.data.extend(data);
+		Ok(())
+	}
+
+	/// parses a block file, decompressing all its entries (with whichever of the dictionaries it
+	/// was compressed with) and checking its checksum
+	pub fn parse(
+		id: u32,
+		bytes: &[u8],
+		dicts: &HashMap<u32, DecoderDictionary<'static>>,
+	) -> Result<Self> {
+		let (version, dictionary, frames, sum_at) = Self::frame_table(id, bytes)?;
+		let dict = decoder(dicts, id, dictionary)?;
+
+		// the last frame ends the file, so one that ends past it was cut short (e.g. while written)
+		if let Some(&(_, end)) = frames.last().filter(|(_, end)| *end != usize::MAX) {
+			if end > bytes.len() {
+				return Err(WiktError::corrupt_block(
+					id,
+					format!("is truncated: it has {} of its {} bytes", bytes.len(), end),
+				));
+			}
+		}
+
+		let mut block = Self {
+			id,
+			..Self::default()
 		};
+		let sizes = frames
+			.iter()
+			.map(|(start, end)| bytes.get(*start..*end).map_or(0, size_hint));
+		block.data.reserve(sizes.sum::<usize>().min(MAX_SIZE_HINT));
+		block.starts.reserve(frames.len());
+		for (n, (start, end)) in (0..).zip(frames) {
+			let store_ref = Ref::new(id, n);
+			let frame = bytes.get(start..end).ok_or_else(|| {
+				let problem = format!(
+					"entry frame is past the end of the block ({} bytes)",
+					bytes.len()
+				);
+				WiktError::corrupt_entry(store_ref, problem)
+			})?;
+			let entry = decompress_frame(dict, frame).map_err(|err| {
+				WiktError::corrupt_entry(store_ref, format!("entry can't be decompressed: {}", err))
+			})?;
+			block.push_raw(entry)?;
+		}
 
-		let file = File::create(self.dir.join(format!("{}.zst", n)))?;
-		let mut target = Encoder::with_prepared_dictionary(file, dict)?;
-
-		let block_bytes = block.finish()?;
-		target.write_all(&block_bytes)?;
-		target.finish()?;
+		let expected = u32::from_le_bytes(bytes[sum_at..sum_at + BLOCK_CHECKSUM_LEN].try_into()?);
+		let actual = checksum(&[&bytes[..sum_at], &block.data]);
+		if actual != expected {
+			return Err(WiktError::corrupt_block(
+				id,
+				format!(
+					"is corrupt: checksum is {:08x} but should be {:08x}",
+					actual, expected
+				),
+			));
+		}
 
-		Ok(())
+		block.upgrade(version)
 	}
 
-	pub fn new(dir: impl AsRef<Path>) -> Self {
-		Self {
-			dir: dir.as_ref().into(),
-			dict_en: None,
-			dict_de: None,
+	/// re-serialises the entries of a block made by an older version in the current entry layout
+	fn upgrade(self, version: u16) -> Result<Self> {
+		// entries are laid out the same since
+		if version >= ENTRY_VARINT_VERSION {
+			return Ok(self);
 		}
-	}
 
-	pub fn create(&self) -> Result<()> {
-		if !self.dir.exists() {
-			create_dir_all(&self.dir)?;
+		let mut block = Self {
+			id: self.id,
+			..Self::default()
+		};
+		for n in 0..self.n {
+			block.add(self.entry_of_version(n, version)?)?;
 		}
+		Ok(block)
+	}
 
+	/// appends the bytes of an already serialised entry
+	fn push_raw(&mut self, entry: Vec<u8>) -> Result<()> {
+		self.n += 1;
+		self.starts.push(u64::try_from(self.data.len())?);
+		self.data.extend(entry);
 		Ok(())
 	}
 
-	pub fn open(&mut self) -> Result<()> {
-		let mut dict = File::open(self.dir.join("zst.dictionary"))?;
-		let mut dict_bytes = Vec::with_capacity(dict.metadata()?.len().try_into()?);
-		dict.read_to_end(&mut dict_bytes)?;
-		self.dict_de = Some(DecoderDictionary::copy(&dict_bytes));
-		debug!("loaded dictionary size={}", dict_bytes.len());
+	/// the version of a block file, its dictionary id, the byte range of each entry's frame, and
+	/// where its checksum is
+	///
+	/// Ranges aren't checked against the length of the file, so that a truncated block still
+	/// yields its leading entries.
+	fn frame_table(id: u32, bytes: &[u8]) -> Result<FrameTable> {
+		let prefix = bytes
+			.get(..BLOCK_PREFIX_LEN)
+			.ok_or_else(|| WiktError::corrupt_block(id, "is truncated within its header"))?;
+		let (version, n, dictionary) = Self::check_prefix(id, prefix)?;
+		let n = usize::try_from(n)?;
 
-		Ok(())
+		let frames = frames_start(version, n)
+			.filter(|start| *start <= bytes.len())
+			.ok_or_else(|| WiktError::corrupt_block(id, "is truncated within its frame table"))?;
+		let sum_at = frames - BLOCK_CHECKSUM_LEN;
+
+		let offsets = bytes[prefix_len(version)..sum_at]
+			.chunks_exact(8)
+			.map(|offset| {
+				usize::try_from(u64::from_le_bytes(offset.try_into().unwrap()))
+					.ok()
+					.and_then(|offset| offset.checked_add(frames))
+					.unwrap_or(usize::MAX)
+			})
+			.collect::<Vec<_>>();
+
+		Ok((
+			version,
+			dictionary,
+			offsets.windows(2).map(|pair| (pair[0], pair[1])).collect(),
+			sum_at,
+		))
 	}
 
-	pub fn blocks(&self) -> Result<Vec<PathBuf>> {
-		let mut blocks = Vec::new();
-		for d in self.dir.read_dir()? {
-			let d = d?;
-			if !d.file_type()?.is_file() {
-				continue;
-			}
-			if !d.path().display().to_string().ends_with(".zst") {
-				continue;
-			}
+	/// checks the version header of a block and returns its version, number of entries, and
+	/// dictionary id
+	///
+	/// `prefix` is the first `BLOCK_PREFIX_LEN` bytes, of which older versions use less.
+	fn check_prefix(id: u32, prefix: &[u8]) -> Result<(u16, u32, u32)> {
+		let (version, rest) = Self::check_header(id, prefix)?;
+		let field = |at: usize| {
+			rest.get(at..at + 4)
+				.map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+				.ok_or_else(|| WiktError::corrupt_block(id, "is truncated within its header"))
+		};
+		let dictionary = if version >= 5 { field(4)? } else { 0 };
+		Ok((version, field(0)?, dictionary))
+	}
 
-			blocks.push(d.path());
+	/// reads the version in the header of a block's bytes and returns it with what follows
+	fn read_header(id: u32, bytes: &[u8]) -> Result<(u16, &[u8])> {
+		if !bytes.starts_with(&BLOCK_MAGIC) {
+			return Err(eyre!(
+				"block id={} has no version header (made by an older wikt?), run `wikt store migrate`",
+				id
+			)
+			.into());
 		}
 
-		Ok(blocks)
+		let version = bytes
+			.get(BLOCK_MAGIC.len()..BLOCK_HEADER_LEN)
+			.map(|v| u16::from_le_bytes(v.try_into().unwrap()))
+			.ok_or_else(|| WiktError::corrupt_block(id, "is truncated within its header"))?;
+		Ok((version, &bytes[BLOCK_HEADER_LEN..]))
 	}
 
-	/// reads a block
-	///
-	/// panics if decoder dictionary isn't ready (call `open()` first)
-	pub fn read_block(&self, path: impl AsRef<Path>) -> Result<Block> {
-		let path = path.as_ref();
+	/// checks that a block's bytes are of a version that can be read without migrating, and
+	/// returns it with what follows the header
+	fn check_header(id: u32, bytes: &[u8]) -> Result<(u16, &[u8])> {
+		let (version, rest) = Self::read_header(id, bytes)?;
+		if version < MIN_BLOCK_VERSION {
+			Err(eyre!(
+				"block id={} is version {} (made by an older wikt), run `wikt store migrate`",
+				id,
+				version
+			)
+			.into())
+		} else if version > BLOCK_VERSION {
+			Err(eyre!(
+				"block id={}: unsupported block version {} (this wikt reads version {})",
+				id,
+				version,
+				BLOCK_VERSION
+			)
+			.into())
+		} else {
+			Ok((version, rest))
+		}
+	}
+
+	/// parses the decompressed contents of a block from before version 3
+	fn parse_stream(id: u32, bytes: &[u8]) -> Result<Self> {
+		if !bytes.starts_with(&BLOCK_MAGIC) {
+			return Self::parse_legacy(id, bytes);
+		}
+
+		match Self::read_header(id, bytes)? {
+			(1, body) => Self::parse_body(id, body),
+			(2, rest) => {
+				let at = rest.len().checked_sub(BLOCK_CHECKSUM_LEN).ok_or_else(|| {
+					WiktError::corrupt_block(id, "is truncated before its checksum")
+				})?;
+				let expected = u32::from_le_bytes(rest[at..].try_into()?);
+				let actual = checksum(&[&bytes[..bytes.len() - BLOCK_CHECKSUM_LEN]]);
+				if actual != expected {
+					return Err(WiktError::corrupt_block(
+						id,
+						format!(
+							"is corrupt: checksum is {:08x} but should be {:08x}",
+							actual, expected
+						),
+					));
+				}
+
+				Self::parse_body(id, &rest[..at])
+			}
+			(version, _) => Err(eyre!(
+				"block id={}: unsupported block version {} in a compressed block",
+				id,
+				version
+			)
+			.into()),
+		}
+	}
 
-		let file = File::open(path)?;
-		let filelen: usize = file.metadata()?.len().try_into()?;
-		let file = BufReader::new(file);
-		let mut source = Decoder::with_prepared_dictionary(file, self.dict_de.as_ref().unwrap())?;
-		let mut block_bytes = Vec::with_capacity(filelen * 2);
-		source.read_to_end(&mut block_bytes)?;
-
-		let id: u32 = path
-			.file_stem()
-			.ok_or_else(|| eyre!("no file stem for block filename {:?}", path))?
-			.to_string_lossy()
-			.parse()?;
-
-		debug!("loaded block id={} size={}", id, block_bytes.len());
-		let mut block = Block::from_bytes((&block_bytes, 0))?.1;
+	/// parses a block's contents between the version header and the checksum, up to version 2
+	fn parse_body(id: u32, body: &[u8]) -> Result<Self> {
+		let mut block = Self::from_bytes((body, 0))?.1;
 		block.id = id;
 		Ok(block)
 	}
 
-	/// reads an entry directly from its ref
-	///
-	/// panics if decoder dictionary isn't ready (call `open()` first)
-	pub fn read_entry(&mut self, refid: Ref) -> Result<Entry> {
-		let path = self.dir.join(format!("{}.zst", refid.block_id));
-		let block = self.read_block(path)?;
-		block.entry(refid.entry_id)
+	/// parses a block from before version headers, where `n` was followed by 4 bytes of padding
+	fn parse_legacy(id: u32, bytes: &[u8]) -> Result<Self> {
+		if bytes.len() < 8 {
+			return Err(WiktError::corrupt_block(id, "is too short to be a block"));
+		}
+
+		let unpadded: Vec<u8> = bytes[..4].iter().chain(&bytes[8..]).copied().collect();
+		Self::parse_body(id, &unpadded)
 	}
-}
 
-#[derive(Debug, Default, DekuRead, DekuWrite)]
-#[deku(endian = "little")]
-pub struct Block {
-	#[deku(skip)]
-	pub id: u32,
+	/// the leading complete entries of a block file that didn't fully decode
+	fn salvage(id: u32, bytes: &[u8], dicts: &HashMap<u32, DecoderDictionary<'static>>) -> Self {
+		let mut block = Self {
+			id,
+			..Self::default()
+		};
 
-	#[deku(update = "self.starts.len()", pad_bytes_after = "4")]
-	// FIXME: regenerate the store with 4-byte n!
-	pub n: u32,
-	#[deku(count = "n")]
-	pub starts: Vec<u64>,
-	#[deku(bits_read = "deku::rest.len()")]
-	pub data: Vec<u8>,
-}
+		// the frame table comes before any entry, so if it's cut short there's no entry to read
+		let (version, frames, dict) = match Self::frame_table(id, bytes) {
+			Ok((version, dictionary, frames, _)) => match decoder(dicts, id, dictionary) {
+				Ok(dict) => (version, frames, dict),
+				Err(_) => return block,
+			},
+			Err(_) => return block,
+		};
 
-impl Block {
-	pub fn add(&mut self, entry: Entry) -> Result<()> {
-		let data = entry.to_bytes()?;
-		self.n += 1;
-		self.starts.push(u64::try_from(self.data.len())?);
-		self.data.extend(data);
-		Ok(())
+		for (start, end) in frames {
+			match bytes
+				.get(start..end)
+				.map(|frame| decompress_frame(dict, frame))
+			{
+				Some(Ok(entry)) => block.push_raw(entry).unwrap(),
+				_ => break,
+			}
+		}
+
+		let complete = (0..block.n)
+			.take_while(|n| block.entry_of_version(*n, version).is_ok())
+			.count();
+		block.starts.truncate(complete);
+		block.n = u32::try_from(complete).unwrap();
+		block.upgrade(version).unwrap()
 	}
 
-	pub fn finish(self) -> Result<Vec<u8>> {
-		Ok(self.to_bytes()?)
+	/// the bytes of the block file: version header, entry count, dictionary id, frame offsets,
+	/// checksum, and then each entry as its own zstd frame
+	pub(crate) fn finish(self, dict: &EncoderDict) -> Result<Vec<u8>> {
+		let frames = (0..self.starts.len())
+			.map(|n| {
+				let start = usize::try_from(self.starts[n])?;
+				let end = match self.starts.get(n + 1) {
+					Some(end) => usize::try_from(*end)?,
+					None => self.data.len(),
+				};
+				compress_frame(&dict.dict, dict.long, &self.data[start..end])
+			})
+			.collect::<Result<Vec<_>>>()?;
+
+		let frames_len: usize = frames.iter().map(Vec::len).sum();
+		let mut bytes = Vec::with_capacity(
+			frames_start(BLOCK_VERSION, frames.len()).ok_or_else(|| eyre!("too many entries"))?
+				+ frames_len,
+		);
+		bytes.extend_from_slice(&BLOCK_MAGIC);
+		bytes.extend_from_slice(&BLOCK_VERSION.to_le_bytes());
+		bytes.extend_from_slice(&u32::try_from(frames.len())?.to_le_bytes());
+		bytes.extend_from_slice(&dict.id.to_le_bytes());
+
+		let mut offset = 0_u64;
+		bytes.extend_from_slice(&offset.to_le_bytes());
+		for frame in &frames {
+			offset += u64::try_from(frame.len())?;
+			bytes.extend_from_slice(&offset.to_le_bytes());
+		}
+
+		let sum = checksum(&[&bytes, &self.data]);
+		bytes.extend_from_slice(&sum.to_le_bytes());
+		for frame in frames {
+			bytes.extend(frame);
+		}
+		Ok(bytes)
 	}
 
+	/// the `n`th entry
 	pub fn entry(&self, n: u32) -> Result<Entry> {
+		self.entry_of_version(n, BLOCK_VERSION)
+	}
+
+	/// reads an entry laid out as in blocks of that version
+	fn entry_of_version(&self, n: u32, version: u16) -> Result<Entry> {
 		let start = *self
 			.starts
 			.get(usize::try_from(n)?)
-			.ok_or_else(|| eyre!("no such entry: {}", n))?;
+			.ok_or(WiktError::RefOutOfRange(Ref::new(self.id, n)))?;
 		let start: usize = start.try_into()?;
 		debug!(
 			"[block={}] reading entry {}/{} start={}",
@@ -178,60 +2960,119 @@ impl Block {
 		);
 
 		let store_ref = Ref::new(self.id, n);
-
-		let title_len =
-			usize::try_from(u32::from_le_bytes(self.data[start..start + 4].try_into()?))?;
-		let body_len = usize::try_from(u32::from_le_bytes(
-			self.data[start + 4..start + 8].try_into()?,
-		))?;
-		debug!(
-			"[{}] entry title len={} body len={}",
-			store_ref, title_len, body_len
-		);
-
-		let entry_slice = &self.data[start..(start + 8 + title_len + body_len)];
-		trace!("[{}] entry slice = {:?}", store_ref, entry_slice);
-
-		if body_len == 0 {
-			// this really should work with deku but whatever
-			Ok(Entry {
-				store_ref,
-				title_len: title_len.try_into()?,
-				body_len: body_len.try_into()?,
-				title: entry_slice[8..].to_vec(),
-				body: Vec::new(),
-			})
-		} else {
-			let mut entry = Entry::from_bytes((entry_slice, 0))
-				.map_err(|err| {
-					error!(
-						"entry {} t={} b={} data={:?}",
-						n, title_len, body_len, entry_slice
-					);
-					err
-				})?
-				.1;
-			entry.store_ref = Ref::new(self.id, n);
-			Ok(entry)
-		}
+		let data = self.data.get(start..).ok_or_else(|| {
+			let problem = format!(
+				"entry start is past the end of the block ({} bytes)",
+				self.data.len()
+			);
+			WiktError::corrupt_entry(store_ref, problem)
+		})?;
+		Entry::parse(store_ref, data, version)
 	}
 }
 
+/// An entry, as laid out in blocks since version 6.
+///
+/// `timestamp` (seconds since the epoch) and `page_id` are those of the revision the entry was
+/// made from, and 0 when unknown (e.g. for entries from older blocks). They and the lengths are
+/// LEB128 varints, which for most entries take 11 bytes rather than 20.
 #[derive(Debug, DekuRead, DekuWrite)]
 #[deku(endian = "little")]
 pub struct Entry {
+	/// where the entry is in the store, once it's been read from there
 	#[deku(skip)]
 	pub store_ref: Ref,
 
+	#[deku(
+		reader = "read_varint(deku::rest)",
+		writer = "write_varint(u64::from(*title_len), deku::output)"
+	)]
+	/// the length of the title, in bytes
 	pub title_len: u32,
+	/// the length of the text, in bytes
+	#[deku(
+		reader = "read_varint(deku::rest)",
+		writer = "write_varint(u64::from(*body_len), deku::output)"
+	)]
 	pub body_len: u32,
+	#[deku(
+		reader = "read_varint(deku::rest)",
+		writer = "write_varint(*timestamp, deku::output)"
+	)]
+	pub(crate) timestamp: u64,
+	#[deku(
+		reader = "read_varint(deku::rest)",
+		writer = "write_varint(u64::from(*page_id), deku::output)"
+	)]
+	pub(crate) page_id: u32,
 	#[deku(bytes_read = "title_len")]
-	pub title: Vec<u8>,
+	pub(crate) title: Vec<u8>,
 	#[deku(bytes_read = "body_len")]
-	pub body: Vec<u8>,
+	pub(crate) body: Vec<u8>,
+}
+
+/// first block version whose entries have a timestamp and page id
+const ENTRY_REVISION_VERSION: u16 = 4;
+
+/// first block version whose entries start with varints
+const ENTRY_VARINT_VERSION: u16 = 6;
+
+/// the most bytes a LEB128 varint of a u64 takes
+const MAX_VARINT_LEN: usize = 10;
+
+/// the LEB128 varint at the start of `data`, and how many bytes it took
+fn decode_varint(data: &[u8]) -> Option<(u64, usize)> {
+	let mut value = 0_u64;
+	for (n, byte) in data.iter().take(MAX_VARINT_LEN).enumerate() {
+		value |= u64::from(byte & 0x7f).checked_shl(7 * u32::try_from(n).ok()?)?;
+		if byte & 0x80 == 0 {
+			return Some((value, n + 1));
+		}
+	}
+	None
+}
+
+/// appends a value as a LEB128 varint: 7 bits per byte, low bits first, with the high bit of each
+/// byte set if more follow
+fn encode_varint(mut value: u64, out: &mut Vec<u8>) {
+	while value >= 0x80 {
+		out.push((value & 0x7f) as u8 | 0x80);
+		value >>= 7;
+	}
+	out.push(value as u8);
+}
+
+fn read_varint<T: TryFrom<u64>>(
+	rest: &BitSlice<Msb0, u8>,
+) -> Result<(&BitSlice<Msb0, u8>, T), DekuError> {
+	let mut bytes = Vec::with_capacity(MAX_VARINT_LEN);
+	let mut rest = rest;
+	while bytes.len() < MAX_VARINT_LEN {
+		let (after, byte) = u8::read(rest, ())?;
+		rest = after;
+		bytes.push(byte);
+		if byte & 0x80 == 0 {
+			break;
+		}
+	}
+
+	let value = decode_varint(&bytes)
+		.and_then(|(value, _)| T::try_from(value).ok())
+		.ok_or_else(|| DekuError::Parse(format!("bad varint {:02x?}", bytes)))?;
+	Ok((rest, value))
+}
+
+fn write_varint(value: u64, output: &mut BitVec<Msb0, u8>) -> Result<(), DekuError> {
+	let mut bytes = Vec::with_capacity(MAX_VARINT_LEN);
+	encode_varint(value, &mut bytes);
+	for byte in bytes {
+		byte.write(output, ())?;
+	}
+	Ok(())
 }
 
 impl Entry {
+	/// an entry of a page's title and text
 	pub fn new(title: &str, body: &str) -> Self {
 		let title = title.as_bytes();
 		let body = body.as_bytes();
@@ -240,39 +3081,203 @@ impl Entry {
 			store_ref: Ref::default(),
 			title_len: u32::try_from(title.len()).unwrap(),
 			body_len: u32::try_from(body.len()).unwrap(),
+			timestamp: 0,
+			page_id: 0,
 			title: title.into(),
 			body: body.into(),
 		}
 	}
 
+	/// records the page id and revision timestamp (seconds since the epoch) of the entry
+	pub fn with_revision(mut self, page_id: Option<u32>, timestamp: Option<u64>) -> Self {
+		self.page_id = page_id.unwrap_or_default();
+		self.timestamp = timestamp.unwrap_or_default();
+		self
+	}
+
+	/// the page id and timestamp of the revision, if known
+	pub fn revision(&self) -> (Option<u32>, Option<u64>) {
+		(
+			Some(self.page_id).filter(|id| *id != 0),
+			Some(self.timestamp).filter(|ts| *ts != 0),
+		)
+	}
+
+	/// parses an entry from the start of `data`, laid out as in blocks of that version
+	fn parse(store_ref: Ref, data: &[u8], version: u16) -> Result<Self> {
+		let truncated =
+			|| WiktError::corrupt_entry(store_ref, "entry header is past the end of the block");
+		let (title_len, body_len, timestamp, page_id, header_len) =
+			if version >= ENTRY_VARINT_VERSION {
+				let mut header_len = 0;
+				let mut field = || {
+					let (value, len) = decode_varint(data.get(header_len..).unwrap_or_default())
+						.ok_or_else(truncated)?;
+					header_len += len;
+					Ok::<_, WiktError>(value)
+				};
+				let title_len = usize::try_from(field()?)?;
+				let body_len = usize::try_from(field()?)?;
+				let timestamp = field()?;
+				let page_id = u32::try_from(field()?)?;
+				(title_len, body_len, timestamp, page_id, header_len)
+			} else {
+				let header_len = if version >= ENTRY_REVISION_VERSION {
+					20
+				} else {
+					8
+				};
+				let header = data.get(..header_len).ok_or_else(truncated)?;
+				let title_len = usize::try_from(u32::from_le_bytes(header[..4].try_into()?))?;
+				let body_len = usize::try_from(u32::from_le_bytes(header[4..8].try_into()?))?;
+				let (timestamp, page_id) = if header_len == 20 {
+					(
+						u64::from_le_bytes(header[8..16].try_into()?),
+						u32::from_le_bytes(header[16..].try_into()?),
+					)
+				} else {
+					(0, 0)
+				};
+				(title_len, body_len, timestamp, page_id, header_len)
+			};
+		debug!(
+			"[{}] entry title len={} body len={}",
+			store_ref, title_len, body_len
+		);
+
+		// lengths come from the data, so a corrupt block could claim anything
+		let end = title_len
+			.checked_add(header_len)
+			.and_then(|e| e.checked_add(body_len))
+			.filter(|end| *end <= data.len())
+			.ok_or_else(|| {
+				let problem = format!(
+					"entry lengths (title={} body={}) overrun the block ({} bytes)",
+					title_len,
+					body_len,
+					data.len()
+				);
+				WiktError::corrupt_entry(store_ref, problem)
+			})?;
+
+		let entry_slice = &data[..end];
+		trace!("[{}] entry slice = {:?}", store_ref, entry_slice);
+
+		let body_start = header_len + title_len;
+		Ok(Self {
+			store_ref,
+			title_len: title_len.try_into()?,
+			body_len: body_len.try_into()?,
+			timestamp,
+			page_id,
+			title: entry_slice[header_len..body_start].to_vec(),
+			body: entry_slice[body_start..].to_vec(),
+		})
+	}
+
+	/// the title of the entry, with any bytes that aren't UTF-8 replaced
+	pub fn title(&self) -> Cow<'_, str> {
+		String::from_utf8_lossy(&self.title)
+	}
+
+	/// the title, text, and ref of the entry, with any bytes that aren't UTF-8 replaced
 	pub fn open(self) -> (String, String, Ref) {
-		let title = String::from_utf8(self.title).unwrap();
-		let body = String::from_utf8(self.body).unwrap();
-		(title, body, self.store_ref)
+		let store_ref = self.store_ref;
+		let lossy = |bytes: Vec<u8>, what: &str| {
+			String::from_utf8(bytes).unwrap_or_else(|err| {
+				warn!(
+					"{} of entry [{}] isn't valid UTF-8: {}",
+					what, store_ref, err
+				);
+				String::from_utf8_lossy(err.as_bytes()).into_owned()
+			})
+		};
+
+		(
+			lossy(self.title, "title"),
+			lossy(self.body, "body"),
+			store_ref,
+		)
 	}
 }
 
-#[derive(Clone, Copy, Debug, Default, DekuRead, DekuWrite)]
+/// Where an entry is in a store: its block, and its position in there. Written as `block/entry`,
+/// or as a token of letters and digits (see `to_token`), both of which it's parsed from.
+#[derive(
+	Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, DekuRead, DekuWrite,
+)]
 #[deku(endian = "little")]
 pub struct Ref {
+	/// the id of the block
 	pub block_id: u32,
+	/// the position of the entry in the block, from 0
 	pub entry_id: u32,
 }
 
 impl Ref {
+	/// the ref of an entry in a block
 	pub fn new(block_id: u32, entry_id: u32) -> Self {
 		Self { block_id, entry_id }
 	}
 
+	/// the ref packed into a number, as stored in indexes
 	pub fn as_u64(self) -> u64 {
 		u64::from_le_bytes(self.to_bytes().unwrap().try_into().unwrap())
 	}
 
+	/// a ref from `as_u64`
 	pub fn from_u64(r: u64) -> Self {
 		Self::from_bytes((&r.to_le_bytes(), 0)).unwrap().1
 	}
+
+	/// the ref as a short token of letters and digits, to put in URLs: `as_u64` in base 62
+	///
+	/// Tokens are always `TOKEN_LEN` characters, padded with leading zeros, so one is never taken
+	/// for a number someone meant as a ref.
+	pub fn to_token(self) -> String {
+		let mut n = self.as_u64();
+		let mut token = vec![TOKEN_DIGITS[0]; TOKEN_LEN];
+		for digit in token.iter_mut().rev() {
+			*digit = TOKEN_DIGITS[(n % 62) as usize];
+			n /= 62;
+		}
+		String::from_utf8(token).unwrap()
+	}
+
+	/// a ref from `to_token`
+	pub fn from_token(token: &str) -> Result<Self> {
+		if token.chars().count() != TOKEN_LEN {
+			return Err(eyre!(
+				"bad ref token {:?}: tokens are {} letters and digits long",
+				token,
+				TOKEN_LEN
+			)
+			.into());
+		}
+
+		let mut n: u64 = 0;
+		for c in token.chars() {
+			let digit = TOKEN_DIGITS
+				.iter()
+				.position(|d| char::from(*d) == c)
+				.ok_or_else(|| {
+					eyre!("bad ref token {:?}: {:?} isn't a letter or digit", token, c)
+				})?;
+			n = n
+				.checked_mul(62)
+				.and_then(|n| n.checked_add(digit as u64))
+				.ok_or_else(|| eyre!("bad ref token {:?}: too big", token))?;
+		}
+		Ok(Self::from_u64(n))
+	}
 }
 
+/// The digits of ref tokens, in order.
+const TOKEN_DIGITS: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// The length of ref tokens: as many base 62 digits as `u64::MAX` has.
+const TOKEN_LEN: usize = 11;
+
 impl fmt::Display for Ref {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		write!(f, "{}/{}", self.block_id, self.entry_id)
@@ -280,15 +3285,571 @@ impl fmt::Display for Ref {
 }
 
 impl FromStr for Ref {
-	type Err = Report;
+	type Err = WiktError;
 
 	fn from_str(s: &str) -> Result<Self> {
-		let (b, e) = s
-			.split_once('/')
-			.ok_or_else(|| eyre!("missing / in refid"))?;
+		let (b, e) = match s.split_once('/') {
+			Some(parts) => parts,
+			None => return Self::from_token(s),
+		};
 		Ok(Self {
 			block_id: b.parse()?,
 			entry_id: e.parse()?,
 		})
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::{env, fs::remove_dir_all, process};
+
+	use super::*;
+	use crate::redirects::RedirectsBuilder;
+
+	/// A store in a temporary directory, removed when dropped.
+	struct TestStore {
+		store: Store,
+		/// the refs of the entries it was made with, block by block
+		refs: Vec<Vec<Ref>>,
+	}
+
+	impl TestStore {
+		/// makes a store with a block of these (title, body) entries per slice, with its title
+		/// map and redirects, as `store make` would
+		fn new(name: &str, blocks: &[&[(&str, &str)]]) -> Self {
+			let dir = env::temp_dir().join(format!("wikt-test-{}-{}", name, process::id()));
+			let _ = remove_dir_all(&dir);
+			let mut store = Store::new(&dir);
+			store.create().unwrap();
+
+			let mut redirects = RedirectsBuilder::default();
+			let mut titles = Vec::new();
+			let mut refs = Vec::new();
+			let mut n = 0;
+			for entries in blocks {
+				let mut block = Block::default();
+				for (title, body) in *entries {
+					block
+						.add(Entry::new(title, body).with_revision(Some(7), Some(1_600_000_000)))
+						.unwrap();
+					redirects.add(title, xmldump::redirect_target(body).as_deref());
+				}
+				n += entries.len();
+				let id = u32::try_from(n).unwrap();
+				store.commit(&mut block, n).unwrap();
+				redirects.commit(id);
+
+				let ids = (0..).zip(*entries).map(|(e, (title, _))| {
+					titles.push((title.to_string(), Ref::new(id, e)));
+					Ref::new(id, e)
+				});
+				refs.push(ids.collect());
+			}
+			store.write_redirects(&redirects.finish().0).unwrap();
+			store.write_title_refs(&TitleRefs::build(titles)).unwrap();
+
+			Self { store, refs }
+		}
+
+		/// the store opened again, as another process would
+		fn reopen(&self) -> Store {
+			let mut store = Store::new(&self.store.dir);
+			store.open().unwrap();
+			store
+		}
+	}
+
+	impl Drop for TestStore {
+		fn drop(&mut self) {
+			let _ = remove_dir_all(&self.store.dir);
+		}
+	}
+
+	fn body(store: &Store, refid: Ref) -> String {
+		store.read_entry(refid).unwrap().open().1
+	}
+
+	#[test]
+	fn update_round_trip() {
+		let mut test = TestStore::new(
+			"update",
+			&[
+				&[
+					("cat", "a small feline"),
+					("dog", "a canine"),
+					("cow", "moo"),
+				],
+				&[("owl", "a bird")],
+			],
+		);
+		let dog = test.refs[0][1];
+		let longer = "a domesticated canine, ".repeat(50);
+		test.store.update(dog, &longer).unwrap();
+
+		let store = test.reopen();
+		let entry = store.read_entry(dog).unwrap();
+		assert_eq!(entry.revision(), (Some(7), Some(1_600_000_000)));
+		assert_eq!(entry.open(), ("dog".into(), longer, dog));
+		assert_eq!(body(&store, test.refs[0][0]), "a small feline");
+		assert_eq!(body(&store, test.refs[0][2]), "moo");
+		assert_eq!(body(&store, test.refs[1][0]), "a bird");
+		assert_eq!(
+			store.entry_count(store.block_path(dog.block_id)).unwrap(),
+			3
+		);
+
+		// and back to something shorter
+		test.store.update(dog, "").unwrap();
+		let store = test.reopen();
+		assert_eq!(body(&store, dog), "");
+		assert_eq!(body(&store, test.refs[0][2]), "moo");
+	}
+
+	#[test]
+	fn update_errors() {
+		let mut test = TestStore::new("update-errors", &[&[("cat", "a small feline")]]);
+		let cat = test.refs[0][0];
+		let past = Ref::new(cat.block_id, 1);
+		assert!(matches!(
+			test.store.update(past, "x"),
+			Err(WiktError::RefOutOfRange(r)) if r == past
+		));
+
+		test.store.delete(cat).unwrap();
+		assert!(matches!(
+			test.store.update(cat, "x"),
+			Err(WiktError::DeletedEntry(r)) if r == cat
+		));
+	}
+
+	#[test]
+	fn update_redirects() {
+		let mut test = TestStore::new(
+			"update-redirects",
+			&[&[
+				("cat", "a small feline"),
+				("kitty", "#REDIRECT [[cat]]"),
+				("dog", "a canine"),
+			]],
+		);
+		let [cat, kitty, dog] = test.refs[0][..] else {
+			unreachable!()
+		};
+		assert_eq!(test.reopen().read_redirects().unwrap().resolve(kitty), cat);
+
+		// no longer a redirect
+		test.store.update(kitty, "a young cat").unwrap();
+		let redirects = test.reopen().read_redirects().unwrap();
+		assert_eq!(redirects.resolve(kitty), kitty);
+		assert_eq!(body(&test.reopen(), kitty), "a young cat");
+
+		// a redirect now
+		test.store
+			.update(dog, "#redirect [[cat#Noun|cats]]")
+			.unwrap();
+		assert_eq!(test.reopen().read_redirects().unwrap().resolve(dog), cat);
+
+		// to another entry
+		test.store.update(dog, "#REDIRECT [[kitty]]").unwrap();
+		assert_eq!(test.reopen().read_redirects().unwrap().resolve(dog), kitty);
+
+		// to a title the store doesn't have, so to nowhere
+		test.store.update(dog, "#REDIRECT [[wolf]]").unwrap();
+		assert_eq!(test.reopen().read_redirects().unwrap().resolve(dog), dog);
+	}
+
+	/// blocks of 2, 1, and 2 entries, which compact into blocks 3 and 5 at a block size of 3
+	const SMALL_BLOCKS: &[&[(&str, &str)]] = &[
+		&[("ant", "an insect"), ("bee", "#REDIRECT [[eel]]")],
+		&[("cat", "a small feline")],
+		&[("dog", "a canine"), ("eel", "a fish")],
+	];
+
+	/// checks every entry of `SMALL_BLOCKS` is where the remap says it went
+	fn check_compacted(test: &TestStore, store: &Store, remap: &[(Ref, Option<Ref>)]) {
+		let moved = |old: Ref| match remap.iter().find(|(from, _)| *from == old) {
+			Some((_, new)) => *new,
+			None => Some(old),
+		};
+		for (refs, entries) in test.refs.iter().zip(SMALL_BLOCKS) {
+			for (old, (title, text)) in refs.iter().zip(*entries) {
+				let new = moved(*old).unwrap();
+				let entry = store.read_entry(new).unwrap();
+				assert_eq!(entry.revision(), (Some(7), Some(1_600_000_000)));
+				assert_eq!(entry.open(), (title.to_string(), text.to_string(), new));
+			}
+		}
+		let mut ids: Vec<u32> = store
+			.blocks()
+			.unwrap()
+			.iter()
+			.map(|p| block_id(p).unwrap())
+			.collect();
+		ids.sort_unstable();
+		assert_eq!(ids, [3, 5]);
+		assert_eq!(
+			store
+				.manifest()
+				.unwrap()
+				.blocks
+				.keys()
+				.copied()
+				.collect::<Vec<_>>(),
+			[3, 5]
+		);
+		assert!(!store.dir.join(COMPACTION_FILE).exists());
+	}
+
+	#[test]
+	fn compaction_keeps_entries() {
+		let mut test = TestStore::new("compact", SMALL_BLOCKS);
+		let remap = test.store.compact(3).unwrap();
+		// dog and eel stay where they are
+		let (bee, eel) = (Ref::new(3, 1), Ref::new(5, 1));
+		assert_eq!(
+			remap,
+			[
+				(test.refs[0][0], Some(Ref::new(3, 0))),
+				(test.refs[0][1], Some(bee)),
+				(test.refs[1][0], Some(Ref::new(3, 2))),
+			]
+		);
+		assert_eq!(test.refs[2][1], eel);
+
+		let store = test.reopen();
+		check_compacted(&test, &store, &remap);
+		assert_eq!(store.read_redirects().unwrap().resolve(bee), eel);
+		assert_eq!(store.read_title_refs().unwrap().get("eel").unwrap(), [eel]);
+
+		// and again, with nothing left to do
+		assert!(test.reopen().compact(3).unwrap().is_empty());
+	}
+
+	#[test]
+	fn compaction_drops_deleted_entries() {
+		let mut test = TestStore::new("compact-deleted", SMALL_BLOCKS);
+		let cat = test.refs[1][0];
+		test.store.delete(cat).unwrap();
+		let remap = test.store.compact(3).unwrap();
+		assert!(remap.contains(&(cat, None)));
+
+		let store = test.reopen();
+		assert_eq!(store.deleted().count(), 0);
+		let kept = test.refs.concat().into_iter().zip(SMALL_BLOCKS.concat());
+		for (old, (title, text)) in kept.filter(|(old, _)| *old != cat) {
+			let new = remap
+				.iter()
+				.find(|(from, _)| *from == old)
+				.map_or(old, |m| m.1.unwrap());
+			assert_eq!(
+				store.read_entry(new).unwrap().open(),
+				(title.into(), text.into(), new)
+			);
+		}
+		assert!(store
+			.read_title_refs()
+			.unwrap()
+			.get("cat")
+			.unwrap()
+			.is_empty());
+	}
+
+	#[test]
+	fn interrupted_compaction() {
+		// cut short after the swap was recorded, after each new block was renamed into place,
+		// and after the old block left over was removed
+		for placed in 0..=3 {
+			let test = TestStore::new(&format!("compact-interrupted-{}", placed), SMALL_BLOCKS);
+			let store = test.reopen();
+			let run = test
+				.refs
+				.iter()
+				.map(|refs| {
+					store
+						.read_block(store.block_path(refs[0].block_id))
+						.unwrap()
+				})
+				.collect();
+			let mut remap = Vec::new();
+			let pending = store
+				.write_compaction(run, 0, 3, &mut remap)
+				.unwrap()
+				.unwrap();
+			assert_eq!(pending.old, [2, 3, 5]);
+			for &(id, _, _) in pending.new.iter().take(placed) {
+				let path = store.block_path(id);
+				place_block(&path.with_extension("zst.tmp"), &path).unwrap();
+			}
+			if placed == 3 {
+				remove_block(&store.block_path(2)).unwrap();
+			}
+			drop(store);
+
+			let store = test.reopen();
+			check_compacted(&test, &store, &remap);
+			// which refs changed is lost, so what's keyed by them is too
+			assert!(store.read_title_refs().is_err());
+			assert!(store.read_redirects().is_err());
+		}
+	}
+
+	#[test]
+	fn merge_round_trip() {
+		let mut base = TestStore::new("merge-base", &SMALL_BLOCKS[..2]);
+		let other = TestStore::new(
+			"merge-other",
+			&[
+				&[("fox", "a canid"), ("gnu", "a bovid")],
+				&[("hen", "a bird"), ("ibis", "a wader"), ("jay", "a corvid")],
+			],
+		);
+		let gnu = other.refs[0][1];
+		let mut deleting = other.reopen();
+		deleting.delete(gnu).unwrap();
+		let remap = base.store.merge(&other.reopen()).unwrap();
+
+		// as in remap.tsv
+		let remap: Vec<(Ref, Ref)> = remap
+			.iter()
+			.map(|(old, new)| format!("{}\t{}", old, new.unwrap()))
+			.map(|line| {
+				let (old, new) = line.split_once('\t').unwrap();
+				(old.parse().unwrap(), new.parse().unwrap())
+			})
+			.collect();
+		let expected: Vec<(Ref, Ref)> = other
+			.refs
+			.concat()
+			.into_iter()
+			.map(|old| (old, Ref::new(old.block_id + 3, old.entry_id)))
+			.collect();
+		assert_eq!(remap, expected);
+
+		// export has every entry of both, the base's where they were, but the one deleted
+		let store = base.reopen();
+		let exported: Vec<(String, String, Ref)> =
+			store.entries().unwrap().map(Result::unwrap).collect();
+		let mut wanted: Vec<(String, String, Ref)> = Vec::new();
+		for (refs, entries) in base.refs.iter().zip(&SMALL_BLOCKS[..2]) {
+			for (refid, (title, text)) in refs.iter().zip(*entries) {
+				wanted.push((title.to_string(), text.to_string(), *refid));
+			}
+		}
+		for (old, new) in &remap {
+			if *old != gnu {
+				let (title, text, _) = other.reopen().read_entry(*old).unwrap().open();
+				wanted.push((title, text, *new));
+			}
+		}
+		assert_eq!(exported, wanted);
+		assert!(store.is_deleted(Ref::new(gnu.block_id + 3, gnu.entry_id)));
+		assert_eq!(store.manifest().unwrap().entries(), 8);
+	}
+
+	#[test]
+	fn empty_bodies() {
+		let entries = [
+			("empty", ""),
+			("", ""),
+			("cat", "a small feline"),
+			("end", ""),
+		];
+		let mut block = Block::default();
+		for (title, body) in &entries {
+			block.add(Entry::new(title, body)).unwrap();
+		}
+		block
+			.add(Entry::new("dated", "").with_revision(Some(300), Some(1_600_000_000)))
+			.unwrap();
+		assert_eq!(block.n, 5);
+
+		// the varint layout: the title length, body length, timestamp, and page id, then the
+		// title and body
+		let start = usize::try_from(block.starts[0]).unwrap();
+		let end = usize::try_from(block.starts[1]).unwrap();
+		assert_eq!(&block.data[start..end], b"\x05\x00\x00\x00empty");
+		assert_eq!(block.starts[2] - block.starts[1], 4);
+		let start = usize::try_from(block.starts[4]).unwrap();
+		assert_eq!(
+			&block.data[start..],
+			b"\x05\x00\x80\xa0\xf8\xfa\x05\xac\x02dated"
+		);
+
+		for (n, (title, body)) in (0..).zip(&entries) {
+			let (t, b, _) = block.entry(n).unwrap().open();
+			assert_eq!((t.as_str(), b.as_str()), (*title, *body));
+		}
+		let dated = block.entry(4).unwrap();
+		assert_eq!(dated.revision(), (Some(300), Some(1_600_000_000)));
+		assert_eq!(dated.open().1, "");
+		assert!(matches!(block.entry(5), Err(WiktError::RefOutOfRange(_))));
+
+		// and compressed in a store
+		let test = TestStore::new("empty-bodies", &[&entries]);
+		let store = test.reopen();
+		for (refid, (_, text)) in test.refs[0].iter().zip(&entries) {
+			assert_eq!(body(&store, *refid), *text);
+		}
+
+		// and in the fixed layout of blocks from before, upgraded
+		let mut old = Block::default();
+		old.push_raw([&5_u32.to_le_bytes()[..], &[0; 16], b"empty"].concat())
+			.unwrap();
+		old.push_raw(
+			[
+				&3_u32.to_le_bytes()[..],
+				&1_u32.to_le_bytes(),
+				&[0; 12],
+				b"catx",
+			]
+			.concat(),
+		)
+		.unwrap();
+		let upgraded = old.upgrade(ENTRY_VARINT_VERSION - 1).unwrap();
+		assert_eq!(upgraded.entry(0).unwrap().open().1, "");
+		assert_eq!(upgraded.entry(1).unwrap().open().1, "x");
+		assert_eq!(&upgraded.data[..4], b"\x05\x00\x00\x00");
+	}
+
+	/// the outputs of these futures, polling them all on this thread until they're done
+	#[cfg(feature = "async")]
+	fn block_on_all<T>(futures: Vec<impl std::future::Future<Output = T>>) -> Vec<T> {
+		use std::{
+			task::{Context, Poll, Wake, Waker},
+			time::Duration,
+		};
+
+		struct Unpark(thread::Thread);
+		impl Wake for Unpark {
+			fn wake(self: Arc<Self>) {
+				self.0.unpark();
+			}
+		}
+
+		let waker = Waker::from(Arc::new(Unpark(thread::current())));
+		let mut cx = Context::from_waker(&waker);
+		let mut futures: Vec<_> = futures.into_iter().map(Box::pin).collect();
+		let mut outputs: Vec<Option<T>> = futures.iter().map(|_| None).collect();
+		while outputs.iter().any(Option::is_none) {
+			for (future, output) in futures.iter_mut().zip(&mut outputs) {
+				if output.is_none() {
+					if let Poll::Ready(done) = future.as_mut().poll(&mut cx) {
+						*output = Some(done);
+					}
+				}
+			}
+			if outputs.iter().any(Option::is_none) {
+				thread::park_timeout(Duration::from_millis(10));
+			}
+		}
+		outputs.into_iter().map(Option::unwrap).collect()
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn reads_entries_async() {
+		let test = TestStore::new("async", SMALL_BLOCKS);
+		let store = Arc::new(test.reopen());
+		let refs: Vec<Ref> = test.refs.concat();
+
+		// all the reads are under way before any is waited for, several times over
+		let reads = refs
+			.iter()
+			.cycle()
+			.take(refs.len() * 4)
+			.map(|refid| store.read_entry_async(*refid))
+			.collect();
+		let entries = block_on_all(reads);
+		let expected = SMALL_BLOCKS.concat();
+		for (n, entry) in entries.into_iter().enumerate() {
+			let (title, text, refid) = entry.unwrap().open();
+			let (t, b) = expected[n % refs.len()];
+			assert_eq!(
+				(title.as_str(), text.as_str(), refid),
+				(t, b, refs[n % refs.len()])
+			);
+		}
+
+		let blocks = block_on_all(vec![
+			store.read_block_async(store.block_path(2)),
+			store.read_block_async(store.block_path(5)),
+			store.read_block_async(store.block_path(4)),
+		]);
+		assert_eq!(blocks[0].as_ref().unwrap().n, 2);
+		assert_eq!(blocks[1].as_ref().unwrap().n, 2);
+		assert!(blocks[2].is_err());
+
+		let past = Ref::new(3, 1);
+		let failed = block_on_all(vec![store.read_entry_async(past)]);
+		assert!(matches!(failed[0], Err(WiktError::RefOutOfRange(r)) if r == past));
+		// a read whose future is dropped still finishes, without anything waiting for it
+		drop(store.read_entry_async(refs[0]));
+	}
+
+	#[test]
+	fn token_round_trip() {
+		let mut refs = vec![
+			Ref::new(0, 0),
+			Ref::new(u32::MAX, u32::MAX),
+			Ref::new(u32::MAX, 0),
+			Ref::new(0, u32::MAX),
+		];
+		// and some spread all over, from a xorshift
+		let mut n: u64 = 0x9E37_79B9_7F4A_7C15;
+		for _ in 0..1000 {
+			n ^= n << 13;
+			n ^= n >> 7;
+			n ^= n << 17;
+			refs.push(Ref::from_u64(n));
+			refs.push(Ref::new(n as u32 % 100_000, (n >> 32) as u32 % 1000));
+		}
+
+		for r in refs {
+			let token = r.to_token();
+			assert!(token.bytes().all(|b| b.is_ascii_alphanumeric()));
+			assert_eq!(Ref::from_token(&token).unwrap(), r, "{}", token);
+			assert_eq!(token.parse::<Ref>().unwrap(), r);
+		}
+		assert_eq!(Ref::new(0, 0).to_token(), "00000000000");
+		assert_eq!(Ref::new(500, 223).to_token(), "0000GrSLeWS");
+		assert_eq!(Ref::new(u32::MAX, u32::MAX).to_token(), "LygHa16AHYF");
+	}
+
+	#[test]
+	fn bad_tokens() {
+		let error = |token: &str| Ref::from_token(token).unwrap_err().to_string();
+		assert!(error("").contains("11 letters and digits long"));
+		// one past u64::MAX, and far past it
+		assert!(error("LygHa16AHYG").contains("too big"));
+		assert!(error("zzzzzzzzzzz").contains("too big"));
+		assert!(error("0000000ab-c").contains("isn't a letter or digit"));
+		assert!(error("0000000000é").contains("isn't a letter or digit"));
+		// without its leading zeros, or with more
+		assert!(error("GrSLeWS").contains("11 letters and digits long"));
+		assert!(error("000000GrSLeWS").contains("11 letters and digits long"));
+	}
+
+	#[test]
+	fn no_title_bloom() {
+		let test = TestStore::new("no-bloom", &[&[("cat", "meow")]]);
+		let error = match test.reopen().read_title_bloom() {
+			Ok(_) => panic!("read a title bloom filter that wasn't made"),
+			Err(err) => err.to_string(),
+		};
+		assert!(
+			error.contains("no title bloom filter in store"),
+			"{}",
+			error
+		);
+		assert!(error.contains("--bloom"), "{}", error);
+	}
+
+	#[test]
+	fn numbers_arent_refs() {
+		// which would otherwise be the tokens of 64/0 and 0/0
+		assert!("12".parse::<Ref>().is_err());
+		assert!("0".parse::<Ref>().is_err());
+		assert_eq!("12/0".parse::<Ref>().unwrap(), Ref::new(12, 0));
+		assert_eq!("00000000012".parse::<Ref>().unwrap(), Ref::new(64, 0));
+	}
+}
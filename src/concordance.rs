@@ -0,0 +1,67 @@
+//! Keyword-in-context concordances: every occurrence of the words of a search in a text, with the
+//! text around it.
+//!
+//! Words are found as the index finds them, by tokenizing the text with the tokenizer of its field,
+//! so with a stemming tokenizer `kings` is an occurrence of a search for `king`.
+
+use std::collections::BTreeSet;
+
+use serde::Serialize;
+use tantivy::tokenizer::TextAnalyzer;
+
+/// A word of a search where it occurs in a text.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Occurrence {
+	/// the offset of the word in the text, in characters
+	pub start: usize,
+	/// the offset of the end of the word in the text, in characters
+	pub end: usize,
+	/// up to the context length of text before the word
+	pub before: String,
+	/// the word, as it is in the text
+	pub word: String,
+	/// up to the context length of text after the word
+	pub after: String,
+}
+
+/// every occurrence in `text` of the `words` (as tokenized), in order, each with up to `context`
+/// characters on either side
+pub fn occurrences(
+	analyzer: &TextAnalyzer,
+	words: &BTreeSet<String>,
+	text: &str,
+	context: usize,
+) -> Vec<Occurrence> {
+	let mut found = Vec::new();
+	if words.is_empty() {
+		return found;
+	}
+
+	// tokens come in order, so characters are counted from the last one on
+	let (mut byte, mut char) = (0, 0);
+	let mut chars_to = |offset: usize| {
+		char += text[byte..offset].chars().count();
+		byte = offset;
+		char
+	};
+
+	let mut tokens = analyzer.token_stream(text);
+	while let Some(token) = tokens.next() {
+		if !words.contains(&token.text) {
+			continue;
+		}
+
+		let (from, to) = (token.offset_from, token.offset_to);
+		let start = chars_to(from);
+		let end = chars_to(to);
+		let before: Vec<char> = text[..from].chars().rev().take(context).collect();
+		found.push(Occurrence {
+			start,
+			end,
+			before: before.into_iter().rev().collect(),
+			word: text[from..to].to_string(),
+			after: text[to..].chars().take(context).collect(),
+		});
+	}
+	found
+}
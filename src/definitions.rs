@@ -0,0 +1,54 @@
+//! The numbered definitions of entries, as a list to read rather than wikitext.
+//!
+//! On Wiktionary, the senses of a part of speech are the lines starting with `#`, and sub-senses
+//! those starting with `##`. Lines of examples (`#:`) and quotations (`#*`) under them aren't
+//! definitions, and are left out.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::wikitext::clean_wikitext;
+
+/// `{{plural of|en|word}}` and other templates that make a whole definition of a form of a word
+static FORM_OF_RX: Lazy<Regex> =
+	Lazy::new(|| Regex::new(r"\{\{([^|{}]+ of)\|[^|{}]*\|([^|{}]+)(?:\|[^{}]*)?\}\}").unwrap());
+
+/// the definitions of a section, numbered and with their markup stripped, one per line
+///
+/// Top-level definitions are numbered from 1, and those under them from 1 again, after their
+/// parent's number and indented (`1.`, `  1.1.`). Numbering starts over at each heading, as each
+/// part of speech has its own senses. Definitions left empty once stripped are skipped.
+pub fn parse_definitions(section_text: &str) -> Vec<String> {
+	let mut numbers: Vec<usize> = Vec::new();
+	let mut definitions = Vec::new();
+	for line in section_text.lines() {
+		if line.starts_with('=') {
+			numbers.clear();
+			continue;
+		}
+
+		let depth = line.len() - line.trim_start_matches('#').len();
+		if depth == 0 || line[depth..].starts_with(&[':', '*'][..]) {
+			continue;
+		}
+
+		let text = FORM_OF_RX.replace_all(&line[depth..], "$1 $2");
+		let text = clean_wikitext(&text);
+		let text = text.trim();
+		if text.is_empty() {
+			continue;
+		}
+
+		// a sub-sense without a sense above it counts as under an unnumbered one
+		numbers.resize(depth, 0);
+		numbers[depth - 1] += 1;
+		let number: Vec<String> = numbers.iter().map(usize::to_string).collect();
+		definitions.push(format!(
+			"{}{}. {}",
+			"  ".repeat(depth - 1),
+			number.join("."),
+			text
+		));
+	}
+	definitions
+}
@@ -0,0 +1,139 @@
+//! Errors of stores, their blocks and their entries, for callers to tell apart.
+
+use std::{
+	array::TryFromSliceError,
+	io,
+	num::{ParseIntError, TryFromIntError},
+};
+
+use color_eyre::{eyre::eyre, Report};
+use deku::DekuError;
+use thiserror::Error;
+
+use crate::blockstore::Ref;
+
+/// `Result` with `WiktError` as the error.
+pub type Result<T, E = WiktError> = std::result::Result<T, E>;
+
+/// What went wrong reading or writing a store.
+///
+/// Failures a caller may want to handle (a missing block or entry, a deleted entry, corruption,
+/// a store that wasn't opened) have a variant of their own; the rest, which mostly come of files
+/// that aren't what they should be, are `Other`, with a message.
+#[derive(Debug, Error)]
+pub enum WiktError {
+	/// the store has no block with this id
+	#[error("no such block: {0}")]
+	MissingBlock(u32),
+
+	/// the block of this ref has no entry with its id
+	#[error("no such entry: {0}")]
+	RefOutOfRange(Ref),
+
+	/// the entry was deleted from the store
+	#[error("entry {0} was deleted")]
+	DeletedEntry(Ref),
+
+	/// a block can't be read: it's truncated, fails its checksum, or isn't a block at all
+	#[error("block id={id} {problem}")]
+	CorruptBlock {
+		/// the block's id
+		id: u32,
+		/// what's wrong with it, e.g. "is truncated within its header"
+		problem: String,
+	},
+
+	/// an entry can't be read, even though its block could
+	#[error("[{refid}] {problem}")]
+	CorruptEntry {
+		/// the entry's ref
+		refid: Ref,
+		/// what's wrong with it
+		problem: String,
+	},
+
+	/// the store's dictionaries aren't loaded, as it wasn't opened with `Store::open`
+	#[error("the store's dictionaries aren't loaded (open it first)")]
+	DictionaryNotLoaded,
+
+	/// a block was compressed with a dictionary the store doesn't have
+	#[error(
+		"block id={block} was compressed with dictionary {dictionary}, which the store doesn't have"
+	)]
+	MissingDictionary {
+		/// the block's id
+		block: u32,
+		/// the id of the dictionary
+		dictionary: u32,
+	},
+
+	/// reading or writing a file failed
+	#[error(transparent)]
+	Io(#[from] io::Error),
+
+	/// anything else, e.g. a manifest or pack that can't be parsed
+	#[error("{0}")]
+	Other(Report),
+}
+
+impl WiktError {
+	pub(crate) fn corrupt_block(id: u32, problem: impl Into<String>) -> Self {
+		Self::CorruptBlock {
+			id,
+			problem: problem.into(),
+		}
+	}
+
+	/// the same error, for another of the things it failed (errors from elsewhere being copied as
+	/// their message)
+	pub(crate) fn again(&self) -> Self {
+		match self {
+			Self::MissingBlock(id) => Self::MissingBlock(*id),
+			Self::RefOutOfRange(refid) => Self::RefOutOfRange(*refid),
+			Self::DeletedEntry(refid) => Self::DeletedEntry(*refid),
+			Self::CorruptBlock { id, problem } => Self::corrupt_block(*id, problem.clone()),
+			Self::CorruptEntry { refid, problem } => Self::corrupt_entry(*refid, problem.clone()),
+			Self::DictionaryNotLoaded => Self::DictionaryNotLoaded,
+			Self::MissingDictionary { block, dictionary } => Self::MissingDictionary {
+				block: *block,
+				dictionary: *dictionary,
+			},
+			Self::Io(err) => Self::Io(io::Error::new(err.kind(), err.to_string())),
+			Self::Other(report) => Self::Other(eyre!("{}", report)),
+		}
+	}
+
+	pub(crate) fn corrupt_entry(refid: Ref, problem: impl Into<String>) -> Self {
+		Self::CorruptEntry {
+			refid,
+			problem: problem.into(),
+		}
+	}
+}
+
+impl From<Report> for WiktError {
+	fn from(report: Report) -> Self {
+		Self::Other(report)
+	}
+}
+
+// these only go wrong on data that isn't what it should be, or on bugs: not worth a variant each
+macro_rules! other_from {
+	($($error:ty),*) => {
+		$(
+			impl From<$error> for WiktError {
+				fn from(err: $error) -> Self {
+					Self::Other(err.into())
+				}
+			}
+		)*
+	};
+}
+
+other_from!(
+	DekuError,
+	ParseIntError,
+	TryFromIntError,
+	TryFromSliceError,
+	serde_json::Error
+);
@@ -0,0 +1,364 @@
+//! The schema and tokenizers of search indexes over a store, and opening them whole or in shards.
+
+use std::{
+	collections::HashMap,
+	path::{Path, PathBuf},
+	str::FromStr,
+};
+
+use color_eyre::eyre::{eyre, Report, Result};
+use levenshtein_automata::{Distance, LevenshteinAutomatonBuilder, DFA, SINK_STATE};
+use serde::Deserialize;
+use tantivy::{
+	directory::error::OpenReadError,
+	schema::{
+		Field, FieldEntry, FieldType, IndexRecordOption, Schema, TextFieldIndexing, TextOptions,
+		FAST, INDEXED, STORED, STRING, TEXT,
+	},
+	tokenizer::{
+		Language, LowerCaser, RemoveLongFilter, SimpleTokenizer, Stemmer, StopWordFilter,
+		TextAnalyzer,
+	},
+	Index, Searcher, SegmentComponent,
+};
+use tantivy_fst::Automaton;
+
+/// How the text of entries is split into words, when indexing and so when searching.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextTokenizer {
+	/// words lowercased as they are
+	Default,
+	/// words lowercased and reduced to their stem in a language (e.g. `kings` to `king`)
+	Stem(Language),
+}
+
+/// Languages with a stemming tokenizer, by the prefix of its name (`en_stem`).
+pub const STEMMERS: &[(&str, Language)] = &[
+	("ar", Language::Arabic),
+	("da", Language::Danish),
+	("de", Language::German),
+	("el", Language::Greek),
+	("en", Language::English),
+	("es", Language::Spanish),
+	("fi", Language::Finnish),
+	("fr", Language::French),
+	("hu", Language::Hungarian),
+	("it", Language::Italian),
+	("nl", Language::Dutch),
+	("no", Language::Norwegian),
+	("pt", Language::Portuguese),
+	("ro", Language::Romanian),
+	("ru", Language::Russian),
+	("sv", Language::Swedish),
+	("ta", Language::Tamil),
+	("tr", Language::Turkish),
+];
+
+impl TextTokenizer {
+	/// the tokenizer the text field of an index was made with
+	pub fn of(schema: &Schema) -> Result<Self> {
+		let field = schema
+			.get_field("text")
+			.ok_or_else(|| eyre!("no text field in the index"))?;
+		match schema.get_field_entry(field).field_type() {
+			FieldType::Str(options) => options
+				.get_indexing_options()
+				.ok_or_else(|| eyre!("text field isn't indexed"))?
+				.tokenizer()
+				.parse(),
+			_ => Err(eyre!("text field isn't text")),
+		}
+	}
+
+	/// the name it's registered under, as recorded in the schema
+	pub fn name(self) -> String {
+		match self {
+			Self::Default => "default".into(),
+			Self::Stem(language) => {
+				let (code, _) = STEMMERS.iter().find(|(_, l)| *l == language).unwrap();
+				format!("{}_stem", code)
+			}
+		}
+	}
+
+	/// the analyzer of this tokenizer, leaving out `stopwords` if given
+	pub fn analyzer(self, stopwords: Option<Vec<String>>) -> TextAnalyzer {
+		let mut analyzer = TextAnalyzer::from(SimpleTokenizer)
+			.filter(RemoveLongFilter::limit(40))
+			.filter(LowerCaser);
+		if let Some(words) = stopwords {
+			analyzer = analyzer.filter(StopWordFilter::remove(words));
+		}
+		if let Self::Stem(language) = self {
+			analyzer = analyzer.filter(Stemmer::new(language));
+		}
+		analyzer
+	}
+}
+
+impl FromStr for TextTokenizer {
+	type Err = Report;
+
+	fn from_str(s: &str) -> Result<Self> {
+		if s == "default" {
+			return Ok(Self::Default);
+		}
+
+		s.strip_suffix("_stem")
+			.and_then(|code| STEMMERS.iter().find(|(c, _)| *c == code))
+			.map(|(_, language)| Self::Stem(*language))
+			.ok_or_else(|| {
+				let codes: Vec<&str> = STEMMERS.iter().map(|(code, _)| *code).collect();
+				eyre!(
+					"unknown tokenizer: {} (default, or a language then _stem, from {})",
+					s,
+					codes.join(", ")
+				)
+			})
+	}
+}
+
+/// whether an index has the text of its documents, so hits can be shown without reading the store
+pub fn stores_text(schema: &Schema) -> bool {
+	schema
+		.get_field("text")
+		.is_some_and(|field| schema.get_field_entry(field).is_stored())
+}
+
+/// opens an index, with the tokenizers it may have been made with and the stopwords it was made
+/// without
+///
+/// Errors on a sharded index, which isn't one index but several: see `open_shards`, and on one
+/// whose schema isn't what `schema` makes (see `check_schema`).
+pub fn open_index(path: &Path) -> Result<Index> {
+	if shard_dir(path, 0).is_dir() {
+		return Err(eyre!(
+			"{:?} is a sharded index, which only `index query` and `index verify` read whole: give \
+			 one of its shards instead (e.g. {:?})",
+			path,
+			shard_dir(path, 0)
+		));
+	}
+
+	let index = Index::open_in_dir(path)?;
+	register_tokenizers(&index);
+	check_schema(&index.schema())?;
+
+	// searches leave out the words indexing did, or phrases with them would look for words (and
+	// positions) the index doesn't have
+	let stopwords = indexed_stopwords(&index)?;
+	if !stopwords.is_empty() {
+		let tokenizer = TextTokenizer::of(&index.schema())?;
+		index
+			.tokenizers()
+			.register(&tokenizer.name(), tokenizer.analyzer(Some(stopwords)));
+	}
+	Ok(index)
+}
+
+/// the stopwords an index was made without, which `index make` records in the payload of its
+/// commits (none for an index made without, or before they were recorded)
+pub fn indexed_stopwords(index: &Index) -> Result<Vec<String>> {
+	#[derive(Deserialize)]
+	struct Payload {
+		#[serde(default)]
+		stopwords: Vec<String>,
+	}
+
+	match index.load_metas()?.payload {
+		Some(payload) => Ok(serde_json::from_str::<Payload>(&payload)?.stopwords),
+		None => Ok(Vec::new()),
+	}
+}
+
+/// errors if an index's schema isn't what `schema` makes, with the tokenizer and stored text it
+/// was made with, naming the fields that differ
+///
+/// tantivy keeps the schema an index was made with, and searches by it, so an index made by a
+/// version of wikt with other fields (or the same ones, indexed otherwise) would find nothing
+/// where it should, or fail on a missing field, rather than say so.
+pub fn check_schema(index_schema: &Schema) -> Result<()> {
+	let incompatible = |why: String| {
+		eyre!(
+			"index built with an incompatible schema ({}), please reindex with `index make --force`",
+			why
+		)
+	};
+	let tokenizer = TextTokenizer::of(index_schema).map_err(|err| incompatible(err.to_string()))?;
+	let expected = schema(tokenizer, stores_text(index_schema));
+	if *index_schema == expected {
+		return Ok(());
+	}
+
+	let entries = |schema: &Schema| -> HashMap<String, FieldEntry> {
+		schema
+			.fields()
+			.map(|(_, entry)| (entry.name().to_string(), entry.clone()))
+			.collect()
+	};
+	let (had, wanted) = (entries(index_schema), entries(&expected));
+	let mut differ: Vec<&str> = wanted
+		.iter()
+		.filter(|(name, entry)| had.get(*name) != Some(entry))
+		.chain(had.iter().filter(|(name, _)| !wanted.contains_key(*name)))
+		.map(|(name, _)| name.as_str())
+		.collect();
+	differ.sort_unstable();
+	differ.dedup();
+	Err(incompatible(if differ.is_empty() {
+		"fields in another order".into()
+	} else {
+		format!("fields that differ: {}", differ.join(", "))
+	}))
+}
+
+/// where the Nth shard of a sharded index is: each is an index of its own, of some of the blocks
+pub fn shard_dir(path: &Path, n: usize) -> PathBuf {
+	path.join(format!("shard.{}", n))
+}
+
+/// opens the shards of a sharded index, in order, or just the index if it isn't sharded
+pub fn open_shards(path: &Path) -> Result<Vec<Index>> {
+	let shards: Vec<PathBuf> = (0..)
+		.map(|n| shard_dir(path, n))
+		.take_while(|dir| dir.is_dir())
+		.collect();
+	if shards.is_empty() {
+		return Ok(vec![open_index(path)?]);
+	}
+
+	shards.iter().map(|dir| open_index(dir)).collect()
+}
+
+/// registers the tokenizers an index's schema may name, which tantivy doesn't know of itself
+pub fn register_tokenizers(index: &Index) {
+	for (_, language) in STEMMERS {
+		let tokenizer = TextTokenizer::Stem(*language);
+		index
+			.tokenizers()
+			.register(&tokenizer.name(), tokenizer.analyzer(None));
+	}
+}
+
+/// What `warm_up` went through.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WarmUp {
+	/// segments whose dictionaries were opened
+	pub segments: usize,
+	/// files read
+	pub files: usize,
+	/// bytes in those files
+	pub bytes: u64,
+}
+
+/// reads the files of an index's segments and opens their term dictionaries in `searcher`, so the
+/// first search is as quick as the ones after it
+///
+/// tantivy maps the files of an index into memory and opens the dictionary of each field of a
+/// segment the first time a search needs it, so otherwise that search waits on the disk. Reading
+/// a byte of each page brings the files into the page cache, and the dictionaries stay open for
+/// as long as the searcher (or another from the same reader and generation) is kept.
+pub fn warm_up(index: &Index, searcher: &Searcher) -> Result<WarmUp> {
+	const PAGE: usize = 4096;
+
+	let mut warm = WarmUp::default();
+	for segment in index.searchable_segments()? {
+		for component in SegmentComponent::iterator() {
+			let file = match segment.open_read(*component) {
+				Ok(file) => file,
+				Err(OpenReadError::FileDoesNotExist(_)) => continue,
+				Err(err) => return Err(err.into()),
+			};
+			let bytes = file.read_bytes()?;
+			let touched = bytes.as_slice().iter().step_by(PAGE).fold(0, |a, b| a ^ b);
+			std::hint::black_box(touched);
+			warm.files += 1;
+			warm.bytes += bytes.len() as u64;
+		}
+	}
+
+	let schema = index.schema();
+	for segment in searcher.segment_readers() {
+		for (field, entry) in schema.fields() {
+			if entry.is_indexed() {
+				segment.inverted_index(field)?;
+			}
+		}
+		warm.segments += 1;
+	}
+	Ok(warm)
+}
+
+/// the terms of a field up to `distance` edits (letters added, removed, changed, or swapped) away
+/// from `text`, other than it, with how many documents have each, most first
+pub fn similar_terms(
+	searcher: &Searcher,
+	field: Field,
+	text: &str,
+	distance: u8,
+) -> Result<Vec<(String, u64)>> {
+	let dfa = LevenshteinAutomatonBuilder::new(distance, true).build_dfa(text);
+	let mut found: HashMap<String, u64> = HashMap::new();
+	for segment in searcher.segment_readers() {
+		let inverted = segment.inverted_index(field)?;
+		let mut terms = inverted.terms().search(Levenshtein(&dfa)).into_stream()?;
+		while terms.advance() {
+			let term = String::from_utf8_lossy(terms.key()).into_owned();
+			*found.entry(term).or_insert(0) += u64::from(terms.value().doc_freq);
+		}
+	}
+	found.remove(text);
+
+	let mut found: Vec<(String, u64)> = found.into_iter().collect();
+	found.sort_by(|(a, n), (b, m)| m.cmp(n).then_with(|| a.cmp(b)));
+	Ok(found)
+}
+
+/// Matches terms within some edit distance of a word, as tantivy's fuzzy queries do.
+struct Levenshtein<'d>(&'d DFA);
+
+impl Automaton for Levenshtein<'_> {
+	type State = u32;
+
+	fn start(&self) -> u32 {
+		self.0.initial_state()
+	}
+
+	fn is_match(&self, state: &u32) -> bool {
+		matches!(self.0.distance(*state), Distance::Exact(_))
+	}
+
+	fn can_match(&self, state: &u32) -> bool {
+		*state != SINK_STATE
+	}
+
+	fn accept(&self, state: &u32, byte: u8) -> u32 {
+		self.0.transition(*state, byte)
+	}
+}
+
+/// the fields of an index, with its text split by `tokenizer`
+///
+/// An index has a document per entry, per language section of it, and per part-of-speech section
+/// (and subsection) of those: `ref` is the entry's, and `lang`, `gram` and `sub` say which section a
+/// document is of. `title` is stored so hits can be listed without reading the store, and `text`
+/// only with `store_text`, to keep the index small otherwise. `facets` has the lang and gram again,
+/// as `/lang/...` and `/gram/...`, so hits can be counted by those.
+pub fn schema(tokenizer: TextTokenizer, store_text: bool) -> Schema {
+	let mut schema_builder = Schema::builder();
+	schema_builder.add_text_field("title", TEXT | STORED);
+	let text = TextOptions::default().set_indexing_options(
+		TextFieldIndexing::default()
+			.set_tokenizer(&tokenizer.name())
+			.set_index_option(IndexRecordOption::WithFreqsAndPositions),
+	);
+	schema_builder.add_text_field("text", if store_text { text | STORED } else { text });
+	schema_builder.add_u64_field("ref", INDEXED | STORED | FAST);
+	schema_builder.add_text_field("lang", STRING | STORED);
+	schema_builder.add_text_field("gram", STRING | STORED);
+	schema_builder.add_text_field("sub", STRING | STORED);
+	schema_builder.add_bytes_field("sortkey", STORED | FAST);
+	schema_builder.add_f64_field("lang_confidence", STORED);
+	schema_builder.add_facet_field("facets", INDEXED);
+	schema_builder.build()
+}
@@ -0,0 +1,35 @@
+//! Stopping long commands early with Ctrl-C, keeping what they've done so far.
+//!
+//! Commands that can stop early `catch` Ctrl-C, then check `interrupted` as they go: the first
+//! Ctrl-C asks them to stop, and a second one ends the program as usual, in case they don't.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// makes Ctrl-C (SIGINT) ask to stop, instead of ending the program right away
+///
+/// Where there are no signals (not unix), Ctrl-C still ends the program.
+pub fn catch() {
+	#[cfg(unix)]
+	unsafe {
+		let handler: extern "C" fn(libc::c_int) = on_interrupt;
+		libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+	}
+}
+
+/// whether Ctrl-C was pressed since `catch`
+pub fn interrupted() -> bool {
+	INTERRUPTED.load(Ordering::Relaxed)
+}
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+	// only async-signal-safe calls in here
+	if INTERRUPTED.swap(true, Ordering::SeqCst) {
+		unsafe {
+			libc::signal(libc::SIGINT, libc::SIG_DFL);
+			libc::raise(libc::SIGINT);
+		}
+	}
+}
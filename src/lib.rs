@@ -0,0 +1,25 @@
+//! Wiktionary (or any MediaWiki) XML dumps, read into a compact store of entries and indexed for
+//! search.
+//!
+//! The `wikt` binary is built on this: [`xmldump`] reads dumps (parsed with [`xmlreader`]),
+//! [`blockstore`] writes and reads stores of their entries, and [`index`] has the schema of tantivy
+//! indexes over a store, whose documents are the [`sections`] of entries. Searches of the store
+//! itself skip blocks without the words they look for with a [`wordfilter`]. Hits can be shown as
+//! their numbered [`definitions`], or as a [`concordance`] of the words searched for.
+//!
+//! Stores fail with an [`error::WiktError`], which says what went wrong in a way callers can match
+//! on; the rest of the library reports errors as `color_eyre` ones, with a message.
+
+pub mod blockstore;
+pub mod concordance;
+pub mod definitions;
+pub mod error;
+pub mod index;
+mod pack;
+pub mod redirects;
+pub mod sections;
+pub mod titles;
+pub mod wikitext;
+pub mod wordfilter;
+pub mod xmldump;
+pub mod xmlreader;
@@ -1,29 +1,301 @@
 use std::{
-	collections::HashMap,
-	fs::{create_dir_all, remove_dir_all, File},
-	path::PathBuf,
-	sync::atomic::{AtomicUsize, Ordering},
+	borrow::Cow,
+	cmp::Reverse,
+	collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap},
+	convert::{TryFrom, TryInto},
+	fmt::Display,
+	fs::{create_dir_all, read_to_string, remove_dir_all, File},
+	io::{self, BufRead, BufWriter, IsTerminal, Write},
+	path::{Component, Path, PathBuf},
+	str::FromStr,
+	sync::{
+		atomic::{AtomicBool, AtomicUsize, Ordering},
+		mpsc::{sync_channel, SyncSender},
+		Condvar, Mutex,
+	},
+	time::{Duration, Instant},
 };
 
-use blockstore::Ref;
-use color_eyre::eyre::{eyre, Result};
-use log::{debug, info, trace};
-use once_cell::sync::Lazy;
-use regex::Regex;
+use color_eyre::eyre::{eyre, Report, Result};
+use deku::DekuContainerWrite;
+use log::{debug, error, info, trace, warn};
+use regex::RegexBuilder;
+use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 use tantivy::{
-	collector::TopDocs,
+	collector::{Collector, FacetCollector, SegmentCollector, TopDocs},
 	directory::MmapDirectory,
 	doc,
-	query::QueryParser,
-	schema::{Schema, FAST, INDEXED, STORED, TEXT},
-	DocAddress, Index, Score,
+	fastfield::FastFieldReader,
+	query::{
+		BooleanQuery, BoostQuery, FuzzyTermQuery, Occur, PhraseQuery, Query, QueryParser,
+		QueryParserError, Scorer, TermQuery, Weight,
+	},
+	schema::{Facet, Field, IndexRecordOption, Schema, Value},
+	tokenizer::TextAnalyzer,
+	DocAddress, DocId, DocSet, Index, IndexReader, Postings, ReloadPolicy, Score, Searcher,
+	SegmentReader, SnippetGenerator, Term, UserOperation, TERMINATED,
+};
+
+use output::{ColorChoice, Style};
+use progress::Progress;
+use wikt::{
+	blockstore::{self, Ref},
+	concordance,
+	definitions::parse_definitions,
+	error::WiktError,
+	index::{
+		self, open_index, open_shards, register_tokenizers, schema, shard_dir, similar_terms,
+		stores_text, TextTokenizer,
+	},
+	redirects::{Redirects, RedirectsBuilder},
+	sections::{
+		keep_sections, sections_named, split_by_level, Headings, GRAM_LEVEL, LANG_LEVEL, SUB_LEVEL,
+	},
+	titles::{self, TitleBloom, TitleDedup, TitleRefs, TitleRefsBuilder},
+	wikitext, wordfilter,
+	xmldump::{self, Page, Site},
+	xmlreader,
 };
 
-use xmldump::Page;
+mod interrupt;
+mod output;
+mod progress;
+mod serve;
+mod textsearch;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SortOrder {
+	Score,
+	Title,
+	Ref,
+}
+
+impl FromStr for SortOrder {
+	type Err = Report;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"score" => Ok(Self::Score),
+			"title" => Ok(Self::Title),
+			"ref" => Ok(Self::Ref),
+			_ => Err(eyre!("unknown sort order: {} (score, title, ref)", s)),
+		}
+	}
+}
+
+/// How `store make --dedup` tells which titles were seen already.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DedupMode {
+	Exact,
+	Bloom,
+}
+
+impl FromStr for DedupMode {
+	type Err = Report;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"exact" => Ok(Self::Exact),
+			"bloom" => Ok(Self::Bloom),
+			_ => Err(eyre!("unknown dedup mode: {} (exact, bloom)", s)),
+		}
+	}
+}
+
+/// The number of hits per value of a facet, most first.
+type FacetCounts = (FacetKind, Vec<(String, u64)>);
+
+/// The documents of each indexed ref: block id => entry id => (documents, one of them).
+type IndexedRefs = BTreeMap<u32, BTreeMap<u32, (usize, DocAddress)>>;
+
+/// What index query hits can be counted by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FacetKind {
+	Lang,
+	Gram,
+}
+
+impl FacetKind {
+	fn name(self) -> &'static str {
+		match self {
+			Self::Lang => "lang",
+			Self::Gram => "gram",
+		}
+	}
+}
+
+impl FromStr for FacetKind {
+	type Err = Report;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"lang" => Ok(Self::Lang),
+			"gram" => Ok(Self::Gram),
+			_ => Err(eyre!("unknown facet: {} (lang, gram)", s)),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MatchMode {
+	Any,
+	All,
+	Phrase,
+}
+
+impl FromStr for MatchMode {
+	type Err = Report;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"any" => Ok(Self::Any),
+			"all" => Ok(Self::All),
+			"phrase" => Ok(Self::Phrase),
+			_ => Err(eyre!("unknown match mode: {} (any, all, phrase)", s)),
+		}
+	}
+}
+
+/// A score multiplier for matches in one field, given as `field=N`.
+#[derive(Clone, Debug, PartialEq)]
+struct FieldBoost {
+	field: String,
+	boost: Score,
+}
+
+impl FromStr for FieldBoost {
+	type Err = Report;
+
+	fn from_str(s: &str) -> Result<Self> {
+		let (field, boost) = s
+			.split_once('=')
+			.ok_or_else(|| eyre!("expected field=N, got: {}", s))?;
+		Ok(Self {
+			field: field.to_string(),
+			boost: boost
+				.parse()
+				.map_err(|err| eyre!("bad boost for {}: {}", field, err))?,
+		})
+	}
+}
+
+/// How index query hits are printed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+	/// a header and excerpt per hit, for reading
+	Text,
+	/// a JSON array of hits
+	Json,
+	/// a JSON object per hit, one per line
+	Jsonl,
+	/// a row of tab-separated fields per hit, after a header row, as spreadsheets take them
+	Tsv,
+}
+
+impl FromStr for OutputFormat {
+	type Err = Report;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"text" => Ok(Self::Text),
+			"json" => Ok(Self::Json),
+			"jsonl" => Ok(Self::Jsonl),
+			"tsv" => Ok(Self::Tsv),
+			_ => Err(eyre!(
+				"unknown output format: {} (text, json, jsonl, tsv)",
+				s
+			)),
+		}
+	}
+}
+
+/// Which tantivy reload policy index readers use.
+///
+/// Either way, each query searches one snapshot of the index, so its hits are consistent; what
+/// differs is which snapshot the next query gets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReloadMode {
+	/// see commits shortly after they land: a thread polls the index's meta.json (every half
+	/// second), so new entries are searchable within about that, but two queries in a row (such as
+	/// two pages of the same search) may see different snapshots
+	OnCommit,
+	/// only see commits from when the reader was opened, or last reloaded with `:reload` or `GET
+	/// /reload`: no watcher thread, and pages of a search always agree, but new entries stay
+	/// unsearchable (and segments merged away still take up disk space) until then
+	Manual,
+}
+
+impl ReloadMode {
+	fn policy(self) -> ReloadPolicy {
+		match self {
+			Self::OnCommit => ReloadPolicy::OnCommit,
+			Self::Manual => ReloadPolicy::Manual,
+		}
+	}
+}
+
+impl FromStr for ReloadMode {
+	type Err = Report;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"on-commit" => Ok(Self::OnCommit),
+			"manual" => Ok(Self::Manual),
+			_ => Err(eyre!("unknown reload policy: {} (on-commit, manual)", s)),
+		}
+	}
+}
+
+/// A point in time given as `YYYY-MM-DD` or in RFC 3339, in seconds since the epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Date(u64);
+
+impl FromStr for Date {
+	type Err = Report;
+
+	fn from_str(s: &str) -> Result<Self> {
+		xmldump::parse_date(s).map(Self).ok_or_else(|| {
+			eyre!(
+				"bad date: {} (YYYY-MM-DD, or RFC 3339 like 2021-06-01T12:34:56Z)",
+				s
+			)
+		})
+	}
+}
+
+/// Which entries to keep by the timestamp of their revision.
+#[derive(StructOpt, Debug, Clone)]
+struct RevisionFilter {
+	/// only entries whose revision is from this date on (`YYYY-MM-DD` or RFC 3339)
+	#[structopt(long)]
+	after: Option<Date>,
+
+	/// only entries whose revision is from before this date (`YYYY-MM-DD` or RFC 3339)
+	#[structopt(long)]
+	before: Option<Date>,
+
+	/// with --after or --before, also keep entries whose revision isn't known (those of stores
+	/// made before timestamps were kept)
+	#[structopt(long)]
+	include_undated: bool,
+}
 
-mod blockstore;
-mod xmldump;
+impl RevisionFilter {
+	/// whether the revision of the entry is within the dates, if any are given
+	fn keeps(&self, entry: &blockstore::Entry) -> bool {
+		if self.after.is_none() && self.before.is_none() {
+			return true;
+		}
+		match entry.revision().1 {
+			None => self.include_undated,
+			Some(ts) => {
+				self.after.is_none_or(|Date(after)| ts >= after)
+					&& self.before.is_none_or(|Date(before)| ts < before)
+			}
+		}
+	}
+}
 
 #[derive(StructOpt, Debug, Clone)]
 struct Args {
@@ -36,391 +308,5657 @@ struct Args {
 	#[structopt(short = "I", long, default_value = "index")]
 	pub index_dir: PathBuf,
 
+	/// use the store and index of this name, in the store and index directories (e.g. `store/fr`
+	/// and `index/fr`), to keep several side by side; can also be given after the subcommand
+	#[structopt(short = "D", long, global = true)]
+	pub dataset: Option<String>,
+
+	/// colorize output (auto: only when it goes to a terminal, always, never)
+	#[structopt(long, default_value = "auto")]
+	pub color: ColorChoice,
+
+	/// don't colorize output, like `--color never`
+	#[structopt(long)]
+	pub no_color: bool,
+
+	/// write results to this file instead of stdout (logs still go to stderr)
+	#[structopt(long)]
+	pub output: Option<PathBuf>,
+
+	/// when an open index picks up new commits: on-commit (within half a second, but queries in a
+	/// row may see different commits) or manual (only on `:reload` in the REPL or `GET /reload` in
+	/// serve, so queries agree until then)
+	#[structopt(long, default_value = "on-commit")]
+	pub reload: ReloadMode,
+
+	/// read blocks by memory-mapping them rather than reading them into memory first
+	#[structopt(long)]
+	pub mmap: bool,
+
 	#[structopt(subcommand)]
 	pub action: Action,
 }
 
+impl Args {
+	/// points the store and index directories at those of the `--dataset`, if there's one
+	fn resolve_dataset(&mut self) -> Result<()> {
+		let name = match self.dataset {
+			Some(ref name) => name,
+			None => return Ok(()),
+		};
+
+		// a name, not a path, so it can't be somewhere else than in the directories
+		let mut parts = Path::new(name).components();
+		if !matches!(
+			(parts.next(), parts.next()),
+			(Some(Component::Normal(_)), None)
+		) {
+			return Err(eyre!(
+				"bad dataset name {:?}: it's a directory name, not a path",
+				name
+			));
+		}
+
+		self.store_dir = self.store_dir.join(name);
+		self.index_dir = self.index_dir.join(name);
+		debug!(
+			"dataset {}: store {:?}, index {:?}",
+			name, self.store_dir, self.index_dir
+		);
+		Ok(())
+	}
+}
+
 #[derive(StructOpt, Debug, Clone)]
 enum Action {
 	Store(StoreAction),
 	Index(IndexAction),
-}
 
-#[derive(StructOpt, Debug, Clone)]
-enum StoreAction {
-	Make {
-		dump: PathBuf,
+	/// time common operations on an existing store (and index) and print the results as JSON
+	///
+	/// Samples are picked with a seeded generator, so runs with the same seed and store read the
+	/// same blocks and entries and can be diffed.
+	Bench {
+		/// number of blocks to decompress
+		#[structopt(long, default_value = "10")]
+		blocks: usize,
+
+		/// number of entries to read
+		#[structopt(long, default_value = "100")]
+		entries: usize,
+
+		#[structopt(long, default_value = "1")]
+		seed: u64,
+
+		/// index queries to time (none skips the index)
+		queries: Vec<String>,
 	},
 
-	Get {
-		refid: Ref,
+	/// run index queries read line by line from stdin, opening the index and store only once
+	///
+	/// Lines starting with `:` change the options of the queries after them instead, e.g. `:lang
+	/// fr`, `:limit 50`, or `:full`; `:help` lists them.
+	Repl {
+		#[structopt(flatten)]
+		options: QueryOptions,
+
+		/// keep up to N decompressed blocks in memory, shared by all queries (0 disables)
+		#[structopt(long, default_value = "0")]
+		cache_blocks: usize,
+
+		/// read what's left of truncated or corrupt blocks instead of failing
+		#[structopt(long)]
+		skip_bad_blocks: bool,
+
+		/// show the text of redirect entries themselves instead of the entries they redirect to
+		#[structopt(long)]
+		no_follow: bool,
+
+		/// before reading queries, read the index's files and open its dictionaries, and read the
+		/// first blocks of the store (see --warm-blocks), so the first query is as quick as the next
+		#[structopt(long)]
+		warm: bool,
+
+		/// blocks of the store to read when warming up [default: as many as --cache-blocks keeps]
+		#[structopt(long, requires = "warm")]
+		warm_blocks: Option<usize>,
 	},
 
-	Query {
-		searches: Vec<String>,
+	/// answer searches and entry lookups over HTTP, as JSON
+	///
+	/// `GET /search?q=...&limit=...&lang=...&gram=...&sub=...` returns hits like `index query
+	/// --format json`, `GET /entry/{block}/{entry}` returns a whole entry, and `GET /reload` makes
+	/// the index see its last commit.
+	Serve {
+		/// address to listen on
+		#[structopt(long, default_value = "127.0.0.1:8080")]
+		bind: String,
+
+		/// options of searches, as for `index query`: `--limit` is the number of hits returned
+		/// when a search doesn't ask for a number (and the most it can ask for is 10x)
+		#[structopt(flatten)]
+		options: QueryOptions,
+
+		/// connections handled at once, past which new ones are answered 503
+		#[structopt(long, default_value = "64")]
+		max_connections: usize,
 
+		/// keep up to N decompressed blocks in memory, shared by all requests (0 disables)
+		#[structopt(long, default_value = "0")]
+		cache_blocks: usize,
+
+		/// read what's left of truncated or corrupt blocks instead of failing
 		#[structopt(long)]
-		count: bool,
+		skip_bad_blocks: bool,
+
+		/// before listening, read the index's files and open its dictionaries, and read the
+		/// first blocks of the store (see --warm-blocks), so the first query is as quick as the next
+		#[structopt(long)]
+		warm: bool,
+
+		/// blocks of the store to read when warming up [default: as many as --cache-blocks keeps]
+		#[structopt(long, requires = "warm")]
+		warm_blocks: Option<usize>,
 	},
 }
 
 #[derive(StructOpt, Debug, Clone)]
-enum IndexAction {
+enum StoreAction {
 	Make {
-		#[structopt(long)]
-		force: bool,
+		/// the dump, or `-` to read it from stdin; several (like the numbered files of a split
+		/// dump) are stored one after the other, as if they were one
+		#[structopt(required = true)]
+		dumps: Vec<PathBuf>,
 
-		/// only index N blocks (0 disables)
+		/// only store N pages (0 disables)
 		#[structopt(short = "n", long, default_value = "0")]
-		limited: usize,
-	},
-
-	Query {
-		#[structopt(short = "n", long, default_value = "20")]
 		limit: usize,
 
-		// return only titles (ie don't read the store)
+		/// also build a bloom filter of titles
 		#[structopt(long)]
-		titles: bool,
+		bloom: bool,
 
-		// return full entries instead of truncating
+		/// also build a bloom filter of the words of each block, for `store query --words` to skip
+		/// blocks without those searched for (about 10 bits per distinct word of a block)
 		#[structopt(long)]
-		full: bool,
+		word_filters: bool,
 
-		search: String,
-	},
-}
+		/// spread the block files over 256 subdirectories, for dumps of so many blocks that a
+		/// single directory of them gets slow (only for a new store; one made so keeps it)
+		#[structopt(long)]
+		subdirs: bool,
 
-fn main() -> Result<()> {
-	color_eyre::install()?;
+		/// zstd compression level: higher compresses better but slower (decompression speed
+		/// doesn't depend on it)
+		#[structopt(long, default_value = "3")]
+		level: i32,
 
-	let args = Args::from_args();
+		/// compress with zstd's long-distance matching, which finds repeats further back
+		#[structopt(long)]
+		long: bool,
 
-	stderrlog::new()
-		.verbosity(match args.log_level {
-			log::Level::Error => 0,
-			log::Level::Warn => 1,
-			log::Level::Info => 2,
-			log::Level::Debug => 3,
-			log::Level::Trace => 4,
-		})
-		.timestamp(stderrlog::Timestamp::Millisecond)
-		.show_module_names(true)
-		.module("wikt")
-		.init()?;
+		/// window log of long-distance matching: it looks back 2^N bytes [default: 27]
+		#[structopt(long, requires = "long")]
+		window_log: Option<u32>,
 
-	match args.action {
-		Action::Store(StoreAction::Make { dump }) => {
-			let mut store = blockstore::Store::new(args.store_dir);
-			store.create()?;
+		/// entries per block: bigger blocks compress better, smaller ones are quicker to read whole
+		#[structopt(long, default_value = "10000")]
+		block_size: usize,
 
-			let dump = File::open(dump)?;
-			let xml = xml::EventReader::new(dump);
+		/// only store pages in these namespaces (comma-separated numbers, e.g. `0,10`); pages
+		/// without a namespace are always stored
+		#[structopt(
+			long,
+			use_delimiter = true,
+			require_delimiter = true,
+			default_value = "0"
+		)]
+		namespaces: Vec<i32>,
 
-			let mut n = 0;
-			let mut current = Page::None;
-			let mut block = blockstore::Block::default();
+		/// carry on from where an interrupted make of this store stopped (with the same dump and
+		/// options)
+		#[structopt(long)]
+		resume: bool,
 
-			for event in xml {
-				let event = event?;
-
-				current = Page::parse(current, event);
-				if let Page::Texted {
-					ref title,
-					ref text,
-				} = current {
-					let entry = blockstore::Entry::new(title, text);
-					block.add(entry)?;
+		/// when resuming, train a new dictionary on the next block and compress the rest with it
+		/// (e.g. when the rest of the dump is unlike its start)
+		#[structopt(long, requires = "resume")]
+		new_dictionary: bool,
 
-					n += 1;
-					print!("\x1b[2K\x1b[0G{}", n);
-					if n % 10000 == 0 {
-						println!(": commit");
-						store.commit(&mut block, n)?;
-					}
-				}
-			}
+		/// leave out pages whose title was stored already, keeping the first: `exact` keeps a hash
+		/// of every title (about 16 bytes each), `bloom` a filter of fixed size which drops about
+		/// one in a thousand unique pages too
+		#[structopt(long)]
+		dedup: Option<DedupMode>,
 
-			println!(": commit");
-			store.commit(&mut block, n)?;
-			println!("{}! done.", n);
-		}
+		/// titles the `--dedup bloom` filter is sized for (about 2 bytes each): past that, more
+		/// unique pages are dropped
+		#[structopt(long, default_value = "10000000")]
+		dedup_capacity: usize,
 
-		Action::Store(StoreAction::Get { refid }) => {
-			let mut store = blockstore::Store::new(args.store_dir);
-			store.open()?;
-			let entry = store.read_entry(refid)?.open();
-			println!("{}\n\n{}", entry.0, entry.1);
-		}
+		/// only store this language's sections of pages, by heading (e.g. `French`; repeatable),
+		/// leaving out pages with none of them
+		#[structopt(long, number_of_values = 1)]
+		only_lang: Vec<String>,
 
-		Action::Store(StoreAction::Query { searches, count }) => {
-			use rayon::prelude::*;
-			use std::sync::Arc;
+		/// with --only-lang, still store pages without any language section (whole)
+		#[structopt(long, requires = "only-lang")]
+		keep_no_lang: bool,
 
-			let mut store = blockstore::Store::new(args.store_dir);
-			store.open()?;
+		/// leave out pages with no text, or only whitespace (after --only-lang, if given)
+		#[structopt(long)]
+		skip_empty: bool,
 
-			let blocks = store.blocks()?;
-			let filtered = blocks
-				.par_iter()
-				.flat_map(|path| {
-					let block = store.read_block(path).expect("error reading block");
-					let block = Arc::new(block);
-					(0..block.n).into_par_iter().map(move |n| {
-						let block = block.clone();
-						block.entry(n).expect("error parsing entry").open()
-					})
-				})
-				.filter(move |(_, text, _)| {
-					searches.iter().all(|search| {
-						if search.starts_with('~') {
-							!text.contains(search)
-						} else {
-							text.contains(search)
-						}
-					})
-				});
+		/// skip pages with malformed XML (logging each) and carry on from the next one, instead
+		/// of stopping
+		#[structopt(long)]
+		skip_errors: bool,
 
-			if count {
-				println!("{}", filtered.count());
-			} else {
-				filtered.for_each(|(title, _, id)| println!("{}: {}", id, title));
-			}
-		}
+		/// threads compressing blocks while the dump is read (0 for one per CPU)
+		#[structopt(long, default_value = "0")]
+		threads: usize,
 
-		Action::Index(IndexAction::Make { force, limited }) => {
-			if args.index_dir.exists() {
-				if force {
-					remove_dir_all(&args.index_dir)?;
-				} else {
-					return Err(eyre!(
-						"index already exists, refusing to clobber without --force"
-					));
-				}
-			}
+		/// don't write anything, only read the whole dump and report what's in it: pages per
+		/// namespace, the largest ones, and anomalies (fails if there are no pages to store)
+		#[structopt(long, conflicts_with = "resume")]
+		dry_run: bool,
+	},
 
-			create_dir_all(&args.index_dir)?;
+	/// train the compression dictionary of a new store on a sample from across the whole dump
+	///
+	/// By default, the dictionary is trained on the first block only. Run this before `make` to
+	/// use one trained on entries sampled from the whole dump instead.
+	TrainDict {
+		/// the dump, or `-` to read it from stdin
+		dump: PathBuf,
 
-			let dir = MmapDirectory::open(args.index_dir)?;
-			let schema = schema();
-			let index = Index::open_or_create(dir, schema.clone())?;
-			let mut index_writer = index.writer(100_000_000)?;
+		/// how many entries to train on
+		#[structopt(long, default_value = "10000")]
+		samples: usize,
 
-			use rayon::prelude::*;
-			use std::sync::Arc;
+		/// zstd compression level, to compare the dictionaries at (use the same for `make`)
+		#[structopt(long, default_value = "3")]
+		level: i32,
 
-			let mut store = blockstore::Store::new(args.store_dir);
-			store.open()?;
+		/// only sample pages in these namespaces (as for `make`)
+		#[structopt(
+			long,
+			use_delimiter = true,
+			require_delimiter = true,
+			default_value = "0"
+		)]
+		namespaces: Vec<i32>,
 
-			let mut blocks = store.blocks()?;
-			if limited > 0 {
-				blocks.truncate(limited);
-			}
+		/// seed for picking the samples
+		#[structopt(long, default_value = "1")]
+		seed: u64,
+	},
 
-			let entries = blocks.par_iter().flat_map(|path| {
-				let block = store.read_block(path).expect("error reading block");
-				let block = Arc::new(block);
-				(0..block.n).into_par_iter().map(move |n| {
-					let block = block.clone();
-					block.entry(n).expect("error parsing entry").open()
-				})
-			});
+	/// mark an entry as deleted, so it's left out of reads until `compact` drops it for good
+	Delete { refid: Ref },
 
-			let s_title = schema.get_field("title").unwrap();
-			let s_text = schema.get_field("text").unwrap();
-			let s_ref = schema.get_field("ref").unwrap();
-			let s_lang = schema.get_field("lang").unwrap();
-			let s_gram = schema.get_field("gram").unwrap();
+	/// replace the text of an entry with the contents of a file
+	///
+	/// The entry keeps its ref, title, and revision, and becomes a redirect (or stops being one)
+	/// if its new text says so. Indexes still have the old text until they're made again.
+	Update { refid: Ref, body_file: PathBuf },
 
-			let n = Arc::new(AtomicUsize::new(0));
+	/// print an entry, given its ref as `block/entry` or as a token
+	Get {
+		refid: Ref,
 
-			info!("populating the index");
-			entries.for_each(|(title, text, store_ref)| {
-				let mut docs = Vec::with_capacity(10);
-
-				for (name, text) in split_by_section(&LANG_RX, &text).into_iter() {
-					debug!("[{}] lang={:?} section: {:?}", &store_ref, &name, &text);
-					docs.push(doc!(
-						s_title => title.as_str(),
-						s_text => text.as_str(),
-						s_ref => store_ref.as_u64(),
-						s_lang => name.as_str(),
-					));
+		/// show a redirect entry itself instead of the entry it redirects to
+		#[structopt(long)]
+		no_follow: bool,
 
-					let lang = name;
-					for (name, text) in split_by_section(&GRAM_RX, &text).into_iter() {
-						debug!(
-							"[{}] lang={:?} gram={:?} section: {:?}",
-							&store_ref, &lang, &name, &text
-						);
-						docs.push(doc!(
-							s_title => title.as_str(),
-							s_text => text.as_str(),
-							s_ref => store_ref.as_u64(),
-							s_lang => lang.as_str(),
-							s_gram => name.as_str(),
-						));
-					}
-				}
+		/// also print the ref of the entry as a token, for URLs
+		#[structopt(long)]
+		token: bool,
+	},
 
-				if docs.is_empty() {
-					docs.push(doc!(
-						s_title => title.as_str(),
-						s_text => text.as_str(),
-						s_ref => store_ref.as_u64(),
-					));
-				}
+	/// find entries by their exact title, using the store's title map
+	///
+	/// Prints every entry with that title (a dump may have several), as `get` does.
+	GetByTitle {
+		title: String,
 
-				for doc in docs {
-					debug!("[{}] store document {:?}", &store_ref, doc);
-					index_writer.add_document(doc);
-				}
+		/// only print the refs of the entries
+		#[structopt(long)]
+		refs: bool,
 
-				let sofar = n.fetch_add(1, Ordering::Relaxed);
-				if sofar % 10000 == 0 {
-					info!("indexed {}k entries so far", sofar / 1000);
-				}
-			});
+		/// show redirect entries themselves instead of the entries they redirect to
+		#[structopt(long)]
+		no_follow: bool,
+	},
 
-			info!("indexed {} entries", n.load(Ordering::Relaxed));
-			info!("committing the index");
-			index_writer.commit()?;
-			info!(
-				"index has {} documents",
-				index
-					.load_metas()?
-					.segments
-					.into_iter()
-					.map(|m| m.num_docs())
-					.sum::<u32>()
-			);
-		}
+	/// print every language and part-of-speech section of an entry, e.g. all the senses of the
+	/// headword of an index hit
+	Sections {
+		refid: Ref,
 
-		Action::Index(IndexAction::Query {
-			search,
-			limit,
-			titles,
-			full,
-		}) => {
-			let mut store = blockstore::Store::new(args.store_dir);
-			store.open()?;
+		/// show a redirect entry itself instead of the entry it redirects to
+		#[structopt(long)]
+		no_follow: bool,
 
-			let index = Index::open_in_dir(args.index_dir)?;
-			let reader = index.reader()?;
-			let searcher = reader.searcher();
+		/// how to print sections: text, json (an object with all of them), jsonl (one per line), or
+		/// tsv (a row per section)
+		#[structopt(long, default_value = "text")]
+		format: OutputFormat,
+	},
 
-			let schema = schema();
-			let s_text = schema.get_field("text").unwrap();
+	/// list titles starting with a prefix, with their refs, using the store's title map
+	///
+	/// Titles come out in order (by bytes, so case-sensitively), fast enough for a typeahead.
+	Complete {
+		prefix: String,
 
-			let query_parser = QueryParser::for_index(&index, vec![s_text]);
-			let query = query_parser.parse_query(&search)?;
+		/// at most this many titles (0 for all of them)
+		#[structopt(short = "n", long, default_value = "20")]
+		limit: usize,
+	},
 
-			let top_docs: Vec<(Score, DocAddress)> =
-				searcher.search(&query, &TopDocs::with_limit(limit))?;
-			for (score, doc_address) in top_docs {
-				let retrieved_doc = searcher.doc(doc_address)?;
-				let nameddoc = schema.to_named_doc(&retrieved_doc).0;
+	/// show the siteinfo of the dump the store was made from
+	Info,
 
-				let rid = Ref::from_u64(nameddoc.get("ref").unwrap()[0].u64_value().unwrap());
-				let lang = nameddoc.get("lang").and_then(|f| f[0].text());
-				let gram = nameddoc.get("gram").and_then(|f| f[0].text());
+	/// pack the store into a single file
+	///
+	/// The packed file can then be used as a (read-only) store by giving its path as the store dir.
+	Pack { output: PathBuf },
 
-				if titles {
-					let title = nameddoc.get("title").unwrap()[0].text().unwrap();
+	/// write every entry as a line of JSON (`{"ref":"b/e","title":...,"body":...}`)
+	///
+	/// Blocks are read in parallel and their entries written as they're done, so entries come out
+	/// in no particular order unless `--sorted` is given.
+	Export {
+		/// write to this file instead of stdout
+		#[structopt(long)]
+		output: Option<PathBuf>,
 
-					println!(
-						"\x1b[2mscore={} [{}] ({}/{}) \x1b[0m\x1b[1m{}\x1b[0m",
-						score,
+		/// write entries in ref order (a little slower)
+		#[structopt(long)]
+		sorted: bool,
+
+		/// read what's left of truncated or corrupt blocks instead of failing
+		#[structopt(long)]
+		skip_bad_blocks: bool,
+
+		#[structopt(flatten)]
+		revisions: RevisionFilter,
+	},
+
+	/// print all entries of a block
+	Cat {
+		block_id: u32,
+
+		/// print whole entries instead of an excerpt
+		#[structopt(long)]
+		full: bool,
+
+		/// length of excerpts, in characters (they end at a word, so may be a little shorter)
+		#[structopt(long, default_value = "80")]
+		excerpt_len: usize,
+	},
+
+	/// print how a block file is laid out: its header, and where each entry is and how long its
+	/// parts are, one per line
+	///
+	/// For looking into the format or a corruption: entries that can't be read say why, and the
+	/// others are still shown.
+	Dump {
+		block_id: u32,
+
+		/// also print the decompressed entries as a hexdump
+		#[structopt(long)]
+		hex: bool,
+	},
+
+	/// print the entries just before and after one, in ref order (that of the dump)
+	Around {
+		refid: Ref,
+
+		/// how many entries to print on each side
+		#[structopt(short = "n", long, default_value = "5")]
+		n: usize,
+
+		/// print whole entries instead of an excerpt
+		#[structopt(long)]
+		full: bool,
+
+		/// length of excerpts, in characters (they end at a word, so may be a little shorter)
+		#[structopt(long, default_value = "80")]
+		excerpt_len: usize,
+	},
+
+	/// rewrite blocks made by older versions of wikt in the current block layout
+	///
+	/// Also builds the title map of stores made before there was one, and makes the manifest
+	/// again from the block files.
+	Migrate,
+
+	/// rewrite every block at another compression level, or with a newly trained dictionary
+	///
+	/// Refs stay the same, so indexes made on the store still work.
+	Recompress {
+		/// zstd compression level to rewrite blocks at (by default, the store's)
+		#[structopt(long)]
+		level: Option<i32>,
+
+		/// train a new dictionary first, on entries sampled from across all blocks
+		#[structopt(long)]
+		retrain: bool,
+
+		/// how many entries to train on [default: 10000]
+		#[structopt(long, requires = "retrain")]
+		samples: Option<usize>,
+
+		/// seed for picking the samples
+		#[structopt(long, default_value = "1")]
+		seed: u64,
+	},
+
+	/// check every block against its checksum and that all its entries can be read
+	///
+	/// Also checks that the manifest lists exactly the block files there are.
+	Verify,
+
+	/// show how many blocks and entries the store has, and how big they are
+	Stats {
+		/// how many of the largest entries to list
+		#[structopt(long, default_value = "10")]
+		top: usize,
+
+		/// print the stats as JSON
+		#[structopt(long)]
+		json: bool,
+	},
+
+	/// check whether a title may be in the store, using the title bloom filter
+	HasTitle { title: String },
+
+	/// print how many entries the store has, from its manifest (or the header of each block)
+	Count,
+
+	/// merge runs of small blocks into full ones, and drop deleted entries
+	///
+	/// This changes the refs of moved entries, so indexes must be rebuilt afterwards. The old to
+	/// new mapping is written to `remap.tsv` in the store (`-` for dropped entries).
+	Compact {
+		/// blocks with fewer entries than this are merged
+		#[structopt(long, default_value = "10000")]
+		block_size: usize,
+	},
+
+	/// append the entries of another store to this one
+	///
+	/// Its blocks are recompressed with this store's dictionary and numbered after this store's
+	/// last block, so refs here don't change. The old to new refs of the merged entries are
+	/// written to `remap.tsv` in the store. Its redirects and titles are merged too.
+	Merge {
+		/// the store to merge in (left as it is)
+		other: PathBuf,
+	},
+
+	/// search the text of every entry (see the README for how searches combine)
+	Query {
+		/// substrings (or regexes) to match: all of them, or `|x` for either this or the one
+		/// before, `~x` for not this, and `(` ... `)` to group
+		searches: Vec<String>,
+
+		/// match entries where any of the searches (or groups) matches, rather than all
+		#[structopt(long)]
+		any: bool,
+
+		#[structopt(long)]
+		count: bool,
+
+		/// only search within this language's section
+		#[structopt(long)]
+		lang: Option<String>,
+
+		/// only search within this part-of-speech's section
+		#[structopt(long)]
+		gram: Option<String>,
+
+		/// read what's left of truncated or corrupt blocks instead of failing
+		#[structopt(long)]
+		skip_bad_blocks: bool,
+
+		/// stop at the first block or entry that can't be read, instead of skipping it
+		#[structopt(long)]
+		strict: bool,
+
+		/// print the paths of blocks with at least one matching entry instead of the entries
+		#[structopt(long, alias = "files-with-matches")]
+		blocks: bool,
+
+		/// stop after N matches (0 disables)
+		#[structopt(short = "n", long, default_value = "0")]
+		limit: usize,
+
+		/// match each search as a regular expression instead of literally
+		#[structopt(long)]
+		regex: bool,
+
+		/// match regardless of case (slower without --regex, as each entry is lowercased)
+		#[structopt(short = "i", long)]
+		ignore_case: bool,
+
+		/// match searches only as whole words (`king` isn't in `kingdom`), which skips the blocks
+		/// whose word filter doesn't have them (see `store make --word-filters`)
+		#[structopt(short = "w", long, conflicts_with = "regex")]
+		words: bool,
+
+		#[structopt(flatten)]
+		revisions: RevisionFilter,
+	},
+}
+
+#[derive(StructOpt, Debug, Clone)]
+enum IndexAction {
+	Make {
+		#[structopt(long)]
+		force: bool,
+
+		/// only index N blocks (0 disables)
+		#[structopt(short = "n", long, default_value = "0")]
+		limited: usize,
+
+		/// decode HTML entities (e.g. `&nbsp;`) in the indexed text
+		#[structopt(long)]
+		unescape: bool,
+
+		/// strip wikitext markup (templates, links, bold...) from the indexed text
+		#[structopt(long)]
+		clean: bool,
+
+		/// don't write anything, only report what would be indexed
+		#[structopt(long)]
+		dry_run: bool,
+
+		/// JSON object of section names to canonical names (e.g. `{"pronunciations":
+		/// "pronunciation"}`), applied to lang and gram values
+		#[structopt(long)]
+		section_map: Option<PathBuf>,
+
+		/// file of words (one per line) to leave out of the indexed text
+		#[structopt(long)]
+		stopwords: Option<PathBuf>,
+
+		/// how to split the text into words: default, or stemmed in a language (e.g. en_stem)
+		#[structopt(long)]
+		tokenizer: Option<TextTokenizer>,
+
+		/// also keep the text of each section in the index, so queries show hits without reading
+		/// the store (the index gets about as big as the text)
+		#[structopt(long)]
+		store_text: bool,
+
+		/// read what's left of truncated or corrupt blocks instead of failing
+		#[structopt(long)]
+		skip_bad_blocks: bool,
+
+		/// stop at the first block or entry that can't be read, instead of skipping it
+		#[structopt(long)]
+		strict: bool,
+
+		/// also make documents of the subsections of parts of speech (`====Synonyms====`)
+		#[structopt(long)]
+		subsections: bool,
+
+		/// find the headings of a level with a regex instead, as `lang=REGEX`, `gram=REGEX`, or
+		/// `sub=REGEX`, its first group capturing the name (can be repeated, once per level)
+		#[structopt(long, number_of_values = 1)]
+		section_regex: Vec<String>,
+
+		/// guess the language of entries without any language section
+		#[structopt(long)]
+		detect_lang: bool,
+
+		/// minimum confidence (0 to 1) of a guessed language for it to be used
+		#[structopt(long, default_value = "0.5")]
+		min_confidence: f64,
+
+		/// give entries without any language section (whose language isn't guessed) this lang,
+		/// e.g. `und`, so `--lang` and facets can find them
+		#[structopt(long)]
+		default_lang: Option<String>,
+
+		/// add the blocks made since the index was last built to it, instead of building it anew
+		#[structopt(long, conflicts_with = "force")]
+		append: bool,
+
+		/// memory for documents being indexed, in bytes, shared by the writer threads; when it's
+		/// full they're written out as a segment
+		#[structopt(long, default_value = "100000000")]
+		writer_mem: usize,
+
+		/// threads writing the index, or each shard (0 for one per CPU, up to 8, shared between
+		/// shards, as many as --writer-mem allows)
+		#[structopt(long, default_value = "0")]
+		writer_threads: usize,
+
+		/// split the index into this many shards of its own, each of some of the blocks, which
+		/// `index query` searches in parallel; --writer-mem is split between them, and each gets
+		/// --writer-threads (appending keeps the index's)
+		#[structopt(long)]
+		shards: Option<usize>,
+	},
+
+	Query {
+		#[structopt(flatten)]
+		options: QueryOptions,
+
+		/// keep up to N decompressed blocks in memory, for hits from the same few blocks (0
+		/// disables, reading only the entry of each hit)
+		#[structopt(long, default_value = "0")]
+		cache_blocks: usize,
+
+		/// read what's left of truncated or corrupt blocks instead of failing
+		#[structopt(long)]
+		skip_bad_blocks: bool,
+
+		/// show the text of redirect entries themselves instead of the entries they redirect to
+		#[structopt(long)]
+		no_follow: bool,
+
+		search: String,
+	},
+
+	/// check that every ref in the index still points at the same entry in the store
+	Verify,
+
+	/// list terms by how often they occur across the corpus
+	///
+	/// Only documents for whole language sections (and entries without any) are counted, so each
+	/// word of the corpus is counted once. The document frequency is thus the number of language
+	/// sections the term appears in.
+	Frequencies {
+		/// field to count terms of (text or title)
+		#[structopt(long, default_value = "text")]
+		field: String,
+
+		/// number of terms to list (0 for all)
+		#[structopt(short = "n", long, default_value = "100")]
+		limit: usize,
+
+		/// only count within this language (a single word, as it's a term of the lang field)
+		#[structopt(long)]
+		lang: Option<String>,
+	},
+
+	/// show how many documents each block and entry made, to find entries split into too many
+	Stats {
+		/// how many of the entries with the most documents to list
+		#[structopt(long, default_value = "10")]
+		top: usize,
+
+		/// also list the entries and documents of every block
+		#[structopt(long)]
+		per_block: bool,
+
+		/// print the stats as JSON
+		#[structopt(long)]
+		json: bool,
+	},
+}
+
+/// How index queries are run and their hits printed, which the REPL can change between queries.
+#[derive(StructOpt, Debug, Clone)]
+struct QueryOptions {
+	#[structopt(short = "n", long, default_value = "20")]
+	limit: usize,
+
+	/// skip this many hits first, to page through them (e.g. `--offset 20` for the second page)
+	#[structopt(long, default_value = "0")]
+	offset: usize,
+
+	// return only titles (ie don't read the store)
+	#[structopt(long)]
+	titles: bool,
+
+	// return full entries instead of truncating
+	#[structopt(long)]
+	full: bool,
+
+	/// strip wikitext markup (templates, links, bold...) from the text of hits
+	#[structopt(long)]
+	clean: bool,
+
+	/// print the numbered definitions of hits, stripped of markup, instead of their text (which
+	/// is printed as usual for hits without any)
+	#[structopt(long)]
+	definitions: bool,
+
+	/// list every occurrence of the words searched for in the text of each hit, with its offsets
+	/// in characters and the text around it, instead of an excerpt
+	#[structopt(long, conflicts_with_all = &["titles", "definitions"])]
+	concordance: bool,
+
+	/// characters of text shown on each side of an occurrence, with --concordance
+	#[structopt(long, default_value = "40")]
+	context: usize,
+
+	/// show refs as tokens of letters and digits instead of `block/entry`, e.g. for URLs (JSON
+	/// hits get a `token` as well)
+	#[structopt(long)]
+	token: bool,
+
+	// print the score breakdown of each hit
+	#[structopt(long)]
+	explain: bool,
+
+	// order results by relevance (score), alphabetically (title), or by store position (ref)
+	#[structopt(long, alias = "sort-by", default_value = "score")]
+	sort: SortOrder,
+
+	/// print hits as they're found, in no particular order and with their raw scores, rather than
+	/// the best ones once all are (ignores --sort; `-n 0` prints all of them)
+	#[structopt(long)]
+	stream: bool,
+
+	// how to combine the words of the search: any of them, all of them, or all of them in
+	// order (phrase, which ignores query syntax)
+	#[structopt(long = "match", default_value = "any")]
+	mode: MatchMode,
+
+	/// match words up to N letters added, removed, changed, or swapped away, up to 2, and
+	/// require all of them (0 disables; much slower)
+	#[structopt(long, default_value = "0")]
+	fuzzy: u8,
+
+	/// with --match phrase, also match the words with up to N others between them in all (still
+	/// in order), scoring those further apart lower
+	#[structopt(long, default_value = "0")]
+	slop: usize,
+
+	/// leave out hits scoring under this
+	#[structopt(long, default_value = "0")]
+	min_score: Score,
+
+	/// leave out hits scoring under this fraction of the best one (e.g. 0.5 for half), as scores
+	/// only compare within a search
+	#[structopt(long, default_value = "0")]
+	min_score_ratio: Score,
+
+	/// stop searching after this many seconds, and show the best of the hits found by then (0
+	/// searches until done)
+	#[structopt(long, default_value = "0")]
+	timeout: f64,
+
+	/// print hits as text, a JSON array (json), a JSON object per line (jsonl), or a row of
+	/// tab-separated score, ref, lang, gram, title, and text per line (tsv)
+	#[structopt(long, default_value = "text")]
+	format: OutputFormat,
+
+	/// with --format tsv, leave out the header row
+	#[structopt(long)]
+	no_header: bool,
+
+	/// when nothing is found, suggest words in the index close to those of the search
+	#[structopt(long)]
+	suggest: bool,
+
+	/// fields searched by words without a `field:` prefix (comma-separated)
+	#[structopt(
+		long,
+		use_delimiter = true,
+		require_delimiter = true,
+		default_value = "title,text"
+	)]
+	fields: Vec<String>,
+
+	/// only return hits in this language's sections (repeatable, hits in any of them are
+	/// returned)
+	#[structopt(long, number_of_values = 1)]
+	lang: Vec<String>,
+
+	/// only return hits in this part-of-speech's sections (repeatable, like --lang)
+	#[structopt(long, number_of_values = 1)]
+	gram: Vec<String>,
+
+	/// only return hits in subsections with this name, like synonyms (repeatable, like --lang;
+	/// needs an index made with --subsections)
+	#[structopt(long, number_of_values = 1)]
+	sub: Vec<String>,
+
+	/// length in characters of the excerpt of each hit's text, around the words that matched
+	#[structopt(long, default_value = "80")]
+	snippet_len: usize,
+
+	/// also count all the hits per language or part of speech (lang, gram; comma-separated), and
+	/// with `-n 0`, only that
+	#[structopt(long, use_delimiter = true, require_delimiter = true)]
+	facets: Vec<FacetKind>,
+
+	/// multiply the score of matches in a field, as `field=N` (comma-separated)
+	#[structopt(
+		long,
+		use_delimiter = true,
+		require_delimiter = true,
+		default_value = "title=2"
+	)]
+	boost: Vec<FieldBoost>,
+}
+
+fn main() -> Result<()> {
+	color_eyre::install()?;
+
+	let mut args = Args::from_args();
+	if args.no_color || (args.output.is_some() && args.color == ColorChoice::Auto) {
+		args.color = ColorChoice::Never;
+	}
+
+	stderrlog::new()
+		.verbosity(match args.log_level {
+			log::Level::Error => 0,
+			log::Level::Warn => 1,
+			log::Level::Info => 2,
+			log::Level::Debug => 3,
+			log::Level::Trace => 4,
+		})
+		.timestamp(stderrlog::Timestamp::Millisecond)
+		.show_module_names(true)
+		.module("wikt")
+		.init()?;
+
+	args.resolve_dataset()?;
+	let mut out = output::open(args.output.as_deref())?;
+	match args.action {
+		Action::Store(StoreAction::Make {
+			dumps,
+			limit,
+			bloom,
+			word_filters,
+			subdirs,
+			level,
+			long,
+			window_log,
+			block_size,
+			namespaces,
+			resume,
+			new_dictionary,
+			dedup,
+			dedup_capacity,
+			only_lang,
+			keep_no_lang,
+			skip_empty,
+			skip_errors,
+			threads,
+			dry_run,
+		}) => {
+			if block_size == 0 {
+				return Err(eyre!("block size must be at least 1"));
+			}
+
+			// all are looked at first, rather than failing on one after hours on the others
+			let stdin = Path::new(xmldump::STDIN);
+			if dumps.iter().filter(|dump| *dump == stdin).count() > 1 {
+				return Err(eyre!(
+					"stdin can only be read once, so only one dump can be `-`"
+				));
+			}
+			let mut dumps_len = 0;
+			for dump in &dumps {
+				if dump != stdin {
+					let len = dump
+						.metadata()
+						.map_err(|err| eyre!("can't read dump {:?}: {}", dump, err))?
+						.len();
+					dumps_len += len;
+				}
+			}
+			// how big stdin is isn't known, so neither is how big they all are
+			if dumps.iter().any(|dump| dump == stdin) {
+				dumps_len = 0;
+			}
+
+			let threads = if threads == 0 {
+				std::thread::available_parallelism().map_or(1, usize::from)
+			} else {
+				threads
+			};
+			let mut store = blockstore::Store::new(args.store_dir)
+				.with_level(level)?
+				.with_long(if long {
+					Some(window_log.unwrap_or(27))
+				} else {
+					None
+				})?
+				.with_writers(threads)
+				.with_new_dictionary(new_dictionary)
+				.with_word_filters(word_filters)
+				.with_subdirs(subdirs);
+			if dry_run {
+				info!("dry run: nothing will be written");
+			} else {
+				store.create()?;
+			}
+
+			let resume_from = if resume {
+				store.resume()?
+			} else if dry_run {
+				0
+			} else {
+				if store.use_trained_dictionary()? {
+					info!("using the dictionary trained with train-dict");
+				}
+				0
+			};
+			if resume_from % block_size != 0 {
+				return Err(eyre!(
+					"store has {} entries, which isn't a whole number of blocks of {} (finished already, or made with another --block-size?)",
+					resume_from,
+					block_size
+				));
+			} else if resume_from > 0 {
+				info!(
+					"resuming after {} pages, at level {}{}",
+					resume_from,
+					store.level(),
+					if new_dictionary {
+						", with a new dictionary"
+					} else {
+						""
+					}
+				);
+			}
+
+			let mut dumps = dumps.iter();
+			let mut dump = dumps.next().unwrap();
+			let (reader, mut position) = xmldump::open_tracked(dump)?;
+			let mut xml = xmlreader::Reader::new(reader);
+			// the pages stored from each dump, and the bytes of the dumps read before this one
+			let mut per_dump = Vec::new();
+			let (mut dump_start, mut read_before) = (0, 0);
+
+			// with a limit, the whole dump won't be read, so go by pages instead
+			let progress = if limit > 0 {
+				Progress::new("pages", u64::try_from(limit)?)
+			} else {
+				Progress::new("pages", dumps_len)
+			};
+
+			let start = Instant::now();
+			let mut stored_bytes = 0;
+			let mut n = 0;
+			let mut site = Site::None;
+			let mut anomalies = BTreeMap::new();
+			let mut skipped = BTreeMap::new();
+			let mut dedup = dedup.map(|mode| match mode {
+				DedupMode::Exact => TitleDedup::exact(),
+				DedupMode::Bloom => TitleDedup::bloom(dedup_capacity),
+			});
+			let mut duplicates = 0_usize;
+			let only_lang: Vec<String> = only_lang.iter().map(|lang| lang.to_lowercase()).collect();
+			let (mut other_langs, mut no_lang) = (0_usize, 0_usize);
+			let mut empty = 0_usize;
+			let mut titles = TitleRefsBuilder::default();
+			let mut redirects = RedirectsBuilder::default();
+			let mut current = Page::None;
+			let mut block = blockstore::Block::default();
+			// with --dry-run, pages per namespace (before any are left out), and the largest
+			let mut per_namespace = BTreeMap::<Option<i32>, usize>::new();
+			let mut largest = BinaryHeap::<Reverse<(usize, String)>>::new();
+			let mut redirect_pages = 0_usize;
+
+			let mut broken = 0_usize;
+			loop {
+				let event = match xml.next() {
+					Some(Ok(event)) => Some(event),
+					Some(Err(err)) if skip_errors => {
+						warn!("skipping a page after {} pages: {}", n, err);
+						broken += 1;
+						current = Page::None;
+						if xml.resume_at("page")? {
+							continue;
+						}
+						None
+					}
+					Some(Err(err)) => return Err(err),
+					None => None,
+				};
+
+				// at the end of a dump, on to the next one
+				let event = match event {
+					Some(event) => event,
+					None => {
+						per_dump.push((dump, n - dump_start));
+						dump = match dumps.next() {
+							Some(next) => next,
+							None => break,
+						};
+						debug!("reading the next dump, {:?}", dump);
+						read_before += position.len();
+						let (reader, next_position) = xmldump::open_tracked(dump)?;
+						xml = xmlreader::Reader::new(reader);
+						position = next_position;
+						current = Page::None;
+						dump_start = n;
+						continue;
+					}
+				};
+
+				if !matches!(site, Site::Done(_)) {
+					site = Site::parse(site, &event);
+					if let Site::Done(ref info) = site {
+						debug!("siteinfo: {:?}", info);
+						if !dry_run {
+							store.write_siteinfo(info)?;
+						}
+					}
+				}
+
+				let (page, warning) = Page::parse(current, event);
+				current = page;
+				if let Some(warning) = warning {
+					warn!("anomaly after {} pages: {}", n, warning);
+					*anomalies.entry(warning).or_insert(0_usize) += 1;
+				}
+
+				let (title, meta, text, redirect) = match current {
+					Page::Texted {
+						ref title,
+						ref meta,
+						ref text,
+					} => (title, meta, Cow::Borrowed(text.as_str()), None),
+					Page::Redirect {
+						ref from,
+						ref meta,
+						ref to,
+					} => (
+						from,
+						meta,
+						Cow::Owned(format!("#REDIRECT [[{}]]", to)),
+						Some(to.as_str()),
+					),
+					_ => continue,
+				};
+
+				if dry_run {
+					*per_namespace.entry(meta.ns).or_insert(0) += 1;
+				}
+				if let Some(ns) = meta.ns {
+					if !namespaces.contains(&ns) {
+						*skipped.entry(ns).or_insert(0_usize) += 1;
+						continue;
+					}
+				}
+
+				// redirects have no sections, and are kept to point at what's left
+				let text = if only_lang.is_empty() || redirect.is_some() {
+					text
+				} else {
+					match keep_sections(&text, LANG_LEVEL, &only_lang) {
+						None => {
+							no_lang += 1;
+							if !keep_no_lang {
+								continue;
+							}
+							text
+						}
+						Some(kept) if kept.is_empty() => {
+							other_langs += 1;
+							continue;
+						}
+						Some(kept) => Cow::Owned(kept),
+					}
+				};
+
+				if skip_empty && text.trim().is_empty() {
+					debug!("empty page after {} pages: {}", n, title);
+					empty += 1;
+					continue;
+				}
+
+				// before `n` counts the page, so a resume drops the same ones
+				if let Some(ref mut dedup) = dedup {
+					if dedup.seen(title) {
+						debug!("duplicate title after {} pages: {}", n, title);
+						duplicates += 1;
+						continue;
+					}
+				}
+
+				if dry_run {
+					if redirect.is_some() {
+						redirect_pages += 1;
+					} else {
+						largest.push(Reverse((text.len(), title.clone())));
+						if largest.len() > DRY_RUN_LARGEST {
+							largest.pop();
+						}
+					}
+					stored_bytes += text.len();
+				} else if n >= resume_from {
+					// pages already in the store are only replayed into what's kept until the end
+					let entry =
+						blockstore::Entry::new(title, &text).with_revision(meta.id, meta.timestamp);
+					block.add(entry)?;
+					stored_bytes += text.len();
+				}
+				redirects.add(title, redirect);
+				titles.add(title);
+
+				n += 1;
+				let done = if limit > 0 {
+					n
+				} else {
+					usize::try_from(read_before + position.read())?
+				};
+				progress.update(u64::try_from(done)?, u64::try_from(n)?);
+				if n % block_size == 0 && !dry_run {
+					if block.n > 0 {
+						debug!("committing block {}", n);
+						store.commit(&mut block, n)?;
+					}
+					redirects.commit(u32::try_from(n)?);
+					titles.commit(u32::try_from(n)?);
+				}
+
+				if limit > 0 && n >= limit {
+					per_dump.push((dump, n - dump_start));
+					break;
+				}
+			}
+
+			progress.finish();
+			if per_dump.len() > 1 {
+				for (dump, pages) in &per_dump {
+					info!("{} pages from {:?}", pages, dump);
+				}
+			}
+			if dry_run {
+				// the main namespace's name is empty
+				let namespace_name = |ns: i32| match site {
+					Site::Done(ref info) => info.namespaces.get(&ns).map(|ns| match &*ns.name {
+						"" => "main".to_string(),
+						name => name.to_string(),
+					}),
+					_ => None,
+				};
+				writeln!(out, "pages: {}", n)?;
+				if per_dump.len() > 1 {
+					writeln!(out, "pages per dump:")?;
+					for (dump, pages) in &per_dump {
+						writeln!(out, "\t{}\t{}", pages, dump.display())?;
+					}
+				}
+				writeln!(out, "redirects: {}", redirect_pages)?;
+				writeln!(out, "text: {}", human_bytes(u64::try_from(stored_bytes)?))?;
+				writeln!(out, "pages per namespace:")?;
+				for (ns, count) in &per_namespace {
+					match ns {
+						Some(ns) => writeln!(
+							out,
+							"\t{}\t{} ({}){}",
+							count,
+							ns,
+							namespace_name(*ns).unwrap_or_else(|| "?".into()),
+							if namespaces.contains(ns) {
+								""
+							} else {
+								", left out"
+							}
+						)?,
+						None => writeln!(out, "\t{}\tnone", count)?,
+					}
+				}
+				writeln!(out, "largest pages:")?;
+				for Reverse((len, title)) in largest.into_sorted_vec() {
+					writeln!(out, "\t{}\t{}", human_bytes(u64::try_from(len)?), title)?;
+				}
+				writeln!(out, "anomalies:")?;
+				for (warning, count) in &anomalies {
+					writeln!(out, "\t{}\t{}", count, warning)?;
+				}
+				if broken > 0 {
+					writeln!(out, "\t{}\tmalformed XML", broken)?;
+				}
+
+				if n == 0 {
+					return Err(eyre!("no pages to store in the dump"));
+				}
+				return Ok(());
+			}
+
+			if block.n > 0 {
+				debug!("committing block {}", n);
+				store.commit(&mut block, n)?;
+				redirects.commit(u32::try_from(n)?);
+				titles.commit(u32::try_from(n)?);
+			}
+			store.flush()?;
+
+			let elapsed = start.elapsed().as_secs_f64();
+			let stored = n.saturating_sub(resume_from);
+			info!(
+				"stored {} pages ({} of text) in {:.1}s: {:.0} pages/s, {}/s, compressing on {} threads",
+				stored,
+				human_bytes(u64::try_from(stored_bytes)?),
+				elapsed,
+				stored as f64 / elapsed,
+				human_bytes((stored_bytes as f64 / elapsed) as u64),
+				threads
+			);
+
+			let (redirects, missing) = redirects.finish();
+			store.write_redirects(&redirects)?;
+			for to in &missing {
+				debug!("redirect to missing page: {}", to);
+			}
+			if !missing.is_empty() {
+				warn!(
+					"{} redirects point to pages not in the store",
+					missing.len()
+				);
+			}
+
+			if bloom {
+				info!("building title bloom filter");
+				store.write_title_bloom(&TitleBloom::build(titles.len(), titles.titles()))?;
+			}
+
+			info!("writing title map");
+			store.write_title_refs(&titles.finish())?;
+
+			for (warning, count) in anomalies {
+				warn!("{} anomalies: {}", count, warning);
+			}
+			if broken > 0 {
+				warn!("skipped {} pages with malformed XML (see above)", broken);
+			}
+
+			for (ns, count) in skipped {
+				let name = match site {
+					Site::Done(ref info) => info.namespaces.get(&ns).map(|ns| ns.name.as_str()),
+					_ => None,
+				};
+				info!(
+					"skipped {} pages in namespace {} ({})",
+					count,
+					ns,
+					name.unwrap_or("?")
+				);
+			}
+
+			if !only_lang.is_empty() {
+				info!(
+					"left out {} pages without a section in {}",
+					other_langs,
+					only_lang.join(", ")
+				);
+				info!(
+					"{} {} pages without any language section",
+					if keep_no_lang { "kept" } else { "left out" },
+					no_lang
+				);
+			}
+
+			if skip_empty {
+				info!("left out {} pages with no text", empty);
+			}
+
+			if let Some(dedup) = dedup {
+				info!(
+					"dropped {} pages with a title stored already{}",
+					duplicates,
+					if dedup.is_approximate() {
+						" (some may be unique pages the bloom filter took for duplicates)"
+					} else {
+						""
+					}
+				);
+			}
+		}
+
+		Action::Store(StoreAction::TrainDict {
+			dump,
+			samples,
+			level,
+			namespaces,
+			seed,
+		}) => {
+			if samples < 2 {
+				return Err(eyre!("need at least 2 samples"));
+			}
+
+			let mut store = blockstore::Store::new(args.store_dir).with_level(level)?;
+			store.create()?;
+			if !store.blocks()?.is_empty() {
+				return Err(eyre!(
+					"store has blocks already, which need the dictionary they were made with"
+				));
+			}
+
+			let xml = xmlreader::Reader::new(xmldump::open(&dump)?);
+			let mut random = xorshift(seed);
+
+			// twice as many as trained on, half of them held out to compare the dictionaries
+			let mut reservoir: Vec<Vec<u8>> = Vec::with_capacity(samples * 2);
+			let mut first = Vec::with_capacity(samples);
+			let mut seen = 0;
+			let mut current = Page::None;
+			for event in xml {
+				let (page, _) = Page::parse(current, event?);
+				current = page;
+
+				let entry = match current {
+					Page::Texted {
+						ref title,
+						ref meta,
+						ref text,
+					} if meta.ns.is_none_or(|ns| namespaces.contains(&ns)) => {
+						blockstore::Entry::new(title, text).to_bytes()?
+					}
+					_ => continue,
+				};
+
+				seen += 1;
+				if seen % 10000 == 0 {
+					info!("sampled from {}k pages so far", seen / 1000);
+				}
+
+				if first.len() < samples {
+					first.push(entry.clone());
+				}
+
+				reservoir_sample(&mut reservoir, samples * 2, seen, entry, &mut random);
+			}
+
+			let (train, held_out): (Vec<_>, Vec<_>) = reservoir
+				.into_iter()
+				.enumerate()
+				.partition(|(n, _)| n % 2 == 0);
+			let train: Vec<Vec<u8>> = train.into_iter().map(|(_, e)| e).collect();
+			let held_out: Vec<Vec<u8>> = held_out.into_iter().map(|(_, e)| e).collect();
+			info!(
+				"training on {} entries sampled from {} pages",
+				train.len(),
+				seen
+			);
+
+			let train_dict = |entries: &[Vec<u8>]| {
+				let sizes: Vec<usize> = entries.iter().map(Vec::len).collect();
+				blockstore::train_dictionary(&entries.concat(), &sizes)
+			};
+			let sampled = train_dict(&train);
+			let from_first = train_dict(&first);
+
+			let raw: usize = held_out.iter().map(Vec::len).sum();
+			let sampled_len = blockstore::compressed_len(&sampled, level, &held_out)?;
+			let first_len = blockstore::compressed_len(&from_first, level, &held_out)?;
+			info!(
+				"{} held out entries ({}) compress to {} (ratio {:.2}), against {} (ratio {:.2}) with a dictionary from the first {} entries: {:.1}% smaller",
+				held_out.len(),
+				human_bytes(u64::try_from(raw)?),
+				human_bytes(u64::try_from(sampled_len)?),
+				raw as f64 / sampled_len.max(1) as f64,
+				human_bytes(u64::try_from(first_len)?),
+				raw as f64 / first_len.max(1) as f64,
+				first.len(),
+				100.0 * (1.0 - sampled_len as f64 / first_len.max(1) as f64)
+			);
+
+			store.set_dictionary(&sampled)?;
+			info!("dictionary written, now run `store make` on the same store");
+		}
+
+		Action::Store(StoreAction::Delete { refid }) => {
+			let mut store = blockstore::Store::new(args.store_dir);
+			store.open()?;
+
+			if store.delete(refid)? {
+				info!(
+					"deleted [{}], {} entries to drop at the next compact",
+					refid,
+					store.deleted().count()
+				);
+			} else {
+				info!("[{}] was already deleted", refid);
+			}
+		}
+
+		Action::Store(StoreAction::Update { refid, body_file }) => {
+			let mut store = blockstore::Store::new(args.store_dir);
+			store.open()?;
+
+			let body = std::fs::read_to_string(&body_file)
+				.map_err(|err| eyre!("can't read {:?}: {}", body_file, err))?;
+			store.update(refid, &body)?;
+
+			info!("updated [{}] from {:?}", refid, body_file);
+			warn!("the index still has the old text: make it again with `index make --force`");
+		}
+
+		Action::Store(StoreAction::Get {
+			refid,
+			no_follow,
+			token,
+		}) => {
+			let mut store = blockstore::Store::new(args.store_dir).with_mmap(args.mmap);
+			store.open()?;
+
+			let redirects = if no_follow {
+				Redirects::default()
+			} else {
+				read_redirects(&store)
+			};
+			if token {
+				writeln!(out, "token: {}", redirects.resolve(refid).to_token())?;
+			}
+			print_entry(&mut out, &store, &redirects, refid)?;
+		}
+
+		Action::Store(StoreAction::GetByTitle {
+			title,
+			refs,
+			no_follow,
+		}) => {
+			let mut store = blockstore::Store::new(args.store_dir).with_mmap(args.mmap);
+			store.open()?;
+
+			let mut found = store.read_title_refs()?.get(&title)?;
+			found.retain(|refid| !store.is_deleted(*refid));
+			if found.is_empty() {
+				return Err(eyre!("no entry titled {:?}", title));
+			}
+
+			if refs {
+				for refid in found {
+					writeln!(out, "{}", refid)?;
+				}
+				return Ok(());
+			}
+
+			let redirects = if no_follow {
+				Redirects::default()
+			} else {
+				read_redirects(&store)
+			};
+			for (i, refid) in found.into_iter().enumerate() {
+				if i > 0 {
+					writeln!(out, "\n---\n")?;
+				}
+				print_entry(&mut out, &store, &redirects, refid)?;
+			}
+		}
+
+		Action::Store(StoreAction::Sections {
+			refid,
+			no_follow,
+			format,
+		}) => {
+			let mut store = blockstore::Store::new(args.store_dir).with_mmap(args.mmap);
+			store.open()?;
+
+			let to = if no_follow {
+				refid
+			} else {
+				read_redirects(&store).resolve(refid)
+			};
+			if to != refid {
+				info!("[{}] redirects to [{}]", refid, to);
+			}
+
+			let (title, text, _) = store.read_entry(to)?.open();
+			let sections = entry_sections(&text);
+			let section_json = |(lang, gram, text): &(Option<String>, Option<String>, String)| serde_json::json!({ "lang": lang, "gram": gram, "text": text });
+			match format {
+				OutputFormat::Text => {
+					writeln!(out, "[{}] {}", to, title)?;
+					for (lang, gram, text) in &sections {
+						writeln!(
+							out,
+							"\n({}/{})",
+							lang.as_deref().unwrap_or("?"),
+							gram.as_deref().unwrap_or("?")
+						)?;
+						for line in text.lines() {
+							writeln!(out, "\t{}", line)?;
+						}
+					}
+				}
+				OutputFormat::Json => {
+					let entry = serde_json::json!({
+						"ref": to.to_string(),
+						"title": title,
+						"sections": sections.iter().map(section_json).collect::<Vec<_>>(),
+					});
+					writeln!(out, "{}", serde_json::to_string_pretty(&entry)?)?;
+				}
+				OutputFormat::Jsonl => {
+					for section in &sections {
+						let mut line = section_json(section);
+						line["ref"] = to.to_string().into();
+						line["title"] = title.as_str().into();
+						writeln!(out, "{}", line)?;
+					}
+				}
+				OutputFormat::Tsv => {
+					writeln!(out, "ref\ttitle\tlang\tgram\ttext")?;
+					for (lang, gram, text) in &sections {
+						writeln!(
+							out,
+							"{}\t{}\t{}\t{}\t{}",
+							to,
+							tsv_field(&title),
+							tsv_field(lang.as_deref().unwrap_or("")),
+							tsv_field(gram.as_deref().unwrap_or("")),
+							tsv_field(text)
+						)?;
+					}
+				}
+			}
+		}
+
+		Action::Store(StoreAction::Complete { prefix, limit }) => {
+			let mut store = blockstore::Store::new(args.store_dir);
+			store.open()?;
+			let titles = store.read_title_refs()?;
+
+			let start = Instant::now();
+			let limit = if limit == 0 { usize::MAX } else { limit };
+			let completions = titles
+				.starting_with(&prefix)
+				.filter(|title| !matches!(title, Ok((_, refid)) if store.is_deleted(*refid)))
+				.take(limit)
+				.collect::<Result<Vec<_>>>()?;
+			debug!(
+				"found {} titles starting with {:?} in {:?}",
+				completions.len(),
+				prefix,
+				start.elapsed()
+			);
+
+			for (title, refid) in completions {
+				writeln!(out, "{}: {}", refid, title)?;
+			}
+		}
+
+		Action::Store(StoreAction::Info) => {
+			let mut store = blockstore::Store::new(args.store_dir);
+			store.open()?;
+			let info = store.read_siteinfo()?;
+			let unknown = String::from("?");
+			writeln!(
+				out,
+				"sitename:  {}",
+				info.sitename.as_ref().unwrap_or(&unknown)
+			)?;
+			writeln!(
+				out,
+				"dbname:    {}",
+				info.dbname.as_ref().unwrap_or(&unknown)
+			)?;
+			writeln!(out, "base:      {}", info.base.as_ref().unwrap_or(&unknown))?;
+			writeln!(
+				out,
+				"generator: {}",
+				info.generator.as_ref().unwrap_or(&unknown)
+			)?;
+			writeln!(out, "case:      {}", info.case.as_ref().unwrap_or(&unknown))?;
+			writeln!(out, "level:     {}", store.level())?;
+			match store.long() {
+				Some(log) => writeln!(out, "long:      window log {}", log)?,
+				None => writeln!(out, "long:      off")?,
+			}
+			writeln!(out, "dicts:     {}", store.dictionaries())?;
+			writeln!(out, "namespaces:")?;
+			for (key, ns) in &info.namespaces {
+				writeln!(
+					out,
+					"\t{:>4} {} ({})",
+					key,
+					if ns.name.is_empty() {
+						"(main)"
+					} else {
+						&ns.name
+					},
+					ns.case.as_ref().unwrap_or(&unknown)
+				)?;
+			}
+		}
+
+		Action::Store(StoreAction::Pack { output }) => {
+			let store = blockstore::Store::new(args.store_dir);
+			store.pack(&output)?;
+			info!("packed store into {:?}", output);
+		}
+
+		Action::Store(StoreAction::Export {
+			output,
+			sorted,
+			skip_bad_blocks,
+			revisions,
+		}) => {
+			use rayon::prelude::*;
+
+			let mut store = blockstore::Store::new(args.store_dir)
+				.with_mmap(args.mmap)
+				.lenient(skip_bad_blocks);
+			store.open()?;
+
+			let out: Box<dyn Write + Send + '_> = match &output {
+				Some(path) => Box::new(File::create(path)?),
+				None => Box::new(&mut out),
+			};
+			let out = Mutex::new(BufWriter::new(out));
+
+			#[derive(Serialize)]
+			struct ExportedEntry<'a> {
+				#[serde(rename = "ref")]
+				rid: String,
+				title: &'a str,
+				body: &'a str,
+			}
+
+			// each block is written whole, so at most a block per thread is held in memory
+			let lines = |path: &PathBuf| -> Result<(Vec<u8>, usize)> {
+				let mut lines = Vec::new();
+				let mut n = 0;
+				for entry in store.block_entries_where(path, |entry| revisions.keeps(entry)) {
+					let (title, body, rid) = entry?;
+					serde_json::to_writer(
+						&mut lines,
+						&ExportedEntry {
+							rid: rid.to_string(),
+							title: &title,
+							body: &body,
+						},
+					)?;
+					lines.push(b'\n');
+					n += 1;
+				}
+				Ok((lines, n))
+			};
+
+			let blocks = store.blocks_by_id()?;
+			let exported = AtomicUsize::new(0);
+			interrupt::catch();
+			if sorted {
+				let mut out = out.lock().unwrap();
+				in_order(
+					blocks.len(),
+					|i| lines(&blocks[i].1),
+					|(lines, n)| {
+						out.write_all(&lines)?;
+						exported.fetch_add(n, Ordering::Relaxed);
+						Ok(())
+					},
+				)?;
+			} else {
+				blocks.par_iter().try_for_each(|(_, path)| -> Result<()> {
+					if interrupt::interrupted() {
+						return Ok(());
+					}
+					let (lines, n) = lines(path)?;
+					out.lock().unwrap().write_all(&lines)?;
+					exported.fetch_add(n, Ordering::Relaxed);
+					Ok(())
+				})?;
+			}
+
+			out.into_inner().unwrap().flush()?;
+			if interrupt::interrupted() {
+				return Err(eyre!(
+					"interrupted after exporting {} entries, from some of the {} blocks",
+					exported.into_inner(),
+					blocks.len()
+				));
+			}
+			info!(
+				"exported {} entries from {} blocks",
+				exported.into_inner(),
+				blocks.len()
+			);
+		}
+
+		Action::Store(StoreAction::Cat {
+			block_id,
+			full,
+			excerpt_len,
+		}) => {
+			let mut store = blockstore::Store::new(args.store_dir).with_mmap(args.mmap);
+			store.open()?;
+
+			let style = Style::new(args.color);
+			let block = store.read_block(store.block_path(block_id))?;
+			for n in 0..block.n {
+				let (title, text, rid) = block.entry(n)?.open();
+				if store.is_deleted(rid) {
+					continue;
+				}
+				let text = if full {
+					text
+				} else {
+					excerpt(&text, excerpt_len)
+				};
+				writeln!(out, "{}: {}\n\t{}", style.dim(rid), style.bold(title), text)?;
+			}
+		}
+
+		Action::Store(StoreAction::Dump { block_id, hex }) => {
+			let mut store = blockstore::Store::new(args.store_dir).with_mmap(args.mmap);
+			store.open()?;
+
+			let layout = store.inspect_block(block_id)?;
+			writeln!(
+				out,
+				"block={} version={} entries={} dictionary={} size={} frames_start={} checksum={:08x} actual={}",
+				layout.id,
+				layout.version,
+				layout.entries.len(),
+				layout.dictionary,
+				layout.len,
+				layout.frames_start,
+				layout.checksum,
+				layout
+					.actual_checksum
+					.map_or_else(|| String::from("unknown"), |sum| format!("{:08x}", sum))
+			)?;
+
+			for (n, entry) in (0..).zip(&layout.entries) {
+				let refid = blockstore::Ref::new(block_id, n);
+				write!(
+					out,
+					"entry={} frame={}..{} data={}..{}",
+					refid, entry.frame.start, entry.frame.end, entry.data.start, entry.data.end
+				)?;
+				match entry.entry {
+					Ok(ref e) => {
+						let (page_id, timestamp) = e.revision();
+						write!(
+							out,
+							" title_len={} body_len={} page_id={} timestamp={}",
+							e.title_len,
+							e.body_len,
+							page_id.unwrap_or_default(),
+							timestamp.unwrap_or_default()
+						)?;
+					}
+					Err(ref err) => write!(out, " error={:?}", err.to_string())?,
+				}
+				if store.is_deleted(refid) {
+					write!(out, " deleted")?;
+				}
+				if let Ok(ref e) = entry.entry {
+					write!(out, " title={:?}", e.title())?;
+				}
+				writeln!(out)?;
+			}
+
+			if hex {
+				hexdump(&mut out, &layout.data)?;
+			}
+		}
+
+		Action::Store(StoreAction::Around {
+			refid,
+			n,
+			full,
+			excerpt_len,
+		}) => {
+			let mut store = blockstore::Store::new(args.store_dir).with_mmap(args.mmap);
+			store.open()?;
+
+			let style = Style::new(args.color);
+			let (before, after) = store.neighbors(refid, n)?;
+			let entry = store.read_entry(refid)?;
+			let entries = before.into_iter().chain(Some(entry)).chain(after);
+			for entry in entries {
+				let (title, text, rid) = entry.open();
+				let text = if full {
+					text
+				} else {
+					excerpt(&text, excerpt_len)
+				};
+				if rid == refid {
+					writeln!(
+						out,
+						"> {}: {}\n\t{}",
+						style.dim(rid),
+						style.bold(title),
+						text
+					)?;
+				} else {
+					writeln!(out, "  {}: {}\n\t{}", style.dim(rid), title, text)?;
+				}
+			}
+		}
+
+		Action::Store(StoreAction::Count) => {
+			use rayon::prelude::*;
+
+			let mut store = blockstore::Store::new(args.store_dir).with_mmap(args.mmap);
+			store.open()?;
+
+			let start = Instant::now();
+			let blocks = store.blocks()?;
+			let entries = match store.manifest() {
+				Some(manifest) => manifest.entries(),
+				// packs have no manifest
+				None => blocks
+					.par_iter()
+					.map(|path| {
+						// blocks from before version 3 have no header, so they have to be read whole
+						let n = match store.entry_count(path) {
+							Ok(n) => n,
+							Err(_) => store.read_block(path)?.n,
+						};
+						Ok(u64::from(n))
+					})
+					.sum::<Result<u64>>()?,
+			};
+			let deleted = u64::try_from(store.deleted().count())?;
+			debug!(
+				"counted {} entries ({} deleted) in {} blocks in {:?}",
+				entries,
+				deleted,
+				blocks.len(),
+				start.elapsed()
+			);
+
+			writeln!(out, "{}", entries - deleted)?;
+		}
+
+		Action::Store(StoreAction::HasTitle { title }) => {
+			let mut store = blockstore::Store::new(args.store_dir);
+			store.open()?;
+			let bloom = store.read_title_bloom()?;
+			if bloom.check(&title) {
+				writeln!(out, "maybe present")?;
+			} else {
+				writeln!(out, "definitely absent")?;
+			}
+		}
+
+		Action::Store(StoreAction::Recompress {
+			level,
+			retrain,
+			samples,
+			seed,
+		}) => {
+			let mut store = blockstore::Store::new(args.store_dir);
+			store.open()?;
+			let level = level.unwrap_or_else(|| store.level());
+
+			let dict = if retrain {
+				let samples = samples.unwrap_or(10000);
+				if samples < 2 {
+					return Err(eyre!("need at least 2 samples"));
+				}
+
+				let mut random = xorshift(seed);
+				let mut reservoir: Vec<Vec<u8>> = Vec::with_capacity(samples);
+				let mut seen = 0;
+				for entry in store.entries()? {
+					let (title, body, _) = entry?;
+					seen += 1;
+					let entry = blockstore::Entry::new(&title, &body).to_bytes()?;
+					reservoir_sample(&mut reservoir, samples, seen, entry, &mut random);
+				}
+				info!(
+					"training on {} entries sampled from {}",
+					reservoir.len(),
+					seen
+				);
+
+				let sizes: Vec<usize> = reservoir.iter().map(Vec::len).collect();
+				Some(blockstore::train_dictionary(&reservoir.concat(), &sizes))
+			} else {
+				None
+			};
+
+			let blocks = store.blocks()?.len();
+			info!("recompressing {} blocks at level {}", blocks, level);
+			let (before, after) = store.recompress(level, dict.as_deref())?;
+			info!(
+				"recompressed {} blocks: {} before, {} after ({:+.1}%)",
+				blocks,
+				human_bytes(before),
+				human_bytes(after),
+				100.0 * (after as f64 / before.max(1) as f64 - 1.0)
+			);
+		}
+
+		Action::Store(StoreAction::Migrate) => {
+			let mut store = blockstore::Store::new(args.store_dir);
+			store.open()?;
+
+			let migrated = store.migrate()?;
+			if migrated == 0 {
+				info!(
+					"all blocks are already version {}",
+					blockstore::BLOCK_VERSION
+				);
+			} else {
+				info!(
+					"migrated {} blocks to version {}",
+					migrated,
+					blockstore::BLOCK_VERSION
+				);
+			}
+
+			if store.read_title_refs().is_err() {
+				info!("building the title map");
+				let titles = store
+					.entries()?
+					.map(|entry| entry.map(|(title, _, refid)| (title, refid)))
+					.collect::<Result<Vec<_>, _>>()?;
+				store.write_title_refs(&TitleRefs::build(titles))?;
+			}
+
+			if store.read_redirects().is_err() {
+				info!("finding redirects");
+				let titles = store.read_title_refs()?;
+				let mut redirects = Redirects::default();
+				for entry in store.entries()? {
+					let (_, body, refid) = entry?;
+					if let Some(target) = xmldump::redirect_target(&body) {
+						let to = titles
+							.get(&target)?
+							.into_iter()
+							.find(|to| !store.is_deleted(*to));
+						redirects.set(refid, to);
+					}
+				}
+				store.write_redirects(&redirects)?;
+			}
+
+			let blocks = store.rebuild_manifest()?;
+			info!("made the manifest of {} blocks", blocks);
+		}
+
+		Action::Store(StoreAction::Verify) => {
+			use rayon::prelude::*;
+
+			let mut store = blockstore::Store::new(args.store_dir).with_mmap(args.mmap);
+			store.open()?;
+
+			let mismatches = store.check_manifest()?;
+			for problem in &mismatches {
+				writeln!(out, "manifest: {}", problem)?;
+			}
+
+			let blocks = store.blocks_by_id()?;
+			info!("checking {} blocks", blocks.len());
+
+			let corrupt: Vec<(u32, WiktError)> = blocks
+				.par_iter()
+				.filter_map(|(id, path)| {
+					store
+						.read_block(path)
+						.and_then(|block| (0..block.n).try_for_each(|n| block.entry(n).map(drop)))
+						.err()
+						.map(|err| (*id, err))
+				})
+				.collect();
+
+			for (id, err) in &corrupt {
+				writeln!(out, "{}: {}", id, err)?;
+			}
+
+			if corrupt.is_empty() && mismatches.is_empty() {
+				info!("all {} blocks are intact", blocks.len());
+			} else if corrupt.is_empty() {
+				return Err(eyre!(
+					"the manifest doesn't match the block files ({} problems), run store migrate to make it again",
+					mismatches.len()
+				));
+			} else {
+				return Err(eyre!(
+					"{} of {} blocks are corrupt: {}",
+					corrupt.len(),
+					blocks.len(),
+					corrupt
+						.iter()
+						.map(|(id, _)| id.to_string())
+						.collect::<Vec<_>>()
+						.join(", ")
+				));
+			}
+		}
+
+		Action::Store(StoreAction::Stats { top, json }) => {
+			use rayon::prelude::*;
+
+			let mut store = blockstore::Store::new(args.store_dir).with_mmap(args.mmap);
+			store.open()?;
+
+			let blocks = store.blocks()?;
+			info!("reading {} blocks", blocks.len());
+
+			// per block: size on disk, size uncompressed, and the body length of each entry
+			let per_block = blocks
+				.par_iter()
+				.map(|path| {
+					let block = store.read_block(path)?;
+					let bodies = (0..block.n)
+						.map(|n| block.entry(n).map(|e| (e.body_len, e.store_ref)))
+						.collect::<Result<Vec<_>, _>>()?;
+					Ok((
+						store.block_len(path)?,
+						u64::try_from(block.data.len())?,
+						bodies,
+					))
+				})
+				.collect::<Result<Vec<_>>>()?;
+
+			let on_disk: u64 = per_block.iter().map(|(size, _, _)| size).sum();
+			let uncompressed: u64 = per_block.iter().map(|(_, size, _)| size).sum();
+			let mut bodies: Vec<(u32, Ref)> = per_block
+				.into_iter()
+				.flat_map(|(_, _, bodies)| bodies)
+				.collect();
+			bodies.sort_unstable_by_key(|(len, _)| *len);
+
+			let entries = bodies.len();
+			let average = if entries == 0 {
+				0.0
+			} else {
+				bodies.iter().map(|(len, _)| f64::from(*len)).sum::<f64>() / entries as f64
+			};
+			let percentile = |p: f64| {
+				let at = ((entries.saturating_sub(1)) as f64 * p).round() as usize;
+				bodies.get(at).map_or(0, |(len, _)| *len)
+			};
+			let percentiles = [
+				("p50", percentile(0.5)),
+				("p90", percentile(0.9)),
+				("p99", percentile(0.99)),
+				("max", percentile(1.0)),
+			];
+
+			let largest = bodies
+				.iter()
+				.rev()
+				.take(top)
+				.map(|(len, rid)| Ok((*len, *rid, store.read_entry(*rid)?.open().0)))
+				.collect::<Result<Vec<_>>>()?;
+
+			if json {
+				let report = serde_json::json!({
+					"blocks": blocks.len(),
+					"entries": entries,
+					"bytes_on_disk": on_disk,
+					"bytes_uncompressed": uncompressed,
+					"body_len": {
+						"avg": average,
+						"p50": percentiles[0].1,
+						"p90": percentiles[1].1,
+						"p99": percentiles[2].1,
+						"max": percentiles[3].1,
+					},
+					"largest": largest.iter().map(|(len, rid, title)| serde_json::json!({
+						"ref": rid.to_string(),
+						"title": title,
+						"body_len": len,
+					})).collect::<Vec<_>>(),
+				});
+				writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+				return Ok(());
+			}
+
+			writeln!(out, "blocks:       {}", blocks.len())?;
+			writeln!(out, "entries:      {}", entries)?;
+			writeln!(
+				out,
+				"per block:    {:.0} entries",
+				entries as f64 / blocks.len().max(1) as f64
+			)?;
+			writeln!(out, "on disk:      {}", human_bytes(on_disk))?;
+			writeln!(
+				out,
+				"uncompressed: {} ({:.1}× the size on disk)",
+				human_bytes(uncompressed),
+				uncompressed as f64 / on_disk.max(1) as f64
+			)?;
+			writeln!(
+				out,
+				"body length:  avg {:.0} {}",
+				average,
+				percentiles
+					.iter()
+					.map(|(name, len)| format!("{} {}", name, len))
+					.collect::<Vec<_>>()
+					.join(" ")
+			)?;
+			if !largest.is_empty() {
+				writeln!(out, "largest entries:")?;
+				for (len, rid, title) in largest {
+					writeln!(
+						out,
+						"\t[{}] {} ({})",
 						rid,
-						lang.unwrap_or("?"),
-						gram.unwrap_or("?"),
 						title,
+						human_bytes(u64::from(len))
+					)?;
+				}
+			}
+		}
+
+		Action::Store(StoreAction::Compact { block_size }) => {
+			let mut store = blockstore::Store::new(args.store_dir);
+			store.open()?;
+
+			let remap = store.compact(block_size)?;
+			if remap.is_empty() {
+				info!("nothing to compact");
+				return Ok(());
+			}
+
+			let path = write_remap(&store, &remap)?;
+			let dropped = remap.iter().filter(|(_, new)| new.is_none()).count();
+			info!(
+				"moved {} entries and dropped {} deleted ones, remap written to {:?}",
+				remap.len() - dropped,
+				dropped,
+				path
+			);
+			warn!("refs have changed: rebuild the index with `index make --force`");
+		}
+
+		Action::Store(StoreAction::Merge { other }) => {
+			let mut store = blockstore::Store::new(args.store_dir);
+			store.open()?;
+			let mut other = blockstore::Store::new(other);
+			other.open()?;
+			if store.dir.canonicalize()? == other.dir.canonicalize()? {
+				return Err(eyre!("can't merge a store into itself"));
+			}
+
+			// read before merging, as stores without a title map are read whole for it
+			let mut titles = store_titles(&store)?;
+			let other_titles = store_titles(&other)?;
+
+			let remap = store.merge(&other)?;
+			let path = write_remap(&store, &remap)?;
+
+			if let (Ok(mut redirects), Ok(mut more)) =
+				(store.read_redirects(), other.read_redirects())
+			{
+				more.remap(&remap);
+				redirects.append(more);
+				store.write_redirects(&redirects)?;
+			}
+
+			let moved: HashMap<Ref, Ref> = remap
+				.iter()
+				.filter_map(|(old, new)| Some((*old, (*new)?)))
+				.collect();
+			titles.extend(
+				other_titles
+					.into_iter()
+					.filter_map(|(title, refid)| Some((title, *moved.get(&refid)?))),
+			);
+			if store.read_title_bloom().is_ok() {
+				store.write_title_bloom(&TitleBloom::build(
+					titles.len(),
+					titles.iter().map(|(title, _)| title.as_str()),
+				))?;
+			}
+			store.write_title_refs(&TitleRefs::build(titles))?;
+
+			info!(
+				"merged {} entries from {:?}, remap written to {:?}",
+				remap.len(),
+				other.dir,
+				path
+			);
+			warn!("indexes don't have the merged entries yet: add them with `index make --append`");
+		}
+
+		Action::Store(StoreAction::Query {
+			searches,
+			any,
+			count,
+			lang,
+			gram,
+			skip_bad_blocks,
+			strict,
+			blocks: blocks_only,
+			limit,
+			regex,
+			ignore_case,
+			words,
+			revisions,
+		}) => {
+			use rayon::prelude::*;
+
+			let mut store = blockstore::Store::new(args.store_dir)
+				.with_mmap(args.mmap)
+				.lenient(skip_bad_blocks);
+			store.open()?;
+
+			let searches = textsearch::parse(searches, any)?.try_map(&mut |search| {
+				let pattern = if regex {
+					Some(
+						RegexBuilder::new(&search)
+							.case_insensitive(ignore_case)
+							.build()
+							.map_err(|err| eyre!("bad regex {:?}: {}", search, err))?,
+					)
+				} else {
+					None
+				};
+				let search = if ignore_case {
+					search.to_lowercase()
+				} else {
+					search
+				};
+				Ok((search, pattern))
+			})?;
+
+			// blocks whose word filter doesn't have the words, which can't match
+			let filtered_searches = searches.clone();
+			let skipped = AtomicUsize::new(0);
+			let may_match = |path: &PathBuf| {
+				let filter = match store.word_filter(path) {
+					Some(filter) if words => filter,
+					_ => return true,
+				};
+				let may = filtered_searches.may_hold(&|(search, _)| filter.may_have(search));
+				if !may {
+					skipped.fetch_add(1, Ordering::Relaxed);
+				}
+				may
+			};
+			let report_skipped = |blocks: usize| {
+				if words {
+					info!(
+						"skipped {} of {} blocks without the words searched for",
+						skipped.load(Ordering::Relaxed),
+						blocks
+					);
+				}
+			};
+
+			let entry_matches = move |text: &str| {
+				let matches = |text: &str| {
+					// regexes ignore case themselves, literal searches need the text lowercased
+					let text = if ignore_case && !regex {
+						Cow::Owned(text.to_lowercase())
+					} else {
+						Cow::Borrowed(text)
+					};
+					searches.eval(&|(search, pattern)| match pattern {
+						Some(pattern) => pattern.is_match(&text),
+						None if words => wordfilter::contains_words(&text, search),
+						None => text.contains(search.as_str()),
+					})
+				};
+
+				if lang.is_none() && gram.is_none() {
+					return matches(text);
+				}
+
+				let langs = match lang {
+					None => vec![text.to_owned()],
+					Some(ref lang) => sections_named(LANG_LEVEL, text, lang),
+				};
+
+				langs.iter().any(|text| match gram {
+					None => matches(text),
+					Some(ref gram) => sections_named(GRAM_LEVEL, text, gram)
+						.iter()
+						.any(|text| matches(text)),
+				})
+			};
+
+			let unreadable = &Unreadable::new(strict);
+			let out = Mutex::new(&mut out);
+			let blocks = store.blocks()?;
+			let total_blocks = blocks.len();
+			// blocks aren't started once Ctrl-C is pressed, and what's found by then is printed
+			interrupt::catch();
+			let check_interrupted = || {
+				if interrupt::interrupted() {
+					return Err(eyre!(
+						"interrupted, so only some of the blocks were searched"
+					));
+				}
+				Ok(())
+			};
+			if blocks_only {
+				// a block is in as soon as one entry matches, so stop reading it there
+				let matching = blocks.into_par_iter().filter(|path| {
+					!interrupt::interrupted()
+						&& may_match(path) && store
+						.block_entries_where(path, |entry| revisions.keeps(entry))
+						.filter_map(|entry| unreadable.skip(entry))
+						.any(|(_, text, _)| entry_matches(&text))
+				});
+
+				let found = for_each_limited(matching, limit, |path| {
+					if !count {
+						writeln!(out.lock().unwrap(), "{}", path.display())?;
+					}
+					Ok(())
+				})?;
+				if count {
+					writeln!(out.lock().unwrap(), "{}", found)?;
+				}
+				report_skipped(total_blocks);
+				unreadable.report();
+				return check_interrupted();
+			}
+
+			let filtered = blocks
+				.into_par_iter()
+				.filter(|path| !interrupt::interrupted() && may_match(path))
+				.flat_map_iter(|path| {
+					store.block_entries_where(&path, |entry| revisions.keeps(entry))
+				})
+				.filter_map(|entry| unreadable.skip(entry))
+				.filter(|(_, text, _)| entry_matches(text));
+
+			let found = for_each_limited(filtered, limit, |(title, _, id)| {
+				if !count {
+					writeln!(out.lock().unwrap(), "{}: {}", id, title)?;
+				}
+				Ok(())
+			})?;
+			if count {
+				writeln!(out.lock().unwrap(), "{}", found)?;
+			}
+			report_skipped(total_blocks);
+			unreadable.report();
+			check_interrupted()?;
+		}
+
+		Action::Index(IndexAction::Make {
+			force,
+			limited,
+			unescape,
+			clean,
+			dry_run,
+			section_map,
+			stopwords,
+			tokenizer,
+			store_text,
+			skip_bad_blocks,
+			strict,
+			subsections,
+			section_regex,
+			detect_lang,
+			min_confidence,
+			default_lang,
+			append,
+			writer_mem,
+			writer_threads,
+			shards,
+		}) => {
+			let section_map = match section_map {
+				Some(path) => {
+					let map = read_section_map(&path)?;
+					info!("mapping {} section names from {:?}", map.len(), path);
+					for (from, to) in &map {
+						debug!("section map: {:?} => {:?}", from, to);
+					}
+					map
+				}
+				None => HashMap::new(),
+			};
+			// lowercased, as lang and gram are matched exactly
+			let canonical = |name: String| {
+				section_map
+					.get(&name)
+					.map(|to| to.to_lowercase())
+					.unwrap_or(name)
+			};
+
+			let headings = Headings::parse(&section_regex)?;
+			// lowercased as headings are, so it's searched for like them
+			let default_lang = default_lang.map(|lang| lang.trim().to_lowercase());
+
+			// the first shard's payload is committed last, so it's the one to go by
+			let existing = if append {
+				let indexes = open_shards(&args.index_dir)
+					.map_err(|err| eyre!("no index to append to: {}", err))?;
+				let indexed = IndexedBlocks::read(&indexes[0])?;
+				Some((indexes, indexed))
+			} else {
+				None
+			};
+
+			let shards = match (shards, &existing) {
+				(Some(0), _) => return Err(eyre!("--shards must be at least 1")),
+				(Some(shards), Some((indexes, _))) if shards != indexes.len() => {
+					return Err(eyre!(
+						"index has {} shards, not {}, rebuild it with --force to change that",
+						indexes.len(),
+						shards
+					));
+				}
+				(Some(shards), _) => shards,
+				(None, Some((indexes, _))) => indexes.len(),
+				(None, None) => 1,
+			};
+			let writer_threads = writer_threads_for(writer_mem / shards, writer_threads, shards)?;
+
+			// appending keeps the index's section regexes unless told otherwise
+			let headings = match &existing {
+				Some((_, indexed)) if section_regex.is_empty() => {
+					Headings::parse(&indexed.section_regexes)?
+				}
+				Some((_, indexed)) if indexed.section_regexes != section_regex => {
+					return Err(eyre!(
+						"index was made with other section regexes, rebuild it with --force"
+					));
+				}
+				_ => headings,
+			};
+
+			// appending keeps the index's stopwords unless told otherwise; indexes made before they
+			// were recorded have none on record, so take whatever is given for those
+			let stopwords = stopwords
+				.map(|path| {
+					let words = read_stopwords(&path)?;
+					info!("leaving out {} stopwords from {:?}", words.len(), path);
+					Ok::<_, Report>(words)
+				})
+				.transpose()?;
+			let stopwords = match (stopwords, &existing) {
+				(Some(words), Some((_, indexed)))
+					if !indexed.stopwords.is_empty() && words != indexed.stopwords =>
+				{
+					return Err(eyre!(
+						"index was made without other stopwords, rebuild it with --force"
+					));
+				}
+				(None, Some((_, indexed))) if !indexed.stopwords.is_empty() => {
+					info!(
+						"leaving out the {} stopwords of the index",
+						indexed.stopwords.len()
 					);
+					Some(indexed.stopwords.clone())
+				}
+				(stopwords, _) => stopwords,
+			};
+
+			// appending keeps the index's tokenizer unless told otherwise
+			let tokenizer = match (tokenizer, &existing) {
+				(Some(tokenizer), _) => tokenizer,
+				(None, Some((indexes, _))) => TextTokenizer::of(&indexes[0].schema())?,
+				(None, None) => TextTokenizer::Default,
+			};
+			let store_text = store_text
+				|| existing
+					.as_ref()
+					.is_some_and(|(indexes, _)| stores_text(&indexes[0].schema()));
+			let schema = schema(tokenizer, store_text);
+			if let Some((indexes, _)) = &existing {
+				if indexes.iter().any(|index| index.schema() != schema) {
+					return Err(eyre!(
+						"index was made with other fields or another tokenizer, rebuild it with --force"
+					));
+				}
+			}
+
+			let indexes = if dry_run {
+				info!("dry run: nothing will be written");
+				Vec::new()
+			} else if let Some((ref indexes, _)) = existing {
+				indexes.clone()
+			} else {
+				if args.index_dir.exists() {
+					if force {
+						remove_dir_all(&args.index_dir)?;
+					} else {
+						return Err(eyre!(
+							"index already exists, refusing to clobber without --force"
+						));
+					}
+				}
+
+				let dirs = if shards > 1 {
+					info!("splitting the index into {} shards", shards);
+					let index_dir = &args.index_dir;
+					(0..shards).map(|n| shard_dir(index_dir, n)).collect()
+				} else {
+					vec![args.index_dir.clone()]
+				};
+
+				dirs.iter()
+					.map(|path| {
+						create_dir_all(path)?;
+						let dir = MmapDirectory::open(path)?;
+						let index = Index::open_or_create(dir, schema.clone())?;
+						register_tokenizers(&index);
+						Ok(index)
+					})
+					.collect::<Result<Vec<_>>>()?
+			};
+
+			if !indexes.is_empty() {
+				if tokenizer != TextTokenizer::Default || stopwords.is_some() {
+					info!("indexing text with the {} tokenizer", tokenizer.name());
+					for index in &indexes {
+						index
+							.tokenizers()
+							.register(&tokenizer.name(), tokenizer.analyzer(stopwords.clone()));
+					}
+				}
+				info!(
+					"writing the index on {} threads with {} of memory{}",
+					writer_threads,
+					human_bytes((writer_mem / shards) as u64),
+					if shards > 1 { " per shard" } else { "" }
+				);
+			}
+			let index_writers = indexes
+				.iter()
+				.map(|index| index.writer_with_num_threads(writer_threads, writer_mem / shards))
+				.collect::<tantivy::Result<Vec<_>>>()?;
+
+			use rayon::prelude::*;
+			use std::sync::Arc;
+
+			let mut store = blockstore::Store::new(args.store_dir)
+				.with_mmap(args.mmap)
+				.lenient(skip_bad_blocks);
+			store.open()?;
+
+			// in id order, so the blocks indexed are always the oldest ones
+			let mut blocks = store.blocks_by_id()?;
+
+			// blocks go to shards in turn, by their place in the store, so appending carries on
+			let shard_of: HashMap<u32, usize> = blocks
+				.iter()
+				.enumerate()
+				.map(|(n, (id, _))| (*id, n % shards))
+				.collect();
+
+			let appending_after = existing.and_then(|(_, indexed)| indexed.last_block);
+			if let Some(last) = appending_after {
+				blocks.retain(|(id, _)| *id > last);
+				if blocks.is_empty() {
+					info!("index is up to date with the store (last block {})", last);
+					return Ok(());
+				}
+				info!("appending {} blocks after block {}", blocks.len(), last);
+			}
+
+			if limited > 0 {
+				blocks.truncate(limited);
+			}
+			let indexed = IndexedBlocks {
+				last_block: blocks.last().map(|(id, _)| *id).or(appending_after),
+				section_regexes: headings.specs(),
+				stopwords: stopwords.clone().unwrap_or_default(),
+			};
+			// block ids are running counts of entries, so they tell how many there are to index
+			let expected_entries = u64::from(
+				indexed
+					.last_block
+					.unwrap_or(0)
+					.saturating_sub(appending_after.unwrap_or(0)),
+			);
+
+			let unreadable = &Unreadable::new(strict);
+			let entries = store
+				.par_entries_of(blocks.into_iter().map(|(_, path)| path).collect())
+				.filter_map(|entry| unreadable.skip(entry));
+
+			let s_title = schema.get_field("title").unwrap();
+			let s_text = schema.get_field("text").unwrap();
+			let s_ref = schema.get_field("ref").unwrap();
+			let s_lang = schema.get_field("lang").unwrap();
+			let s_gram = schema.get_field("gram").unwrap();
+			let s_sub = schema.get_field("sub").unwrap();
+			let s_sortkey = schema.get_field("sortkey").unwrap();
+			let s_lang_confidence = schema.get_field("lang_confidence").unwrap();
+			let s_facets = schema.get_field("facets").unwrap();
+
+			let n = Arc::new(AtomicUsize::new(0));
+			let total_docs = AtomicUsize::new(0);
+			let fallbacks = AtomicUsize::new(0);
+			let detected = AtomicUsize::new(0);
+			let defaulted = AtomicUsize::new(0);
+			let docs_per_lang = Mutex::new(BTreeMap::<String, usize>::new());
+
+			info!("populating the index");
+			let progress = Progress::new("entries", expected_entries);
+			// each worker gathers documents for each shard, and hands them to its writer in batches
+			// rather than one at a time through the writer's queue
+			let no_batches = || -> Vec<Vec<UserOperation>> {
+				index_writers.iter().map(|_| Vec::new()).collect()
+			};
+			entries
+				.fold(no_batches, |mut batches, (title, text, store_ref)| {
+					let mut docs = Vec::with_capacity(10);
+					let sortkey = titles::sort_key(&title).into_bytes();
+					// cleaned first, so entities decoded to `<` or `[` aren't taken for markup
+					let text = if clean {
+						wikitext::clean_wikitext(&text)
+					} else {
+						text
+					};
+					let text = if unescape {
+						wikitext::unescape_html(&text)
+					} else {
+						text
+					};
+
+					for (name, text) in headings.split(&text, LANG_LEVEL) {
+						let name = canonical(name);
+						debug!("[{}] lang={:?} section: {:?}", &store_ref, &name, &text);
+						docs.push(doc!(
+							s_title => title.as_str(),
+							s_text => text.as_str(),
+							s_ref => store_ref.as_u64(),
+							s_lang => name.as_str(),
+							s_sortkey => sortkey.clone(),
+							s_facets => Facet::from_path(&["lang", &name]),
+						));
+
+						let lang = name;
+						for (name, text) in headings.split(&text, GRAM_LEVEL) {
+							let name = canonical(name);
+							debug!(
+								"[{}] lang={:?} gram={:?} section: {:?}",
+								&store_ref, &lang, &name, &text
+							);
+							docs.push(doc!(
+								s_title => title.as_str(),
+								s_text => text.as_str(),
+								s_ref => store_ref.as_u64(),
+								s_lang => lang.as_str(),
+								s_gram => name.as_str(),
+								s_sortkey => sortkey.clone(),
+								s_facets => Facet::from_path(&["lang", &lang]),
+								s_facets => Facet::from_path(&["gram", &name]),
+							));
+
+							if !subsections {
+								continue;
+							}
+
+							let gram = name;
+							for (name, text) in headings.split(&text, SUB_LEVEL) {
+								let name = canonical(name);
+								debug!(
+									"[{}] lang={:?} gram={:?} sub={:?} section: {:?}",
+									&store_ref, &lang, &gram, &name, &text
+								);
+								docs.push(doc!(
+									s_title => title.as_str(),
+									s_text => text.as_str(),
+									s_ref => store_ref.as_u64(),
+									s_lang => lang.as_str(),
+									s_gram => gram.as_str(),
+									s_sub => name.as_str(),
+									s_sortkey => sortkey.clone(),
+									s_facets => Facet::from_path(&["lang", &lang]),
+									s_facets => Facet::from_path(&["gram", &gram]),
+								));
+							}
+						}
+					}
+
+					if docs.is_empty() {
+						fallbacks.fetch_add(1, Ordering::Relaxed);
+						let mut doc = doc!(
+							s_title => title.as_str(),
+							s_text => text.as_str(),
+							s_ref => store_ref.as_u64(),
+							s_sortkey => sortkey,
+						);
+
+						if detect_lang {
+							match detect_language(&text) {
+								Some((name, confidence)) if confidence >= min_confidence => {
+									let name = canonical(name);
+									debug!(
+										"[{}] detected lang={:?} confidence={}",
+										&store_ref, &name, confidence
+									);
+									detected.fetch_add(1, Ordering::Relaxed);
+									doc.add_text(s_lang, &name);
+									doc.add_f64(s_lang_confidence, confidence);
+									doc.add_facet(s_facets, Facet::from_path(&["lang", &name]));
+								}
+								guess => {
+									trace!("[{}] no confident lang guess: {:?}", &store_ref, guess);
+								}
+							}
+						}
+						if let Some(ref lang) = default_lang {
+							if doc.get_first(s_lang).is_none() {
+								defaulted.fetch_add(1, Ordering::Relaxed);
+								doc.add_text(s_lang, lang);
+								doc.add_facet(s_facets, Facet::from_path(&["lang", lang]));
+							}
+						}
+
+						docs.push(doc);
+					}
+
+					total_docs.fetch_add(docs.len(), Ordering::Relaxed);
+					if !index_writers.is_empty() {
+						let shard = shard_of[&store_ref.block_id];
+						let batch = &mut batches[shard];
+						for doc in docs {
+							debug!("[{}] store document {:?}", &store_ref, doc);
+							batch.push(UserOperation::Add(doc));
+						}
+						if batch.len() >= INDEX_BATCH_DOCS {
+							index_writers[shard].run(std::mem::take(batch));
+						}
+					} else {
+						let mut per_lang = docs_per_lang.lock().unwrap();
+						for doc in docs {
+							let lang = doc.get_first(s_lang).and_then(|v| v.text()).unwrap_or("?");
+							*per_lang.entry(lang.to_owned()).or_insert(0) += 1;
+						}
+					}
+
+					let sofar = u64::try_from(n.fetch_add(1, Ordering::Relaxed) + 1).unwrap();
+					progress.update(sofar, sofar);
+					batches
+				})
+				.for_each(|batches| {
+					for (index_writer, batch) in index_writers.iter().zip(batches) {
+						if !batch.is_empty() {
+							index_writer.run(batch);
+						}
+					}
+				});
+			progress.finish();
+
+			info!("indexed {} entries", n.load(Ordering::Relaxed));
+			unreadable.report();
+			if detect_lang {
+				info!(
+					"detected the language of {} entries without sections",
+					detected.load(Ordering::Relaxed)
+				);
+			}
+			if let Some(ref lang) = default_lang {
+				info!(
+					"gave {} entries without sections the lang {:?}",
+					defaulted.load(Ordering::Relaxed),
+					lang
+				);
+			}
+
+			if index_writers.is_empty() {
+				writeln!(out, "entries: {}", n.load(Ordering::Relaxed))?;
+				writeln!(out, "documents: {}", total_docs.load(Ordering::Relaxed))?;
+				writeln!(
+					out,
+					"entries without sections: {}",
+					fallbacks.load(Ordering::Relaxed)
+				)?;
+				if detect_lang {
+					writeln!(
+						out,
+						"entries with detected lang: {}",
+						detected.load(Ordering::Relaxed)
+					)?;
+				}
+				if default_lang.is_some() {
+					writeln!(
+						out,
+						"entries with default lang: {}",
+						defaulted.load(Ordering::Relaxed)
+					)?;
+				}
+				writeln!(out, "documents per lang:")?;
+				let mut per_lang: Vec<_> =
+					docs_per_lang.into_inner().unwrap().into_iter().collect();
+				per_lang.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+				for (lang, count) in per_lang {
+					writeln!(out, "\t{}\t{}", count, lang)?;
+				}
+				return Ok(());
+			}
+
+			// the first shard last, so its payload says what all of them have
+			info!("committing the index");
+			let payload = serde_json::to_string(&indexed)?;
+			for mut index_writer in index_writers.into_iter().rev() {
+				let mut commit = index_writer.prepare_commit()?;
+				commit.set_payload(&payload);
+				commit.commit()?;
+			}
+			let mut docs = 0;
+			let mut segments = 0;
+			for index in &indexes {
+				let metas = index.load_metas()?.segments;
+				docs += metas.iter().map(|m| m.num_docs()).sum::<u32>();
+				segments += metas.len();
+			}
+			info!(
+				"index has {} documents in {} segments{}",
+				docs,
+				segments,
+				if shards > 1 {
+					format!(" across {} shards", shards)
+				} else {
+					String::new()
+				}
+			);
+		}
+
+		Action::Index(IndexAction::Verify) => {
+			let mut store = blockstore::Store::new(args.store_dir).with_mmap(args.mmap);
+			store.open()?;
+
+			let indexes = open_shards(&args.index_dir)?;
+			let sharded = indexes.len() > 1;
+
+			let mut orphans = 0;
+			let mut orphaned_docs = 0;
+			for (shard, index) in indexes.iter().enumerate() {
+				let searcher = index_reader(index, args.reload)?.searcher();
+				for (rid, docs, problem) in verify_refs(&store, &searcher)? {
+					if sharded {
+						write!(out, "shard {}: ", shard)?;
+					}
+					writeln!(out, "{}: {} ({} documents)", rid, problem, docs)?;
+					orphans += 1;
+					orphaned_docs += docs;
+				}
+			}
+
+			if orphans == 0 {
+				info!("index is consistent with the store");
+			} else {
+				return Err(eyre!(
+					"{} refs ({} documents) don't match the store, rebuild the index",
+					orphans,
+					orphaned_docs
+				));
+			}
+		}
+
+		Action::Index(IndexAction::Stats {
+			top,
+			per_block,
+			json,
+		}) => {
+			let index = open_index(&args.index_dir)?;
+			let reader = index_reader(&index, args.reload)?;
+			let searcher = reader.searcher();
+
+			let schema = index.schema();
+			let s_ref = schema.get_field("ref").unwrap();
+			let s_title = schema.get_field("title").unwrap();
+
+			let refs = indexed_refs(&searcher, s_ref)?;
+
+			// (documents, entries) of each block, and (documents, ref, a document) of each entry
+			let blocks: Vec<(u32, usize, usize)> = refs
+				.iter()
+				.map(|(block_id, entries)| {
+					let docs = entries.values().map(|(docs, _)| docs).sum();
+					(*block_id, docs, entries.len())
+				})
+				.collect();
+			let mut entries: Vec<(usize, Ref, DocAddress)> = refs
+				.iter()
+				.flat_map(|(block_id, entries)| {
+					entries.iter().map(move |(entry_id, (docs, address))| {
+						(*docs, Ref::new(*block_id, *entry_id), *address)
+					})
+				})
+				.collect();
+			let documents: usize = blocks.iter().map(|(_, docs, _)| docs).sum();
+
+			let mut per_block_docs: Vec<usize> = blocks.iter().map(|(_, docs, _)| *docs).collect();
+			per_block_docs.sort_unstable();
+			entries.sort_unstable_by(|(a, ..), (b, ..)| b.cmp(a));
+			let per_entry_docs: Vec<usize> = entries.iter().rev().map(|(docs, ..)| *docs).collect();
+
+			// avg, p50, p90, p99, and max of sorted counts
+			let distribution = |counts: &[usize]| {
+				let percentile = |p: f64| {
+					let at = ((counts.len().saturating_sub(1)) as f64 * p).round() as usize;
+					counts.get(at).copied().unwrap_or(0)
+				};
+				let average = counts.iter().sum::<usize>() as f64 / counts.len().max(1) as f64;
+				(
+					average,
+					[
+						("p50", percentile(0.5)),
+						("p90", percentile(0.9)),
+						("p99", percentile(0.99)),
+						("max", percentile(1.0)),
+					],
+				)
+			};
+			let block_distribution = distribution(&per_block_docs);
+			let entry_distribution = distribution(&per_entry_docs);
+
+			let most = entries
+				.iter()
+				.take(top)
+				.map(|(docs, rid, address)| {
+					let doc = searcher.doc(*address)?;
+					let title = doc.get_first(s_title).and_then(|v| v.text()).unwrap_or("");
+					Ok((*docs, *rid, title.to_string()))
+				})
+				.collect::<Result<Vec<_>>>()?;
+
+			if json {
+				let distribution_json = |(average, percentiles): &(f64, [(&str, usize); 4])| {
+					let mut json = serde_json::json!({ "avg": average });
+					for (name, count) in percentiles {
+						json[name] = (*count).into();
+					}
+					json
+				};
+				let mut report = serde_json::json!({
+					"documents": documents,
+					"entries": entries.len(),
+					"blocks": blocks.len(),
+					"docs_per_block": distribution_json(&block_distribution),
+					"docs_per_entry": distribution_json(&entry_distribution),
+					"most_documents": most.iter().map(|(docs, rid, title)| serde_json::json!({
+						"ref": rid.to_string(),
+						"title": title,
+						"documents": docs,
+					})).collect::<Vec<_>>(),
+				});
+				if per_block {
+					report["per_block"] = blocks
+						.iter()
+						.map(|(block_id, docs, entries)| {
+							serde_json::json!({
+								"block": block_id,
+								"entries": entries,
+								"documents": docs,
+							})
+						})
+						.collect::<Vec<_>>()
+						.into();
+				}
+				writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+				return Ok(());
+			}
+
+			let distribution_line = |(average, percentiles): &(f64, [(&str, usize); 4])| {
+				format!(
+					"avg {:.1} {}",
+					average,
+					percentiles
+						.iter()
+						.map(|(name, count)| format!("{} {}", name, count))
+						.collect::<Vec<_>>()
+						.join(" ")
+				)
+			};
+
+			writeln!(out, "documents:      {}", documents)?;
+			writeln!(out, "entries:        {}", entries.len())?;
+			writeln!(out, "blocks:         {}", blocks.len())?;
+			writeln!(
+				out,
+				"docs per block: {}",
+				distribution_line(&block_distribution)
+			)?;
+			writeln!(
+				out,
+				"docs per entry: {}",
+				distribution_line(&entry_distribution)
+			)?;
+			if !most.is_empty() {
+				writeln!(out, "most documents:")?;
+				for (docs, rid, title) in most {
+					writeln!(out, "\t[{}] {} ({})", rid, title, docs)?;
+				}
+			}
+			if per_block {
+				writeln!(out, "per block:")?;
+				for (block_id, docs, entries) in blocks {
+					writeln!(
+						out,
+						"\t{}: {} documents of {} entries",
+						block_id, docs, entries
+					)?;
+				}
+			}
+		}
+
+		Action::Index(IndexAction::Frequencies { field, limit, lang }) => {
+			let index = open_index(&args.index_dir)?;
+			let reader = index_reader(&index, args.reload)?;
+			let searcher = reader.searcher();
+
+			let schema = index.schema();
+			let s_field = match field.as_str() {
+				"text" | "title" => schema.get_field(&field).unwrap(),
+				_ => {
+					return Err(eyre!(
+						"can only count terms of text or title, not {}",
+						field
+					))
+				}
+			};
+
+			let mut frequencies = term_frequencies(
+				&searcher,
+				&schema,
+				s_field,
+				lang.map(|l| l.to_lowercase()).as_deref(),
+			)?;
+			frequencies.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| b.2.cmp(&a.2)));
+			if limit > 0 {
+				frequencies.truncate(limit);
+			}
+
+			for (term, total, docs) in frequencies {
+				writeln!(out, "{}\t{}\t{}", term, total, docs)?;
+			}
+		}
+
+		Action::Repl {
+			mut options,
+			cache_blocks,
+			skip_bad_blocks,
+			no_follow,
+			warm,
+			warm_blocks,
+		} => {
+			let mut store = blockstore::Store::new(args.store_dir)
+				.with_mmap(args.mmap)
+				.lenient(skip_bad_blocks);
+			if cache_blocks > 0 {
+				store = store.with_cache(cache_blocks);
+			}
+			store.open()?;
+			let redirects = if no_follow {
+				Redirects::default()
+			} else {
+				read_redirects(&store)
+			};
+
+			let indexes = open_shards(&args.index_dir)?;
+			let reload = args.reload;
+			let readers = indexes
+				.iter()
+				.map(|index| index_reader(index, reload))
+				.collect::<Result<Vec<_>>>()?;
+			if warm {
+				warm_up(
+					&store,
+					&indexes,
+					&readers,
+					warm_blocks.unwrap_or(cache_blocks),
+				)?;
+			}
+			let headings = IndexedBlocks::headings(&indexes[0])?;
+			let session = QuerySession {
+				index: &indexes[0],
+				readers: &readers,
+				store: &store,
+				redirects: &redirects,
+				headings: &headings,
+				style: Style::new(args.color),
+			};
+
+			let stdin = io::stdin();
+			let interactive = stdin.is_terminal();
+			let mut line = String::new();
+			loop {
+				if interactive {
+					eprint!("> ");
+					io::stderr().flush()?;
+				}
+
+				line.clear();
+				if stdin.lock().read_line(&mut line)? == 0 {
+					break;
+				}
+				let line = line.trim();
+				if line.is_empty() {
+					continue;
+				}
+
+				// a bad directive or query shouldn't end the session
+				let result = match line.strip_prefix(':') {
+					Some("quit" | "q") => break,
+					Some("help") => {
+						eprintln!("{}", REPL_HELP);
+						Ok(())
+					}
+					Some("options") => {
+						eprintln!("{:#?}", options);
+						Ok(())
+					}
+					Some("reload") => session.reload().map(|docs| {
+						info!("reloaded the index: {} documents", docs);
+					}),
+					Some(directive) => {
+						let (name, value) = match directive.split_once(char::is_whitespace) {
+							Some((name, value)) => (name, Some(value.trim())),
+							None => (directive, None),
+						};
+						options.set(name, value)
+					}
+					None => session
+						.query(&mut out, &options, line)
+						.and_then(|()| Ok(out.flush()?)),
+				};
+				if let Err(err) = result {
+					error!("{}", err);
+				}
+			}
+
+			if let Some((hits, misses)) = store.cache_stats() {
+				debug!("block cache: {} hits, {} misses", hits, misses);
+			}
+		}
+
+		Action::Serve {
+			bind,
+			options,
+			max_connections,
+			cache_blocks,
+			skip_bad_blocks,
+			warm,
+			warm_blocks,
+		} => {
+			if max_connections == 0 {
+				return Err(eyre!("--max-connections must be at least 1"));
+			}
+
+			let mut store = blockstore::Store::new(args.store_dir)
+				.with_mmap(args.mmap)
+				.lenient(skip_bad_blocks);
+			if cache_blocks > 0 {
+				store = store.with_cache(cache_blocks);
+			}
+			store.open()?;
+			let redirects = read_redirects(&store);
+
+			let indexes = open_shards(&args.index_dir)?;
+			let reload = args.reload;
+			let readers = indexes
+				.iter()
+				.map(|index| index_reader(index, reload))
+				.collect::<Result<Vec<_>>>()?;
+			if warm {
+				warm_up(
+					&store,
+					&indexes,
+					&readers,
+					warm_blocks.unwrap_or(cache_blocks),
+				)?;
+			}
+			let headings = IndexedBlocks::headings(&indexes[0])?;
+			let session = QuerySession {
+				index: &indexes[0],
+				readers: &readers,
+				store: &store,
+				redirects: &redirects,
+				headings: &headings,
+				style: Style::new(ColorChoice::Never),
+			};
+
+			// hits come as a JSON array, with their tokens for links
+			let defaults = QueryOptions {
+				format: OutputFormat::Json,
+				token: true,
+				stream: false,
+				suggest: false,
+				facets: Vec::new(),
+				..options
+			};
+			defaults.check()?;
+
+			let search = |req: &serve::Request| -> Result<serve::Response> {
+				let q = match req.param("q") {
+					Some(q) if !q.trim().is_empty() => q,
+					_ => return Ok(serve::Response::error(400, "nothing to search for (q)")),
+				};
+
+				let mut options = defaults.clone();
+				if let Err(err) = search_params(&mut options, req) {
+					return Ok(serve::Response::error(400, err));
+				}
+				if options.limit == 0 || options.limit > 10 * defaults.limit {
+					return Ok(serve::Response::error(
+						400,
+						format!("limit must be between 1 and {}", 10 * defaults.limit),
+					));
+				}
+				if options.offset > MAX_OFFSET {
+					return Ok(serve::Response::error(
+						400,
+						format!("offset must be between 0 and {}", MAX_OFFSET),
+					));
+				}
+				if let Err(err) = options.check() {
+					return Ok(serve::Response::error(400, err));
+				}
+
+				let mut hits = Vec::new();
+				match session.query(&mut hits, &options, q) {
+					Ok(()) => Ok(serve::Response::ok(serde_json::from_slice(&hits)?)),
+					Err(err) if err.downcast_ref::<QueryParserError>().is_some() => {
+						Ok(serve::Response::error(400, err))
+					}
+					Err(err) => Err(err),
+				}
+			};
+
+			let entry = |rid: Result<Ref, WiktError>| -> serve::Response {
+				let rid = match rid {
+					Ok(rid) => rid,
+					Err(_) => {
+						return serve::Response::error(
+							400,
+							"expected /entry/{block}/{entry} or /entry/{token}",
+						)
+					}
+				};
+				let to = redirects.resolve(rid);
+				let entry = match store.read_entry(to) {
+					Ok(entry) => entry,
+					Err(err) => {
+						return serve::Response::error(404, format!("no entry [{}]: {}", to, err))
+					}
+				};
+
+				let (page_id, timestamp) = entry.revision();
+				let (title, text, _) = entry.open();
+				serve::Response::ok(serde_json::json!({
+					"ref": to.to_string(),
+					"token": to.to_token(),
+					"redirected_from": (to != rid).then(|| rid.to_string()),
+					"title": title,
+					"text": text,
+					"page_id": page_id,
+					"revision": timestamp.map(xmldump::format_timestamp),
+				}))
+			};
+
+			serve::serve(&bind, max_connections, |req| {
+				let path: Vec<&str> = req.path.trim_matches('/').split('/').collect();
+				match path.as_slice() {
+					["search"] => search(req).unwrap_or_else(|err| {
+						error!("search failed: {}", err);
+						serve::Response::error(500, err)
+					}),
+					["reload"] => match session.reload() {
+						Ok(docs) => {
+							info!("reloaded the index: {} documents", docs);
+							serve::Response::ok(serde_json::json!({ "documents": docs }))
+						}
+						Err(err) => {
+							error!("reload failed: {}", err);
+							serve::Response::error(500, err)
+						}
+					},
+					["entry", block, id] => entry(format!("{}/{}", block, id).parse()),
+					["entry", token] => entry(Ref::from_token(token)),
+					_ => serve::Response::error(404, format!("nothing at {}", req.path)),
+				}
+			})?;
+		}
+
+		Action::Bench {
+			blocks: n_blocks,
+			entries: n_entries,
+			seed,
+			queries,
+		} => {
+			let mut random = xorshift(seed);
+
+			// no cache, so every read pays for the decompression
+			let mut store = blockstore::Store::new(args.store_dir).with_mmap(args.mmap);
+			store.open()?;
+
+			let start = Instant::now();
+			let blocks = store.blocks()?;
+			let enumerate = start.elapsed();
+			if blocks.is_empty() {
+				return Err(eyre!("store has no blocks"));
+			}
+
+			let mut decompress = Duration::default();
+			let mut sampled = Vec::with_capacity(n_blocks);
+			for _ in 0..n_blocks {
+				let path = &blocks[random(blocks.len())];
+				let start = Instant::now();
+				let block = store.read_block(path)?;
+				decompress += start.elapsed();
+				sampled.push(block.id);
+			}
+
+			let mut read_entry = Duration::default();
+			let mut refs = Vec::with_capacity(n_entries);
+			for _ in 0..n_entries {
+				let path = &blocks[random(blocks.len())];
+				let id = blockstore::block_id(path)?;
+				let n = store.entry_count(path)?;
+				let rid = Ref::new(id, u32::try_from(random(usize::try_from(n)?))?);
+
+				let start = Instant::now();
+				store.read_entry(rid)?;
+				read_entry += start.elapsed();
+				refs.push(rid);
+			}
+
+			// the same entries again, at once
+			let start = Instant::now();
+			for entry in store.read_entries(&refs) {
+				entry?;
+			}
+			let read_entries = start.elapsed();
+
+			let mut query_times = Vec::with_capacity(queries.len());
+			if !queries.is_empty() {
+				let index = open_index(&args.index_dir)?;
+				let reader = index_reader(&index, args.reload)?;
+				let searcher = reader.searcher();
+				let s_text = index.schema().get_field("text").unwrap();
+				let query_parser = QueryParser::for_index(&index, vec![s_text]);
+
+				for search in &queries {
+					let start = Instant::now();
+					let query = query_parser.parse_query(search)?;
+					let hits = searcher.search(&query, &TopDocs::with_limit(20))?.len();
+					let elapsed = start.elapsed();
+					query_times.push(serde_json::json!({
+						"query": search,
+						"hits": hits,
+						"ms": millis(elapsed),
+					}));
+				}
+			}
+
+			let average = |total: Duration, n: usize| {
+				if n == 0 {
+					0.0
+				} else {
+					millis(total) / n as f64
+				}
+			};
+
+			let report = serde_json::json!({
+				"seed": seed,
+				"mmap": args.mmap,
+				"blocks": {
+					"count": blocks.len(),
+					"enumerate_ms": millis(enumerate),
+				},
+				"decompress": {
+					"samples": n_blocks,
+					"total_ms": millis(decompress),
+					"avg_ms": average(decompress, n_blocks),
+					"blocks": sampled,
+				},
+				"read_entry": {
+					"samples": n_entries,
+					"total_ms": millis(read_entry),
+					"avg_ms": average(read_entry, n_entries),
+				},
+				"read_entries": {
+					"samples": n_entries,
+					"total_ms": millis(read_entries),
+					"avg_ms": average(read_entries, n_entries),
+				},
+				"query": {
+					"samples": query_times.len(),
+					"avg_ms": if query_times.is_empty() {
+						0.0
+					} else {
+						query_times.iter().filter_map(|q| q["ms"].as_f64()).sum::<f64>()
+							/ query_times.len() as f64
+					},
+					"queries": query_times,
+				},
+			});
+
+			writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+		}
+
+		Action::Index(IndexAction::Query {
+			options,
+			cache_blocks,
+			skip_bad_blocks,
+			no_follow,
+			search,
+		}) => {
+			let mut store = blockstore::Store::new(args.store_dir)
+				.with_mmap(args.mmap)
+				.lenient(skip_bad_blocks);
+			if cache_blocks > 0 {
+				store = store.with_cache(cache_blocks);
+			}
+			store.open()?;
+			let redirects = if no_follow || options.titles {
+				Redirects::default()
+			} else {
+				read_redirects(&store)
+			};
+
+			let indexes = open_shards(&args.index_dir)?;
+			let reload = args.reload;
+			let readers = indexes
+				.iter()
+				.map(|index| index_reader(index, reload))
+				.collect::<Result<Vec<_>>>()?;
+			let headings = IndexedBlocks::headings(&indexes[0])?;
+			let session = QuerySession {
+				index: &indexes[0],
+				readers: &readers,
+				store: &store,
+				redirects: &redirects,
+				headings: &headings,
+				style: Style::new(args.color),
+			};
+			session.query(&mut out, &options, &search)?;
+
+			if let Some((hits, misses)) = store.cache_stats() {
+				debug!("block cache: {} hits, {} misses", hits, misses);
+			}
+		}
+	}
+
+	out.flush()?;
+	Ok(())
+}
+
+const REPL_HELP: &str = "\
+searches are run as with `index query`, and these change the options of the ones after them:
+  :limit N           number of hits
+  :offset N          hits skipped first (for the next page)
+  :lang [L,...]      only hits in these languages (none clears)
+  :gram [G,...]      only hits in these parts of speech (none clears)
+  :sub [S,...]       only hits in these subsections (none clears)
+  :full              toggle printing whole entries
+  :clean             toggle stripping wikitext markup
+  :definitions       toggle printing numbered definitions
+  :concordance       toggle listing every occurrence of the words searched for
+  :context N         characters around occurrences
+  :token             toggle showing refs as tokens
+  :titles            toggle printing only titles
+  :explain           toggle printing score breakdowns
+  :suggest           toggle suggesting close words when nothing is found
+  :sort ORDER        score, title, or ref
+  :stream            toggle printing hits as they're found, unranked
+  :match MODE        any, all, or phrase
+  :fuzzy N           typos allowed per word (0 disables)
+  :slop N            other words allowed within a phrase
+  :min-score S       leave out hits scoring under S (0 disables)
+  :min-score-ratio R leave out hits under R times the best score (0 disables)
+  :timeout SECS      stop searching after this long (0 disables)
+  :format FORMAT     text, json, jsonl, or tsv
+  :no-header         toggle the header row of tsv
+  :fields F,...      fields searched
+  :boost F=N,...     score multipliers per field
+  :snippet-len N     length of excerpts
+  :facets [F,...]    count hits per lang or gram (none clears)
+  :options           show the current options
+  :reload            pick up commits made to the index since it was opened (or last reloaded)
+  :quit              exit (or end the input)";
+
+impl QueryOptions {
+	/// errors if the options don't go together
+	fn check(&self) -> Result<()> {
+		if self.format == OutputFormat::Tsv && !self.facets.is_empty() {
+			return Err(eyre!("facet counts can't be printed as tsv, only hits"));
+		}
+		if self.format == OutputFormat::Tsv && self.concordance {
+			return Err(eyre!("a concordance can't be printed as tsv, only hits"));
+		}
+		if self.slop > 0 && self.mode != MatchMode::Phrase {
+			return Err(eyre!("--slop only applies to --match phrase"));
+		}
+		if !(0.0..=1.0).contains(&self.min_score_ratio) {
+			return Err(eyre!("--min-score-ratio is a fraction, from 0 to 1"));
+		}
+		Ok(())
+	}
+
+	/// changes an option from a REPL directive, like `:limit 50`
+	fn set(&mut self, name: &str, value: Option<&str>) -> Result<()> {
+		let required = || value.ok_or_else(|| eyre!(":{} needs a value", name));
+		let list = |value: Option<&str>| -> Vec<String> {
+			value
+				.into_iter()
+				.flat_map(|v| v.split(','))
+				.map(|v| v.trim().to_string())
+				.filter(|v| !v.is_empty())
+				.collect()
+		};
+
+		match name {
+			"limit" | "n" => self.limit = parse_directive(name, value)?,
+			"offset" => self.offset = parse_directive(name, value)?,
+			"lang" => self.lang = list(value),
+			"gram" => self.gram = list(value),
+			"sub" => self.sub = list(value),
+			"full" => self.full = !self.full,
+			"clean" => self.clean = !self.clean,
+			"definitions" => self.definitions = !self.definitions,
+			"concordance" => self.concordance = !self.concordance,
+			"context" => self.context = parse_directive(name, value)?,
+			"token" => self.token = !self.token,
+			"titles" => self.titles = !self.titles,
+			"explain" => self.explain = !self.explain,
+			"suggest" => self.suggest = !self.suggest,
+			"no-header" | "no_header" => self.no_header = !self.no_header,
+			"sort" => self.sort = parse_directive(name, value)?,
+			"stream" => self.stream = !self.stream,
+			"match" => self.mode = parse_directive(name, value)?,
+			"fuzzy" => self.fuzzy = parse_directive(name, value)?,
+			"slop" => self.slop = parse_directive(name, value)?,
+			"min-score" | "min_score" => self.min_score = parse_directive(name, value)?,
+			"min-score-ratio" | "min_score_ratio" => {
+				self.min_score_ratio = parse_directive(name, value)?
+			}
+			"timeout" => self.timeout = parse_directive(name, value)?,
+			"format" => self.format = parse_directive(name, value)?,
+			"fields" => self.fields = list(Some(required()?)),
+			"boost" => {
+				self.boost = list(Some(required()?))
+					.iter()
+					.map(|b| b.parse())
+					.collect::<Result<_>>()?
+			}
+			"snippet-len" | "snippet_len" => self.snippet_len = parse_directive(name, value)?,
+			"facets" => {
+				self.facets = list(value)
+					.iter()
+					.map(|f| f.parse())
+					.collect::<Result<_>>()?
+			}
+			_ => return Err(eyre!("unknown directive :{} (see :help)", name)),
+		}
+		Ok(())
+	}
+}
+
+/// changes the options of a search from the parameters of a `GET /search`
+///
+/// They're named as the REPL's directives are, and `lang`, `gram`, and `sub` can be repeated.
+fn search_params(options: &mut QueryOptions, req: &serve::Request) -> Result<()> {
+	for name in ["limit", "offset", "match", "fuzzy", "slop", "sort"] {
+		if let Some(value) = req.param(name) {
+			options.set(name, Some(value))?;
+		}
+	}
+	for name in ["lang", "gram", "sub"] {
+		let values = req.params(name);
+		if !values.is_empty() {
+			options.set(name, Some(&values.join(",")))?;
+		}
+	}
+	Ok(())
+}
+
+/// the value of a REPL directive, parsed
+fn parse_directive<T: FromStr>(name: &str, value: Option<&str>) -> Result<T>
+where
+	T::Err: Display,
+{
+	let value = value.ok_or_else(|| eyre!(":{} needs a value", name))?;
+	value
+		.parse()
+		.map_err(|err| eyre!("bad value for :{}: {}", name, err))
+}
+
+/// What's opened once to run index queries, by `index query` or many times by the REPL.
+struct QuerySession<'a> {
+	/// the first shard, whose schema and tokenizers are those of all of them
+	index: &'a Index,
+	/// one per shard, or just the one of an index that isn't sharded
+	readers: &'a [IndexReader],
+	store: &'a blockstore::Store,
+	redirects: &'a Redirects,
+	headings: &'a Headings,
+	style: Style,
+}
+
+impl QuerySession<'_> {
+	/// makes the readers see the index as of its last commit, and returns how many documents
+	/// that has
+	fn reload(&self) -> Result<u64> {
+		let mut docs = 0;
+		for reader in self.readers {
+			reader.reload()?;
+			docs += reader.searcher().num_docs();
+		}
+		Ok(docs)
+	}
+
+	/// runs a query and writes its hits to `out`
+	fn query(&self, out: &mut dyn Write, options: &QueryOptions, search: &str) -> Result<()> {
+		options.check()?;
+
+		let leased: Vec<_> = self.readers.iter().map(IndexReader::searcher).collect();
+		let searchers: Vec<&Searcher> = leased.iter().map(|searcher| &**searcher).collect();
+		let schema = self.index.schema();
+		let field = |name: &str| {
+			schema
+				.get_field(name)
+				.ok_or_else(|| eyre!("no such field in the index: {}", name))
+		};
+		let fields = options
+			.fields
+			.iter()
+			.map(|name| field(name))
+			.collect::<Result<Vec<_>>>()?;
+		let boosts = options
+			.boost
+			.iter()
+			.map(|b| Ok((field(&b.field)?, b.boost)))
+			.collect::<Result<HashMap<_, _>>>()?;
+
+		let mut query_parser = QueryParser::for_index(self.index, fields.clone());
+		for (field, boost) in &boosts {
+			query_parser.set_field_boost(*field, *boost);
+		}
+		if options.fuzzy > MAX_FUZZY {
+			warn!("--fuzzy {} is too far, using {}", options.fuzzy, MAX_FUZZY);
+		}
+		let query: Box<dyn Query> = match options.mode {
+			_ if options.fuzzy > 0 => fuzzy_query(
+				self.index,
+				&fields,
+				&boosts,
+				search,
+				options.fuzzy.min(MAX_FUZZY),
+			)?,
+			MatchMode::Any => query_parser.parse_query(search)?,
+			MatchMode::All => {
+				query_parser.set_conjunction_by_default();
+				query_parser.parse_query(search)?
+			}
+			MatchMode::Phrase => phrase_query(self.index, &fields, &boosts, search, options.slop)?,
+		};
+
+		let mut filters = Vec::new();
+		if !options.lang.is_empty() {
+			filters.push(any_of(schema.get_field("lang").unwrap(), &options.lang));
+		}
+		if !options.gram.is_empty() {
+			filters.push(any_of(schema.get_field("gram").unwrap(), &options.gram));
+		}
+		if !options.sub.is_empty() {
+			filters.push(any_of(schema.get_field("sub").unwrap(), &options.sub));
+		}
+		// until the index is made again, it still has entries deleted from the store: leave them
+		// out of the search, rather than its hits, so pages are still full
+		let s_ref = field("ref")?;
+		let deleted = self.store.deleted().map(|rid| -> Box<dyn Query> {
+			let term = Term::from_field_u64(s_ref, rid.as_u64());
+			Box::new(TermQuery::new(term, IndexRecordOption::Basic))
+		});
+		let deleted: Vec<_> = deleted.map(|query| (Occur::MustNot, query)).collect();
+		let query: Box<dyn Query> = if filters.is_empty() && deleted.is_empty() {
+			query
+		} else {
+			let mut clauses = vec![(Occur::Must, query)];
+			for filter in filters {
+				// filters don't count towards the score
+				clauses.push((Occur::Must, Box::new(BoostQuery::new(filter, 0.0))));
+			}
+			clauses.extend(deleted);
+			Box::new(BooleanQuery::new(clauses))
+		};
+
+		// per shard, as they weigh the words of the query by how common they are in it
+		let s_text = field("text")?;
+		let mut snippets = searchers
+			.iter()
+			.map(|searcher| SnippetGenerator::create(searcher, &*query, s_text))
+			.collect::<tantivy::Result<Vec<_>>>()?;
+		for snippets in &mut snippets {
+			snippets.set_max_num_chars(options.snippet_len);
+		}
+
+		let concordance = if options.concordance {
+			let mut terms = BTreeMap::new();
+			query.query_terms(&mut terms);
+			let words = terms
+				.keys()
+				.filter(|term| term.field() == s_text)
+				.map(|term| term.text().to_string())
+				.collect();
+			Some((self.index.tokenizer_for_field(s_text)?, words))
+		} else {
+			None
+		};
+		let sources = HitSources {
+			query: &*query,
+			searchers: &searchers,
+			snippets: &snippets,
+			concordance,
+		};
+
+		// the clock starts once the query is parsed, for the searches themselves
+		let deadline = Deadline::after(options.timeout)?;
+		if options.stream {
+			return self.stream(out, options, &sources, &deadline);
+		}
+
+		// ties are broken by shard and doc address, so pages don't overlap or skip hits, as long as
+		// the index doesn't change in between
+		let (offset, limit) = (options.offset, options.limit);
+		let top_docs = TopDocs::with_limit(limit.max(1) + offset);
+		let top_docs: Vec<(Score, usize, DocAddress)> = match options.sort {
+			_ if limit == 0 => Vec::new(),
+			SortOrder::Score => top_hits(&searchers, &*query, &top_docs, &deadline, offset, limit)?,
+			SortOrder::Title => {
+				let s_sortkey = schema.get_field("sortkey").unwrap();
+				let collector = top_docs.tweak_score(move |segment_reader: &SegmentReader| {
+					let sortkeys = segment_reader
+						.fast_fields()
+						.bytes(s_sortkey)
+						.expect("index has no sortkey, rebuild it");
+
+					// top docs keeps the highest, so reverse to get a-z, and
+					// break ties on titles that fold alike by score
+					move |doc: DocId, score: Score| {
+						(Reverse(sortkeys.get_bytes(doc).to_vec()), score)
+					}
+				});
+
+				top_hits(&searchers, &*query, &collector, &deadline, offset, limit)?
+					.into_iter()
+					.map(|((_, score), shard, doc_address)| (score, shard, doc_address))
+					.collect()
+			}
+			SortOrder::Ref => {
+				let collector = by_ref(top_docs, schema.get_field("ref").unwrap());
+				top_hits(&searchers, &*query, &collector, &deadline, offset, limit)?
+					.into_iter()
+					.map(|((_, score), shard, doc_address)| (score, shard, doc_address))
+					.collect()
+			}
+		};
+		let facets = self.facets(&searchers, &*query, &options.facets, &deadline)?;
+		if deadline.passed() {
+			warn!(
+				"search timed out after {}s: these are the best of the hits found until then{}",
+				options.timeout,
+				if facets.is_empty() {
+					""
+				} else {
+					", and the counts are of those"
+				}
+			);
+		}
+		// past the first page, nothing more found isn't a misspelling
+		let suggestions =
+			if options.suggest && options.limit > 0 && options.offset == 0 && top_docs.is_empty() {
+				self.suggestions(&searchers, &fields, search)?
+			} else {
+				Vec::new()
+			};
+
+		// the best is that of the hits retrieved, so with --offset, of this page
+		let best = top_docs
+			.iter()
+			.map(|(score, _, _)| *score)
+			.fold(0.0, Score::max);
+		let min_score = options.min_score.max(best * options.min_score_ratio);
+		let retrieved = top_docs.len();
+		let top_docs: Vec<(Score, usize, DocAddress)> = top_docs
+			.into_iter()
+			.filter(|(score, _, _)| *score >= min_score)
+			.collect();
+		if options.min_score > 0.0 || options.min_score_ratio > 0.0 {
+			info!(
+				"left out {} of {} hits scoring under {}",
+				retrieved - top_docs.len(),
+				retrieved,
+				min_score
+			);
+		}
+
+		if options.format == OutputFormat::Tsv && !options.no_header {
+			writeln!(out, "score\tref\tlang\tgram\ttitle\ttext")?;
+		}
+
+		let mut json_hits = Vec::new();
+		for hit in top_docs {
+			if let Some(hit) = self.write_hit(out, options, &sources, hit)? {
+				json_hits.push(hit);
+			}
+		}
+
+		let suggestions_json = || -> serde_json::Value {
+			suggestions
+				.iter()
+				.map(|(word, terms)| serde_json::json!({ "word": word, "terms": terms }))
+				.collect()
+		};
+		match options.format {
+			OutputFormat::Text => {
+				for (kind, counts) in &facets {
+					writeln!(out, "{}:", self.style.bold(kind.name()))?;
+					for (value, count) in counts {
+						writeln!(out, "\t{}\t{}", count, value)?;
+					}
+				}
+				for (word, terms) in &suggestions {
+					if terms.is_empty() {
+						writeln!(out, "nothing like {} in the index", self.style.bold(word))?;
+					} else {
+						writeln!(
+							out,
+							"did you mean: {} (for {})",
+							self.style.bold(terms.join(", ")),
+							word
+						)?;
+					}
+				}
+			}
+			OutputFormat::Json if facets.is_empty() && suggestions.is_empty() => {
+				writeln!(out, "{}", serde_json::to_string_pretty(&json_hits)?)?;
+			}
+			OutputFormat::Json => {
+				let mut output = serde_json::json!({ "hits": json_hits });
+				if !facets.is_empty() {
+					output["facets"] = facets_json(&facets);
+				}
+				if !suggestions.is_empty() {
+					output["suggestions"] = suggestions_json();
+				}
+				writeln!(out, "{}", serde_json::to_string_pretty(&output)?)?;
+			}
+			// the rows are only hits, so suggestions go to the log
+			OutputFormat::Tsv => {
+				for (word, terms) in &suggestions {
+					if terms.is_empty() {
+						info!("nothing like {} in the index", word);
+					} else {
+						info!("did you mean: {} (for {})", terms.join(", "), word);
+					}
+				}
+			}
+			OutputFormat::Jsonl => {
+				if !facets.is_empty() {
+					writeln!(
+						out,
+						"{}",
+						serde_json::json!({ "facets": facets_json(&facets) })
+					)?;
+				}
+				if !suggestions.is_empty() {
+					writeln!(
+						out,
+						"{}",
+						serde_json::json!({ "suggestions": suggestions_json() })
+					)?;
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// writes the hits of a query as they're found, for `--stream`
+	///
+	/// The shards are searched one after the other on another thread, which sends hits over as it
+	/// collects them. Once there are enough, the search still goes through the rest of the index,
+	/// but nothing more is done with them.
+	fn stream(
+		&self,
+		out: &mut dyn Write,
+		options: &QueryOptions,
+		sources: &HitSources,
+		deadline: &Deadline,
+	) -> Result<()> {
+		if options.format == OutputFormat::Json {
+			return Err(eyre!(
+				"--stream prints hits one by one, so not as a JSON array: use --format jsonl"
+			));
+		}
+		if !options.facets.is_empty() {
+			return Err(eyre!(
+				"--stream can't count facets, which takes all the hits"
+			));
+		}
+		if options.min_score_ratio > 0.0 {
+			return Err(eyre!(
+				"--stream can't compare scores to the best one, as it doesn't know it yet"
+			));
+		}
+		if options.sort != SortOrder::Score {
+			warn!("--stream prints hits as they're found, ignoring --sort");
+		}
+
+		if options.format == OutputFormat::Tsv && !options.no_header {
+			writeln!(out, "score\tref\tlang\tgram\ttitle\ttext")?;
+		}
+
+		let (sender, hits) = sync_channel(STREAM_BUFFER);
+		let (query, searchers) = (sources.query, sources.searchers);
+		std::thread::scope(|scope| {
+			let search = scope.spawn(move || {
+				for (shard, searcher) in searchers.iter().enumerate() {
+					let hits = sender.clone();
+					let collector = StreamCollector { shard, hits };
+					searcher.search(
+						query,
+						&Until {
+							collector: &collector,
+							deadline,
+						},
+					)?;
+				}
+				Ok::<_, tantivy::TantivyError>(())
+			});
+
+			let mut found = 0;
+			let mut printed = 0;
+			for hit in hits
+				.iter()
+				.filter(|(score, _, _)| *score >= options.min_score)
+			{
+				found += 1;
+				if found <= options.offset {
+					continue;
+				}
+				self.write_hit(out, options, sources, hit)?;
+				printed += 1;
+				if printed == options.limit {
+					break;
+				}
+			}
+			drop(hits);
+
+			search.join().expect("search thread panicked")?;
+			debug!("streamed {} hits", printed);
+			if deadline.passed() {
+				warn!(
+					"search timed out after {}s, so there may be more hits",
+					options.timeout
+				);
+			}
+			Ok(())
+		})
+	}
+
+	/// writes a hit to `out`, or with `--format json`, returns it to go in the array of them
+	fn write_hit(
+		&self,
+		out: &mut dyn Write,
+		options: &QueryOptions,
+		sources: &HitSources,
+		(score, shard, doc_address): (Score, usize, DocAddress),
+	) -> Result<Option<serde_json::Value>> {
+		let schema = self.index.schema();
+		let retrieved_doc = sources.searchers[shard].doc(doc_address)?;
+		let nameddoc = schema.to_named_doc(&retrieved_doc).0;
+
+		// the ref is shared by all documents made from the same entry, and the
+		// lang/gram fields say which part of that entry this document is
+		let rid = Ref::from_u64(nameddoc.get("ref").unwrap()[0].u64_value().unwrap());
+		// until the index is made again, it still has entries deleted from the store
+		if self.store.is_deleted(rid) {
+			return Ok(None);
+		}
+		let lang = nameddoc.get("lang").and_then(|f| f[0].text());
+		let gram = nameddoc.get("gram").and_then(|f| f[0].text());
+		let sub = nameddoc.get("sub").and_then(|f| f[0].text());
+		let shown_ref = if options.token {
+			rid.to_token()
+		} else {
+			rid.to_string()
+		};
+
+		let mut occurrences = None;
+		let (title, text) = if options.titles {
+			let title = nameddoc.get("title").unwrap()[0].text().unwrap();
+			(title.to_string(), None)
+		} else {
+			let (title, mut text) =
+				hit_text(self.store, self.redirects, self.headings, &nameddoc, rid)?;
+			let definitions = if options.definitions && sources.concordance.is_none() {
+				parse_definitions(&text)
+			} else {
+				Vec::new()
+			};
+
+			if !definitions.is_empty() {
+				// lines of text output are indented under the header
+				text = definitions.join(if options.format == OutputFormat::Text {
+					"\n\t"
+				} else {
+					"\n"
+				});
+			} else if options.clean {
+				text = wikitext::clean_wikitext(&text);
+			}
+
+			// offsets are in the text as it's shown in full
+			if let Some((analyzer, words)) = &sources.concordance {
+				occurrences = Some(concordance::occurrences(
+					analyzer,
+					words,
+					&text,
+					options.context,
+				));
+			}
+
+			if !options.full && definitions.is_empty() {
+				let snippets = &sources.snippets[shard];
+				text = if options.format == OutputFormat::Text {
+					snippet(snippets, &text, options.snippet_len, |s| {
+						self.style.highlight(s)
+					})
 				} else {
-					let (title, mut text, _) = store.read_entry(rid)?.open();
+					snippet(snippets, &text, options.snippet_len, str::to_string)
+				};
+			}
+
+			(title, Some(text))
+		};
+
+		let explanation = if options.explain {
+			Some(
+				sources
+					.query
+					.explain(sources.searchers[shard], doc_address)?,
+			)
+		} else {
+			None
+		};
+
+		if options.format == OutputFormat::Text {
+			let header = hit_header(self.style, score, &shown_ref, (lang, gram, sub), &title);
+			match (text, occurrences) {
+				// a line per occurrence, lined up on the words (as long as the text is)
+				(_, Some(occurrences)) => {
+					writeln!(out, "{}", header)?;
+					let one_line = |text: &str| text.replace(['\n', '\t'], " ");
+					for at in occurrences {
+						writeln!(
+							out,
+							"\t{:>width$} {} {}",
+							one_line(&at.before),
+							self.style.highlight(&at.word),
+							one_line(&at.after),
+							width = options.context
+						)?;
+					}
+				}
+				(Some(text), None) => writeln!(out, "{}\n\t{}", header, text)?,
+				(None, None) => writeln!(out, "{}", header)?,
+			}
+
+			if let Some(explanation) = explanation {
+				for line in explanation.to_pretty_json().lines() {
+					writeln!(out, "\t{}", self.style.dim(line))?;
+				}
+			}
+			return Ok(None);
+		}
+
+		if options.format == OutputFormat::Tsv {
+			writeln!(
+				out,
+				"{}\t{}\t{}\t{}\t{}\t{}",
+				score,
+				shown_ref,
+				tsv_field(lang.unwrap_or("")),
+				tsv_field(gram.unwrap_or("")),
+				tsv_field(&title),
+				tsv_field(text.as_deref().unwrap_or(""))
+			)?;
+			return Ok(None);
+		}
+
+		let mut hit = serde_json::json!({
+			"score": score,
+			"ref": rid.to_string(),
+			"ref_u64": rid.as_u64(),
+			"title": title,
+			"lang": lang,
+			"gram": gram,
+			"sub": sub,
+		});
+		if options.token {
+			hit["token"] = shown_ref.into();
+		}
+		if let Some(text) = text {
+			hit["text"] = text.into();
+		}
+		if let Some(occurrences) = occurrences {
+			hit["concordance"] = serde_json::to_value(occurrences)?;
+		}
+		if let Some(explanation) = explanation {
+			hit["explanation"] = serde_json::from_str(&explanation.to_pretty_json())?;
+		}
+
+		if options.format == OutputFormat::Jsonl {
+			writeln!(out, "{}", hit)?;
+			return Ok(None);
+		}
+		Ok(Some(hit))
+	}
+
+	/// words in the index close to each word of a search that isn't in it, most common first
+	///
+	/// A word is looked for as each field would tokenize it, so with a stemming tokenizer the
+	/// suggestions are stems. Words within one edit are suggested, or else within two.
+	fn suggestions(
+		&self,
+		searchers: &[&Searcher],
+		fields: &[Field],
+		search: &str,
+	) -> Result<Vec<(String, Vec<String>)>> {
+		let mut suggestions = Vec::new();
+		for word in search.split_whitespace() {
+			// query syntax around a word, as in `+word`, `"a phrase"`, or `title:word`
+			let word = word
+				.rsplit(':')
+				.next()
+				.unwrap_or(word)
+				.trim_matches(|c: char| !c.is_alphanumeric());
+			if word.is_empty() {
+				continue;
+			}
+
+			let mut terms = Vec::new();
+			for field in fields {
+				self.index
+					.tokenizer_for_field(*field)?
+					.token_stream(word)
+					.process(&mut |token| terms.push(Term::from_field_text(*field, &token.text)));
+			}
+			let mut found = 0;
+			for term in &terms {
+				for searcher in searchers {
+					found += searcher.doc_freq(term)?;
+				}
+			}
+			if terms.is_empty() || found > 0 {
+				continue;
+			}
+
+			let mut similar: HashMap<String, u64> = HashMap::new();
+			for distance in 1..=MAX_FUZZY {
+				for term in &terms {
+					let text = term.text();
+					for searcher in searchers {
+						for (close, docs) in similar_terms(searcher, term.field(), text, distance)?
+						{
+							*similar.entry(close).or_insert(0) += docs;
+						}
+					}
+				}
+				if !similar.is_empty() {
+					break;
+				}
+			}
+
+			let mut similar: Vec<(String, u64)> = similar.into_iter().collect();
+			similar.sort_by(|(a, n), (b, m)| m.cmp(n).then_with(|| a.cmp(b)));
+			suggestions.push((
+				word.to_string(),
+				similar
+					.into_iter()
+					.take(MAX_SUGGESTIONS)
+					.map(|(term, _)| term)
+					.collect(),
+			));
+		}
+		Ok(suggestions)
+	}
+
+	/// counts all the hits of a query per value of each kind of facet, most first
+	fn facets(
+		&self,
+		searchers: &[&Searcher],
+		query: &dyn Query,
+		kinds: &[FacetKind],
+		deadline: &Deadline,
+	) -> Result<Vec<FacetCounts>> {
+		if kinds.is_empty() {
+			return Ok(Vec::new());
+		}
+
+		let s_facets = self
+			.index
+			.schema()
+			.get_field("facets")
+			.ok_or_else(|| eyre!("index has no facets, make it again to count them"))?;
+		let mut collector = FacetCollector::for_field(s_facets);
+		for kind in kinds {
+			collector.add_facet(Facet::from_path(&[kind.name()]));
+		}
+		let until = Until {
+			collector: &collector,
+			deadline,
+		};
+		let counts = searchers
+			.iter()
+			.map(|searcher| searcher.search(query, &until))
+			.collect::<tantivy::Result<Vec<_>>>()?;
+		let counts = collector.merge_fruits(counts)?;
+
+		Ok(kinds
+			.iter()
+			.map(|kind| {
+				let mut values: Vec<(String, u64)> = counts
+					.get(Facet::from_path(&[kind.name()]))
+					.map(|(facet, count)| {
+						let value = facet.to_path().last().copied().unwrap_or_default();
+						(value.to_string(), count)
+					})
+					.collect();
+				values.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+				(*kind, values)
+			})
+			.collect())
+	}
+}
+
+/// the hits of a query in each shard, searched in parallel and merged, best first
+///
+/// Each shard is searched for `offset + limit` hits, as any of them may be on the page. Hits that
+/// tie stay in their shard's order, and those of the first shard come first.
+fn top_hits<C, T>(
+	searchers: &[&Searcher],
+	query: &dyn Query,
+	collector: &C,
+	deadline: &Deadline,
+	offset: usize,
+	limit: usize,
+) -> Result<Vec<(T, usize, DocAddress)>>
+where
+	C: Collector<Fruit = Vec<(T, DocAddress)>>,
+	T: PartialOrd + Send,
+{
+	use rayon::prelude::*;
+
+	let collector = &Until {
+		collector,
+		deadline,
+	};
+	let per_shard = searchers
+		.par_iter()
+		.map(|searcher| searcher.search(query, collector))
+		.collect::<tantivy::Result<Vec<_>>>()?;
+	let mut hits: Vec<(T, usize, DocAddress)> = per_shard
+		.into_iter()
+		.enumerate()
+		.flat_map(|(shard, hits)| {
+			hits.into_iter()
+				.map(move |(key, doc_address)| (key, shard, doc_address))
+		})
+		.collect();
+	// stable, so ties keep their order
+	hits.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+	Ok(hits.into_iter().skip(offset).take(limit).collect())
+}
+
+/// What the hits of a query are read and shown from.
+struct HitSources<'a> {
+	query: &'a dyn Query,
+	/// one per shard
+	searchers: &'a [&'a Searcher],
+	/// one per shard
+	snippets: &'a [SnippetGenerator],
+	/// with `--concordance`, the tokenizer of the text and the words of the query in it
+	concordance: Option<(TextAnalyzer, BTreeSet<String>)>,
+}
+
+/// hits sent on their way before the one printing them has caught up, with `--stream`
+const STREAM_BUFFER: usize = 100;
+
+/// A collector sending every hit of a query down a channel as it's found, for `--stream`.
+///
+/// It stops sending once the other end is gone, but still goes through all of the hits.
+struct StreamCollector {
+	shard: usize,
+	hits: SyncSender<(Score, usize, DocAddress)>,
+}
+
+impl Collector for StreamCollector {
+	type Fruit = ();
+	type Child = StreamSegmentCollector;
+
+	fn for_segment(&self, segment_ord: u32, _: &SegmentReader) -> tantivy::Result<Self::Child> {
+		Ok(StreamSegmentCollector {
+			segment_ord,
+			shard: self.shard,
+			hits: Some(self.hits.clone()),
+		})
+	}
+
+	fn requires_scoring(&self) -> bool {
+		true
+	}
+
+	fn merge_fruits(&self, _: Vec<()>) -> tantivy::Result<()> {
+		Ok(())
+	}
+}
+
+/// The hits of a segment for `StreamCollector`.
+struct StreamSegmentCollector {
+	segment_ord: u32,
+	shard: usize,
+	/// none once the hits aren't wanted anymore
+	hits: Option<SyncSender<(Score, usize, DocAddress)>>,
+}
+
+impl SegmentCollector for StreamSegmentCollector {
+	type Fruit = ();
+
+	fn collect(&mut self, doc: DocId, score: Score) {
+		let hit = (score, self.shard, DocAddress::new(self.segment_ord, doc));
+		if let Some(Err(_)) = self.hits.as_ref().map(|hits| hits.send(hit)) {
+			self.hits = None;
+		}
+	}
+
+	fn harvest(self) {}
+}
+
+/// how many documents a search goes through between looking at the time, with `--timeout`
+const DEADLINE_CHECK_DOCS: u32 = 1024;
+
+/// When a search stops going through hits, with `--timeout`.
+struct Deadline {
+	/// none for searches that go through all of them
+	at: Option<Instant>,
+	passed: AtomicBool,
+}
+
+impl Deadline {
+	/// `secs` seconds from now, or never with 0
+	fn after(secs: f64) -> Result<Self> {
+		if !secs.is_finite() || secs < 0.0 {
+			return Err(eyre!(
+				"--timeout must be a number of seconds, or 0 for none"
+			));
+		}
+		Ok(Self {
+			at: (secs > 0.0).then(|| Instant::now() + Duration::from_secs_f64(secs)),
+			passed: AtomicBool::new(false),
+		})
+	}
+
+	/// whether a search has given up on the rest of its hits because of it
+	fn passed(&self) -> bool {
+		self.passed.load(Ordering::Relaxed)
+	}
+
+	/// whether it's passed, looking at the time
+	fn check(&self) -> bool {
+		match self.at {
+			_ if self.passed() => true,
+			Some(at) if Instant::now() >= at => {
+				self.passed.store(true, Ordering::Relaxed);
+				true
+			}
+			_ => false,
+		}
+	}
+}
+
+/// A collector that stops going through the hits once a deadline has passed, keeping those it
+/// found until then.
+///
+/// Without a deadline, it collects just like the collector it wraps.
+struct Until<'a, C> {
+	collector: &'a C,
+	deadline: &'a Deadline,
+}
 
-					if let Some(lang) = lang {
-						if let Some(sub) = split_by_section(&LANG_RX, &text).get(lang) {
-							text = sub.to_owned();
-						}
-					}
+impl<C: Collector> Collector for Until<'_, C> {
+	type Fruit = C::Fruit;
+	type Child = C::Child;
 
-					if let Some(gram) = gram {
-						if let Some(sub) = split_by_section(&GRAM_RX, &text).get(gram) {
-							text = sub.to_owned();
-						}
-					}
+	fn for_segment(&self, segment_ord: u32, reader: &SegmentReader) -> tantivy::Result<C::Child> {
+		self.collector.for_segment(segment_ord, reader)
+	}
 
-					if !full {
-						text = text.replace("\n", " ");
-						if text.len() > 80 {
-							text.truncate(text.char_indices().nth(79).unwrap().0);
-							text.push('…');
-						}
+	fn requires_scoring(&self) -> bool {
+		self.collector.requires_scoring()
+	}
+
+	fn merge_fruits(
+		&self,
+		fruits: Vec<<C::Child as SegmentCollector>::Fruit>,
+	) -> tantivy::Result<C::Fruit> {
+		self.collector.merge_fruits(fruits)
+	}
+
+	fn collect_segment(
+		&self,
+		weight: &dyn Weight,
+		segment_ord: u32,
+		reader: &SegmentReader,
+	) -> tantivy::Result<<C::Child as SegmentCollector>::Fruit> {
+		if self.deadline.at.is_none() {
+			return self.collector.collect_segment(weight, segment_ord, reader);
+		}
+
+		// like tantivy does it, but going through the documents one at a time, which is slower
+		// than the collector's own way for top docs, but can stop
+		let mut collector = self.collector.for_segment(segment_ord, reader)?;
+		let deletes = reader.delete_bitset();
+		let mut scorer = weight.scorer(reader, 1.0)?;
+		let mut doc = scorer.doc();
+		let mut seen = 0;
+		while doc != TERMINATED {
+			if seen % DEADLINE_CHECK_DOCS == 0 && self.deadline.check() {
+				break;
+			}
+			if deletes.is_none_or(|deletes| deletes.is_alive(doc)) {
+				collector.collect(doc, scorer.score());
+			}
+			doc = scorer.advance();
+			seen += 1;
+		}
+		Ok(collector.harvest())
+	}
+}
+
+/// facet counts as JSON: an array of `{value, count}` per kind, most first
+fn facets_json(facets: &[FacetCounts]) -> serde_json::Value {
+	facets
+		.iter()
+		.map(|(kind, counts)| {
+			let counts: Vec<_> = counts
+				.iter()
+				.map(|(value, count)| serde_json::json!({ "value": value, "count": count }))
+				.collect();
+			(kind.name().to_string(), counts.into())
+		})
+		.collect::<serde_json::Map<_, _>>()
+		.into()
+}
+
+/// Counts the blocks and entries that couldn't be read, which long runs skip (and report at the
+/// end) rather than stop at, unless strict.
+#[derive(Debug, Default)]
+struct Unreadable {
+	strict: bool,
+	skipped: AtomicUsize,
+}
+
+impl Unreadable {
+	fn new(strict: bool) -> Self {
+		Self {
+			strict,
+			..Self::default()
+		}
+	}
+
+	/// the entry if it was read, or none if it's skipped (panics if strict)
+	fn skip<T, E: Display>(&self, entry: Result<T, E>) -> Option<T> {
+		match entry {
+			Ok(entry) => Some(entry),
+			Err(err) if self.strict => panic!("error reading {}", err),
+			Err(err) => {
+				error!("skipping {}", err);
+				self.skipped.fetch_add(1, Ordering::Relaxed);
+				None
+			}
+		}
+	}
+
+	fn report(&self) {
+		let skipped = self.skipped.load(Ordering::Relaxed);
+		if skipped > 0 {
+			warn!(
+				"skipped {} unreadable blocks or entries (see above)",
+				skipped
+			);
+		}
+	}
+}
+
+/// makes items `0..n` on as many threads as rayon's pool has, and hands them to `write` in order
+///
+/// Items are taken in order, and each is written as soon as those before it are, so a slow one
+/// holds up writing but not making the ones after it, up to `ORDERED_AHEAD` per thread past the
+/// last written: only those are in memory at once. Ctrl-C (once `interrupt::catch`ed) stops
+/// taking new items, and what was made before them is still written. Stops at the first error.
+fn in_order<T: Send>(
+	n: usize,
+	make: impl Fn(usize) -> Result<T> + Sync,
+	mut write: impl FnMut(T) -> Result<()>,
+) -> Result<()> {
+	let threads = rayon::current_num_threads();
+	let ahead = ORDERED_AHEAD * threads;
+	let next = AtomicUsize::new(0);
+	// how many items were written, or `usize::MAX` once writing is over
+	let written = (Mutex::new(0), Condvar::new());
+	let set_written = |at: usize| {
+		*written.0.lock().unwrap() = at;
+		written.1.notify_all();
+	};
+
+	let (made, results) = sync_channel::<(usize, Result<T>)>(ahead);
+	// threads of their own, as this one waits on them, which a thread of the pool mustn't
+	std::thread::scope(|scope| {
+		for _ in 0..threads {
+			let made = made.clone();
+			let (next, written, make) = (&next, &written, &make);
+			scope.spawn(move || loop {
+				let i = next.fetch_add(1, Ordering::Relaxed);
+				if i >= n || interrupt::interrupted() {
+					return;
+				}
+				let (lock, turned) = written;
+				let at = turned
+					.wait_while(lock.lock().unwrap(), |at| {
+						*at != usize::MAX && i >= *at + ahead
+					})
+					.unwrap();
+				if *at == usize::MAX {
+					return;
+				}
+				drop(at);
+				if made.send((i, make(i))).is_err() {
+					return;
+				}
+			});
+		}
+		drop(made);
+
+		let mut pending = BTreeMap::new();
+		let mut at = 0;
+		let result = results.iter().try_for_each(|(i, item)| {
+			pending.insert(i, item);
+			while let Some(item) = pending.remove(&at) {
+				write(item?)?;
+				at += 1;
+				set_written(at);
+			}
+			Ok(())
+		});
+		// lets the threads go, whether it's done or failed
+		set_written(usize::MAX);
+		drop(results);
+		result
+	})
+}
+
+/// prints an entry with its revision, or the one it redirects to
+fn print_entry(
+	out: &mut dyn Write,
+	store: &blockstore::Store,
+	redirects: &Redirects,
+	refid: Ref,
+) -> Result<()> {
+	let to = redirects.resolve(refid);
+	if to != refid {
+		info!("[{}] redirects to [{}]", refid, to);
+	}
+
+	let entry = store.read_entry(to)?;
+	let (page_id, timestamp) = entry.revision();
+	let (title, text, _) = entry.open();
+	writeln!(out, "{}", title)?;
+	if let Some(id) = page_id {
+		writeln!(out, "page id: {}", id)?;
+	}
+	if let Some(ts) = timestamp {
+		writeln!(out, "revision: {}", xmldump::format_timestamp(ts))?;
+	}
+	writeln!(out, "\n{}", text)?;
+	Ok(())
+}
+
+/// the `(lang, gram, text)` sections of an entry, split as `index make` does: each part of speech
+/// of each language, or the whole language (or entry) where it has no sections under it
+fn entry_sections(text: &str) -> Vec<(Option<String>, Option<String>, String)> {
+	let langs = split_by_level(text, LANG_LEVEL);
+	if langs.is_empty() {
+		return vec![(None, None, text.trim().to_string())];
+	}
+
+	let mut sections = Vec::new();
+	for (lang, text) in langs {
+		let grams = split_by_level(&text, GRAM_LEVEL);
+		if grams.is_empty() {
+			sections.push((Some(lang), None, text));
+			continue;
+		}
+		for (gram, text) in grams {
+			sections.push((Some(lang.clone()), Some(gram), text));
+		}
+	}
+	sections
+}
+
+/// writes the old and new refs of moved entries to `remap.tsv` in the store, `-` for those gone
+fn write_remap(store: &blockstore::Store, remap: &[(Ref, Option<Ref>)]) -> Result<PathBuf> {
+	let path = store.dir.join("remap.tsv");
+	let mut file = BufWriter::new(File::create(&path)?);
+	for (old, new) in remap {
+		match new {
+			Some(new) => writeln!(file, "{}\t{}", old, new)?,
+			None => writeln!(file, "{}\t-", old)?,
+		}
+	}
+	file.flush()?;
+	Ok(path)
+}
+
+/// every title of the store and its ref, from the title map, or the entries if there's none
+fn store_titles(store: &blockstore::Store) -> Result<Vec<(String, Ref)>> {
+	match store.read_title_refs() {
+		Ok(titles) => titles
+			.iter()
+			.map(|title| title.map(|(title, refid)| (title.to_string(), refid)))
+			.collect(),
+		Err(err) => {
+			debug!("reading titles from entries: {}", err);
+			Ok(store
+				.entries()?
+				.map(|entry| entry.map(|(title, _, refid)| (title, refid)))
+				.collect::<Result<_, _>>()?)
+		}
+	}
+}
+
+/// reads the files of the indexes and opens their dictionaries in their readers, and reads the
+/// first `blocks` blocks of the store (into its cache, if it has one), logging how long that took
+///
+/// A block that can't be read is only warned of, as it would be when a query gets to it.
+fn warm_up(
+	store: &blockstore::Store,
+	indexes: &[Index],
+	readers: &[IndexReader],
+	blocks: usize,
+) -> Result<()> {
+	let start = Instant::now();
+	let mut warmed = index::WarmUp::default();
+	for (index, reader) in indexes.iter().zip(readers) {
+		let warm = index::warm_up(index, &reader.searcher())?;
+		warmed.segments += warm.segments;
+		warmed.files += warm.files;
+		warmed.bytes += warm.bytes;
+	}
+	let indexed = start.elapsed();
+
+	let preloaded = store.preload(blocks).unwrap_or_else(|err| {
+		warn!("couldn't read all of the first {} blocks: {}", blocks, err);
+		0
+	});
+	info!(
+		"warmed up in {:?}: {} index files ({} KiB) of {} segments in {:?}, {} blocks in {:?}",
+		start.elapsed(),
+		warmed.files,
+		warmed.bytes / 1024,
+		warmed.segments,
+		indexed,
+		preloaded,
+		start.elapsed() - indexed
+	);
+	Ok(())
+}
+
+/// the store's redirects, or none for stores made before they were recorded
+fn read_redirects(store: &blockstore::Store) -> Redirects {
+	store.read_redirects().unwrap_or_else(|err| {
+		debug!("not following redirects: {}", err);
+		Redirects::default()
+	})
+}
+
+/// Which blocks of the store an index has, and how their sections were split, recorded in the
+/// payload of its commits so that it's updated atomically with them.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IndexedBlocks {
+	/// the highest block id indexed (all blocks up to it are)
+	last_block: Option<u32>,
+	/// the `--section-regex`es it was made with
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	section_regexes: Vec<String>,
+	/// the `--stopwords` it was made without, which `index::open_index` leaves out of searches
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	stopwords: Vec<String>,
+}
+
+impl IndexedBlocks {
+	fn read(index: &Index) -> Result<Self> {
+		let payload = index.load_metas()?.payload.ok_or_else(|| {
+			eyre!("index doesn't record which blocks it has (made by an older wikt?), rebuild it with --force")
+		})?;
+		Ok(serde_json::from_str(&payload)?)
+	}
+
+	/// how the sections of an index's documents were found, to find them again for its hits
+	fn headings(index: &Index) -> Result<Headings> {
+		match Self::read(index) {
+			Ok(indexed) => Headings::parse(&indexed.section_regexes),
+			// older indexes don't have a payload, and were all made with the default headings
+			Err(_) => Ok(Headings::default()),
+		}
+	}
+}
+
+fn index_reader(index: &Index, mode: ReloadMode) -> Result<IndexReader> {
+	Ok(index
+		.reader_builder()
+		.reload_policy(mode.policy())
+		.try_into()?)
+}
+
+/// a query matching any of the values exactly in a `STRING` field, ignoring case and surrounding
+/// whitespace as section names are
+fn any_of(field: Field, values: &[String]) -> Box<dyn Query> {
+	Box::new(BooleanQuery::new(
+		values
+			.iter()
+			.map(|value| {
+				let term = Term::from_field_text(field, &value.trim().to_lowercase());
+				let query: Box<dyn Query> =
+					Box::new(TermQuery::new(term, IndexRecordOption::Basic));
+				(Occur::Should, query)
+			})
+			.collect(),
+	))
+}
+
+/// The sort key of `by_ref`: a ref's block and entry ids, reversed, then the score.
+type RefOrder = (Reverse<(u32, u32)>, Score);
+
+/// top docs by lowest ref first (by block, then entry), and the documents of one entry by score
+fn by_ref(top_docs: TopDocs, s_ref: Field) -> impl Collector<Fruit = Vec<(RefOrder, DocAddress)>> {
+	top_docs.tweak_score(move |segment_reader: &SegmentReader| {
+		let refs = segment_reader
+			.fast_fields()
+			.u64(s_ref)
+			.expect("ref is a fast field");
+
+		// the fast field is `Ref::as_u64`, which has the entry id in its high bits, so it's
+		// decoded rather than compared as it is
+		move |doc: DocId, score: Score| {
+			let refid = Ref::from_u64(refs.get(doc));
+			(Reverse((refid.block_id, refid.entry_id)), score)
+		}
+	})
+}
+
+/// a query for the words of `search`, in order, in any of the fields (as each tokenizes them)
+fn phrase_query(
+	index: &Index,
+	fields: &[Field],
+	boosts: &HashMap<Field, Score>,
+	search: &str,
+	slop: usize,
+) -> Result<Box<dyn Query>> {
+	let mut queries: Vec<(Occur, Box<dyn Query>)> = Vec::with_capacity(fields.len());
+	for field in fields {
+		// positions skip the words a tokenizer leaves out (stopwords), as they do in the index
+		let mut terms = Vec::new();
+		index
+			.tokenizer_for_field(*field)?
+			.token_stream(search)
+			.process(&mut |token| {
+				terms.push((token.position, Term::from_field_text(*field, &token.text)))
+			});
+
+		let query: Box<dyn Query> = match terms.len() {
+			0 => continue,
+			1 => Box::new(TermQuery::new(
+				terms.remove(0).1,
+				IndexRecordOption::WithFreqs,
+			)),
+			_ if slop == 0 => Box::new(PhraseQuery::new_with_offset(terms)),
+			_ => sloppy_phrase_query(terms, slop)?,
+		};
+
+		let query = match boosts.get(field) {
+			Some(boost) => Box::new(BoostQuery::new(query, *boost)),
+			None => query,
+		};
+		queries.push((Occur::Should, query));
+	}
+
+	match queries.len() {
+		0 => Err(eyre!("nothing to search for in {:?}", search)),
+		1 => Ok(queries.remove(0).1),
+		_ => Ok(Box::new(BooleanQuery::new(queries))),
+	}
+}
+
+/// the most phrases a --slop search is made into
+const MAX_SLOP_PHRASES: usize = 1000;
+
+/// a phrase with up to `slop` other words between its words in all, as a phrase query for each
+/// way of spreading them, scored less the more there are (tantivy's phrases have no slop)
+fn sloppy_phrase_query(terms: Vec<(usize, Term)>, slop: usize) -> Result<Box<dyn Query>> {
+	// the extra words before each word but the first, from all none to all `slop` before the last
+	let mut spreads: Vec<Vec<usize>> = vec![Vec::new()];
+	for _ in 1..terms.len() {
+		spreads = spreads
+			.into_iter()
+			.flat_map(|spread| {
+				let used: usize = spread.iter().sum();
+				(0..=slop - used).map(move |gap| {
+					let mut spread = spread.clone();
+					spread.push(gap);
+					spread
+				})
+			})
+			.collect();
+		if spreads.len() > MAX_SLOP_PHRASES {
+			return Err(eyre!(
+				"--slop {} over {} words is too many phrases, try less",
+				slop,
+				terms.len()
+			));
+		}
+	}
+
+	let phrases = spreads
+		.into_iter()
+		.map(|spread| {
+			let extra: usize = spread.iter().sum();
+			let mut shift = 0;
+			let terms = terms
+				.iter()
+				.enumerate()
+				.map(|(i, (position, term))| {
+					if i > 0 {
+						shift += spread[i - 1];
 					}
+					(position + shift, term.clone())
+				})
+				.collect();
+			let phrase: Box<dyn Query> = Box::new(BoostQuery::new(
+				Box::new(PhraseQuery::new_with_offset(terms)),
+				1.0 / (1 + extra) as Score,
+			));
+			(Occur::Should, phrase)
+		})
+		.collect();
+	Ok(Box::new(BooleanQuery::new(phrases)))
+}
+
+/// how many of the largest pages `store make --dry-run` lists
+const DRY_RUN_LARGEST: usize = 10;
+
+/// the furthest --fuzzy goes (tantivy's automata only go up to 2)
+const MAX_FUZZY: u8 = 2;
+
+/// How many close words are suggested for each word of a search not in the index.
+const MAX_SUGGESTIONS: usize = 5;
+
+/// the most hits `serve` skips for a page, as it has to collect all of them to skip them
+const MAX_OFFSET: usize = 10_000;
+
+/// the least memory tantivy's index writer takes per thread
+const WRITER_MEM_MIN: usize = 3_000_000;
+
+/// the most memory tantivy's index writer takes per thread (just under 4GiB)
+const WRITER_MEM_MAX: usize = u32::MAX as usize - 1_000_000;
+
+/// how many documents a worker gathers for a shard before handing them to its writer
+const INDEX_BATCH_DOCS: usize = 1000;
+
+/// how many items per thread `in_order` makes past the last one written
+const ORDERED_AHEAD: usize = 4;
+
+/// the number of threads to use for each of `shards` index writers with `mem` bytes, given as
+/// `threads` (0 for one per CPU, like tantivy does, but shared between the shards so they don't
+/// outnumber the CPUs, and fewer if the memory doesn't go around)
+fn writer_threads_for(mem: usize, threads: usize, shards: usize) -> Result<usize> {
+	let threads = if threads == 0 {
+		let cpus = std::thread::available_parallelism().map_or(1, usize::from);
+		(cpus / shards).min(8).min(mem / WRITER_MEM_MIN).max(1)
+	} else {
+		threads
+	};
+
+	if mem / threads < WRITER_MEM_MIN {
+		return Err(eyre!(
+			"--writer-mem {} is too little for {} threads: each needs at least {} bytes",
+			mem,
+			threads,
+			WRITER_MEM_MIN
+		));
+	}
+	if mem / threads >= WRITER_MEM_MAX {
+		return Err(eyre!(
+			"--writer-mem {} is too much for {} threads: each can use less than {} bytes",
+			mem,
+			threads,
+			WRITER_MEM_MAX
+		));
+	}
+	Ok(threads)
+}
+
+/// all the words of `search`, each within `distance` edits in any of the fields
+fn fuzzy_query(
+	index: &Index,
+	fields: &[Field],
+	boosts: &HashMap<Field, Score>,
+	search: &str,
+	distance: u8,
+) -> Result<Box<dyn Query>> {
+	let mut words: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+	for word in search.split_whitespace() {
+		let mut queries: Vec<(Occur, Box<dyn Query>)> = Vec::with_capacity(fields.len());
+		for field in fields {
+			let mut terms: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+			index
+				.tokenizer_for_field(*field)?
+				.token_stream(word)
+				.process(&mut |token| {
+					let term = Term::from_field_text(*field, &token.text);
+					terms.push((
+						Occur::Must,
+						Box::new(FuzzyTermQuery::new(term, distance, true)),
+					))
+				});
+
+			let query: Box<dyn Query> = match terms.len() {
+				0 => continue,
+				1 => terms.remove(0).1,
+				_ => Box::new(BooleanQuery::new(terms)),
+			};
+
+			let query = match boosts.get(field) {
+				Some(boost) => Box::new(BoostQuery::new(query, *boost)),
+				None => query,
+			};
+			queries.push((Occur::Should, query));
+		}
+
+		match queries.len() {
+			0 => continue,
+			1 => words.push((Occur::Must, queries.remove(0).1)),
+			_ => words.push((Occur::Must, Box::new(BooleanQuery::new(queries)))),
+		}
+	}
+
+	match words.len() {
+		0 => Err(eyre!("nothing to search for in {:?}", search)),
+		1 => Ok(words.remove(0).1),
+		_ => Ok(Box::new(BooleanQuery::new(words))),
+	}
+}
+
+/// calls `f` on up to `limit` items (0 for all) and returns how many that was
+///
+/// Once the limit is reached, the iterator stops being driven, so the rest isn't computed.
+fn for_each_limited<T: Send>(
+	iter: impl rayon::iter::ParallelIterator<Item = T>,
+	limit: usize,
+	f: impl Fn(T) -> Result<()> + Sync,
+) -> Result<usize> {
+	let limit = if limit == 0 { usize::MAX } else { limit };
+	let taken = AtomicUsize::new(0);
+	// stopping at the limit is an error without a report, to tell it from `f` failing
+	let stopped = iter.try_for_each(|item| {
+		let n = taken.fetch_add(1, Ordering::Relaxed);
+		if n >= limit {
+			return Err(None);
+		}
+
+		f(item).map_err(Some)?;
+		if n + 1 == limit {
+			Err(None)
+		} else {
+			Ok(())
+		}
+	});
+	if let Err(Some(err)) = stopped {
+		return Err(err);
+	}
+	Ok(taken.into_inner().min(limit))
+}
+
+/// the documents of each indexed ref
+fn indexed_refs(searcher: &Searcher, s_ref: Field) -> Result<IndexedRefs> {
+	let mut refs = IndexedRefs::new();
+	for (ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+		let fast_refs = segment_reader.fast_fields().u64(s_ref)?;
+		for doc in segment_reader.doc_ids_alive() {
+			let rid = Ref::from_u64(fast_refs.get(doc));
+			refs.entry(rid.block_id)
+				.or_default()
+				.entry(rid.entry_id)
+				.or_insert((0, DocAddress::new(ord.try_into()?, doc)))
+				.0 += 1;
+		}
+	}
+	Ok(refs)
+}
+
+/// the refs of an index (or one shard of it) that don't match the store, with how many documents
+/// each has and what's wrong
+fn verify_refs(
+	store: &blockstore::Store,
+	searcher: &Searcher,
+) -> Result<Vec<(Ref, usize, String)>> {
+	use rayon::prelude::*;
 
-					println!(
-						"\x1b[2mscore={} [{}] ({}/{}) \x1b[1m{}\x1b[0m\n\t{}",
-						score,
+	let schema = searcher.schema();
+	let s_ref = schema.get_field("ref").unwrap();
+	let s_title = schema.get_field("title").unwrap();
+
+	let refs = indexed_refs(searcher, s_ref)?;
+
+	info!(
+		"checking {} refs across {} blocks",
+		refs.values().map(|entries| entries.len()).sum::<usize>(),
+		refs.len()
+	);
+
+	let problems = refs
+		.par_iter()
+		.map(|(block_id, entries)| -> Result<Vec<(Ref, usize, String)>> {
+			let path = store.block_path(*block_id);
+			if !path.exists() {
+				return Ok(entries
+					.iter()
+					.map(|(entry_id, (docs, _))| {
+						(
+							Ref::new(*block_id, *entry_id),
+							*docs,
+							"no such block".into(),
+						)
+					})
+					.collect());
+			}
+
+			let block = store.read_block(path)?;
+			let mut problems = Vec::new();
+			for (entry_id, (docs, address)) in entries {
+				let rid = Ref::new(*block_id, *entry_id);
+				if *entry_id >= block.n {
+					problems.push((rid, *docs, "no such entry".into()));
+					continue;
+				}
+				if store.is_deleted(rid) {
+					problems.push((rid, *docs, "entry was deleted".into()));
+					continue;
+				}
+
+				let (title, _, _) = block.entry(*entry_id)?.open();
+				let doc = searcher.doc(*address)?;
+				let indexed = doc.get_first(s_title).and_then(|v| v.text()).unwrap_or("");
+				if indexed != title {
+					problems.push((
 						rid,
-						lang.unwrap_or("?"),
-						gram.unwrap_or("?"),
-						title,
-						text,
-					);
+						*docs,
+						format!(
+							"title mismatch: index has {:?}, store has {:?}",
+							indexed, title
+						),
+					));
+				}
+			}
+			Ok(problems)
+		})
+		.collect::<Result<Vec<_>>>()?;
+
+	Ok(problems.into_iter().flatten().collect())
+}
+
+/// reads a JSON object of section names (matched lowercased) to canonical names
+fn read_section_map(path: &Path) -> Result<HashMap<String, String>> {
+	let map: HashMap<String, String> = serde_json::from_reader(File::open(path)?)?;
+	Ok(map
+		.into_iter()
+		.map(|(from, to)| (from.to_lowercase(), to.to_lowercase()))
+		.collect())
+}
+
+/// guesses the language of text without a language heading, as a lowercased name like the
+/// headings' and the detector's confidence (0 to 1)
+fn detect_language(text: &str) -> Option<(String, f64)> {
+	let info = whatlang::detect(text)?;
+	Some((info.lang().eng_name().to_lowercase(), info.confidence()))
+}
+
+/// reads one stopword per line, lowercased as the tokenizer sees them, sorted and without
+/// duplicates so that lists of the same words compare equal
+fn read_stopwords(path: &Path) -> Result<Vec<String>> {
+	let mut words: Vec<String> = read_to_string(path)?
+		.lines()
+		.map(|line| line.trim().to_lowercase())
+		.filter(|word| !word.is_empty())
+		.collect();
+	words.sort();
+	words.dedup();
+	Ok(words)
+}
+
+fn millis(d: Duration) -> f64 {
+	d.as_secs_f64() * 1000.0
+}
+
+/// total term frequency and document frequency of every term of `field`
+///
+/// Documents made from a part-of-speech section are skipped, as their text is also in the
+/// document for the enclosing language section.
+fn term_frequencies(
+	searcher: &Searcher,
+	schema: &Schema,
+	field: Field,
+	lang: Option<&str>,
+) -> Result<Vec<(String, u64, u64)>> {
+	let s_lang = schema.get_field("lang").unwrap();
+	let s_gram = schema.get_field("gram").unwrap();
+
+	let mut frequencies: HashMap<String, (u64, u64)> = HashMap::new();
+	for segment_reader in searcher.segment_readers() {
+		let max_doc = usize::try_from(segment_reader.max_doc())?;
+		let mut included = vec![false; max_doc];
+		for doc in segment_reader.doc_ids_alive() {
+			included[usize::try_from(doc)?] = lang.is_none();
+		}
+
+		if let Some(lang) = lang {
+			let term = Term::from_field_text(s_lang, lang);
+			if let Some(mut postings) = segment_reader
+				.inverted_index(s_lang)?
+				.read_postings(&term, IndexRecordOption::Basic)?
+			{
+				while postings.doc() != TERMINATED {
+					let doc = postings.doc();
+					included[usize::try_from(doc)?] = !segment_reader.is_deleted(doc);
+					postings.advance();
+				}
+			}
+		}
+
+		let grams = segment_reader.inverted_index(s_gram)?;
+		let mut terms = grams.terms().stream()?;
+		while terms.advance() {
+			let mut postings =
+				grams.read_postings_from_terminfo(terms.value(), IndexRecordOption::Basic)?;
+			while postings.doc() != TERMINATED {
+				included[usize::try_from(postings.doc())?] = false;
+				postings.advance();
+			}
+		}
+
+		let inverted = segment_reader.inverted_index(field)?;
+		let mut terms = inverted.terms().stream()?;
+		while terms.advance() {
+			let mut postings = inverted
+				.read_postings_from_terminfo(terms.value(), IndexRecordOption::WithFreqs)?;
+			let (mut total, mut docs) = (0, 0);
+			while postings.doc() != TERMINATED {
+				if included[usize::try_from(postings.doc())?] {
+					total += u64::from(postings.term_freq());
+					docs += 1;
 				}
+				postings.advance();
 			}
+
+			if docs > 0 {
+				let term = String::from_utf8_lossy(terms.key()).into_owned();
+				let freq = frequencies.entry(term).or_insert((0, 0));
+				freq.0 += total;
+				freq.1 += docs;
+			}
+		}
+	}
+
+	Ok(frequencies
+		.into_iter()
+		.map(|(term, (total, docs))| (term, total, docs))
+		.collect())
+}
+
+/// keeps a uniform sample of at most `capacity` of the items seen so far (`seen` counting this one)
+fn reservoir_sample<T>(
+	reservoir: &mut Vec<T>,
+	capacity: usize,
+	seen: usize,
+	item: T,
+	random: &mut impl FnMut(usize) -> usize,
+) {
+	if reservoir.len() < capacity {
+		reservoir.push(item);
+	} else {
+		let at = random(seen);
+		if at < reservoir.len() {
+			reservoir[at] = item;
+		}
+	}
+}
+
+/// a generator of numbers below a bound, good enough to spread samples and reproducible from the
+/// seed
+fn xorshift(seed: u64) -> impl FnMut(usize) -> usize {
+	let mut state = seed.max(1);
+	move |bound: usize| {
+		state ^= state << 13;
+		state ^= state >> 7;
+		state ^= state << 17;
+		usize::try_from(state % u64::try_from(bound.max(1)).unwrap()).unwrap()
+	}
+}
+
+/// a byte size in B, KiB, MiB, or GiB
+fn human_bytes(bytes: u64) -> String {
+	if bytes < 1024 {
+		return format!("{} B", bytes);
+	}
+
+	let mut size = bytes as f64 / 1024.0;
+	for unit in ["KiB", "MiB"] {
+		if size < 1024.0 {
+			return format!("{:.1} {}", size, unit);
 		}
+		size /= 1024.0;
 	}
+	format!("{:.1} GiB", size)
+}
 
+/// writes bytes as lines of their offset, 16 bytes in hex, and those that are ASCII
+fn hexdump(out: &mut impl Write, bytes: &[u8]) -> Result<()> {
+	for (n, line) in bytes.chunks(16).enumerate() {
+		write!(out, "{:08x} ", n * 16)?;
+		for column in 0..16 {
+			if column == 8 {
+				write!(out, " ")?;
+			}
+			match line.get(column) {
+				Some(byte) => write!(out, " {:02x}", byte)?,
+				None => write!(out, "   ")?,
+			}
+		}
+		let ascii: String = line
+			.iter()
+			.map(|&byte| {
+				if byte.is_ascii_graphic() || byte == b' ' {
+					char::from(byte)
+				} else {
+					'.'
+				}
+			})
+			.collect();
+		writeln!(out, "  |{}|", ascii)?;
+	}
 	Ok(())
 }
 
-fn schema() -> Schema {
-	let mut schema_builder = Schema::builder();
-	schema_builder.add_text_field("title", TEXT | STORED);
-	schema_builder.add_text_field("text", TEXT);
-	schema_builder.add_u64_field("ref", INDEXED | STORED | FAST);
-	schema_builder.add_text_field("lang", TEXT | STORED);
-	schema_builder.add_text_field("gram", TEXT | STORED);
-	schema_builder.build()
+/// text as one field of a TSV row, with its tabs and line breaks made into spaces
+fn tsv_field(text: &str) -> String {
+	text.replace(&['\t', '\n', '\r'][..], " ")
 }
 
-static LANG_RX: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^\s*==([\w\s]+)==\s*$").unwrap());
+/// the start of text, on one line: all of it if it's at most `len` characters, or else up to
+/// `len` and an ellipsis
+///
+/// A cut text ends before the word it would cut, or the link or template (`[[`, `{{`) it would
+/// cut into, unless that's more than half of it.
+fn excerpt(text: &str, len: usize) -> String {
+	// whitespace at the end doesn't count, so it isn't cut with an ellipsis
+	let text = text.trim_end().replace('\n', " ");
+	let end = match text.char_indices().nth(len) {
+		Some((end, _)) => end,
+		None => return text,
+	};
+
+	let head = &text[..end];
+	let mut cut = end;
+	if !text[end..].starts_with(char::is_whitespace) {
+		cut = head.rfind(char::is_whitespace).unwrap_or(0);
+	}
+	for (open, close) in [("{{", "}}"), ("[[", "]]")] {
+		if let Some(at) = head[..cut].rfind(open) {
+			if !head[at..cut].contains(close) {
+				cut = at;
+			}
+		}
+	}
+	if cut < end / 2 {
+		cut = end;
+	}
+
+	format!("{}…", text[..cut].trim_end())
+}
 
-static GRAM_RX: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^\s*===([\w\s]+)===\s*$").unwrap());
+/// the title and text of the section a hit is of: kept in the index if it was made with
+/// `--store-text`, or else read from the store (as are redirects, to show where they point)
+fn hit_text(
+	store: &blockstore::Store,
+	redirects: &Redirects,
+	headings: &Headings,
+	nameddoc: &BTreeMap<String, Vec<Value>>,
+	rid: Ref,
+) -> Result<(String, String)> {
+	let field = |name| nameddoc.get(name).and_then(|f| f[0].text());
+	let to = redirects.resolve(rid);
+	if let (Some(title), Some(text), true) = (field("title"), field("text"), to == rid) {
+		return Ok((title.to_string(), text.to_string()));
+	}
 
-fn split_by_section(rx: &Regex, text: &str) -> HashMap<String, String> {
-	let mut positions = Vec::with_capacity(10);
-	for cap in rx.captures_iter(&text) {
-		trace!("section capture: {:?}", cap);
+	let (title, text, _) = store.read_entry(to)?.open();
+	let text = headings.section_of(text, field("lang"), field("gram"), field("sub"));
+	Ok((title, text))
+}
 
-		let whole = cap.get(0).unwrap();
-		let name = cap.get(1).unwrap().as_str().to_lowercase();
-		trace!("section name: {:?}", name);
+/// the part of `text` around its best match for the query, with the matched words marked, or the
+/// start of it if the words aren't in it (e.g. they matched the title)
+fn snippet(
+	generator: &SnippetGenerator,
+	text: &str,
+	len: usize,
+	mark: impl Fn(&str) -> String,
+) -> String {
+	let snippet = generator.snippet(text);
+	let fragment = snippet.fragments();
+	if fragment.is_empty() {
+		return excerpt(text, len);
+	}
 
-		positions.push((name, whole.start(), whole.end()));
+	let mut marked = String::with_capacity(fragment.len() + 8 * snippet.highlighted().len());
+	let mut last = 0;
+	for range in snippet.highlighted() {
+		marked.push_str(&fragment[last..range.start]);
+		marked.push_str(&mark(&fragment[range.clone()]));
+		last = range.end;
 	}
+	marked.push_str(&fragment[last..]);
+	marked.replace('\n', " ")
+}
 
-	positions
-		.iter()
-		.enumerate()
-		.map(|(i, (name, _, start))| {
-			let end = positions
-				.get(i + 1)
-				.map(|(_, start, _)| *start)
-				.unwrap_or_else(|| text.len() - 1);
-
-			trace!("section part name={:?} start={} end={}", name, start, end);
-			(
-				name.to_owned(),
-				text.chars()
-					.skip(start - 1)
-					.take(end - start)
-					.collect::<String>()
-					.trim()
-					.to_owned(),
+fn hit_header(
+	style: Style,
+	score: Score,
+	rid: &str,
+	(lang, gram, sub): (Option<&str>, Option<&str>, Option<&str>),
+	title: &str,
+) -> String {
+	let sub = sub.map(|sub| format!("/{}", sub)).unwrap_or_default();
+	format!(
+		"{}{}",
+		style.dim(format!(
+			"score={} [{}] ({}/{}{}) ",
+			score,
+			rid,
+			lang.unwrap_or("?"),
+			gram.unwrap_or("?"),
+			sub,
+		)),
+		style.bold(title)
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// the refs of the hits of each way of matching "cat" in the title and text, with these boosts
+	fn boosted_hits(index: &Index, boosts: &[(&str, Score)]) -> Vec<Vec<u64>> {
+		let schema = index.schema();
+		let field = |name| schema.get_field(name).unwrap();
+		let fields = [field("title"), field("text")];
+		let boosts: HashMap<Field, Score> = boosts.iter().map(|(f, b)| (field(f), *b)).collect();
+
+		let mut parser = QueryParser::for_index(index, fields.to_vec());
+		for (field, boost) in &boosts {
+			parser.set_field_boost(*field, *boost);
+		}
+		let queries = [
+			parser.parse_query("cat").unwrap(),
+			phrase_query(index, &fields, &boosts, "cat", 0).unwrap(),
+			fuzzy_query(index, &fields, &boosts, "cat", 1).unwrap(),
+		];
+
+		let searcher = index.reader().unwrap().searcher();
+		let refs = |query: &dyn Query| {
+			let hits = searcher.search(query, &TopDocs::with_limit(10)).unwrap();
+			hits.into_iter()
+				.map(|(_, address)| {
+					let doc = searcher.doc(address).unwrap();
+					doc.get_first(field("ref")).unwrap().u64_value().unwrap()
+				})
+				.collect()
+		};
+		queries.iter().map(|query| refs(query.as_ref())).collect()
+	}
+
+	#[test]
+	fn boosts_order_hits() {
+		let index = Index::create_in_ram(schema(TextTokenizer::Default, false));
+		register_tokenizers(&index);
+		let schema = index.schema();
+		let field = |name| schema.get_field(name).unwrap();
+		let mut writer = index.writer_with_num_threads(1, 10_000_000).unwrap();
+		// the term only in the title of one, and only in the text of the other, which are as long
+		writer.add_document(doc!(
+			field("title") => "cat",
+			field("text") => "small furry animal",
+			field("ref") => 1_u64,
+		));
+		writer.add_document(doc!(
+			field("title") => "pet",
+			field("text") => "small furry cat",
+			field("ref") => 2_u64,
+		));
+		writer.commit().unwrap();
+
+		assert_eq!(
+			boosted_hits(&index, &[("title", 10.0)]),
+			vec![vec![1, 2]; 3]
+		);
+		assert_eq!(boosted_hits(&index, &[("text", 10.0)]), vec![vec![2, 1]; 3]);
+		assert_eq!(
+			boosted_hits(&index, &[("title", 0.1), ("text", 0.2)]),
+			vec![vec![2, 1]; 3]
+		);
+		// and a field boosted to nothing still matches, only last
+		assert_eq!(boosted_hits(&index, &[("title", 0.0)]), vec![vec![2, 1]; 3]);
+	}
+
+	#[test]
+	fn sorts_by_ref() {
+		let index = Index::create_in_ram(schema(TextTokenizer::Default, false));
+		register_tokenizers(&index);
+		let schema = index.schema();
+		let field = |name| schema.get_field(name).unwrap();
+		let mut writer = index.writer_with_num_threads(1, 10_000_000).unwrap();
+		// as `as_u64`, 243/0 is lower than 50/1
+		let refs = [
+			Ref::new(243, 0),
+			Ref::new(50, 1),
+			Ref::new(50, 0),
+			Ref::new(7, 300),
+			Ref::new(u32::MAX, 2),
+			Ref::new(243, 1),
+		];
+		for refid in &refs {
+			writer.add_document(doc!(
+				field("title") => "cat",
+				field("text") => "cat",
+				field("ref") => refid.as_u64(),
+			));
+		}
+		writer.commit().unwrap();
+
+		let searcher = index.reader().unwrap().searcher();
+		let query = QueryParser::for_index(&index, vec![field("title")])
+			.parse_query("cat")
+			.unwrap();
+		let hits = searcher
+			.search(&query, &by_ref(TopDocs::with_limit(10), field("ref")))
+			.unwrap();
+		let sorted: Vec<Ref> = hits
+			.into_iter()
+			.map(|((Reverse((block_id, entry_id)), _), _)| Ref::new(block_id, entry_id))
+			.collect();
+		let mut expected = refs.to_vec();
+		expected.sort();
+		assert_eq!(sorted, expected);
+		assert_eq!(
+			sorted[..3],
+			[Ref::new(7, 300), Ref::new(50, 0), Ref::new(50, 1)]
+		);
+	}
+
+	#[test]
+	fn excerpt_of_short_text() {
+		assert_eq!(excerpt("", 10), "");
+		assert_eq!(excerpt("a cat", 10), "a cat");
+		// up to the limit is whole, and newlines are spaces
+		assert_eq!(excerpt("a\ncat", 5), "a cat");
+		assert_eq!(excerpt("été", 3), "été");
+		assert_eq!(excerpt("été\n", 3), "été");
+		assert_eq!(excerpt("été \n\n", 4), "été");
+	}
+
+	#[test]
+	fn excerpt_of_long_text() {
+		assert_eq!(excerpt("the cat sat on the mat", 10), "the cat…");
+		// at a space, nothing is cut
+		assert_eq!(excerpt("the cat sat on the mat", 11), "the cat sat…");
+		// a word that's more than half of it is cut
+		assert_eq!(excerpt("a catastrophically long word", 10), "a catastro…");
+		assert_eq!(
+			excerpt("see {{l|en|cat}} and {{l|en|dog}}", 24),
+			"see {{l|en|cat}} and…"
+		);
+		assert_eq!(excerpt("one two three [[four|4]]", 18), "one two three…");
+	}
+
+	#[test]
+	fn excerpt_of_multibyte_text() {
+		// the limit counts characters, not bytes
+		assert_eq!(excerpt("ééééé ààààà", 5), "ééééé…");
+		assert_eq!(excerpt("ééééé ààààà", 6), "ééééé…");
+		assert_eq!(excerpt("ééééé ààààà", 8), "ééééé…");
+		assert_eq!(excerpt("ééééé ààààà", 11), "ééééé ààààà");
+		assert_eq!(excerpt("日本語のテキストです", 4), "日本語の…");
+		assert_eq!(excerpt("😀😀😀 😀😀", 3), "😀😀😀…");
+		assert_eq!(excerpt("😀😀😀 😀😀", 4), "😀😀😀…");
+		assert_eq!(excerpt("a 😀😀😀😀", 3), "a 😀…");
+	}
+
+	#[test]
+	fn verifies_every_shard() {
+		use blockstore::{Block, Entry};
+
+		let dir = std::env::temp_dir().join(format!("wikt-test-verify-{}", std::process::id()));
+		let _ = remove_dir_all(&dir);
+
+		let mut store = blockstore::Store::new(dir.join("store"));
+		store.create().unwrap();
+		let mut n = 0;
+		for entries in [
+			&[("cat", "meow"), ("dog", "woof")][..],
+			&[("cow", "moo")][..],
+		] {
+			let mut block = Block::default();
+			for (title, body) in entries {
+				block.add(Entry::new(title, body)).unwrap();
+			}
+			n += entries.len();
+			store.commit(&mut block, n).unwrap();
+		}
+		let mut store = blockstore::Store::new(dir.join("store"));
+		store.open().unwrap();
+
+		// blocks 2 and 3, one in each shard, with refs that are wrong in the second
+		let shards: [&[(Ref, &str)]; 2] = [
+			&[(Ref::new(2, 0), "cat"), (Ref::new(2, 1), "dog")],
+			&[
+				(Ref::new(3, 0), "cow"),
+				(Ref::new(3, 1), "pig"),
+				(Ref::new(2, 1), "hog"),
+				(Ref::new(9, 0), "ewe"),
+			],
+		];
+		let index_dir = dir.join("index");
+		for (n, docs) in shards.iter().enumerate() {
+			create_dir_all(shard_dir(&index_dir, n)).unwrap();
+			let index = Index::create_in_dir(
+				shard_dir(&index_dir, n),
+				schema(TextTokenizer::Default, false),
 			)
-		})
-		.collect()
+			.unwrap();
+			let schema = index.schema();
+			let field = |name| schema.get_field(name).unwrap();
+			let mut writer = index.writer_with_num_threads(1, 10_000_000).unwrap();
+			for (refid, title) in *docs {
+				writer.add_document(doc!(
+					field("title") => *title,
+					field("ref") => refid.as_u64(),
+				));
+			}
+			writer.commit().unwrap();
+		}
+
+		let problems: Vec<Vec<(Ref, usize, String)>> = open_shards(&index_dir)
+			.unwrap()
+			.iter()
+			.map(|index| verify_refs(&store, &index.reader().unwrap().searcher()).unwrap())
+			.collect();
+		let _ = remove_dir_all(&dir);
+
+		assert_eq!(problems.len(), 2);
+		assert_eq!(problems[0], vec![]);
+		let mut second = problems[1].clone();
+		second.sort();
+		assert_eq!(
+			second,
+			vec![
+				(
+					Ref::new(2, 1),
+					1,
+					r#"title mismatch: index has "hog", store has "dog""#.into()
+				),
+				(Ref::new(3, 1), 1, "no such entry".into()),
+				(Ref::new(9, 0), 1, "no such block".into()),
+			]
+		);
+	}
+
+	#[test]
+	fn phrases_leave_out_stopwords() {
+		use tantivy::collector::Count;
+
+		let dir = std::env::temp_dir().join(format!("wikt-test-stopwords-{}", std::process::id()));
+		let _ = remove_dir_all(&dir);
+		create_dir_all(&dir).unwrap();
+
+		// made as `index make --stopwords` makes it
+		let tokenizer = TextTokenizer::Default;
+		let stopwords = vec!["of".to_string()];
+		let index = Index::create_in_dir(&dir, schema(tokenizer, false)).unwrap();
+		index.tokenizers().register(
+			&tokenizer.name(),
+			tokenizer.analyzer(Some(stopwords.clone())),
+		);
+		let schema = index.schema();
+		let field = |name| schema.get_field(name).unwrap();
+		let mut writer = index.writer_with_num_threads(1, 10_000_000).unwrap();
+		writer.add_document(doc!(
+			field("title") => "stale",
+			field("text") => "out of date",
+			field("ref") => 1_u64,
+		));
+		let mut commit = writer.prepare_commit().unwrap();
+		let indexed = IndexedBlocks {
+			stopwords,
+			..Default::default()
+		};
+		commit.set_payload(&serde_json::to_string(&indexed).unwrap());
+		commit.commit().unwrap();
+		drop(writer);
+
+		// and searched as any other command opens it
+		let index = open_index(&dir).unwrap();
+		let searcher = index.reader().unwrap().searcher();
+		let text = [field("text")];
+		let hits = |query: &dyn Query| searcher.search(query, &Count).unwrap();
+		let phrase = phrase_query(&index, &text, &HashMap::new(), "out of date", 0).unwrap();
+		let parsed = QueryParser::for_index(&index, text.to_vec())
+			.parse_query("\"out of date\"")
+			.unwrap();
+		let found = (hits(phrase.as_ref()), hits(parsed.as_ref()));
+		let _ = remove_dir_all(&dir);
+
+		assert_eq!(found, (1, 1));
+	}
 }
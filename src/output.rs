@@ -0,0 +1,94 @@
+use std::{
+	fmt,
+	fs::File,
+	io::{stdout, BufWriter, IsTerminal, Write},
+	path::Path,
+	str::FromStr,
+};
+
+use color_eyre::{
+	eyre::{eyre, Result},
+	Report,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+	Auto,
+	Always,
+	Never,
+}
+
+impl ColorChoice {
+	/// whether to emit ANSI escapes (auto: only when stdout is a terminal)
+	pub fn enabled(self) -> bool {
+		match self {
+			Self::Auto => stdout().is_terminal(),
+			Self::Always => true,
+			Self::Never => false,
+		}
+	}
+}
+
+impl FromStr for ColorChoice {
+	type Err = Report;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"auto" => Ok(Self::Auto),
+			"always" => Ok(Self::Always),
+			"never" => Ok(Self::Never),
+			_ => Err(eyre!("unknown color choice: {} (auto, always, never)", s)),
+		}
+	}
+}
+
+/// where results are written: the file at `path` (made anew), or else stdout
+pub fn open(path: Option<&Path>) -> Result<Box<dyn Write + Send>> {
+	Ok(match path {
+		Some(path) => {
+			Box::new(BufWriter::new(File::create(path).map_err(|err| {
+				eyre!("can't write to {}: {}", path.display(), err)
+			})?))
+		}
+		None => Box::new(stdout()),
+	})
+}
+
+/// Applies (or not) ANSI styling to output.
+#[derive(Clone, Copy, Debug)]
+pub struct Style {
+	pub color: bool,
+}
+
+impl Style {
+	pub fn new(choice: ColorChoice) -> Self {
+		Self {
+			color: choice.enabled(),
+		}
+	}
+
+	pub fn dim(self, s: impl fmt::Display) -> String {
+		self.wrap("2", s)
+	}
+
+	pub fn bold(self, s: impl fmt::Display) -> String {
+		self.wrap("1", s)
+	}
+
+	/// bold, or surrounded by `**` without color, for words to stand out
+	pub fn highlight(self, s: impl fmt::Display) -> String {
+		if self.color {
+			self.bold(s)
+		} else {
+			format!("**{}**", s)
+		}
+	}
+
+	fn wrap(self, code: &str, s: impl fmt::Display) -> String {
+		if self.color {
+			format!("\x1b[{}m{}\x1b[0m", code, s)
+		} else {
+			s.to_string()
+		}
+	}
+}
@@ -0,0 +1,215 @@
+// deku derives generate these
+#![allow(clippy::manual_div_ceil)]
+
+//! Single-file store container.
+//!
+//! A `.wikt` file is a `u64` header length, the header, and then the raw contents of the store's
+//! files (dictionary, metadata) and blocks (still zstd compressed) back to back. The header lists
+//! where each of those is in the file.
+
+use std::{
+	collections::HashMap,
+	convert::{TryFrom, TryInto},
+	fs::File,
+	io::{copy, BufWriter, Read, Seek, SeekFrom, Take, Write},
+	path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::eyre;
+use deku::prelude::*;
+use log::debug;
+use memmap::{Mmap, MmapOptions};
+
+use crate::error::{Result, WiktError};
+
+const VERSION: u16 = 1;
+
+#[derive(Debug, DekuRead, DekuWrite)]
+#[deku(magic = b"WIKTPACK", endian = "little")]
+struct Header {
+	version: u16,
+	n_files: u32,
+	#[deku(count = "n_files")]
+	files: Vec<FileSpan>,
+	n_blocks: u32,
+	#[deku(count = "n_blocks")]
+	blocks: Vec<BlockSpan>,
+}
+
+#[derive(Debug, DekuRead, DekuWrite)]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian")]
+struct FileSpan {
+	name_len: u16,
+	#[deku(count = "name_len")]
+	name: Vec<u8>,
+	offset: u64,
+	len: u64,
+}
+
+#[derive(Debug, DekuRead, DekuWrite)]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian")]
+struct BlockSpan {
+	id: u32,
+	offset: u64,
+	len: u64,
+}
+
+/// An opened `.wikt` container.
+#[derive(Debug)]
+pub struct Pack {
+	path: PathBuf,
+	files: HashMap<String, (u64, u64)>,
+	blocks: HashMap<u32, (u64, u64)>,
+}
+
+impl Pack {
+	pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+		let path = path.as_ref();
+		let mut file = File::open(path)?;
+
+		let mut len = [0; 8];
+		file.read_exact(&mut len)?;
+		let mut header = vec![0; u64::from_le_bytes(len).try_into()?];
+		file.read_exact(&mut header)?;
+
+		let header = Header::from_bytes((&header, 0))
+			.map_err(|err| eyre!("not a wikt pack file: {:?}: {}", path, err))?
+			.1;
+		if header.version != VERSION {
+			return Err(eyre!("unsupported pack version {}", header.version).into());
+		}
+
+		debug!(
+			"opened pack {:?} files={} blocks={}",
+			path,
+			header.files.len(),
+			header.blocks.len()
+		);
+		Ok(Self {
+			path: path.into(),
+			files: header
+				.files
+				.into_iter()
+				.map(|f| {
+					(
+						String::from_utf8_lossy(&f.name).into_owned(),
+						(f.offset, f.len),
+					)
+				})
+				.collect(),
+			blocks: header
+				.blocks
+				.into_iter()
+				.map(|b| (b.id, (b.offset, b.len)))
+				.collect(),
+		})
+	}
+
+	pub fn block_ids(&self) -> Vec<u32> {
+		let mut ids: Vec<u32> = self.blocks.keys().copied().collect();
+		ids.sort_unstable();
+		ids
+	}
+
+	pub fn read_file(&self, name: &str) -> Result<Vec<u8>> {
+		let (offset, len) = *self
+			.files
+			.get(name)
+			.ok_or_else(|| eyre!("no {} in pack {:?}", name, self.path))?;
+		let mut data = Vec::with_capacity(len.try_into()?);
+		self.span(offset, len)?.read_to_end(&mut data)?;
+		Ok(data)
+	}
+
+	/// maps a file of the pack into memory, or none if it's empty (which can't be mapped)
+	pub fn map_file(&self, name: &str) -> Result<Option<Mmap>> {
+		let (offset, len) = *self
+			.files
+			.get(name)
+			.ok_or_else(|| eyre!("no {} in pack {:?}", name, self.path))?;
+		if len == 0 {
+			return Ok(None);
+		}
+
+		let file = File::open(&self.path)?;
+		// SAFETY: packs are never written to once made
+		let map = unsafe {
+			MmapOptions::new()
+				.offset(offset)
+				.len(len.try_into()?)
+				.map(&file)?
+		};
+		Ok(Some(map))
+	}
+
+	/// the offset and length of a block within the pack file
+	pub fn block_span(&self, id: u32) -> Result<(u64, u64)> {
+		self.blocks
+			.get(&id)
+			.copied()
+			.ok_or(WiktError::MissingBlock(id))
+	}
+
+	fn span(&self, offset: u64, len: u64) -> Result<Take<File>> {
+		let mut file = File::open(&self.path)?;
+		file.seek(SeekFrom::Start(offset))?;
+		Ok(file.take(len))
+	}
+
+	/// writes a pack from named files and block files
+	pub fn write(
+		path: impl AsRef<Path>,
+		files: &[(&str, PathBuf)],
+		blocks: &[(u32, PathBuf)],
+	) -> Result<()> {
+		let mut header = Header {
+			version: VERSION,
+			n_files: files.len().try_into()?,
+			files: Vec::with_capacity(files.len()),
+			n_blocks: blocks.len().try_into()?,
+			blocks: Vec::with_capacity(blocks.len()),
+		};
+
+		for (name, path) in files {
+			header.files.push(FileSpan {
+				name_len: name.len().try_into()?,
+				name: name.as_bytes().to_vec(),
+				offset: 0,
+				len: path.metadata()?.len(),
+			});
+		}
+		for (id, path) in blocks {
+			header.blocks.push(BlockSpan {
+				id: *id,
+				offset: 0,
+				len: path.metadata()?.len(),
+			});
+		}
+
+		// offsets don't change the header's size, so lay the data out right after it
+		let mut offset = 8 + u64::try_from(header.to_bytes()?.len())?;
+		for file in &mut header.files {
+			file.offset = offset;
+			offset += file.len;
+		}
+		for block in &mut header.blocks {
+			block.offset = offset;
+			offset += block.len;
+		}
+
+		let header = header.to_bytes()?;
+		let mut out = BufWriter::new(File::create(path)?);
+		out.write_all(&u64::try_from(header.len())?.to_le_bytes())?;
+		out.write_all(&header)?;
+		for path in files
+			.iter()
+			.map(|(_, p)| p)
+			.chain(blocks.iter().map(|(_, p)| p))
+		{
+			copy(&mut File::open(path)?, &mut out)?;
+		}
+		out.flush()?;
+
+		Ok(())
+	}
+}
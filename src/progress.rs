@@ -0,0 +1,140 @@
+use std::{
+	io::{stderr, IsTerminal, Write},
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+use log::{info, LevelFilter};
+
+/// How often the bar is redrawn, at most.
+const DRAW_EVERY: Duration = Duration::from_millis(100);
+
+/// How often progress is logged instead, when there's no bar.
+const LOG_EVERY: Duration = Duration::from_secs(30);
+
+const BAR_WIDTH: usize = 30;
+
+/// Progress through a long run: how far along, how fast, and how long until it's done.
+///
+/// It's a bar on stderr when that's a terminal and the log level shows info, or else a log line
+/// every so often (nothing at all below info). Updates can come from any thread. Without a total,
+/// it only shows how much is done and how fast.
+pub struct Progress {
+	/// what's being counted, e.g. "pages"
+	unit: &'static str,
+	/// the amount of work, in whatever `update` measures it in (bytes, entries...), or 0 if it
+	/// isn't known
+	total: u64,
+	start: Instant,
+	bar: bool,
+	last: Mutex<Instant>,
+}
+
+impl Progress {
+	pub fn new(unit: &'static str, total: u64) -> Self {
+		let start = Instant::now();
+		Self {
+			unit,
+			total,
+			start,
+			bar: log::max_level() >= LevelFilter::Info && stderr().is_terminal(),
+			last: Mutex::new(start),
+		}
+	}
+
+	/// records that `done` of the total is done, which was `count` units
+	///
+	/// Cheap enough to call for every unit: it only draws (or logs) now and then.
+	pub fn update(&self, done: u64, count: u64) {
+		let every = if self.bar { DRAW_EVERY } else { LOG_EVERY };
+		let mut last = match self.last.try_lock() {
+			Ok(last) => last,
+			// another thread is drawing already
+			Err(_) => return,
+		};
+		if last.elapsed() < every {
+			return;
+		}
+		*last = Instant::now();
+
+		let elapsed = self.start.elapsed();
+		let rate = count as f64 / elapsed.as_secs_f64();
+		if self.total == 0 {
+			// there's no telling how far along it is, only how fast it's going
+			if self.bar {
+				eprint!(
+					"\x1b[2K\x1b[0G{} {} ({:.0}/s) {} elapsed",
+					count,
+					self.unit,
+					rate,
+					format_duration(elapsed)
+				);
+				stderr().flush().ok();
+			} else {
+				info!(
+					"{} {} ({:.0}/s) in {}",
+					count,
+					self.unit,
+					rate,
+					format_duration(elapsed)
+				);
+			}
+			return;
+		}
+
+		let fraction = (done as f64 / self.total as f64).min(1.0);
+		let eta = if fraction > 0.0 {
+			let left = elapsed.as_secs_f64() * (1.0 - fraction) / fraction;
+			format_duration(Duration::from_secs_f64(left))
+		} else {
+			String::from("?")
+		};
+
+		if self.bar {
+			let filled = (fraction * BAR_WIDTH as f64) as usize;
+			eprint!(
+				"\x1b[2K\x1b[0G[{}{}] {:>3.0}% {} {} ({:.0}/s) {} elapsed, ETA {}",
+				"#".repeat(filled),
+				"-".repeat(BAR_WIDTH - filled),
+				fraction * 100.0,
+				count,
+				self.unit,
+				rate,
+				format_duration(elapsed),
+				eta
+			);
+			stderr().flush().ok();
+		} else {
+			info!(
+				"{:.0}% done: {} {} ({:.0}/s) in {}, ETA {}",
+				fraction * 100.0,
+				count,
+				self.unit,
+				rate,
+				format_duration(elapsed),
+				eta
+			);
+		}
+	}
+
+	/// clears the bar, if there's one
+	pub fn finish(&self) {
+		if self.bar {
+			eprint!("\x1b[2K\x1b[0G");
+			stderr().flush().ok();
+		}
+	}
+}
+
+/// a duration as e.g. `1h02m03s`, `4m05s`, or `6s`
+fn format_duration(d: Duration) -> String {
+	let secs = d.as_secs();
+	let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+	if h > 0 {
+		format!("{}h{:02}m{:02}s", h, m, s)
+	} else if m > 0 {
+		format!("{}m{:02}s", m, s)
+	} else {
+		format!("{}s", s)
+	}
+}
@@ -0,0 +1,198 @@
+// deku derives generate these
+#![allow(clippy::manual_div_ceil)]
+
+//! Which entries of a store are redirects to others.
+
+use std::{
+	collections::{hash_map::DefaultHasher, HashMap},
+	convert::TryFrom,
+	fs::File,
+	hash::{Hash, Hasher},
+	io::Write,
+	path::Path,
+};
+
+use color_eyre::eyre::Result;
+use deku::prelude::*;
+use log::debug;
+
+use crate::blockstore::Ref;
+
+/// How many redirects in a row are followed before giving up (they may loop).
+const MAX_HOPS: usize = 8;
+
+/// The redirects of a store: which entries are redirects, and to which entry.
+#[derive(Debug, Default)]
+pub struct Redirects(Vec<Redirect>);
+
+#[derive(Clone, Copy, Debug, DekuRead, DekuWrite)]
+struct Redirect {
+	from: Ref,
+	to: Ref,
+}
+
+#[derive(Debug, DekuRead, DekuWrite)]
+struct RedirectsFile {
+	#[deku(endian = "little")]
+	count: u64,
+	#[deku(count = "count")]
+	redirects: Vec<Redirect>,
+}
+
+impl Redirects {
+	/// where `from` redirects to, following chains of redirects
+	///
+	/// Returns `from` itself if it isn't a redirect.
+	pub fn resolve(&self, from: Ref) -> Ref {
+		let mut current = from;
+		for _ in 0..MAX_HOPS {
+			match self.get(current) {
+				Some(to) if to != from => current = to,
+				_ => break,
+			}
+		}
+		current
+	}
+
+	fn get(&self, from: Ref) -> Option<Ref> {
+		self.0
+			.binary_search_by_key(&key(from), |r| key(r.from))
+			.ok()
+			.map(|i| self.0[i].to)
+	}
+
+	/// moves redirects to and from entries that have changed refs (e.g. after a compaction), and
+	/// drops those to or from entries that are gone (no new ref)
+	pub fn remap(&mut self, remap: &[(Ref, Option<Ref>)]) {
+		let remap: HashMap<u64, Option<Ref>> = remap
+			.iter()
+			.map(|(old, new)| (old.as_u64(), *new))
+			.collect();
+		let moved = |r: Ref| remap.get(&r.as_u64()).copied().unwrap_or(Some(r));
+
+		self.0 = self
+			.0
+			.iter()
+			.filter_map(|redirect| {
+				Some(Redirect {
+					from: moved(redirect.from)?,
+					to: moved(redirect.to)?,
+				})
+			})
+			.collect();
+		self.0.sort_unstable_by_key(|r| key(r.from));
+	}
+
+	/// makes `from` redirect to `to`, or no longer be a redirect if that's `None`, returning
+	/// whether that changed anything
+	pub fn set(&mut self, from: Ref, to: Option<Ref>) -> bool {
+		match (self.0.binary_search_by_key(&key(from), |r| key(r.from)), to) {
+			(Ok(i), Some(to)) if key(self.0[i].to) == key(to) => false,
+			(Ok(i), Some(to)) => {
+				self.0[i].to = to;
+				true
+			}
+			(Ok(i), None) => {
+				self.0.remove(i);
+				true
+			}
+			(Err(i), Some(to)) => {
+				self.0.insert(i, Redirect { from, to });
+				true
+			}
+			(Err(_), None) => false,
+		}
+	}
+
+	/// adds the redirects of another store (e.g. merged into this one, with its refs remapped)
+	pub fn append(&mut self, other: Redirects) {
+		self.0.extend(other.0);
+		self.0.sort_unstable_by_key(|r| key(r.from));
+	}
+
+	/// writes the redirects to a file
+	pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+		let data = RedirectsFile {
+			count: u64::try_from(self.0.len())?,
+			redirects: self.0.clone(),
+		}
+		.to_bytes()?;
+
+		let mut file = File::create(path)?;
+		file.write_all(&data)?;
+		Ok(())
+	}
+
+	/// reads redirects written by `write`
+	pub fn from_bytes(data: &[u8]) -> Result<Self> {
+		let file = RedirectsFile::from_bytes((data, 0))?.1;
+		Ok(Self(file.redirects))
+	}
+}
+
+/// Collects redirects while a store is made, and resolves their targets to refs once it's done.
+///
+/// Targets may come before or after their redirects in the dump, so this keeps a hash of every
+/// title stored until the end.
+#[derive(Debug, Default)]
+pub struct RedirectsBuilder {
+	refs: HashMap<u64, Ref>,
+	unresolved: Vec<(Ref, String)>,
+	block_titles: Vec<u64>,
+	block_redirects: Vec<(u32, String)>,
+}
+
+impl RedirectsBuilder {
+	/// records the next entry of the current block, and the title it redirects to, if any
+	pub fn add(&mut self, title: &str, redirect: Option<&str>) {
+		if let Some(to) = redirect {
+			let entry_id = u32::try_from(self.block_titles.len()).unwrap();
+			self.block_redirects.push((entry_id, to.to_string()));
+		}
+		self.block_titles.push(title_hash(title));
+	}
+
+	/// assigns refs to the entries added since the last commit, once their block id is known
+	pub fn commit(&mut self, block_id: u32) {
+		for (entry_id, hash) in self.block_titles.drain(..).enumerate() {
+			let entry_id = u32::try_from(entry_id).unwrap();
+			self.refs
+				.entry(hash)
+				.or_insert_with(|| Ref::new(block_id, entry_id));
+		}
+
+		for (entry_id, to) in self.block_redirects.drain(..) {
+			self.unresolved.push((Ref::new(block_id, entry_id), to));
+		}
+	}
+
+	/// resolves every redirect, and returns those whose target isn't in the store
+	pub fn finish(self) -> (Redirects, Vec<String>) {
+		let mut redirects = Vec::with_capacity(self.unresolved.len());
+		let mut missing = Vec::new();
+		for (from, to) in self.unresolved {
+			match self.refs.get(&title_hash(&to)) {
+				Some(to) => redirects.push(Redirect { from, to: *to }),
+				None => missing.push(to),
+			}
+		}
+
+		debug!(
+			"resolved {} redirects, {} missing",
+			redirects.len(),
+			missing.len()
+		);
+		redirects.sort_unstable_by_key(|r| key(r.from));
+		(Redirects(redirects), missing)
+	}
+}
+
+fn key(r: Ref) -> (u32, u32) {
+	(r.block_id, r.entry_id)
+}
+
+pub(crate) fn title_hash(title: &str) -> u64 {
+	let mut hasher = DefaultHasher::new();
+	title.hash(&mut hasher);
+	hasher.finish()
+}
@@ -0,0 +1,337 @@
+//! Splitting the wikitext of entries into sections by their headings.
+
+use color_eyre::eyre::{eyre, Result};
+use log::trace;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Heading levels of language sections (`==English==`).
+pub const LANG_LEVEL: usize = 2;
+
+/// Heading levels of part-of-speech sections (`===Noun===`).
+pub const GRAM_LEVEL: usize = 3;
+
+/// Heading levels of subsections of parts of speech (`====Synonyms====`).
+pub const SUB_LEVEL: usize = 4;
+
+/// A heading of any level: the `=`s before and after must be as many, which is its level.
+static HEADING_RX: Lazy<Regex> =
+	Lazy::new(|| Regex::new(r"(?m)^[ \t]*(=+)([^=\n]+)(=+)[ \t]*$").unwrap());
+
+/// The levels headings can be given regexes for, by the name of the field they fill.
+const LEVEL_NAMES: [(&str, usize); 3] = [
+	("lang", LANG_LEVEL),
+	("gram", GRAM_LEVEL),
+	("sub", SUB_LEVEL),
+];
+
+/// How the headings of each level are found: `==Lang==`-style, unless a regex is given for it.
+///
+/// Wiktionary's sections are languages, then parts of speech, then subsections, but other wikis
+/// mark theirs otherwise, or mean something else by them. A regex finds the headings of a level
+/// (`^` and `$` match at lines), its first group capturing the section's name, and a section runs
+/// until the next heading it finds.
+#[derive(Clone, Debug, Default)]
+pub struct Headings {
+	/// the level, the regex as given, and compiled
+	custom: Vec<(usize, String, Regex)>,
+}
+
+impl Headings {
+	/// reads regexes given as `lang=REGEX`, `gram=REGEX`, or `sub=REGEX`, checking that each
+	/// compiles and has a group for the name
+	pub fn parse(specs: &[String]) -> Result<Self> {
+		let mut custom: Vec<(usize, String, Regex)> = Vec::with_capacity(specs.len());
+		for spec in specs {
+			let (name, pattern) = spec
+				.split_once('=')
+				.ok_or_else(|| eyre!("section regex {:?} isn't LEVEL=REGEX", spec))?;
+			let level = LEVEL_NAMES
+				.iter()
+				.find(|(level_name, _)| *level_name == name)
+				.map(|(_, level)| *level)
+				.ok_or_else(|| eyre!("no section level {:?}, it's lang, gram, or sub", name))?;
+			if custom.iter().any(|(l, _, _)| *l == level) {
+				return Err(eyre!("more than one section regex for {}", name));
+			}
+
+			let rx = Regex::new(&format!("(?m){}", pattern))
+				.map_err(|err| eyre!("bad section regex for {}: {}", name, err))?;
+			if rx.captures_len() < 2 {
+				return Err(eyre!(
+					"section regex for {} has no group `(...)` to capture the name",
+					name
+				));
+			}
+			custom.push((level, spec.clone(), rx));
+		}
+
+		Ok(Self { custom })
+	}
+
+	/// the regexes as they were given, to make the same `Headings` again with `parse`
+	pub fn specs(&self) -> Vec<String> {
+		self.custom
+			.iter()
+			.map(|(_, spec, _)| spec.clone())
+			.collect()
+	}
+
+	/// splits text into `(name, body)` sections at headings of a level, like `split_by_level`
+	pub fn split(&self, text: &str, level: usize) -> Vec<(String, String)> {
+		let spans = match self.custom.iter().find(|(l, _, _)| *l == level) {
+			Some((_, _, rx)) => custom_spans(text, rx),
+			None => section_spans(text, level),
+		};
+		spans
+			.into_iter()
+			.map(|(name, _, start, end)| (name, text[start..end].trim().to_owned()))
+			.collect()
+	}
+
+	/// the bodies of all sections of this level with this name, like `sections_named`
+	pub fn sections_named(&self, level: usize, text: &str, name: &str) -> Vec<String> {
+		let name = name.to_lowercase();
+		self.split(text, level)
+			.into_iter()
+			.filter(|(section, _)| *section == name)
+			.map(|(_, body)| body)
+			.collect()
+	}
+
+	/// the part of an entry's text a document was made from, like `section_of`
+	pub fn section_of(
+		&self,
+		mut text: String,
+		lang: Option<&str>,
+		gram: Option<&str>,
+		sub: Option<&str>,
+	) -> String {
+		for (level, name) in [(LANG_LEVEL, lang), (GRAM_LEVEL, gram), (SUB_LEVEL, sub)] {
+			if let Some(name) = name {
+				let sections = self.sections_named(level, &text, name);
+				if !sections.is_empty() {
+					text = sections.join("\n\n");
+				}
+			}
+		}
+
+		text
+	}
+}
+
+/// the part of an entry's text a document was made from, by its lang, gram, and sub
+///
+/// The index doesn't say which of several sections of the same name (e.g. two `===Noun===`) a
+/// document is of, so all of them are kept, in order, rather than only the first.
+pub fn section_of(
+	text: String,
+	lang: Option<&str>,
+	gram: Option<&str>,
+	sub: Option<&str>,
+) -> String {
+	Headings::default().section_of(text, lang, gram, sub)
+}
+
+/// the bodies of all sections of this level with this name (case-insensitive)
+pub fn sections_named(level: usize, text: &str, name: &str) -> Vec<String> {
+	Headings::default().sections_named(level, text, name)
+}
+
+/// splits text into `(name, body)` sections at headings of a level (2 for `==Lang==`, etc)
+///
+/// A section runs until the next heading of the same level or higher, so it includes its
+/// subsections. Sections are in document order, and repeated headings (e.g. a Noun under two
+/// etymologies) are all kept. Names are lowercased.
+pub fn split_by_level(text: &str, level: usize) -> Vec<(String, String)> {
+	Headings::default().split(text, level)
+}
+
+/// the sections of this level with any of these names (lowercased), headings and all, or none if
+/// the text has no sections of this level at all
+///
+/// What isn't in one of those sections, like text before the first heading, is left out.
+pub fn keep_sections(text: &str, level: usize, names: &[String]) -> Option<String> {
+	let spans = section_spans(text, level);
+	if spans.is_empty() {
+		return None;
+	}
+
+	let kept: Vec<&str> = spans
+		.iter()
+		.filter(|(name, _, _, _)| names.contains(name))
+		.map(|(_, heading, _, end)| text[*heading..*end].trim())
+		.collect();
+	Some(kept.join("\n\n"))
+}
+
+/// the lowercased name, heading start, body start, and end of each section of a level
+fn section_spans(text: &str, level: usize) -> Vec<(String, usize, usize, usize)> {
+	let mut headings = Vec::with_capacity(10);
+	for cap in HEADING_RX.captures_iter(text) {
+		trace!("heading capture: {:?}", cap);
+
+		let (open, close) = (cap[1].len(), cap[3].len());
+		if open != close {
+			continue;
+		}
+
+		let whole = cap.get(0).unwrap();
+		headings.push((
+			open,
+			cap[2].trim().to_lowercase(),
+			whole.start(),
+			whole.end(),
+		));
+	}
+
+	headings
+		.iter()
+		.enumerate()
+		.filter(|(_, (l, _, _, _))| *l == level)
+		.map(|(i, (_, name, heading, start))| {
+			// regex positions are byte offsets, so these slice on char boundaries
+			let end = headings[i + 1..]
+				.iter()
+				.find(|(l, _, _, _)| *l <= level)
+				.map(|(_, _, start, _)| *start)
+				.unwrap_or(text.len());
+
+			trace!("section part name={:?} start={} end={}", name, start, end);
+			(name.to_owned(), *heading, *start, end)
+		})
+		.collect()
+}
+
+/// the lowercased name, heading start, body start, and end of each section found by a regex
+fn custom_spans(text: &str, rx: &Regex) -> Vec<(String, usize, usize, usize)> {
+	let headings: Vec<(String, usize, usize)> = rx
+		.captures_iter(text)
+		.filter_map(|cap| {
+			let whole = cap.get(0).unwrap();
+			let name = cap.get(1)?.as_str().trim();
+			if name.is_empty() {
+				return None;
+			}
+			Some((name.to_lowercase(), whole.start(), whole.end()))
+		})
+		.collect();
+
+	headings
+		.iter()
+		.enumerate()
+		.map(|(i, (name, heading, start))| {
+			let end = headings.get(i + 1).map_or(text.len(), |(_, next, _)| *next);
+			(name.clone(), *heading, *start, end)
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const TWO_NOUNS: &str = "\
+==English==
+===Etymology 1===
+From Old English.
+===Noun===
+# a bark
+===Etymology 2===
+From Old Norse.
+===Noun===
+# a boat
+==French==
+===Noun===
+# un bateau
+";
+
+	#[test]
+	fn split_keeps_repeated_headings_in_order() {
+		let english = &sections_named(LANG_LEVEL, TWO_NOUNS, "English")[0];
+		let grams = split_by_level(english, GRAM_LEVEL);
+		let names: Vec<&str> = grams.iter().map(|(name, _)| name.as_str()).collect();
+		assert_eq!(names, ["etymology 1", "noun", "etymology 2", "noun"]);
+		assert_eq!(grams[1].1, "# a bark");
+		assert_eq!(grams[3].1, "# a boat");
+	}
+
+	#[test]
+	fn sections_named_keeps_all_of_a_name() {
+		let english = &sections_named(LANG_LEVEL, TWO_NOUNS, "english")[0];
+		assert_eq!(
+			sections_named(GRAM_LEVEL, english, "Noun"),
+			["# a bark", "# a boat"]
+		);
+
+		// across languages, when not within one
+		assert_eq!(
+			sections_named(GRAM_LEVEL, TWO_NOUNS, "NOUN"),
+			["# a bark", "# a boat", "# un bateau"]
+		);
+		assert!(sections_named(GRAM_LEVEL, english, "verb").is_empty());
+	}
+
+	#[test]
+	fn sections_run_until_a_heading_as_high() {
+		let langs = split_by_level(TWO_NOUNS, LANG_LEVEL);
+		assert_eq!(langs.len(), 2);
+		assert!(langs[0].1.starts_with("===Etymology 1==="));
+		assert!(langs[0].1.ends_with("# a boat"));
+		assert_eq!(
+			langs[1],
+			("french".into(), "===Noun===\n# un bateau".into())
+		);
+	}
+
+	#[test]
+	fn section_of_joins_same_named_sections() {
+		assert_eq!(
+			section_of(TWO_NOUNS.into(), Some("english"), Some("noun"), None),
+			"# a bark\n\n# a boat"
+		);
+		assert_eq!(
+			section_of(TWO_NOUNS.into(), Some("French"), Some("Noun"), None),
+			"# un bateau"
+		);
+
+		// a section that isn't there leaves the text as it was at that level
+		assert_eq!(
+			section_of(TWO_NOUNS.into(), Some("english"), Some("verb"), None),
+			sections_named(LANG_LEVEL, TWO_NOUNS, "english")[0]
+		);
+		assert_eq!(section_of(TWO_NOUNS.into(), None, None, None), TWO_NOUNS);
+	}
+
+	#[test]
+	fn split_on_multibyte_text() {
+		// the last character of the text is multibyte, and so are the first and last of sections
+		let text = "==Français==\n===Nom===\né, un café à Genève\n==日本語==\n===名詞===\n猫、ねこ";
+		assert_eq!(
+			split_by_level(text, LANG_LEVEL),
+			[
+				("français".into(), "===Nom===\né, un café à Genève".into()),
+				("日本語".into(), "===名詞===\n猫、ねこ".into()),
+			]
+		);
+		assert_eq!(
+			split_by_level(text, GRAM_LEVEL),
+			[
+				("nom".into(), "é, un café à Genève".into()),
+				("名詞".into(), "猫、ねこ".into()),
+			]
+		);
+		assert_eq!(sections_named(GRAM_LEVEL, text, "名詞"), ["猫、ねこ"]);
+	}
+
+	#[test]
+	fn multibyte_names_fold_case() {
+		let text = "==ÉLÉMENT==\nÀ\n==Ελληνικά==\nΩ";
+		assert_eq!(sections_named(LANG_LEVEL, text, "élément"), ["À"]);
+		assert_eq!(sections_named(LANG_LEVEL, text, "ΕΛΛΗΝΙΚΆ"), ["Ω"]);
+		assert_eq!(
+			keep_sections(text, LANG_LEVEL, &["ελληνικά".into()]).as_deref(),
+			Some("==Ελληνικά==\nΩ")
+		);
+	}
+}
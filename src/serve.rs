@@ -0,0 +1,346 @@
+use std::{
+	fmt::Display,
+	io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+	net::{TcpListener, TcpStream},
+	sync::atomic::{AtomicUsize, Ordering},
+	thread,
+	time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{eyre, Result};
+use log::{debug, info};
+use serde_json::Value;
+
+/// How long a connection may take to send its request before it's dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long writing a response may take before the connection is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The most bytes of request line and headers read, past which the request is refused.
+const MAX_HEAD: u64 = 8192;
+
+/// The most headers a request may have.
+const MAX_HEADERS: usize = 100;
+
+/// A GET request, as much of it as the handlers need.
+#[derive(Debug)]
+pub struct Request {
+	pub path: String,
+	params: Vec<(String, String)>,
+}
+
+impl Request {
+	/// the first value of a query parameter
+	pub fn param(&self, name: &str) -> Option<&str> {
+		self.params
+			.iter()
+			.find(|(key, _)| key == name)
+			.map(|(_, value)| value.as_str())
+	}
+
+	/// all the values of a (repeated) query parameter
+	pub fn params(&self, name: &str) -> Vec<String> {
+		self.params
+			.iter()
+			.filter(|(key, _)| key == name)
+			.map(|(_, value)| value.clone())
+			.collect()
+	}
+}
+
+/// A JSON response.
+#[derive(Debug)]
+pub struct Response {
+	status: u16,
+	body: Value,
+}
+
+impl Response {
+	pub fn ok(body: Value) -> Self {
+		Self { status: 200, body }
+	}
+
+	/// an error, as `{"error": message}`
+	pub fn error(status: u16, message: impl Display) -> Self {
+		Self {
+			status,
+			body: serde_json::json!({ "error": message.to_string() }),
+		}
+	}
+}
+
+/// answers HTTP requests on `bind` with `handler`, forever
+///
+/// Each connection gets a thread and a single request, which is enough for a frontend on the same
+/// machine; put a real server in front of it for anything else. Past `max_connections` open at
+/// once, new ones are answered 503 straight away.
+pub fn serve(
+	bind: &str,
+	max_connections: usize,
+	handler: impl Fn(&Request) -> Response + Sync,
+) -> Result<()> {
+	let listener = TcpListener::bind(bind)?;
+	info!("listening on http://{}", listener.local_addr()?);
+
+	let open = AtomicUsize::new(0);
+	thread::scope(|scope| {
+		for stream in listener.incoming() {
+			let mut stream = match stream {
+				Ok(stream) => stream,
+				Err(err) => {
+					debug!("failed to accept a connection: {}", err);
+					continue;
+				}
+			};
+
+			if open.fetch_add(1, Ordering::SeqCst) >= max_connections {
+				open.fetch_sub(1, Ordering::SeqCst);
+				info!("refused a connection: {} are open already", max_connections);
+				let busy = Response::error(503, "too many connections, try again shortly");
+				if let Err(err) = respond(&mut stream, &busy) {
+					debug!("connection failed: {}", err);
+				}
+				continue;
+			}
+
+			let (handler, open) = (&handler, &open);
+			scope.spawn(move || {
+				if let Err(err) = connection(stream, handler) {
+					debug!("connection failed: {}", err);
+				}
+				open.fetch_sub(1, Ordering::SeqCst);
+			});
+		}
+	});
+
+	Ok(())
+}
+
+fn connection(mut stream: TcpStream, handler: impl Fn(&Request) -> Response) -> Result<()> {
+	let start = Instant::now();
+	let (method, target, response) = match read_head(&stream, start + READ_TIMEOUT)? {
+		Ok((method, target)) => {
+			let response = if method == "GET" {
+				handler(&parse_target(&target))
+			} else {
+				Response::error(405, format!("{} isn't supported, only GET", method))
+			};
+			(method, target, response)
+		}
+		Err(refused) => (String::from("-"), String::from("-"), refused),
+	};
+
+	respond(&mut stream, &response)?;
+	info!(
+		"{} {} {} in {:?}",
+		method,
+		target,
+		response.status,
+		start.elapsed()
+	);
+	Ok(())
+}
+
+/// reads the request line and headers, returning the method and target, or the response to a
+/// request too big to read
+///
+/// No more than `MAX_HEAD` bytes and `MAX_HEADERS` headers are read, and only until `deadline`,
+/// however slowly they come.
+fn read_head(
+	stream: &TcpStream,
+	deadline: Instant,
+) -> Result<std::result::Result<(String, String), Response>> {
+	let mut reader = BufReader::new(Timed { stream, deadline }.take(MAX_HEAD));
+	let mut line = String::new();
+	reader.read_line(&mut line)?;
+	if !line.ends_with('\n') && reader.get_ref().limit() == 0 {
+		return Ok(Err(Response::error(414, "request line too long")));
+	}
+	let mut parts = line.split_whitespace();
+	let (method, target) = match (parts.next(), parts.next()) {
+		(Some(method), Some(target)) => (method.to_string(), target.to_string()),
+		_ => return Err(eyre!("bad request line: {:?}", line)),
+	};
+
+	// the headers don't matter, but have to be read before answering
+	let mut headers = 0;
+	loop {
+		let mut header = String::new();
+		if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+			break;
+		}
+		headers += 1;
+		let cut_short = !header.ends_with('\n') && reader.get_ref().limit() == 0;
+		if headers > MAX_HEADERS || cut_short {
+			return Ok(Err(Response::error(431, "headers too large")));
+		}
+	}
+
+	Ok(Ok((method, target)))
+}
+
+fn respond(stream: &mut TcpStream, response: &Response) -> io::Result<()> {
+	stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+	let body = response.body.to_string();
+	write!(
+		stream,
+		"HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+		response.status,
+		reason(response.status),
+		body.len(),
+		body
+	)
+}
+
+/// Reads a stream until a deadline, rather than with a timeout for each read, so a request sent
+/// byte by byte still can't hold its connection open for long.
+struct Timed<'a> {
+	stream: &'a TcpStream,
+	deadline: Instant,
+}
+
+impl Read for Timed<'_> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let left = self
+			.deadline
+			.checked_duration_since(Instant::now())
+			.filter(|left| !left.is_zero())
+			.ok_or_else(|| io::Error::new(ErrorKind::TimedOut, "request took too long"))?;
+		self.stream.set_read_timeout(Some(left))?;
+		let mut stream = self.stream;
+		stream.read(buf)
+	}
+}
+
+fn parse_target(target: &str) -> Request {
+	let (path, query) = target.split_once('?').unwrap_or((target, ""));
+	Request {
+		path: percent_decode(path),
+		params: query
+			.split('&')
+			.filter(|pair| !pair.is_empty())
+			.map(|pair| {
+				let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+				(
+					percent_decode(&key.replace('+', " ")),
+					percent_decode(&value.replace('+', " ")),
+				)
+			})
+			.collect(),
+	}
+}
+
+fn percent_decode(s: &str) -> String {
+	let bytes = s.as_bytes();
+	let mut decoded = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		let hex = bytes
+			.get(i + 1..i + 3)
+			.and_then(|hex| std::str::from_utf8(hex).ok())
+			.and_then(|hex| u8::from_str_radix(hex, 16).ok());
+		match (bytes[i], hex) {
+			(b'%', Some(byte)) => {
+				decoded.push(byte);
+				i += 3;
+			}
+			(byte, _) => {
+				decoded.push(byte);
+				i += 1;
+			}
+		}
+	}
+	String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn reason(status: u16) -> &'static str {
+	match status {
+		200 => "OK",
+		400 => "Bad Request",
+		404 => "Not Found",
+		405 => "Method Not Allowed",
+		414 => "URI Too Long",
+		431 => "Request Header Fields Too Large",
+		503 => "Service Unavailable",
+		_ => "Internal Server Error",
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// the response to a request sent whole, on a connection of its own
+	fn head(request: &[u8]) -> Result<std::result::Result<(String, String), Response>> {
+		let listener = TcpListener::bind("127.0.0.1:0")?;
+		let mut client = TcpStream::connect(listener.local_addr()?)?;
+		client.write_all(request)?;
+		client.shutdown(std::net::Shutdown::Write)?;
+		let (stream, _) = listener.accept()?;
+		read_head(&stream, Instant::now() + READ_TIMEOUT)
+	}
+
+	fn status(request: &[u8]) -> Option<u16> {
+		head(request).unwrap().err().map(|response| response.status)
+	}
+
+	#[test]
+	fn reads_head() {
+		let (method, target) = head(b"GET /search?q=a HTTP/1.1\r\nHost: x\r\n\r\n")
+			.unwrap()
+			.unwrap();
+		assert_eq!((method.as_str(), target.as_str()), ("GET", "/search?q=a"));
+	}
+
+	#[test]
+	fn refuses_big_heads() {
+		let long_line = [
+			&b"GET /"[..],
+			&[b'a'; MAX_HEAD as usize],
+			b" HTTP/1.1\r\n\r\n",
+		]
+		.concat();
+		assert_eq!(status(&long_line), Some(414));
+
+		let many = [
+			&b"GET / HTTP/1.1\r\n"[..],
+			&b"X: y\r\n".repeat(MAX_HEADERS + 1),
+			b"\r\n",
+		];
+		assert_eq!(status(&many.concat()), Some(431));
+		let enough = [
+			&b"GET / HTTP/1.1\r\n"[..],
+			&b"X: y\r\n".repeat(MAX_HEADERS),
+			b"\r\n",
+		];
+		assert_eq!(status(&enough.concat()), None);
+
+		let long_header = [
+			&b"GET / HTTP/1.1\r\nX: "[..],
+			&[b'y'; MAX_HEAD as usize],
+			b"\r\n\r\n",
+		];
+		assert_eq!(status(&long_header.concat()), Some(431));
+	}
+
+	#[test]
+	fn times_out() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		client.write_all(b"GET / HT").unwrap();
+		let (stream, _) = listener.accept().unwrap();
+		let deadline = Instant::now() + Duration::from_millis(50);
+		assert!(read_head(&stream, deadline).is_err());
+	}
+
+	#[test]
+	fn parses_targets() {
+		let req = parse_target("/entry/a%20b?q=big+cat&lang=fr&lang=en&x");
+		assert_eq!(req.path, "/entry/a b");
+		assert_eq!(req.param("q"), Some("big cat"));
+		assert_eq!(req.params("lang"), ["fr", "en"]);
+		assert_eq!(req.param("x"), Some(""));
+		assert_eq!(req.param("y"), None);
+	}
+}
@@ -0,0 +1,286 @@
+//! The searches of `store query`, combined into an expression over the text of an entry.
+//!
+//! Each argument is a search, which all have to match (AND), except that one starting with `|`
+//! matches if it or the search before it does (OR), and one starting with `~` if it doesn't match
+//! (NOT). `(` and `)` on their own group searches, and take the same prefixes (`~(`, `|(`). So `~`
+//! binds tightest, then `|`, then AND: `a b |c ~d` is `a AND (b OR c) AND NOT d`. A `\` in front
+//! takes the rest literally, to search for text starting with one of those.
+
+use color_eyre::eyre::{eyre, Result};
+
+/// Searches combined with AND, OR, and NOT.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr<T> {
+	Term(T),
+	Not(Box<Expr<T>>),
+	And(Vec<Expr<T>>),
+	Or(Vec<Expr<T>>),
+}
+
+impl<T> Expr<T> {
+	/// the same expression with each search made into something else (e.g. compiled)
+	pub fn try_map<U>(self, f: &mut impl FnMut(T) -> Result<U>) -> Result<Expr<U>> {
+		Ok(match self {
+			Self::Term(term) => Expr::Term(f(term)?),
+			Self::Not(expr) => Expr::Not(Box::new(expr.try_map(f)?)),
+			Self::And(exprs) => Expr::And(
+				exprs
+					.into_iter()
+					.map(|expr| expr.try_map(f))
+					.collect::<Result<_>>()?,
+			),
+			Self::Or(exprs) => Expr::Or(
+				exprs
+					.into_iter()
+					.map(|expr| expr.try_map(f))
+					.collect::<Result<_>>()?,
+			),
+		})
+	}
+
+	/// whether the expression holds, given whether each search matches
+	pub fn eval(&self, matches: &impl Fn(&T) -> bool) -> bool {
+		match self {
+			Self::Term(term) => matches(term),
+			Self::Not(expr) => !expr.eval(matches),
+			Self::And(exprs) => exprs.iter().all(|expr| expr.eval(matches)),
+			Self::Or(exprs) => exprs.iter().any(|expr| expr.eval(matches)),
+		}
+	}
+
+	/// whether the expression may hold, given whether each search may match: anything under a NOT
+	/// may, as a search that may match may just as well not
+	pub fn may_hold(&self, may_match: &impl Fn(&T) -> bool) -> bool {
+		match self {
+			Self::Term(term) => may_match(term),
+			Self::Not(_) => true,
+			Self::And(exprs) => exprs.iter().all(|expr| expr.may_hold(may_match)),
+			Self::Or(exprs) => exprs.iter().any(|expr| expr.may_hold(may_match)),
+		}
+	}
+}
+
+enum Token {
+	/// a search (or none for the start of a group), with its `|` and `~` prefixes
+	Item {
+		or: bool,
+		not: bool,
+		search: Option<String>,
+	},
+	Close,
+}
+
+/// reads the searches given to `store query` as an expression, with any of the top-level ones
+/// matching rather than all of them if `any`
+pub fn parse(args: Vec<String>, any: bool) -> Result<Expr<String>> {
+	let mut tokens = args.into_iter().map(tokenize);
+	let exprs = parse_group(&mut tokens, 0)?;
+	Ok(if any {
+		Expr::Or(exprs)
+	} else {
+		Expr::And(exprs)
+	})
+}
+
+fn tokenize(arg: String) -> Token {
+	if arg == ")" {
+		return Token::Close;
+	}
+
+	let mut rest = arg.as_str();
+	let or = match rest.strip_prefix('|') {
+		Some(after) => {
+			rest = after;
+			true
+		}
+		None => false,
+	};
+	let not = match rest.strip_prefix('~') {
+		Some(after) => {
+			rest = after;
+			true
+		}
+		None => false,
+	};
+
+	let search = match rest {
+		"(" => None,
+		_ => Some(rest.strip_prefix('\\').unwrap_or(rest).to_string()),
+	};
+	Token::Item { or, not, search }
+}
+
+/// the ANDed items of a group (or the whole expression, at depth 0), up to its `)`
+fn parse_group(
+	tokens: &mut impl Iterator<Item = Token>,
+	depth: usize,
+) -> Result<Vec<Expr<String>>> {
+	let mut exprs: Vec<Expr<String>> = Vec::new();
+	loop {
+		let (or, not, search) = match tokens.next() {
+			None if depth > 0 => return Err(eyre!("a `(` isn't closed")),
+			None => break,
+			Some(Token::Close) if depth == 0 => return Err(eyre!("a `)` has no `(` to close")),
+			Some(Token::Close) => break,
+			Some(Token::Item { or, not, search }) => (or, not, search),
+		};
+
+		let expr = match search {
+			Some(search) => Expr::Term(search),
+			None => {
+				let mut group = parse_group(tokens, depth + 1)?;
+				match group.len() {
+					0 => return Err(eyre!("a group `( )` is empty")),
+					1 => group.remove(0),
+					_ => Expr::And(group),
+				}
+			}
+		};
+		let expr = if not { Expr::Not(Box::new(expr)) } else { expr };
+
+		if !or {
+			exprs.push(expr);
+			continue;
+		}
+		match exprs.pop() {
+			Some(Expr::Or(mut alternatives)) => {
+				alternatives.push(expr);
+				exprs.push(Expr::Or(alternatives));
+			}
+			Some(before) => exprs.push(Expr::Or(vec![before, expr])),
+			None => return Err(eyre!("a `|` search has nothing before it to OR with")),
+		}
+	}
+
+	Ok(exprs)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn term(search: &str) -> Expr<String> {
+		Expr::Term(search.to_string())
+	}
+
+	fn not(expr: Expr<String>) -> Expr<String> {
+		Expr::Not(Box::new(expr))
+	}
+
+	fn parsed(args: &str, any: bool) -> Result<Expr<String>> {
+		parse(args.split(' ').map(String::from).collect(), any)
+	}
+
+	/// whether the expression holds of a text with these words in it
+	fn holds(args: &str, text: &str) -> bool {
+		let words: Vec<_> = text.split(' ').collect();
+		parsed(args, false)
+			.unwrap()
+			.eval(&|search: &String| words.contains(&search.as_str()))
+	}
+
+	#[test]
+	fn and_or_not() {
+		assert_eq!(
+			parsed("a b", false).unwrap(),
+			Expr::And(vec![term("a"), term("b")])
+		);
+		assert_eq!(
+			parsed("a b", true).unwrap(),
+			Expr::Or(vec![term("a"), term("b")])
+		);
+		assert_eq!(
+			parsed("a |b |c", false).unwrap(),
+			Expr::And(vec![Expr::Or(vec![term("a"), term("b"), term("c")])])
+		);
+		assert_eq!(
+			parsed("~a", false).unwrap(),
+			Expr::And(vec![not(term("a"))])
+		);
+
+		assert!(holds("a b", "a b c"));
+		assert!(!holds("a b", "a c"));
+		assert!(holds("a |b", "b"));
+		assert!(!holds("a |b", "c"));
+		assert!(holds("~a", "b"));
+		assert!(!holds("~a", "a b"));
+	}
+
+	#[test]
+	fn precedence() {
+		// `~` binds tightest, then `|`, then AND
+		assert_eq!(
+			parsed("a b |c ~d", false).unwrap(),
+			Expr::And(vec![
+				term("a"),
+				Expr::Or(vec![term("b"), term("c")]),
+				not(term("d")),
+			])
+		);
+		assert_eq!(
+			parsed("a |~b", false).unwrap(),
+			Expr::And(vec![Expr::Or(vec![term("a"), not(term("b"))])])
+		);
+		assert!(holds("a b |c ~d", "a c"));
+		assert!(!holds("a b |c ~d", "a c d"));
+		assert!(!holds("a b |c ~d", "b c"));
+		assert!(holds("a |~b", "c"));
+		assert!(!holds("a |~b", "b"));
+	}
+
+	#[test]
+	fn groups() {
+		assert_eq!(
+			parsed("a ~( b |c ) |( d e )", false).unwrap(),
+			Expr::And(vec![
+				term("a"),
+				Expr::Or(vec![
+					not(Expr::Or(vec![term("b"), term("c")])),
+					Expr::And(vec![term("d"), term("e")]),
+				]),
+			])
+		);
+		assert!(holds("a ~( b |c ) |( d e )", "a"));
+		assert!(holds("a ~( b |c ) |( d e )", "a b d e"));
+		assert!(!holds("a ~( b |c ) |( d e )", "a c d"));
+		assert!(!holds("a ~( b |c ) |( d e )", "d e"));
+
+		// a group of one is just what's in it
+		assert_eq!(parsed("( a )", false).unwrap(), Expr::And(vec![term("a")]));
+	}
+
+	#[test]
+	fn escapes() {
+		assert_eq!(
+			parsed("\\|a \\~b \\( \\) \\\\c", false).unwrap(),
+			Expr::And(vec![
+				term("|a"),
+				term("~b"),
+				term("("),
+				term(")"),
+				term("\\c")
+			])
+		);
+		assert_eq!(
+			parsed("|\\~a", false).unwrap_err().to_string(),
+			"a `|` search has nothing before it to OR with"
+		);
+		assert_eq!(
+			parsed("~\\|a", false).unwrap(),
+			Expr::And(vec![not(term("|a"))])
+		);
+	}
+
+	#[test]
+	fn errors() {
+		let error = |args| parsed(args, false).unwrap_err().to_string();
+		assert_eq!(error("( a"), "a `(` isn't closed");
+		assert_eq!(error("a )"), "a `)` has no `(` to close");
+		assert_eq!(error("a ( )"), "a group `( )` is empty");
+		assert_eq!(error("|a"), "a `|` search has nothing before it to OR with");
+		assert_eq!(
+			error("( |a )"),
+			"a `|` search has nothing before it to OR with"
+		);
+	}
+}
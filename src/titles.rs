@@ -0,0 +1,368 @@
+// deku derives generate these
+#![allow(clippy::manual_div_ceil)]
+
+//! Titles of entries: a bloom filter of those in a store, a map of them to refs, and the key they
+//! sort by.
+
+use std::{
+	cmp::Ordering,
+	collections::{HashMap, HashSet},
+	convert::TryFrom,
+	fs::File,
+	io::{BufWriter, Write},
+	ops::Deref,
+	path::Path,
+};
+
+use bloomfilter::Bloom;
+use color_eyre::eyre::{eyre, Result};
+use deku::prelude::*;
+use log::debug;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::{blockstore::Ref, redirects::title_hash};
+
+/// False positive rate the title bloom filter is sized for.
+const BLOOM_FP_RATE: f64 = 0.01;
+
+/// False positive rate the bloom filter of `TitleDedup` is sized for: each is a page dropped.
+const DEDUP_FP_RATE: f64 = 0.001;
+
+/// A bloom filter over every title in the store, to answer "definitely not present" cheaply.
+pub struct TitleBloom(Bloom<str>);
+
+#[derive(Debug, DekuRead, DekuWrite)]
+#[deku(endian = "little")]
+struct BloomFile {
+	bits: u64,
+	hashes: u32,
+	keys: [u64; 4],
+	#[deku(bits_read = "deku::rest.len()")]
+	bitmap: Vec<u8>,
+}
+
+impl TitleBloom {
+	/// a filter of `count` titles (which it's sized for)
+	pub fn build<'t>(count: usize, titles: impl Iterator<Item = &'t str>) -> Self {
+		let mut bloom = Bloom::new_for_fp_rate(count.max(1), BLOOM_FP_RATE);
+		for title in titles {
+			bloom.set(title);
+		}
+
+		debug!(
+			"built title bloom for {} titles bits={} hashes={}",
+			count,
+			bloom.number_of_bits(),
+			bloom.number_of_hash_functions()
+		);
+		Self(bloom)
+	}
+
+	/// false means the title is definitely absent, true that it may be present
+	pub fn check(&self, title: &str) -> bool {
+		self.0.check(title)
+	}
+
+	/// writes the filter to a file
+	pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+		let mut file = File::create(path)?;
+		file.write_all(&bloom_to_bytes(&self.0)?)?;
+		Ok(())
+	}
+
+	/// reads a filter written by `write`
+	pub fn from_bytes(data: &[u8]) -> Result<Self> {
+		Ok(Self(bloom_from_bytes(data)?))
+	}
+}
+
+/// the bytes of a bloom filter: its size, hash count and keys, then its bits
+pub(crate) fn bloom_to_bytes(bloom: &Bloom<str>) -> Result<Vec<u8>> {
+	let [(k0, k1), (k2, k3)] = bloom.sip_keys();
+	Ok(BloomFile {
+		bits: bloom.number_of_bits(),
+		hashes: bloom.number_of_hash_functions(),
+		keys: [k0, k1, k2, k3],
+		bitmap: bloom.bitmap(),
+	}
+	.to_bytes()?)
+}
+
+/// reads a bloom filter written by `bloom_to_bytes`
+pub(crate) fn bloom_from_bytes(data: &[u8]) -> Result<Bloom<str>> {
+	let bloom = BloomFile::from_bytes((data, 0))?.1;
+	let [k0, k1, k2, k3] = bloom.keys;
+	Ok(Bloom::from_existing(
+		&bloom.bitmap,
+		bloom.bits,
+		bloom.hashes,
+		[(k0, k1), (k2, k3)],
+	))
+}
+
+/// Every title in a store and the refs of its entries, to find entries by title without a scan.
+///
+/// It's kept as it's written: lines of `title\tref` sorted by title (then ref), which lookups
+/// binary search in place, so opening it costs nothing more than mapping the file.
+pub struct TitleRefs(Box<dyn Deref<Target = [u8]> + Send + Sync>);
+
+impl TitleRefs {
+	/// the map of these titles to refs
+	pub fn build(mut titles: Vec<(String, Ref)>) -> Self {
+		titles.sort_unstable_by(|(a, ra), (b, rb)| {
+			a.cmp(b)
+				.then_with(|| (ra.block_id, ra.entry_id).cmp(&(rb.block_id, rb.entry_id)))
+		});
+
+		let mut data = Vec::new();
+		for (title, store_ref) in &titles {
+			// titles can't have tabs or newlines in mediawiki, so this only guards the format
+			if title.contains(&['\t', '\n'][..]) {
+				debug!("not mapping title with a tab or newline: {:?}", title);
+				continue;
+			}
+			writeln!(data, "{}\t{}", title, store_ref).unwrap();
+		}
+
+		debug!("built title map for {} titles", titles.len());
+		Self::from_bytes(data)
+	}
+
+	/// the refs of all entries with exactly this title, in ref order
+	///
+	/// There's usually one, but a dump may have several pages of the same title.
+	pub fn get(&self, title: &str) -> Result<Vec<Ref>> {
+		let title = title.as_bytes();
+		self.lines_from(self.first_not_below(title))
+			.take_while(|(start, end)| self.title_at(*start, *end) == title)
+			.map(|(start, end)| self.ref_at(start, end))
+			.collect()
+	}
+
+	/// every title starting with `prefix` and its ref, in title order
+	///
+	/// Titles are read from the map as the iterator goes, so this is as quick as `get` to start,
+	/// and only as slow as however many titles are taken from it.
+	pub fn starting_with<'a>(
+		&'a self,
+		prefix: &'a str,
+	) -> impl Iterator<Item = Result<(&'a str, Ref)>> + 'a {
+		let prefix = prefix.as_bytes();
+		self.lines_from(self.first_not_below(prefix))
+			.take_while(move |(start, end)| self.title_at(*start, *end).starts_with(prefix))
+			.map(move |(start, end)| {
+				Ok((
+					std::str::from_utf8(self.title_at(start, end))?,
+					self.ref_at(start, end)?,
+				))
+			})
+	}
+
+	/// every title and ref, in title order
+	pub fn iter(&self) -> impl Iterator<Item = Result<(&str, Ref)>> {
+		self.0
+			.split(|b| *b == b'\n')
+			.filter(|line| !line.is_empty())
+			.map(|line| {
+				let line = std::str::from_utf8(line)?;
+				let (title, store_ref) = line
+					.rsplit_once('\t')
+					.ok_or_else(|| eyre!("bad line in title map: {:?}", line))?;
+				Ok((title, store_ref.parse()?))
+			})
+	}
+
+	/// how many titles there are
+	pub fn len(&self) -> usize {
+		self.0.iter().filter(|b| **b == b'\n').count()
+	}
+
+	/// whether there are no titles
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+
+	/// the map with the refs of moved entries changed (e.g. after a compaction), and without those
+	/// of entries that are gone (no new ref)
+	pub fn remap(&self, remap: &[(Ref, Option<Ref>)]) -> Result<Self> {
+		let remap: HashMap<u64, Option<Ref>> = remap
+			.iter()
+			.map(|(old, new)| (old.as_u64(), *new))
+			.collect();
+
+		let mut titles = Vec::with_capacity(self.len());
+		for title in self.iter() {
+			let (title, store_ref) = title?;
+			match remap.get(&store_ref.as_u64()) {
+				None => titles.push((title.to_string(), store_ref)),
+				Some(Some(new)) => titles.push((title.to_string(), *new)),
+				Some(None) => {}
+			}
+		}
+		Ok(Self::build(titles))
+	}
+
+	/// writes the map to a file
+	pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+		let mut file = BufWriter::new(File::create(path)?);
+		file.write_all(&self.0)?;
+		file.flush()?;
+		Ok(())
+	}
+
+	/// a map written by `write` (read or mapped into memory)
+	pub fn from_bytes(data: impl Deref<Target = [u8]> + Send + Sync + 'static) -> Self {
+		Self(Box::new(data))
+	}
+
+	/// the start of the first line whose title isn't less than `key`
+	fn first_not_below(&self, key: &[u8]) -> usize {
+		// lines before `lo` are for lesser titles, lines from `hi` for greater or equal ones
+		let (mut lo, mut hi) = (0, self.0.len());
+		while lo < hi {
+			let (start, end) = self.line_around((lo + hi) / 2);
+			if self.title_at(start, end).cmp(key) == Ordering::Less {
+				lo = end + 1;
+			} else {
+				hi = start;
+			}
+		}
+		lo
+	}
+
+	/// the start and end of each line from `offset` (which must be the start of one)
+	fn lines_from(&self, mut offset: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+		std::iter::from_fn(move || {
+			if offset >= self.0.len() {
+				return None;
+			}
+			let (start, end) = self.line_around(offset);
+			offset = end + 1;
+			Some((start, end))
+		})
+	}
+
+	/// the start and end (the newline) of the line the byte at `offset` is in
+	fn line_around(&self, offset: usize) -> (usize, usize) {
+		let start = self.0[..offset]
+			.iter()
+			.rposition(|b| *b == b'\n')
+			.map_or(0, |nl| nl + 1);
+		let end = self.0[offset..]
+			.iter()
+			.position(|b| *b == b'\n')
+			.map_or(self.0.len(), |nl| offset + nl);
+		(start, end)
+	}
+
+	fn title_at(&self, start: usize, end: usize) -> &[u8] {
+		let line = &self.0[start..end];
+		let tab = line.iter().rposition(|b| *b == b'\t').unwrap_or(line.len());
+		&line[..tab]
+	}
+
+	fn ref_at(&self, start: usize, end: usize) -> Result<Ref> {
+		let line = &self.0[start..end];
+		let tab = line
+			.iter()
+			.rposition(|b| *b == b'\t')
+			.ok_or_else(|| eyre!("bad line in title map at byte {}", start))?;
+		Ok(std::str::from_utf8(&line[tab + 1..])?.parse()?)
+	}
+}
+
+/// Collects titles while a store is made, and gives them refs as their blocks are committed.
+#[derive(Debug, Default)]
+pub struct TitleRefsBuilder {
+	titles: Vec<(String, Ref)>,
+	block_titles: Vec<String>,
+}
+
+impl TitleRefsBuilder {
+	/// records the title of the next entry of the current block
+	pub fn add(&mut self, title: &str) {
+		self.block_titles.push(title.to_string());
+	}
+
+	/// assigns refs to the titles added since the last commit, once their block id is known
+	pub fn commit(&mut self, block_id: u32) {
+		for (entry_id, title) in self.block_titles.drain(..).enumerate() {
+			let entry_id = u32::try_from(entry_id).unwrap();
+			self.titles.push((title, Ref::new(block_id, entry_id)));
+		}
+	}
+
+	/// the titles committed so far
+	pub fn titles(&self) -> impl Iterator<Item = &str> {
+		self.titles.iter().map(|(title, _)| title.as_str())
+	}
+
+	/// how many titles have been committed
+	pub fn len(&self) -> usize {
+		self.titles.len()
+	}
+
+	/// whether no titles have been committed
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// the map of the committed titles
+	pub fn finish(self) -> TitleRefs {
+		TitleRefs::build(self.titles)
+	}
+}
+
+/// The titles seen while a store is made, to leave out pages whose title was stored already.
+pub enum TitleDedup {
+	/// A 64-bit hash of every title: exact (barring hash collisions), but its memory grows with
+	/// the titles, about 16 bytes each.
+	Exact(HashSet<u64>),
+
+	/// A bloom filter sized for some number of titles: its memory is fixed (about 2 bytes a title
+	/// it's sized for), but about one in a thousand new titles is taken for a duplicate, and more
+	/// once it holds more titles than it's sized for.
+	Bloom(Bloom<str>),
+}
+
+impl TitleDedup {
+	pub fn exact() -> Self {
+		Self::Exact(HashSet::new())
+	}
+
+	/// a bloom filter sized for `capacity` titles
+	pub fn bloom(capacity: usize) -> Self {
+		let bloom = Bloom::new_for_fp_rate(capacity.max(1), DEDUP_FP_RATE);
+		debug!(
+			"dedup bloom for {} titles bits={} hashes={}",
+			capacity,
+			bloom.number_of_bits(),
+			bloom.number_of_hash_functions()
+		);
+		Self::Bloom(bloom)
+	}
+
+	/// records a title, returning whether it was seen already (or may have been, for a bloom)
+	pub fn seen(&mut self, title: &str) -> bool {
+		match self {
+			Self::Exact(hashes) => !hashes.insert(title_hash(title)),
+			Self::Bloom(bloom) => bloom.check_and_set(title),
+		}
+	}
+
+	/// whether a title may be taken for a duplicate when it isn't one
+	pub fn is_approximate(&self) -> bool {
+		matches!(self, Self::Bloom(_))
+	}
+}
+
+/// A case-folded, diacritic-stripped form of a title, for alphabetical ordering.
+///
+/// `Polish` and `polish` (or `café` and `cafe`) get the same key.
+pub fn sort_key(title: &str) -> String {
+	title
+		.nfd()
+		.filter(|c| !is_combining_mark(*c))
+		.flat_map(char::to_lowercase)
+		.collect()
+}
@@ -0,0 +1,409 @@
+//! Cleaning up the wikitext of entries.
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+static ENTITY_RX: Lazy<Regex> = Lazy::new(|| {
+	Regex::new(r"&(?:#[0-9]{1,7}|#[xX][0-9a-fA-F]{1,6}|[a-zA-Z][a-zA-Z0-9]{1,31});").unwrap()
+});
+
+/// decodes HTML entities (named, decimal, and hex) to their characters
+///
+/// Unknown or invalid entities are left as-is.
+pub fn unescape_html(text: &str) -> String {
+	ENTITY_RX
+		.replace_all(text, |cap: &Captures| {
+			let entity = &cap[0];
+			// htmlescape only knows a lowercase `x` for hex, which HTML doesn't mind the case of
+			let decoded = match entity.strip_prefix("&#X") {
+				Some(hex) => htmlescape::decode_html(&format!("&#x{}", hex)),
+				None => htmlescape::decode_html(entity),
+			};
+			decoded.unwrap_or_else(|_| entity.to_owned())
+		})
+		.into_owned()
+}
+
+static COMMENT_RX: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<!--.*?(?:-->|$)").unwrap());
+
+static REF_RX: Lazy<Regex> =
+	Lazy::new(|| Regex::new(r"(?is)<ref\b[^>]*/>|<ref\b[^>]*>.*?(?:</ref\s*>|$)").unwrap());
+
+static EXTERNAL_LINK_RX: Lazy<Regex> =
+	Lazy::new(|| Regex::new(r"\[(?:[a-z]+:)?//[^\s\]]+(?:[ \t]+([^\]]*))?\]").unwrap());
+
+static TAG_RX: Lazy<Regex> = Lazy::new(|| Regex::new(r"</?[a-zA-Z][^<>]*>").unwrap());
+
+static QUOTES_RX: Lazy<Regex> = Lazy::new(|| Regex::new(r"'{5}|'{3}|'{2}").unwrap());
+
+static SPACES_RX: Lazy<Regex> = Lazy::new(|| Regex::new(r"[ \t]{2,}").unwrap());
+
+/// templates that stand for a word (in their Nth parameter, counting the name as 0), which is
+/// kept, or their alt text (the one after, or `alt=` for `TRANSLATION_TEMPLATES`) if it has one
+const WORD_TEMPLATES: &[(&str, usize)] = &[
+	("l", 2),
+	("l-self", 2),
+	("ll", 2),
+	("m", 2),
+	("m-self", 2),
+	("mention", 2),
+	("t", 2),
+	("t+", 2),
+	("t-simple", 2),
+	("tt", 2),
+	("tt+", 2),
+	("cog", 2),
+	("noncog", 2),
+	("w", 1),
+	("wikipedia", 1),
+	("der", 3),
+	("inh", 3),
+	("bor", 3),
+	("lbor", 3),
+	("slbor", 3),
+	("calque", 3),
+	("uder", 3),
+];
+
+/// templates of translations, whose parameters after the word are its genders
+const TRANSLATION_TEMPLATES: &[&str] = &["t", "t+", "t-simple", "tt", "tt+"];
+
+/// templates that stand for a note in brackets, made of their parameters from the Nth
+const NOTE_TEMPLATES: &[(&str, usize)] = &[
+	("lb", 2),
+	("lbl", 2),
+	("label", 2),
+	("tlb", 2),
+	("q", 1),
+	("qual", 1),
+	("qualifier", 1),
+	("i", 1),
+	("qf", 1),
+	("gloss", 1),
+	("gl", 1),
+	("sense", 1),
+	("s", 1),
+];
+
+/// wikitext with its markup stripped, or made into the plain text it stands for, roughly
+///
+/// Templates are dropped, except common ones that stand for words: links like `{{l|en|word}}`
+/// become `word`, and labels like `{{lb|en|informal}}` become `(informal)`. Links become their
+/// text, and category and file links are dropped, as are HTML comments, `<ref>`s, and tables. Other
+/// tags are dropped but not what's in them, and bold and italics quotes are removed. Headings and
+/// list markers are kept, so the text can still be split into sections.
+pub fn clean_wikitext(text: &str) -> String {
+	let text = COMMENT_RX.replace_all(text, "");
+	let text = REF_RX.replace_all(&text, "");
+	let text = strip_tables(&text);
+	let text = replace_nested(&text, "{{", "}}", template_text);
+	let text = replace_nested(&text, "[[", "]]", link_text);
+	let text = EXTERNAL_LINK_RX.replace_all(&text, "$1");
+	let text = TAG_RX.replace_all(&text, "");
+	let text = QUOTES_RX.replace_all(&text, "");
+
+	let mut clean = String::with_capacity(text.len());
+	let mut blank = true;
+	for line in text.lines() {
+		let line = SPACES_RX.replace_all(line.trim_end(), " ");
+		// list items left empty (e.g. of a quote template) go, and blank lines are collapsed
+		if line
+			.trim_start_matches(&['#', '*', ':', ';'][..])
+			.trim()
+			.is_empty()
+		{
+			if !blank && line.is_empty() {
+				clean.push('\n');
+				blank = true;
+			}
+			continue;
+		}
+
+		clean.push_str(&line);
+		clean.push('\n');
+		blank = false;
+	}
+	let len = clean.trim_end().len();
+	clean.truncate(len);
+	clean
+}
+
+/// drops tables (from a line starting with `{|` to the one starting with `|}`, nested)
+fn strip_tables(text: &str) -> String {
+	let mut kept = String::with_capacity(text.len());
+	let mut depth = 0_usize;
+	for line in text.split_inclusive('\n') {
+		let start = line.trim_start();
+		if start.starts_with("{|") {
+			depth += 1;
+		} else if depth > 0 && start.starts_with("|}") {
+			depth -= 1;
+		} else if depth == 0 {
+			kept.push_str(line);
+		}
+	}
+	kept
+}
+
+/// replaces each `open`...`close` span with what `replace` makes of what's inside, innermost first
+///
+/// A span that isn't closed is left as-is.
+fn replace_nested(text: &str, open: &str, close: &str, replace: fn(&str) -> String) -> String {
+	let mut out = String::with_capacity(text.len());
+	// starts of the spans opened but not yet closed, in `out`
+	let mut opened: Vec<usize> = Vec::new();
+	let mut rest = text;
+	while !rest.is_empty() {
+		if rest.starts_with(open) {
+			opened.push(out.len());
+			out.push_str(open);
+			rest = &rest[open.len()..];
+		} else if let (true, Some(start)) = (rest.starts_with(close), opened.last().copied()) {
+			opened.pop();
+			let inner = out.split_off(start);
+			out.push_str(&replace(&inner[open.len()..]));
+			rest = &rest[close.len()..];
+		} else {
+			let c = rest.chars().next().unwrap();
+			out.push(c);
+			rest = &rest[c.len_utf8()..];
+		}
+	}
+	out
+}
+
+/// splits at `|`s that aren't in a link
+fn split_params(inner: &str) -> Vec<&str> {
+	let mut params = Vec::new();
+	let mut depth = 0_usize;
+	let mut start = 0;
+	let bytes = inner.as_bytes();
+	let mut i = 0;
+	while i < bytes.len() {
+		match bytes[i] {
+			b'[' if bytes.get(i + 1) == Some(&b'[') => {
+				depth += 1;
+				i += 1;
+			}
+			b']' if depth > 0 && bytes.get(i + 1) == Some(&b']') => {
+				depth -= 1;
+				i += 1;
+			}
+			b'|' if depth == 0 => {
+				params.push(&inner[start..i]);
+				start = i + 1;
+			}
+			_ => {}
+		}
+		i += 1;
+	}
+	params.push(&inner[start..]);
+	params
+}
+
+/// the text a template stands for (its inner templates already replaced)
+fn template_text(inner: &str) -> String {
+	let params = split_params(inner);
+	let name = params[0].trim();
+	// named parameters (like `tr=...`) aren't part of the text
+	let positional: Vec<&str> = params
+		.iter()
+		.map(|p| p.trim())
+		.filter(|p| !p.split('[').next().unwrap_or_default().contains('='))
+		.collect();
+
+	if let Some((_, n)) = WORD_TEMPLATES.iter().find(|(t, _)| *t == name) {
+		let alt = if TRANSLATION_TEMPLATES.contains(&name) {
+			params.iter().find_map(|p| p.trim().strip_prefix("alt="))
+		} else {
+			positional.get(n + 1).copied()
+		};
+		let alt = alt.map(str::trim).filter(|alt| !alt.is_empty());
+		return alt
+			.or_else(|| positional.get(*n).copied())
+			.unwrap_or_default()
+			.to_string();
+	}
+
+	if let Some((_, n)) = NOTE_TEMPLATES.iter().find(|(t, _)| *t == name) {
+		let notes: Vec<&str> = positional
+			.iter()
+			.skip(*n)
+			.copied()
+			.filter(|p| !p.is_empty() && *p != "_")
+			.collect();
+		return if notes.is_empty() {
+			String::new()
+		} else {
+			format!("({})", notes.join(", "))
+		};
+	}
+
+	String::new()
+}
+
+/// the text a link shows (its inner links already replaced)
+fn link_text(inner: &str) -> String {
+	let (target, text) = match inner.split_once('|') {
+		Some((target, text)) => (target.trim(), Some(text.trim())),
+		None => (inner.trim(), None),
+	};
+
+	let namespace = target
+		.trim_start_matches(':')
+		.split_once(':')
+		.map(|(ns, _)| ns.trim().to_lowercase());
+	match namespace.as_deref() {
+		Some("category" | "file" | "image" | "media") => return String::new(),
+		// links to the same page in other languages' wiktionaries aren't shown either
+		Some(code)
+			if text.is_none()
+				&& !target.starts_with(':')
+				&& (2..=3).contains(&code.len())
+				&& code.bytes().all(|b| b.is_ascii_lowercase()) =>
+		{
+			return String::new()
+		}
+		_ => {}
+	}
+
+	match text {
+		Some(text) if !text.is_empty() => text.to_string(),
+		_ => {
+			// `[[word#English]]` shows `word`, and `[[w:Word|]]` shows `Word`
+			let target = target.split('#').next().unwrap_or_default();
+			let target = target.trim_start_matches(':');
+			match (text, target.split_once(':')) {
+				(Some(_), Some((_, page))) => page.to_string(),
+				_ => target.to_string(),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const CAT: &str = r#"==English==
+{{wikipedia}}
+[[File:Cat poster 1.jpg|thumb|A domestic '''cat''' {{q|[[Felis catus]]}}]]
+
+===Etymology===
+From {{inh|en|enm|cat}}, {{m|enm|catte}}, from {{inh|en|ang|catt||male cat}}, {{m|ang|catte||female cat}}, from {{der|en|LL.|cattus}}.<ref>{{R:Online Etymology Dictionary}}</ref>
+
+===Pronunciation===
+* {{IPA|en|/kæt/}}
+* {{audio|en|en-us-cat.ogg|Audio (US)}}
+* {{rhymes|en|æt|s=1}}
+
+===Noun===
+{{en-noun}}
+
+# {{lb|en|countable}} An animal of the family [[Felidae]]: {{q|[[domestic cat|domestic]] or wild}}
+#: ''The '''cat''' sat on the mat.''
+#* {{quote-book|en|year=1912|author=Jane|title=Cats|passage=The '''cat''' purred.}}
+# {{lb|en|countable|_|informal}} A [[person]] {{gloss|usually a {{l|en|man}}}}; a [[guy]].<!-- needs citation -->
+# {{lb|en|nautical}} A {{w|cathead|strong beam}} projecting from the bow.
+
+====Synonyms====
+* {{sense|animal}} {{l|en|feline}}, {{l|en|kitty|[[puss]]}}, [[moggy]]
+
+====Translations====
+{{trans-top|domestic species}}
+* French: {{t+|fr|chat|m}}, {{t+|fr|chatte|f|alt=chatte (female)}}
+* German: {{t+|de|Katze|f}}
+{{trans-bottom}}
+
+{| class="wikitable"
+! Form !! Meaning
+|-
+| cat || feline
+|}
+
+[[Category:en:Cats]]
+[[fr:cat]]"#;
+
+	#[test]
+	fn cleans_an_entry() {
+		assert_eq!(
+			clean_wikitext(CAT),
+			"==English==
+
+===Etymology===
+From cat, catte, from catt, catte, from cattus.
+
+===Pronunciation===
+
+===Noun===
+
+# (countable) An animal of the family Felidae: (domestic or wild)
+#: The cat sat on the mat.
+# (countable, informal) A person (usually a man); a guy.
+# (nautical) A strong beam projecting from the bow.
+
+====Synonyms====
+* (animal) feline, puss, moggy
+
+====Translations====
+
+* French: chat, chatte (female)
+* German: Katze"
+		);
+	}
+
+	#[test]
+	fn cleans_nested_templates() {
+		let entry = "==French==
+===Noun===
+{{fr-noun|m|f={{l|fr|chatte}}}}
+# {{lb|fr|{{l|en|slang}}|_|vulgar}} {{l|fr|[[minou|un {{m|fr|minou}}]]|kitty}}
+# {{q|{{gloss|of {{w|Cat|a cat}}}}}} {{der|fr|la|{{l|la|cattus}}|{{l|la|catta|cattam}}|cat}}
+# {{unknown|{{l|fr|x}}|{{l|fr|y}}}}[[chien|{{l|fr|dog}}]] and [[:fr:chat]]";
+		assert_eq!(
+			clean_wikitext(entry),
+			"==French==
+===Noun===
+
+# (slang, vulgar) kitty
+# ((of a cat)) cattam
+# dog and fr:chat"
+		);
+	}
+
+	#[test]
+	fn leaves_unclosed_markup() {
+		assert_eq!(
+			clean_wikitext("# {{l|en|cat}} {{l|en|dog\n# [[cat]] [[dog"),
+			"# cat {{l|en|dog\n# cat [[dog"
+		);
+		// a comment or ref left open runs to the end
+		assert_eq!(clean_wikitext("cat<!-- dog\nmouse"), "cat");
+		assert_eq!(clean_wikitext("cat<ref>dog\nmouse"), "cat");
+	}
+
+	#[test]
+	fn unescapes_entities() {
+		assert_eq!(unescape_html("plain text"), "plain text");
+		assert_eq!(
+			unescape_html("&lt;b&gt; &amp; &quot;q&quot; caf&eacute; &nbsp;|&mdash;"),
+			"<b> & \"q\" café \u{a0}|—"
+		);
+		assert_eq!(unescape_html("&#233;t&#233; &#8212; &#128512;"), "été — 😀");
+		assert_eq!(
+			unescape_html("&#xE9;t&#XE9; &#x2014; &#x1F600;"),
+			"été — 😀"
+		);
+	}
+
+	#[test]
+	fn leaves_unknown_entities() {
+		assert_eq!(unescape_html("&notanentity; &zz;"), "&notanentity; &zz;");
+		// not entities at all: no `;`, a lone `&`, or nothing after the `#`
+		assert_eq!(unescape_html("AT&T & co &amp"), "AT&T & co &amp");
+		assert_eq!(unescape_html("&#; &#x; &#xZZ;"), "&#; &#x; &#xZZ;");
+		// numbers that aren't characters
+		assert_eq!(unescape_html("&#xD800; &#1114112;"), "&#xD800; &#1114112;");
+		assert_eq!(unescape_html("&unknown; &amp;"), "&unknown; &");
+	}
+}
@@ -0,0 +1,90 @@
+//! Bloom filters of the words in each block, so whole-word searches can skip blocks without them.
+//!
+//! Words are the runs of letters and digits of entry bodies, lowercased. A block's filter is saved
+//! next to it (`10000.words` for `10000.zst`) with the checksum of the block it was made from, so
+//! a filter left from before the block was rewritten doesn't match it, and isn't used.
+
+use std::{collections::HashSet, convert::TryInto};
+
+use bloomfilter::Bloom;
+use color_eyre::eyre::{eyre, Result};
+
+use crate::titles::{bloom_from_bytes, bloom_to_bytes};
+
+/// False positive rate the filters are sized for: each is a block read for nothing.
+const WORDS_FP_RATE: f64 = 0.01;
+
+/// The words of a block, as a bloom filter.
+pub struct WordFilter {
+	/// the checksum of the block it was made from
+	pub checksum: u32,
+	bloom: Bloom<str>,
+}
+
+impl WordFilter {
+	/// a filter of the words of these texts, for a block with this checksum
+	pub fn build<'t>(checksum: u32, texts: impl Iterator<Item = &'t str>) -> Self {
+		let mut unique: HashSet<String> = HashSet::new();
+		for text in texts {
+			for word in words(text) {
+				unique.insert(word.to_lowercase());
+			}
+		}
+
+		let mut bloom = Bloom::new_for_fp_rate(unique.len().max(1), WORDS_FP_RATE);
+		for word in &unique {
+			bloom.set(word.as_str());
+		}
+		Self { checksum, bloom }
+	}
+
+	/// false means the block definitely doesn't have all the words of `search` as words of its
+	/// own, true that it may
+	pub fn may_have(&self, search: &str) -> bool {
+		words(search).all(|word| self.bloom.check(&word.to_lowercase()))
+	}
+
+	/// the checksum, then the filter
+	pub fn to_bytes(&self) -> Result<Vec<u8>> {
+		let mut bytes = self.checksum.to_le_bytes().to_vec();
+		bytes.extend(bloom_to_bytes(&self.bloom)?);
+		Ok(bytes)
+	}
+
+	/// reads a filter written by `to_bytes`
+	pub fn from_bytes(data: &[u8]) -> Result<Self> {
+		if data.len() < 4 {
+			return Err(eyre!("word filter is truncated"));
+		}
+		let (checksum, bloom) = data.split_at(4);
+		Ok(Self {
+			checksum: u32::from_le_bytes(checksum.try_into()?),
+			bloom: bloom_from_bytes(bloom)?,
+		})
+	}
+}
+
+/// the runs of letters and digits of a text
+pub fn words(text: &str) -> impl Iterator<Item = &str> {
+	text.split(|c: char| !c.is_alphanumeric())
+		.filter(|word| !word.is_empty())
+}
+
+/// whether `search` is in `text` as whole words: not right after or before another letter or digit
+pub fn contains_words(text: &str, search: &str) -> bool {
+	if search.is_empty() {
+		return true;
+	}
+
+	text.match_indices(search).any(|(at, _)| {
+		let before = text[..at].chars().next_back();
+		let after = text[at + search.len()..].chars().next();
+		let first = search.chars().next();
+		let last = search.chars().next_back();
+		let joined = |outside: Option<char>, inside: Option<char>| match (outside, inside) {
+			(Some(outside), Some(inside)) => outside.is_alphanumeric() && inside.is_alphanumeric(),
+			_ => false,
+		};
+		!joined(before, first) && !joined(after, last)
+	})
+}
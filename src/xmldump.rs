@@ -1,62 +1,1028 @@
-use xml::reader::XmlEvent;
+//! Reading MediaWiki XML dumps (optionally compressed) into pages.
+
+use std::{
+	collections::BTreeMap,
+	fmt,
+	fs::File,
+	io::{self, Cursor, Read, Seek, SeekFrom},
+	path::Path,
+	process::{Child, ChildStdout, Command, Stdio},
+	thread,
+};
+
+use color_eyre::eyre::{eyre, Result};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use xml::{attribute::OwnedAttribute, reader::XmlEvent};
+
+/// the path that stands for stdin, to read a dump piped in
+pub const STDIN: &str = "-";
+
+/// opens a dump, decompressing it on the fly if it's bzip2, gzip, or zstd compressed
+///
+/// Compression is detected from the first bytes of the file rather than its extension. zstd is
+/// decompressed in-process, bzip2 and gzip by piping through the `bzip2` and `gzip` commands. A
+/// path of `-` reads the dump from stdin instead.
+pub fn open(path: &Path) -> Result<Box<dyn Read>> {
+	Ok(open_tracked(path)?.0)
+}
+
+/// opens a dump like `open`, along with a way to tell how far into the file it's been read
+pub fn open_tracked(path: &Path) -> Result<(Box<dyn Read>, DumpPosition)> {
+	if path.as_os_str() == STDIN {
+		return open_stdin();
+	}
+
+	let mut file = File::open(path)?;
+	let mut magic = Vec::with_capacity(4);
+	(&mut file).take(4).read_to_end(&mut magic)?;
+	file.seek(SeekFrom::Start(0))?;
+
+	let position = DumpPosition {
+		len: file.metadata()?.len(),
+		file: Some(file.try_clone()?),
+	};
+	let dump: Box<dyn Read> = if magic.starts_with(b"BZh") {
+		Piped::spawn("bzip2", file)?
+	} else if magic.starts_with(&[0x1f, 0x8b]) {
+		Piped::spawn("gzip", file)?
+	} else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+		debug!("dump is zstd compressed");
+		Box::new(zstd::Decoder::new(file)?)
+	} else {
+		Box::new(file)
+	};
+	Ok((dump, position))
+}
+
+/// opens the dump piped into stdin, whose size isn't known
+///
+/// What's read of it to tell its compression is put back in front, and for bzip2 and gzip it's
+/// all fed to the command from another thread.
+fn open_stdin() -> Result<(Box<dyn Read>, DumpPosition)> {
+	let mut stdin = io::stdin();
+	let mut magic = Vec::with_capacity(4);
+	(&mut stdin).take(4).read_to_end(&mut magic)?;
+
+	let (bzip2, gzip) = (magic.starts_with(b"BZh"), magic.starts_with(&[0x1f, 0x8b]));
+	let zstd = magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]);
+	let input = Cursor::new(magic).chain(stdin);
+	let dump: Box<dyn Read> = if bzip2 {
+		Piped::spawn_fed("bzip2", Box::new(input))?
+	} else if gzip {
+		Piped::spawn_fed("gzip", Box::new(input))?
+	} else if zstd {
+		debug!("dump is zstd compressed");
+		Box::new(zstd::Decoder::new(input)?)
+	} else {
+		Box::new(input)
+	};
+	let position = DumpPosition { file: None, len: 0 };
+	Ok((dump, position))
+}
+
+/// How far into its file a dump has been read, in bytes of the file (so compressed, if it is).
+///
+/// It's a handle to the same open file, which shares its position even with the `bzip2` or
+/// `gzip` reading it. Readers read ahead a little, so it's a bit ahead of what was parsed. A dump
+/// read from stdin has no file to tell, so it's at 0 of 0 throughout.
+pub struct DumpPosition {
+	file: Option<File>,
+	len: u64,
+}
+
+impl DumpPosition {
+	/// bytes of the file read so far
+	pub fn read(&self) -> u64 {
+		self.file
+			.as_ref()
+			.and_then(|mut file| file.stream_position().ok())
+			.unwrap_or(0)
+	}
+
+	/// size of the file, or 0 if it isn't known
+	pub fn len(&self) -> u64 {
+		self.len
+	}
+
+	/// whether the file is empty
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+}
+
+/// The output of a decompression command, which fails at the end if the command did.
+struct Piped {
+	name: &'static str,
+	child: Child,
+	stdout: ChildStdout,
+}
+
+impl Piped {
+	fn spawn(name: &'static str, file: File) -> Result<Box<dyn Read>> {
+		Self::run(name, file.into(), None)
+	}
+
+	/// like `spawn`, but writing `input` to the command from another thread
+	fn spawn_fed(name: &'static str, input: Box<dyn Read + Send>) -> Result<Box<dyn Read>> {
+		Self::run(name, Stdio::piped(), Some(input))
+	}
+
+	fn run(
+		name: &'static str,
+		stdin: Stdio,
+		input: Option<Box<dyn Read + Send>>,
+	) -> Result<Box<dyn Read>> {
+		debug!("dump is {} compressed, piping through `{} -dc`", name, name);
+		let mut child = Command::new(name)
+			.arg("-dc")
+			.stdin(stdin)
+			.stdout(Stdio::piped())
+			.spawn()
+			.map_err(|err| eyre!("can't run `{}` to decompress the dump: {}", name, err))?;
+		if let (Some(mut input), Some(mut feed)) = (input, child.stdin.take()) {
+			thread::spawn(move || {
+				// if this fails, so does the command, which is what's reported
+				if let Err(err) = io::copy(&mut input, &mut feed) {
+					debug!("stopped feeding `{} -dc`: {}", name, err);
+				}
+			});
+		}
+		let stdout = child.stdout.take().unwrap();
+		Ok(Box::new(Self {
+			name,
+			child,
+			stdout,
+		}))
+	}
+}
+
+impl Read for Piped {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let read = self.stdout.read(buf)?;
+		if read == 0 && !buf.is_empty() {
+			let status = self.child.wait()?;
+			if !status.success() {
+				// the xml reader doesn't show io errors well, so say it here too
+				let message = format!(
+					"`{} -dc` failed ({}), the dump may be corrupt",
+					self.name, status
+				);
+				error!("{}", message);
+				return Err(io::Error::other(message));
+			}
+		}
+
+		Ok(read)
+	}
+}
+
+/// a title with runs of spaces, tabs, and newlines made into one space, and those at either end
+/// removed, as MediaWiki does when saving a page
+///
+/// Other whitespace, like non-breaking spaces, is left as it is, as it may be meant.
+pub fn normalize_title(title: &str) -> String {
+	title
+		.split([' ', '\t', '\n', '\r'])
+		.filter(|word| !word.is_empty())
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+/// A page being parsed.
+///
+/// Feed it the events of a dump with `parse`, from `Page::None`; a `Texted` or `Redirect` page is
+/// complete, once its `</page>` is read. Only elements where MediaWiki's export schema has them
+/// count: the `<title>`, `<ns>`, `<id>`, and `<redirect>` right within the `<page>`, and the
+/// `<timestamp>` and `<text>` right within one of its `<revision>`s. Of a page with several
+/// revisions (as in full-history dumps) the text of the last one is kept, skipping stubs without
+/// their text (as in stub dumps, or for deleted revisions): a `<text>` marked `deleted`, or one
+/// with nothing in it, not even whitespace, without `bytes="0"` to say the page is empty.
 pub enum Page {
+	/// outside of any page
 	None,
-	Open,
+	/// within a `<page>`, before its title, this many elements deep within it
+	Open(usize),
+	/// within the `<title>`
 	Title(Vec<String>),
-	Titled(String),
-	Text { title: String, text: Vec<String> },
-	Texted { title: String, text: String },
+	/// after the title
+	Titled(PageBody),
+	/// a whole page
+	Texted {
+		title: String,
+		meta: PageMeta,
+		text: String,
+	},
+	/// a page that only redirects to another, either with a `<redirect>` or a `#REDIRECT` body
+	Redirect {
+		from: String,
+		meta: PageMeta,
+		to: String,
+	},
+}
+
+/// What's known about a page besides its title and text.
+#[derive(Debug, Default)]
+pub struct PageMeta {
+	/// the page's namespace, if it had an `<ns>`
+	pub ns: Option<i32>,
+	/// the page's `<id>`
+	pub id: Option<u32>,
+	/// the `<timestamp>` of the page's revision, in seconds since the epoch
+	pub timestamp: Option<u64>,
+	/// the target of the page's `<redirect>`, if it had one
+	redirect: Option<String>,
+}
+
+impl PageMeta {
+	/// records the contents of a field element of the page
+	fn set(&mut self, name: &str, value: &str) {
+		let value = value.trim();
+		match name {
+			"ns" => self.ns = value.parse().ok(),
+			"id" => self.id = value.parse().ok(),
+			_ => {}
+		}
+	}
+}
+
+/// The rest of a page being parsed, after its title.
+pub struct PageBody {
+	title: String,
+	meta: PageMeta,
+	/// the elements open within the page, outermost first
+	open: Vec<String>,
+	/// the text of the element being read, if it's one that's kept
+	chars: Option<Vec<String>>,
+	/// the size the `<text>` being read says it has, if it does
+	text_bytes: Option<u64>,
+	/// whether the `<text>` being read had whitespace in it
+	text_blank: bool,
+	/// the `<timestamp>` of the revision being read
+	revision_timestamp: Option<u64>,
+	/// the text of the last revision that had one, and that revision's timestamp
+	text: Option<(String, Option<u64>)>,
+}
+
+impl PageBody {
+	fn new(title: String) -> Self {
+		Self {
+			title,
+			meta: PageMeta::default(),
+			open: Vec::with_capacity(4),
+			chars: None,
+			text_bytes: None,
+			text_blank: false,
+			revision_timestamp: None,
+			text: None,
+		}
+	}
+
+	/// whether the elements open within the page are exactly these
+	fn within(&self, path: &[&str]) -> bool {
+		self.open.len() == path.len() && self.open.iter().zip(path).all(|(open, name)| open == name)
+	}
+
+	fn start(&mut self, name: String, attributes: &[OwnedAttribute]) {
+		let attr = |key: &str| {
+			attributes
+				.iter()
+				.find(|a| a.name.local_name == key)
+				.map(|a| a.value.as_str())
+		};
+
+		self.open.push(name);
+		if self.within(&["ns"]) || self.within(&["id"]) || self.within(&["revision", "timestamp"]) {
+			self.chars = Some(Vec::with_capacity(1));
+		} else if self.within(&["revision"]) {
+			self.revision_timestamp = None;
+		} else if self.within(&["redirect"]) {
+			self.meta.redirect = attr("title").map(String::from);
+		} else if self.within(&["revision", "text"]) && attr("deleted").is_none() {
+			self.chars = Some(Vec::with_capacity(5));
+			self.text_bytes = attr("bytes").and_then(|bytes| bytes.parse().ok());
+			self.text_blank = false;
+		}
+	}
+
+	fn chars(&mut self, s: String) {
+		if let Some(ref mut chars) = self.chars {
+			chars.push(s);
+		}
+	}
+
+	fn whitespace(&mut self) {
+		if self.chars.is_some() && self.within(&["revision", "text"]) {
+			self.text_blank = true;
+		}
+	}
+
+	fn end(&mut self) {
+		if let Some(chars) = self.chars.take() {
+			if self.within(&["revision", "timestamp"]) {
+				self.revision_timestamp = parse_timestamp(chars.concat().trim());
+			} else if self.within(&["revision", "text"]) {
+				let text = chars.join(" ");
+				// a stub has nothing in it, and either says how long the text it stands for is or
+				// says nothing at all
+				if !text.is_empty() || self.text_blank || self.text_bytes == Some(0) {
+					self.text = Some((text, self.revision_timestamp));
+				}
+			} else if let Some(name) = self.open.last() {
+				self.meta.set(name, &chars.concat());
+			}
+		}
+
+		self.open.pop();
+	}
+
+	/// the whole page, if it had any text
+	fn finish(self) -> Option<Page> {
+		let (text, timestamp) = self.text?;
+		let (title, mut meta) = (self.title, self.meta);
+		meta.timestamp = timestamp;
+		Some(
+			match meta.redirect.take().or_else(|| redirect_target(&text)) {
+				Some(to) => Page::Redirect {
+					from: title,
+					meta,
+					to: normalize_title(&to.replace('_', " ")),
+				},
+				None => Page::Texted { title, meta, text },
+			},
+		)
+	}
+}
+
+/// Impossible transitions seen by the parser, which point to a malformed dump.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Warning {
+	/// a `<page>` opened while another was still open
+	NestedPage,
+	/// a `<text>` without a preceding `<title>`
+	TextBeforeTitle,
+	/// a second `<title>` in the same page
+	DuplicateTitle,
+	/// a `</page>` before any text was read
+	PageWithoutText,
+}
+
+impl fmt::Display for Warning {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::NestedPage => "page opened while another was open",
+			Self::TextBeforeTitle => "text before title",
+			Self::DuplicateTitle => "duplicate title in page",
+			Self::PageWithoutText => "page closed without text",
+		})
+	}
 }
 
 impl Page {
-	pub fn parse(page: Self, event: XmlEvent) -> Self {
+	/// the page after this event, and what was wrong with the event, if anything
+	pub fn parse(page: Self, event: XmlEvent) -> (Self, Option<Warning>) {
 		match (page, event) {
-			(Page::None, XmlEvent::StartElement { name, .. }) if name.local_name == "page" => {
-				Page::Open
+			(Page::None, XmlEvent::StartElement { name, .. })
+			| (Page::Texted { .. }, XmlEvent::StartElement { name, .. })
+			| (Page::Redirect { .. }, XmlEvent::StartElement { name, .. })
+				if name.local_name == "page" =>
+			{
+				(Page::Open(0), None)
+			}
+
+			(_, XmlEvent::StartElement { name, .. }) if name.local_name == "page" => {
+				(Page::Open(0), Some(Warning::NestedPage))
+			}
+
+			(Page::Open(depth), XmlEvent::StartElement { name, .. })
+				if name.local_name == "text" =>
+			{
+				(Page::Open(depth + 1), Some(Warning::TextBeforeTitle))
+			}
+
+			(Page::Titled(mut body), XmlEvent::StartElement { name, .. })
+				if body.open.is_empty() && name.local_name == "title" =>
+			{
+				body.start(name.local_name, &[]);
+				(Page::Titled(body), Some(Warning::DuplicateTitle))
+			}
+
+			(Page::Open(0), XmlEvent::EndElement { name })
+			| (Page::Title(_), XmlEvent::EndElement { name })
+				if name.local_name == "page" =>
+			{
+				(Page::None, Some(Warning::PageWithoutText))
+			}
+
+			(Page::Titled(body), XmlEvent::EndElement { name })
+				if body.open.is_empty() && name.local_name == "page" =>
+			{
+				match body.finish() {
+					Some(page) => (page, None),
+					None => (Page::None, Some(Warning::PageWithoutText)),
+				}
 			}
 
-			(Page::Open, XmlEvent::StartElement { name, .. }) if name.local_name == "title" => {
+			(page, event) => (Self::transition(page, event), None),
+		}
+	}
+
+	fn transition(page: Self, event: XmlEvent) -> Self {
+		match (page, event) {
+			(Page::Open(0), XmlEvent::StartElement { name, .. }) if name.local_name == "title" => {
 				Page::Title(Vec::with_capacity(1))
 			}
+			(Page::Open(depth), XmlEvent::StartElement { .. }) => Page::Open(depth + 1),
+			(Page::Open(depth), XmlEvent::EndElement { .. }) => Page::Open(depth.saturating_sub(1)),
 
 			(Page::Title(mut ts), XmlEvent::Characters(s))
-			| (Page::Title(mut ts), XmlEvent::CData(s)) => {
+			| (Page::Title(mut ts), XmlEvent::CData(s))
+			| (Page::Title(mut ts), XmlEvent::Whitespace(s)) => {
 				ts.push(s);
 				Page::Title(ts)
 			}
 
 			(Page::Title(ts), XmlEvent::EndElement { name }) if name.local_name == "title" => {
-				Page::Titled(ts.join(" "))
+				Page::Titled(PageBody::new(normalize_title(&ts.concat())))
 			}
 
-			(Page::Titled(title), XmlEvent::StartElement { name, .. })
-				if name.local_name == "text" =>
+			(
+				Page::Titled(mut body),
+				XmlEvent::StartElement {
+					name, attributes, ..
+				},
+			) => {
+				body.start(name.local_name, &attributes);
+				Page::Titled(body)
+			}
+
+			(Page::Titled(mut body), XmlEvent::Characters(s))
+			| (Page::Titled(mut body), XmlEvent::CData(s)) => {
+				body.chars(s);
+				Page::Titled(body)
+			}
+
+			(Page::Titled(mut body), XmlEvent::Whitespace(_)) => {
+				body.whitespace();
+				Page::Titled(body)
+			}
+
+			(Page::Titled(mut body), XmlEvent::EndElement { .. }) => {
+				body.end();
+				Page::Titled(body)
+			}
+
+			(Page::Texted { .. }, _) | (Page::Redirect { .. }, _) => Page::None,
+
+			(p, _) => p,
+		}
+	}
+}
+
+/// parses a dump timestamp (`2021-06-01T12:34:56Z`) into seconds since the epoch
+fn parse_timestamp(ts: &str) -> Option<u64> {
+	let field = |range: std::ops::Range<usize>| ts.get(range)?.parse::<u64>().ok();
+	let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+	let (hour, minute, second) = (field(11..13)?, field(14..16)?, field(17..19)?);
+	if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+		return None;
+	}
+
+	// days since the epoch of the civil date, from Howard Hinnant's algorithms
+	let (y, m) = if month <= 2 {
+		(year - 1, month + 9)
+	} else {
+		(year, month - 3)
+	};
+	let era = y / 400;
+	let yoe = y - era * 400;
+	let doy = (153 * m + 2) / 5 + day - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	let days = (era * 146_097 + doe).checked_sub(719_468)?;
+
+	Some(days * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+/// parses a date given as `YYYY-MM-DD` (its midnight, UTC) or in RFC 3339 (`2021-06-01T12:34:56Z`,
+/// `2021-06-01T14:34:56.5+02:00`) into seconds since the epoch, dropping any fraction of a second
+pub fn parse_date(date: &str) -> Option<u64> {
+	let date = date.trim();
+	let bytes = date.as_bytes();
+	let separated = |at: usize, seps: &[u8]| bytes.get(at).is_some_and(|b| seps.contains(b));
+	if !separated(4, b"-") || !separated(7, b"-") {
+		return None;
+	}
+	if date.len() == 10 {
+		return parse_timestamp(&format!("{}T00:00:00Z", date));
+	}
+	if !separated(10, b"Tt ") || !separated(13, b":") || !separated(16, b":") {
+		return None;
+	}
+
+	let secs = parse_timestamp(date)?;
+	let zone = date.get(19..)?;
+	let zone = match zone.strip_prefix('.') {
+		Some(fraction) => fraction.trim_start_matches(|c: char| c.is_ascii_digit()),
+		None => zone,
+	};
+	if zone.eq_ignore_ascii_case("z") {
+		return Some(secs);
+	}
+
+	// the local time is ahead of UTC by a positive offset, so it's taken off
+	let (ahead, offset) = match zone.split_at_checked(1)? {
+		("+", offset) => (true, offset),
+		("-", offset) => (false, offset),
+		_ => return None,
+	};
+	let (hours, minutes) = offset.split_once(':')?;
+	if hours.len() != 2 || minutes.len() != 2 {
+		return None;
+	}
+	let offset = hours.parse::<u64>().ok()? * 3_600 + minutes.parse::<u64>().ok()? * 60;
+	if ahead {
+		secs.checked_sub(offset)
+	} else {
+		secs.checked_add(offset)
+	}
+}
+
+/// formats seconds since the epoch like dump timestamps (`2021-06-01T12:34:56Z`)
+pub fn format_timestamp(secs: u64) -> String {
+	let (days, time) = (secs / 86_400, secs % 86_400);
+
+	// the civil date of days since the epoch, the inverse of the above
+	let z = days + 719_468;
+	let era = z / 146_097;
+	let doe = z - era * 146_097;
+	let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + u64::from(month <= 2);
+
+	format!(
+		"{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+		year,
+		month,
+		day,
+		time / 3_600,
+		time % 3_600 / 60,
+		time % 60
+	)
+}
+
+/// the target of a `#REDIRECT [[target]]` body, without any `#section` or `|label`
+pub fn redirect_target(text: &str) -> Option<String> {
+	let text = text.trim_start();
+	if !text.get(..9)?.eq_ignore_ascii_case("#redirect") {
+		return None;
+	}
+
+	let link = text[9..]
+		.trim_start()
+		.trim_start_matches(':')
+		.trim_start()
+		.strip_prefix("[[")?;
+	let target = link[..link.find("]]")?].split(['|', '#']).next()?.trim();
+
+	if target.is_empty() {
+		None
+	} else {
+		Some(target.to_string())
+	}
+}
+
+/// The `<siteinfo>` header of a dump: which wiki it's of, and its namespaces.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SiteInfo {
+	/// the wiki's name, like Wiktionary
+	pub sitename: Option<String>,
+	/// the wiki's database name, like enwiktionary
+	pub dbname: Option<String>,
+	/// the URL of the wiki's main page
+	pub base: Option<String>,
+	/// the MediaWiki version that made the dump
+	pub generator: Option<String>,
+	/// whether titles are case-sensitive (`case-sensitive` or `first-letter`)
+	pub case: Option<String>,
+	/// the namespaces, by number
+	pub namespaces: BTreeMap<i32, Namespace>,
+}
+
+/// A namespace of a wiki.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Namespace {
+	/// its name, which prefixes the titles of its pages (empty for the main namespace)
+	pub name: String,
+	/// whether titles are case-sensitive in it
+	pub case: Option<String>,
+}
+
+/// The siteinfo being parsed, like `Page`.
+pub enum Site {
+	/// before the `<siteinfo>`
+	None,
+	/// within the `<siteinfo>`
+	Open(SiteInfo),
+	/// within one of its fields
+	Field {
+		info: SiteInfo,
+		field: String,
+		text: Vec<String>,
+	},
+	/// within a `<namespace>`
+	Namespace {
+		info: SiteInfo,
+		key: i32,
+		case: Option<String>,
+		text: Vec<String>,
+	},
+	/// after the `</siteinfo>`
+	Done(SiteInfo),
+}
+
+impl Site {
+	/// the siteinfo after this event
+	pub fn parse(site: Self, event: &XmlEvent) -> Self {
+		match (site, event) {
+			(Site::None, XmlEvent::StartElement { name, .. }) if name.local_name == "siteinfo" => {
+				Site::Open(SiteInfo::default())
+			}
+
+			// a page before any siteinfo means there isn't one
+			(Site::None, XmlEvent::StartElement { name, .. }) if name.local_name == "page" => {
+				Site::Done(SiteInfo::default())
+			}
+
+			(
+				Site::Open(info),
+				XmlEvent::StartElement {
+					name, attributes, ..
+				},
+			) if name.local_name == "namespace" => {
+				let attr = |key: &str| {
+					attributes
+						.iter()
+						.find(|a| a.name.local_name == key)
+						.map(|a| a.value.clone())
+				};
+
+				Site::Namespace {
+					info,
+					key: attr("key").and_then(|k| k.parse().ok()).unwrap_or_default(),
+					case: attr("case"),
+					text: Vec::with_capacity(1),
+				}
+			}
+
+			(Site::Open(info), XmlEvent::StartElement { name, .. })
+				if name.local_name != "namespaces" =>
 			{
-				Page::Text {
-					title,
-					text: Vec::with_capacity(5),
+				Site::Field {
+					info,
+					field: name.local_name.clone(),
+					text: Vec::with_capacity(1),
 				}
 			}
 
-			(Page::Text { title, mut text }, XmlEvent::Characters(s))
-			| (Page::Text { title, mut text }, XmlEvent::CData(s)) => {
-				text.push(s);
-				Page::Text { title, text }
+			(
+				Site::Field {
+					info,
+					field,
+					mut text,
+				},
+				XmlEvent::Characters(s),
+			)
+			| (
+				Site::Field {
+					info,
+					field,
+					mut text,
+				},
+				XmlEvent::CData(s),
+			) => {
+				text.push(s.clone());
+				Site::Field { info, field, text }
 			}
 
-			(Page::Text { title, text }, XmlEvent::EndElement { name })
-				if name.local_name == "text" =>
-			{
-				Page::Texted {
-					title,
-					text: text.join(" "),
+			(
+				Site::Namespace {
+					info,
+					key,
+					case,
+					mut text,
+				},
+				XmlEvent::Characters(s),
+			)
+			| (
+				Site::Namespace {
+					info,
+					key,
+					case,
+					mut text,
+				},
+				XmlEvent::CData(s),
+			) => {
+				text.push(s.clone());
+				Site::Namespace {
+					info,
+					key,
+					case,
+					text,
 				}
 			}
 
-			(Page::Texted { .. }, _) => Page::None,
-			(_, XmlEvent::EndElement { name }) if name.local_name == "page" => Page::None,
+			(
+				Site::Field {
+					mut info,
+					field,
+					text,
+				},
+				XmlEvent::EndElement { name },
+			) if name.local_name == field => {
+				let value = Some(text.join(" "));
+				match field.as_str() {
+					"sitename" => info.sitename = value,
+					"dbname" => info.dbname = value,
+					"base" => info.base = value,
+					"generator" => info.generator = value,
+					"case" => info.case = value,
+					_ => {}
+				}
+				Site::Open(info)
+			}
 
-			(p, _) => p,
+			(
+				Site::Namespace {
+					mut info,
+					key,
+					case,
+					text,
+				},
+				XmlEvent::EndElement { name },
+			) if name.local_name == "namespace" => {
+				info.namespaces.insert(
+					key,
+					Namespace {
+						name: text.join(" "),
+						case,
+					},
+				);
+				Site::Open(info)
+			}
+
+			(Site::Open(info), XmlEvent::EndElement { name }) if name.local_name == "siteinfo" => {
+				Site::Done(info)
+			}
+
+			(s, _) => s,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use xml::{name::OwnedName, namespace::Namespace, EventReader};
+
+	use super::*;
+	use crate::xmlreader;
+
+	/// a parsed page's title, its text or the title it redirects to, whether it redirects, and
+	/// its timestamp
+	type Parsed = (String, String, bool, Option<u64>);
+
+	/// the pages of a dump, with their timestamps, and the warnings parsing it, checking that
+	/// xml-rs's events make the same of it as those of `xmlreader`
+	fn parse(xml: &str) -> (Vec<Parsed>, Vec<Warning>) {
+		let events: Vec<XmlEvent> = xmlreader::Reader::new(xml.as_bytes())
+			.collect::<Result<_>>()
+			.unwrap();
+		let xml_rs: Vec<XmlEvent> = EventReader::new(xml.as_bytes())
+			.into_iter()
+			.collect::<Result<_, _>>()
+			.unwrap();
+		let parsed = parse_events(events);
+		assert_eq!(parsed, parse_events(xml_rs));
+		parsed
+	}
+
+	fn parse_events(events: Vec<XmlEvent>) -> (Vec<Parsed>, Vec<Warning>) {
+		let (mut pages, mut warnings) = (Vec::new(), Vec::new());
+		let mut page = Page::None;
+		for event in events {
+			let (next, warning) = Page::parse(page, event);
+			page = next;
+			warnings.extend(warning);
+			match page {
+				Page::Texted {
+					ref title,
+					ref meta,
+					ref text,
+				} => pages.push((title.clone(), text.clone(), false, meta.timestamp)),
+				Page::Redirect {
+					ref from,
+					ref meta,
+					ref to,
+				} => pages.push((from.clone(), to.clone(), true, meta.timestamp)),
+				_ => {}
+			}
+		}
+		(pages, warnings)
+	}
+
+	#[test]
+	fn multiple_revisions() {
+		let xml = include_str!("../tests/fixtures/multi-revision.xml");
+		let (pages, warnings) = parse(xml);
+		let titles: Vec<_> = pages
+			.iter()
+			.map(|(title, text, redirect, _)| (title.as_str(), text.as_str(), *redirect))
+			.collect();
+		assert_eq!(
+			titles,
+			[
+				("dog", "third", false),
+				// the last revision is a stub, so the one before is kept
+				("cat", "meow", false),
+				("fox", "dog", true),
+			]
+		);
+
+		// the timestamp is that of the revision whose text is kept
+		let timestamps: Vec<_> = pages.iter().map(|page| page.3).collect();
+		assert_eq!(
+			timestamps,
+			[
+				parse_date("2022-01-01"),
+				parse_date("2020-06-01"),
+				parse_date("2023-01-01")
+			]
+		);
+		assert!(warnings.is_empty());
+	}
+
+	#[test]
+	fn stub_text() {
+		let (pages, warnings) = parse(include_str!("../tests/fixtures/stub.xml"));
+		let pages: Vec<_> = pages
+			.iter()
+			.map(|(title, text, _, _)| (title.as_str(), text.as_str()))
+			.collect();
+		// a stub has no text, but a page that's empty (bytes="0") or blank is kept, empty
+		assert_eq!(
+			pages,
+			[("empty", ""), ("blank", ""), ("whole", "==English==\nx")]
+		);
+		assert_eq!(warnings, [Warning::PageWithoutText]);
+	}
+
+	#[test]
+	fn deleted_and_textless_revisions() {
+		let (pages, warnings) = parse(include_str!("../tests/fixtures/deleted.xml"));
+		let pages: Vec<_> = pages
+			.iter()
+			.map(|(title, text, _, timestamp)| (title.as_str(), text.as_str(), *timestamp))
+			.collect();
+		// pages without any text are skipped, the page after each is whole, and a page whose
+		// later revisions are deleted or textless keeps the text of the one before them
+		assert_eq!(
+			pages,
+			[
+				("after hidden", "shown", parse_date("2021-02-02")),
+				("after textless", "seen", parse_date("2021-02-05")),
+				("redacted", "original", parse_date("2021-02-06")),
+				("last", "end", parse_date("2021-02-09")),
+			]
+		);
+		assert_eq!(warnings, [Warning::PageWithoutText; 3]);
+	}
+
+	#[test]
+	fn titles_of_other_elements() {
+		let (pages, warnings) = parse(include_str!("../tests/fixtures/unrelated-title.xml"));
+		let pages: Vec<_> = pages
+			.iter()
+			.map(|(title, text, _, _)| (title.as_str(), text.as_str()))
+			.collect();
+		assert_eq!(pages, [("star", "a sun"), ("moon", "a rock")]);
+		assert!(warnings.is_empty());
+	}
+
+	#[test]
+	fn normalizes_titles() {
+		assert_eq!(normalize_title("cat"), "cat");
+		assert_eq!(normalize_title("  the\tcat \r\n sat  "), "the cat sat");
+		assert_eq!(normalize_title(" \n\t "), "");
+		// non-breaking and other spaces are left as they are
+		assert_eq!(
+			normalize_title("\u{a0}cat\u{2009}sat\u{a0}"),
+			"\u{a0}cat\u{2009}sat\u{a0}"
+		);
+		assert_eq!(normalize_title("chat  noir "), "chat noir");
+	}
+
+	/// the titles of the pages of these `<page>`s, each with a little text
+	fn titles(pages: &[&str]) -> Vec<String> {
+		let xml: String = pages
+			.iter()
+			.map(|page| format!("<page>{}<revision><text>x</text></revision></page>", page))
+			.collect();
+		let (pages, warnings) = parse(&format!("<mediawiki>{}</mediawiki>", xml));
+		assert!(warnings.is_empty());
+		pages.into_iter().map(|page| page.0).collect()
+	}
+
+	#[test]
+	fn titles_in_pages() {
+		assert_eq!(
+			titles(&[
+				"<title>  cat\n</title>",
+				"<title>the \t cat</title>",
+				"<title>caf&#233; &amp; bar</title>",
+				"<title>a<!-- comment -->b</title>",
+				"<title>a <!-- comment --> b</title>",
+				"<title>a<![CDATA[ & <b> ]]>c</title>",
+				"<title><![CDATA[ cdata ]]></title>",
+				"<title>\u{a0}nbsp</title>",
+			]),
+			[
+				"cat",
+				"the cat",
+				"café & bar",
+				"ab",
+				"a b",
+				"a & <b> c",
+				"cdata",
+				"\u{a0}nbsp"
+			]
+		);
+	}
+
+	#[test]
+	fn title_of_several_events() {
+		let name = |name: &str| OwnedName {
+			local_name: name.to_string(),
+			namespace: None,
+			prefix: None,
+		};
+		let start = |n: &str| XmlEvent::StartElement {
+			name: name(n),
+			attributes: Vec::new(),
+			namespace: Namespace::empty(),
+		};
+		let end = |n: &str| XmlEvent::EndElement { name: name(n) };
+		let events = vec![
+			start("page"),
+			start("title"),
+			XmlEvent::Whitespace(" ".into()),
+			XmlEvent::Characters("black".into()),
+			XmlEvent::Whitespace("\n ".into()),
+			XmlEvent::CData("and".into()),
+			XmlEvent::Characters(" white ".into()),
+			XmlEvent::Characters("cat".into()),
+			end("title"),
+			start("revision"),
+			start("text"),
+			XmlEvent::Characters("x".into()),
+			end("text"),
+			end("revision"),
+			end("page"),
+		];
+		let (pages, warnings) = parse_events(events);
+		assert_eq!(pages[0].0, "black and white cat");
+		assert!(warnings.is_empty());
+	}
+
+	#[test]
+	fn parse_date_formats() {
+		let noon = parse_timestamp("2021-06-01T12:34:56Z").unwrap();
+		assert_eq!(format_timestamp(noon), "2021-06-01T12:34:56Z");
+		assert_eq!(parse_date("2021-06-01"), Some(noon - 45_296));
+		assert_eq!(parse_date("2021-06-01T12:34:56Z"), Some(noon));
+		assert_eq!(parse_date("2021-06-01t12:34:56z"), Some(noon));
+		assert_eq!(parse_date("2021-06-01 12:34:56.789Z"), Some(noon));
+		assert_eq!(parse_date("2021-06-01T14:34:56+02:00"), Some(noon));
+		assert_eq!(parse_date("2021-06-01T09:04:56-03:30"), Some(noon));
+		assert_eq!(parse_date("1970-01-01"), Some(0));
+	}
+
+	#[test]
+	fn parse_date_rejects() {
+		for date in [
+			"",
+			"2021",
+			"2021-6-1",
+			"2021/06/01",
+			"2021-13-01",
+			"1969-12-31",
+			"2021-06-01T12:34:56",
+			"2021-06-01T12:34:56+2",
+			"2021-06-01T12:34:56 UTC",
+			"2021-06-01X12:34:56Z",
+		] {
+			assert_eq!(parse_date(date), None, "{:?}", date);
 		}
 	}
 }
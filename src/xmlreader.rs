@@ -0,0 +1,543 @@
+//! A fast reader of the XML in dumps, as a stream of xml-rs events.
+//!
+//! xml-rs reads a character at a time, which makes parsing the slowest part of making a store.
+//! Dumps only use a small part of XML, so this reads them a buffer at a time instead, skipping
+//! through text with `memchr`, and yields the same events `xml::EventReader` does with its default
+//! settings (whitespace-only text as `Whitespace`, comments and whitespace outside the root left
+//! out), so what's built on those events doesn't change.
+//!
+//! It doesn't resolve namespaces (names only have their prefix), and skips doctypes rather than
+//! reading them, so entities other than XML's own aren't known. Text on either side of a comment
+//! comes as two events rather than one.
+
+use std::{
+	io::{ErrorKind, Read},
+	str,
+};
+
+use color_eyre::eyre::{eyre, Result};
+use memchr::{memchr, memmem};
+use xml::{attribute::OwnedAttribute, name::OwnedName, namespace::Namespace, reader::XmlEvent};
+
+/// How much more of the input is read whenever the buffer runs out.
+const CHUNK: usize = 1 << 16;
+
+/// Reads XML events from a dump, like `xml::EventReader`.
+///
+/// It stops at the first error, like xml-rs does, and also checks that elements are closed in
+/// order and that the input doesn't end within one.
+pub struct Reader<R> {
+	input: R,
+	buf: Vec<u8>,
+	/// where in `buf` the next event starts
+	pos: usize,
+	/// how many bytes of the input came before `buf`, to say where errors are
+	offset: u64,
+	eof: bool,
+	failed: bool,
+	/// the names of the elements open, innermost last
+	open: Vec<String>,
+	/// the end of an empty element (`<a/>`), which comes right after its start
+	pending: Option<XmlEvent>,
+}
+
+impl<R: Read> Reader<R> {
+	pub fn new(input: R) -> Self {
+		Self {
+			input,
+			buf: Vec::with_capacity(CHUNK),
+			pos: 0,
+			offset: 0,
+			eof: false,
+			failed: false,
+			open: Vec::new(),
+			pending: None,
+		}
+	}
+
+	/// carries on after an error from the next `<name>` start tag, as if the element of that name
+	/// which was open (and everything in it) had been closed, returning false if there's none
+	///
+	/// That's for skipping a broken page of a dump, rather than giving up on the rest.
+	pub fn resume_at(&mut self, name: &str) -> Result<bool> {
+		self.failed = false;
+		self.pending = None;
+
+		// from past where the error was, which may well be at that tag
+		let needle = format!("<{}>", name);
+		let found = if self.fill(1)? {
+			self.find(1, needle.as_bytes())?
+		} else {
+			None
+		};
+		let at = match found {
+			Some(at) => at,
+			None => {
+				self.pos = self.buf.len();
+				self.open.clear();
+				return Ok(false);
+			}
+		};
+		self.pos += at;
+
+		if let Some(depth) = self.open.iter().rposition(|open| open == name) {
+			self.open.truncate(depth);
+		}
+		Ok(true)
+	}
+
+	fn next_event(&mut self) -> Result<Option<XmlEvent>> {
+		if let Some(event) = self.pending.take() {
+			return Ok(Some(event));
+		}
+
+		loop {
+			if !self.fill(1)? {
+				return match self.open.last() {
+					Some(name) => Err(self.error(format!("the input ends within <{}>", name))),
+					None => Ok(None),
+				};
+			}
+
+			if self.buf[self.pos] != b'<' {
+				let len = match self.find(0, b"<")? {
+					Some(len) => len,
+					None => self.buf.len() - self.pos,
+				};
+				let text = self.text(len)?;
+				let blank = text.chars().all(is_space);
+				if self.open.is_empty() {
+					if blank {
+						continue;
+					}
+					return Err(self.error("text outside of the root element"));
+				}
+
+				return Ok(Some(if blank {
+					XmlEvent::Whitespace(text)
+				} else {
+					XmlEvent::Characters(text)
+				}));
+			}
+
+			if !self.fill(2)? {
+				return Err(self.error("the input ends within a tag"));
+			}
+			let kind = self.buf[self.pos + 1];
+			match kind {
+				b'/' => return self.end_element().map(Some),
+				b'?' => {
+					// the xml declaration, or a processing instruction
+					let end = self.find_or_fail(2, b"?>")?;
+					self.pos += end + 2;
+				}
+				b'!' if self.starts_with(b"<!--")? => {
+					let end = self.find_or_fail(4, b"-->")?;
+					self.pos += end + 3;
+				}
+				b'!' if self.starts_with(b"<![CDATA[")? => {
+					let end = self.find_or_fail(9, b"]]>")?;
+					let data = self.utf8(9, end)?.to_string();
+					self.pos += end + 3;
+					return Ok(Some(XmlEvent::CData(data)));
+				}
+				b'!' => {
+					// a doctype, whose internal subset (if any) isn't read
+					let end = self.find_or_fail(2, b">")?;
+					self.pos += end + 1;
+				}
+				_ => return self.start_element().map(Some),
+			}
+		}
+	}
+
+	fn start_element(&mut self) -> Result<XmlEvent> {
+		// the end of the tag is the first `>` that isn't in an attribute value
+		let mut quote = None;
+		let mut at = 1;
+		let end = loop {
+			if self.pos + at >= self.buf.len() {
+				if !self.more()? {
+					return Err(self.error("the input ends within a tag"));
+				}
+				continue;
+			}
+
+			let byte = self.buf[self.pos + at];
+			match quote {
+				Some(q) if byte == q => quote = None,
+				Some(_) => {}
+				None if byte == b'"' || byte == b'\'' => quote = Some(byte),
+				None if byte == b'>' => break at,
+				None => {}
+			}
+			at += 1;
+		};
+
+		let tag = self.utf8(1, end)?;
+		let (tag, empty) = match tag.strip_suffix('/') {
+			Some(tag) => (tag, true),
+			None => (tag, false),
+		};
+		let (name, attributes) = parse_tag(tag).map_err(|err| self.error(err))?;
+		self.pos += end + 1;
+
+		if empty {
+			self.pending = Some(XmlEvent::EndElement {
+				name: owned_name(&name),
+			});
+		} else {
+			self.open.push(name.clone());
+		}
+		Ok(XmlEvent::StartElement {
+			name: owned_name(&name),
+			attributes,
+			namespace: Namespace::empty(),
+		})
+	}
+
+	fn end_element(&mut self) -> Result<XmlEvent> {
+		let end = self.find_or_fail(2, b">")?;
+		let name = self.utf8(2, end)?.trim().to_string();
+		match self.open.pop() {
+			Some(open) if open == name => {
+				self.pos += end + 1;
+				Ok(XmlEvent::EndElement {
+					name: owned_name(&name),
+				})
+			}
+			Some(open) => Err(self.error(format!("</{}> closes <{}>", name, open))),
+			None => Err(self.error(format!("</{}> closes no element", name))),
+		}
+	}
+
+	/// the next `len` bytes as text, with entities replaced
+	fn text(&mut self, len: usize) -> Result<String> {
+		let text = unescape(self.utf8(0, len)?).map_err(|err| self.error(err))?;
+		self.pos += len;
+		Ok(text)
+	}
+
+	/// the bytes from `start` to `end` (from the next event) as a string
+	fn utf8(&self, start: usize, end: usize) -> Result<&str> {
+		str::from_utf8(&self.buf[self.pos + start..self.pos + end])
+			.map_err(|err| self.error(format!("invalid UTF-8: {}", err)))
+	}
+
+	/// where `needle` is after `from` (from the next event), reading more until it's found
+	fn find(&mut self, from: usize, needle: &[u8]) -> Result<Option<usize>> {
+		let mut from = from;
+		loop {
+			let haystack = &self.buf[self.pos + from..];
+			let found = match needle {
+				[byte] => memchr(*byte, haystack),
+				_ => memmem::find(haystack, needle),
+			};
+			if let Some(at) = found {
+				return Ok(Some(from + at));
+			}
+
+			// a needle cut across the end of the buffer is found on the next try
+			from = (self.buf.len() - self.pos)
+				.saturating_sub(needle.len() - 1)
+				.max(from);
+			if !self.more()? {
+				return Ok(None);
+			}
+		}
+	}
+
+	fn find_or_fail(&mut self, from: usize, needle: &[u8]) -> Result<usize> {
+		self.find(from, needle)?.ok_or_else(|| {
+			self.error(format!(
+				"the input ends before {:?}",
+				String::from_utf8_lossy(needle)
+			))
+		})
+	}
+
+	fn starts_with(&mut self, prefix: &[u8]) -> Result<bool> {
+		Ok(self.fill(prefix.len())? && self.buf[self.pos..].starts_with(prefix))
+	}
+
+	/// reads until there are at least `len` bytes from the next event, returning false if the
+	/// input ends first
+	fn fill(&mut self, len: usize) -> Result<bool> {
+		while self.buf.len() - self.pos < len {
+			if !self.more()? {
+				return Ok(false);
+			}
+		}
+		Ok(true)
+	}
+
+	/// reads another chunk of the input, dropping what was parsed already, returning false at its
+	/// end
+	fn more(&mut self) -> Result<bool> {
+		if self.eof {
+			return Ok(false);
+		}
+
+		if self.pos > 0 {
+			self.buf.drain(..self.pos);
+			self.offset += self.pos as u64;
+			self.pos = 0;
+		}
+
+		let len = self.buf.len();
+		self.buf.resize(len + CHUNK, 0);
+		let read = loop {
+			match self.input.read(&mut self.buf[len..]) {
+				Ok(read) => break read,
+				Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+				Err(err) => {
+					self.buf.truncate(len);
+					return Err(err.into());
+				}
+			}
+		};
+		self.buf.truncate(len + read);
+		self.eof = read == 0;
+		Ok(read > 0)
+	}
+
+	fn error(&self, message: impl AsRef<str>) -> color_eyre::Report {
+		eyre!(
+			"bad XML at byte {}: {}",
+			self.offset + self.pos as u64,
+			message.as_ref()
+		)
+	}
+}
+
+impl<R: Read> Iterator for Reader<R> {
+	type Item = Result<XmlEvent>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed {
+			return None;
+		}
+
+		match self.next_event() {
+			Ok(event) => event.map(Ok),
+			Err(err) => {
+				self.failed = true;
+				Some(Err(err))
+			}
+		}
+	}
+}
+
+/// the name and attributes in a start tag (between `<` and `>` or `/>`)
+fn parse_tag(tag: &str) -> Result<(String, Vec<OwnedAttribute>), String> {
+	let name_end = tag.find(is_space).unwrap_or(tag.len());
+	let name = &tag[..name_end];
+	if name.is_empty() {
+		return Err(String::from("a tag has no name"));
+	}
+
+	let mut attributes = Vec::new();
+	let mut rest = tag[name_end..].trim_start_matches(is_space);
+	while !rest.is_empty() {
+		let (key, value) = rest
+			.split_once('=')
+			.ok_or_else(|| format!("attribute without a value in <{}>", name))?;
+		let value = value.trim_start_matches(is_space);
+		let quote = value
+			.chars()
+			.next()
+			.filter(|c| *c == '"' || *c == '\'')
+			.ok_or_else(|| format!("unquoted attribute value in <{}>", name))?;
+		let end = value[1..]
+			.find(quote)
+			.ok_or_else(|| format!("unterminated attribute value in <{}>", name))?;
+
+		attributes.push(OwnedAttribute {
+			name: owned_name(key.trim_end_matches(is_space)),
+			value: unescape(&value[1..=end])?,
+		});
+		rest = value[end + 2..].trim_start_matches(is_space);
+	}
+
+	Ok((name.to_string(), attributes))
+}
+
+fn owned_name(name: &str) -> OwnedName {
+	let (prefix, local_name) = match name.split_once(':') {
+		Some((prefix, local_name)) => (Some(prefix.to_string()), local_name),
+		None => (None, name),
+	};
+	OwnedName {
+		local_name: local_name.to_string(),
+		namespace: None,
+		prefix,
+	}
+}
+
+/// text with XML's entities (`&lt;` etc, and numeric ones) replaced by what they stand for
+fn unescape(text: &str) -> Result<String, String> {
+	let mut unescaped = String::with_capacity(text.len());
+	let mut rest = text;
+	while let Some(at) = rest.find('&') {
+		unescaped.push_str(&rest[..at]);
+		let len = rest[at..]
+			.find(';')
+			.ok_or_else(|| String::from("unterminated entity"))?;
+		let entity = &rest[at + 1..at + len];
+		unescaped.push(match entity {
+			"lt" => '<',
+			"gt" => '>',
+			"amp" => '&',
+			"quot" => '"',
+			"apos" => '\'',
+			_ => entity
+				.strip_prefix("#x")
+				.map(|hex| u32::from_str_radix(hex, 16))
+				.or_else(|| entity.strip_prefix('#').map(str::parse))
+				.and_then(|code| code.ok())
+				.and_then(char::from_u32)
+				.ok_or_else(|| format!("unknown entity &{};", entity))?,
+		});
+		rest = &rest[at + len + 1..];
+	}
+	unescaped.push_str(rest);
+	Ok(unescaped)
+}
+
+/// whitespace, as XML has it
+fn is_space(c: char) -> bool {
+	matches!(c, ' ' | '\t' | '\n' | '\r')
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// the events of a document, in brief: `<name a="v">`, `</name>`, `"text"`, `[cdata]`, and
+	/// `_` for whitespace
+	fn events(input: impl Read) -> Result<Vec<String>> {
+		Reader::new(input)
+			.map(|event| {
+				Ok(match event? {
+					XmlEvent::StartElement {
+						name, attributes, ..
+					} => format!(
+						"<{}{}>",
+						name.local_name,
+						attributes
+							.iter()
+							.map(|a| format!(" {}={:?}", a.name.local_name, a.value))
+							.collect::<String>()
+					),
+					XmlEvent::EndElement { name } => format!("</{}>", name.local_name),
+					XmlEvent::Characters(text) => format!("{:?}", text),
+					XmlEvent::CData(data) => format!("[{}]", data),
+					XmlEvent::Whitespace(_) => String::from("_"),
+					event => format!("{:?}", event),
+				})
+			})
+			.collect()
+	}
+
+	/// input that comes a byte at a time, so every event is cut across reads
+	struct Trickle<'a>(&'a [u8]);
+
+	impl Read for Trickle<'_> {
+		fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+			self.0
+				.take(1)
+				.read(buf)
+				.inspect(|read| self.0 = &self.0[*read..])
+		}
+	}
+
+	#[test]
+	fn unescapes() {
+		assert_eq!(unescape("plain").unwrap(), "plain");
+		assert_eq!(
+			unescape("&lt;a&gt; &amp; &quot;b&quot; &apos;c&apos;").unwrap(),
+			"<a> & \"b\" 'c'"
+		);
+		assert_eq!(unescape("&#233;t&#xE9; &#x1F600;").unwrap(), "été 😀");
+		assert_eq!(unescape("a &amp").unwrap_err(), "unterminated entity");
+		assert_eq!(unescape("&nbsp;").unwrap_err(), "unknown entity &nbsp;");
+		assert_eq!(unescape("&#xD800;").unwrap_err(), "unknown entity &#xD800;");
+		assert_eq!(unescape("&#x;").unwrap_err(), "unknown entity &#x;");
+	}
+
+	#[test]
+	fn cdata_and_comments() {
+		let xml = "<?xml version=\"1.0\"?>\n<!-- before -->\n<a>x<!-- <b> -->y \
+			<![CDATA[<b>&amp;]]]]><![CDATA[>]]></a>\n<!-- after -->";
+		let expected = ["<a>", "\"x\"", "\"y \"", "[<b>&amp;]]]", "[>]", "</a>"];
+		assert_eq!(events(xml.as_bytes()).unwrap(), expected);
+		assert_eq!(events(Trickle(xml.as_bytes())).unwrap(), expected);
+
+		assert!(events("<a><!-- open </a>".as_bytes()).is_err());
+		assert!(events("<a><![CDATA[ open </a>".as_bytes()).is_err());
+	}
+
+	#[test]
+	fn quoted_gt_in_attributes() {
+		let xml = "<a b=\"1 > 0\" c = '&gt;\"' d=\"/>\"><e f='>'/></a>";
+		let expected = [
+			"<a b=\"1 > 0\" c=\">\\\"\" d=\"/>\">",
+			"<e f=\">\">",
+			"</e>",
+			"</a>",
+		];
+		assert_eq!(events(xml.as_bytes()).unwrap(), expected);
+		assert_eq!(events(Trickle(xml.as_bytes())).unwrap(), expected);
+		assert!(events("<a b=\"x>".as_bytes()).is_err());
+	}
+
+	#[test]
+	fn needles_across_chunks() {
+		// the first read fills the buffer with `CHUNK` bytes, so each of these ends cuts across
+		// the end of it, one way or another
+		for cut in 0..4 {
+			let fill = |before: &str, after: &str| {
+				let len = CHUNK - cut - before.len();
+				format!("{}{}{}", before, "x".repeat(len), after)
+			};
+
+			let xml = fill("<a><![CDATA[", "]]></a>");
+			let data = format!("[{}]", "x".repeat(CHUNK - cut - 12));
+			assert_eq!(events(xml.as_bytes()).unwrap(), ["<a>", &data, "</a>"]);
+
+			let xml = fill("<a><!--", "--></a>");
+			assert_eq!(events(xml.as_bytes()).unwrap(), ["<a>", "</a>"]);
+
+			let xml = fill("<a>", "</a>");
+			let text = format!("{:?}", "x".repeat(CHUNK - cut - 3));
+			assert_eq!(events(xml.as_bytes()).unwrap(), ["<a>", &text, "</a>"]);
+
+			let xml = fill("<a b='", "'></a>");
+			let tag = format!("<a b={:?}>", "x".repeat(CHUNK - cut - 6));
+			assert_eq!(events(xml.as_bytes()).unwrap(), [&tag, "</a>"]);
+		}
+	}
+
+	#[test]
+	fn resumes_at_the_next_tag() {
+		let xml = "<r><page><a></page>\n<page><t>ok</t></page><page><b></page></r>";
+		let mut reader = Reader::new(xml.as_bytes());
+		let before: Vec<_> = reader.by_ref().collect();
+		assert_eq!(before.len(), 4);
+		assert!(before[3].is_err());
+		assert!(reader.next().is_none());
+
+		assert!(reader.resume_at("page").unwrap());
+		let page: Vec<_> = reader.by_ref().take(5).map(Result::unwrap).collect();
+		assert!(
+			matches!(&page[0], XmlEvent::StartElement { name, .. } if name.local_name == "page")
+		);
+		assert!(matches!(&page[2], XmlEvent::Characters(text) if text == "ok"));
+		assert!(matches!(&page[4], XmlEvent::EndElement { name } if name.local_name == "page"));
+
+		// the third page is broken too, and there's none after it
+		assert!(reader.nth(2).unwrap().is_err());
+		assert!(!reader.resume_at("page").unwrap());
+		assert!(reader.next().is_none());
+	}
+}